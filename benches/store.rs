@@ -0,0 +1,229 @@
+//! Throughput of a shard's store as the number of threads grows: reads with
+//! and without a writer committing alongside them, writers committing to
+//! keys of their own, and threads mixing the two. The same work against a
+//! keyspace behind a single mutex, the way shards used to hold it, is
+//! measured for comparison.
+//!
+//! Run with `cargo bench --bench store`.
+
+use rust_edis::commands::read_key;
+use rust_edis::storage::keyspace::Keyspace;
+use rust_edis::storage::mutation::Mutation;
+use rust_edis::storage::store::Store;
+use std::hint::black_box;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const KEYS: u64 = 10_000;
+const RUN_FOR: Duration = Duration::from_millis(500);
+
+fn key(i: u64) -> Vec<u8> {
+    format!("key:{}", i).into_bytes()
+}
+
+fn set(i: u64) -> Mutation {
+    Mutation::Set {
+        key: key(i),
+        value: vec![b'v'; 64],
+    }
+}
+
+/// Reads per second across `threads` threads each running `read` in a loop,
+/// while `write` runs in a loop on one more thread if given
+fn measure(
+    threads: usize,
+    read: impl Fn(&[u8]) + Send + Sync + 'static,
+    write: Option<Box<dyn FnMut(u64) + Send>>,
+) -> f64 {
+    let read = Arc::new(read);
+    let stop = Arc::new(AtomicBool::new(false));
+    let writer = write.map(|mut write| {
+        let stop = stop.clone();
+        thread::spawn(move || {
+            let mut i = 0;
+            while !stop.load(Ordering::Relaxed) {
+                write(i % KEYS);
+                i += 1;
+            }
+        })
+    });
+
+    let keys: Arc<Vec<Vec<u8>>> = Arc::new((0..KEYS).map(key).collect());
+    let started = Instant::now();
+    let readers: Vec<_> = (0..threads)
+        .map(|t| {
+            let (read, stop, keys) = (read.clone(), stop.clone(), keys.clone());
+            thread::spawn(move || {
+                let mut reads: u64 = 0;
+                let mut i = t as u64 * 7_919;
+                while !stop.load(Ordering::Relaxed) {
+                    read(&keys[(i % KEYS) as usize]);
+                    i += 1;
+                    reads += 1;
+                }
+                reads
+            })
+        })
+        .collect();
+    thread::sleep(RUN_FOR);
+    stop.store(true, Ordering::Relaxed);
+    let reads: u64 = readers.into_iter().map(|r| r.join().unwrap()).sum();
+    let elapsed = started.elapsed().as_secs_f64();
+    if let Some(writer) = writer {
+        writer.join().unwrap();
+    }
+    reads as f64 / elapsed
+}
+
+/// Operations per second across `threads` threads each running `op` in a
+/// loop, with the index of the thread and how many operations it ran so far
+fn throughput(threads: usize, op: impl Fn(usize, u64) + Send + Sync + 'static) -> f64 {
+    let op = Arc::new(op);
+    let stop = Arc::new(AtomicBool::new(false));
+    let started = Instant::now();
+    let workers: Vec<_> = (0..threads)
+        .map(|t| {
+            let (op, stop) = (op.clone(), stop.clone());
+            thread::spawn(move || {
+                let mut ops: u64 = 0;
+                while !stop.load(Ordering::Relaxed) {
+                    op(t, ops);
+                    ops += 1;
+                }
+                ops
+            })
+        })
+        .collect();
+    thread::sleep(RUN_FOR);
+    stop.store(true, Ordering::Relaxed);
+    let ops: u64 = workers.into_iter().map(|w| w.join().unwrap()).sum();
+    ops as f64 / started.elapsed().as_secs_f64()
+}
+
+/// Key the `i`th operation of thread `t` out of `threads` touches. Threads
+/// take turns through the keys, so each one sticks to keys of its own.
+fn own_key(t: usize, i: u64, threads: usize) -> u64 {
+    (t as u64 + i * threads as u64) % KEYS
+}
+
+fn main() {
+    let cores = thread::available_parallelism().map_or(1, |n| n.get());
+    let thread_counts: Vec<usize> = std::iter::successors(Some(1), |&n| Some(n * 2))
+        .take_while(|&n| n <= cores.max(8))
+        .collect();
+
+    let store = Arc::new(Store::default());
+    let mut mutex_keyspace = Keyspace::new();
+    {
+        let mut writer = store.exclusive();
+        for i in 0..KEYS {
+            writer.commit(set(i)).unwrap();
+            set(i).apply(&mut mutex_keyspace, i + 1);
+        }
+    }
+    let mutex_keyspace = Arc::new(Mutex::new(mutex_keyspace));
+
+    println!("{} cores, {} keys", cores, KEYS);
+    println!(
+        "{:>8} {:>16} {:>16} {:>16}",
+        "threads", "store", "store + writer", "mutex + writer"
+    );
+    for &threads in &thread_counts {
+        let reads = {
+            let store = store.clone();
+            measure(
+                threads,
+                move |key| {
+                    black_box(read_key(&store.view([key]).unwrap(), key, 0));
+                },
+                None,
+            )
+        };
+        let reads_with_writer = {
+            let (reader, writer) = (store.clone(), store.clone());
+            measure(
+                threads,
+                move |key| {
+                    black_box(read_key(&reader.view([key]).unwrap(), key, 0));
+                },
+                Some(Box::new(move |i| {
+                    writer.writer([key(i).as_slice()]).commit(set(i)).unwrap();
+                })),
+            )
+        };
+        let mutex_reads_with_writer = {
+            let (reader, writer) = (mutex_keyspace.clone(), mutex_keyspace.clone());
+            let mut version = KEYS;
+            measure(
+                threads,
+                move |key| {
+                    black_box(read_key(&reader.lock().unwrap(), key, 0));
+                },
+                Some(Box::new(move |i| {
+                    version += 1;
+                    set(i).apply(&mut writer.lock().unwrap(), version);
+                })),
+            )
+        };
+        println!(
+            "{:>8} {:>14.0}/s {:>14.0}/s {:>14.0}/s",
+            threads, reads, reads_with_writer, mutex_reads_with_writer
+        );
+    }
+
+    let mutex_version = Arc::new(AtomicU64::new(KEYS));
+    println!();
+    println!(
+        "{:>8} {:>16} {:>16} {:>16} {:>16}",
+        "threads", "store writes", "mutex writes", "store mixed", "mutex mixed"
+    );
+    for &threads in &thread_counts {
+        let writes = {
+            let store = store.clone();
+            throughput(threads, move |t, i| {
+                let i = own_key(t, i, threads);
+                store.writer([key(i).as_slice()]).commit(set(i)).unwrap();
+            })
+        };
+        let mutex_writes = {
+            let (keyspace, version) = (mutex_keyspace.clone(), mutex_version.clone());
+            throughput(threads, move |t, i| {
+                let mut keyspace = keyspace.lock().unwrap();
+                let version = version.fetch_add(1, Ordering::Relaxed) + 1;
+                set(own_key(t, i, threads)).apply(&mut keyspace, version);
+            })
+        };
+        // one write for every three reads, each thread on keys of its own
+        let mixed = {
+            let store = store.clone();
+            throughput(threads, move |t, n| {
+                let i = own_key(t, n, threads);
+                let key = key(i);
+                if n % 4 == 0 {
+                    store.writer([key.as_slice()]).commit(set(i)).unwrap();
+                } else {
+                    black_box(read_key(&store.view([key.as_slice()]).unwrap(), &key, 0));
+                }
+            })
+        };
+        let mutex_mixed = {
+            let (keyspace, version) = (mutex_keyspace.clone(), mutex_version.clone());
+            throughput(threads, move |t, n| {
+                let i = own_key(t, n, threads);
+                let mut keyspace = keyspace.lock().unwrap();
+                if n % 4 == 0 {
+                    let version = version.fetch_add(1, Ordering::Relaxed) + 1;
+                    set(i).apply(&mut keyspace, version);
+                } else {
+                    black_box(read_key(&keyspace, &key(i), 0));
+                }
+            })
+        };
+        println!(
+            "{:>8} {:>14.0}/s {:>14.0}/s {:>14.0}/s {:>14.0}/s",
+            threads, writes, mutex_writes, mixed, mutex_mixed
+        );
+    }
+}
@@ -1,27 +1,58 @@
+pub mod commands;
+pub mod coordinator;
 pub mod integration;
 pub mod io;
 pub mod messages;
+pub mod storage;
 pub mod utils;
 
-use crate::io::router::{RouterBuilder, RouterHandler};
+use crate::commands::CommandKind;
+use crate::coordinator::{Participant, PendingTransactions};
+use crate::io::router::{HandlerResponse, RouterBuilder, RouterHandler};
 use anyhow::Result;
 use messages::{
     requests::{
         announce_shard_request::AnnounceShardRequest,
+        bg_save_request::BgSaveRequest,
+        command_request::CommandRequest,
+        delete_request::DeleteRequest,
+        exists_request::ExistsRequest,
+        expire_request::ExpireRequest,
         get_client_shard_info_request::GetClientShardInfoRequest,
-        get_shared_peers_request::GetSharedPeersRequest, get_version_request::GetVersionRequest,
-        query_version_request::QueryVersionRequest, read_request::ReadRequest,
-        write_request::WriteRequest,
+        get_shared_peers_request::GetSharedPeersRequest,
+        get_version_request::GetVersionRequest,
+        query_version_request::QueryVersionRequest,
+        read_request::ReadRequest,
+        replica_ack_request::ReplicaAckRequest,
+        sync_request::SyncRequest,
+        transaction_request::TransactionRequest,
+        ttl_request::TtlRequest,
+        two_phase_request::TwoPhaseRequest,
+        write_request::{ExpiryType, WriteCondition, WriteRequest},
     },
     responses::{
         announce_shard_response::AnnounceShardResponse,
+        bg_save_response::{BgSaveResponse, BgSaveResponseError},
+        command_response::{CommandResponse, Reply},
+        delete_response::DeleteResponse,
+        exists_response::ExistsResponse,
+        expire_response::ExpireResponse,
         get_client_shard_info_response::GetClientShardInfoResponse,
         get_shared_peers_response::GetSharedPeersResponse,
-        get_version_response::GetVersionResponse, query_version_response::QueryVersionResponse,
-        read_response::ReadResponse, write_response::WriteResponse,
+        get_version_response::GetVersionResponse,
+        query_version_response::QueryVersionResponse,
+        read_response::{ReadResponse, ReadResponseError},
+        replica_ack_response::ReplicaAckResponse,
+        sync_response::SyncResponse,
+        transaction_response::TransactionResponse,
+        ttl_response::{TtlResponse, TTL_KEY_NOT_FOUND, TTL_NO_EXPIRY},
+        two_phase_response::TwoPhaseResponse,
+        write_response::{WriteResponse, WriteResponseError},
     },
 };
+use std::collections::HashMap;
 use std::io::Write;
+use tokio::sync::mpsc;
 
 use std::net::{Ipv6Addr, SocketAddrV6};
 use std::sync::{Arc, Mutex};
@@ -31,25 +62,41 @@ struct ClientState {
     num_write_shards: usize,
     write_shard_info: Vec<SocketAddrV6>,
     read_shard_info: Vec<SocketAddrV6>,
+    /// Session tokens, the highest version each write shard acknowledged for
+    /// this client's writes, by writer number. Reads wait for it so the
+    /// client always sees its own writes.
+    session_versions: HashMap<u16, u64>,
 }
 
 #[derive(Debug, Clone)]
 struct Client {
     shard_state: Arc<Mutex<ClientState>>,
+    /// Cross-shard transactions waiting on the shards' votes and commits
+    pending: PendingTransactions,
+    /// Keys whose read shard was stale, to read again from the write shard
+    redirects: mpsc::UnboundedSender<Vec<u8>>,
 }
 
 impl Client {
-    fn new(shard_state: Arc<Mutex<ClientState>>) -> Self {
-        Client { shard_state }
+    fn new(
+        shard_state: Arc<Mutex<ClientState>>,
+        pending: PendingTransactions,
+        redirects: mpsc::UnboundedSender<Vec<u8>>,
+    ) -> Self {
+        Client {
+            shard_state,
+            pending,
+            redirects,
+        }
     }
 }
 
 impl RouterHandler for Client {
-    fn handle_write_request(&self, _req: &WriteRequest) -> WriteResponse {
+    fn handle_write_request(&self, _req: &WriteRequest) -> HandlerResponse<WriteResponse> {
         unimplemented!()
     }
 
-    fn handle_read_request(&self, _req: &ReadRequest) -> ReadResponse {
+    fn handle_read_request(&self, _req: &ReadRequest) -> HandlerResponse<ReadResponse> {
         unimplemented!()
     }
 
@@ -81,13 +128,52 @@ impl RouterHandler for Client {
     }
 
     fn handle_write_response(&self, res: &WriteResponse) {
-        match res.error {
-            0 => println!("Write operation successful."),
+        match WriteResponseError::try_from(res.error) {
+            Ok(WriteResponseError::NoError) => {
+                let mut shard_state = self.shard_state.lock().unwrap();
+                let session_version = shard_state
+                    .session_versions
+                    .entry(res.writer_number)
+                    .or_default();
+                *session_version = (*session_version).max(res.version);
+                println!("Write operation successful at version {}.", res.version)
+            }
+            Ok(WriteResponseError::PreconditionFailed) => println!(
+                "Write precondition failed, the key is at version {}.",
+                res.version
+            ),
+            Ok(WriteResponseError::Locked) => println!("{}", Reply::locked()),
+            Ok(WriteResponseError::OutOfMemory) => println!("{}", Reply::out_of_memory()),
+            Ok(WriteResponseError::QuorumTimeout) => println!(
+                "Write committed at version {}, but not enough read shards confirmed it in time.",
+                res.version
+            ),
             _ => eprintln!("Write operation failed with error code: {}", res.error),
         }
     }
 
     fn handle_read_response(&self, res: &ReadResponse) {
+        if res.error == ReadResponseError::Stale as u8 {
+            println!(
+                "Read shard is at version {}, behind this session, reading from the write shard.",
+                res.version
+            );
+            if self.redirects.send(res.key.clone()).is_err() {
+                eprintln!("Failed to redirect the read");
+            }
+            return;
+        }
+        if res.error == ReadResponseError::WrongType as u8 {
+            println!("{}", Reply::wrong_type());
+            return;
+        }
+        if res.error == ReadResponseError::StorageFailed as u8 {
+            eprintln!(
+                "Shard failed to load key: {}",
+                String::from_utf8_lossy(&res.key)
+            );
+            return;
+        }
         if res.error == 1 {
             println!(
                 "Read operation failed for key: {}",
@@ -98,9 +184,10 @@ impl RouterHandler for Client {
             println!("Key not found or value is empty.");
         } else {
             println!(
-                "Read operation successful. Key: {}, Value: {}",
+                "Read operation successful. Key: {}, Value: {}, Version: {}",
                 String::from_utf8_lossy(&res.key),
-                String::from_utf8_lossy(&res.value)
+                String::from_utf8_lossy(&res.value),
+                res.version
             );
         }
     }
@@ -148,6 +235,112 @@ impl RouterHandler for Client {
     fn handle_get_version_response(&self, _res: &GetVersionResponse) {
         unimplemented!()
     }
+
+    fn handle_bg_save_request(&self, _req: &BgSaveRequest) -> BgSaveResponse {
+        unimplemented!()
+    }
+
+    fn handle_bg_save_response(&self, res: &BgSaveResponse) {
+        match BgSaveResponseError::try_from(res.error) {
+            Ok(BgSaveResponseError::NoError) => {
+                println!("Background saving started at version {}", res.version)
+            }
+            Ok(BgSaveResponseError::AlreadyInProgress) => {
+                println!("Background save already in progress")
+            }
+            Ok(BgSaveResponseError::SnapshotsDisabled) => {
+                println!("Snapshots are not enabled on this shard")
+            }
+            Ok(BgSaveResponseError::SnapshotFailed) => {
+                println!("Failed to take a snapshot of the shard")
+            }
+            Err(_) => eprintln!("Bgsave failed with error code: {}", res.error),
+        }
+    }
+
+    fn handle_delete_request(&self, _req: &DeleteRequest) -> DeleteResponse {
+        unimplemented!()
+    }
+
+    fn handle_delete_response(&self, res: &DeleteResponse) {
+        match res.error {
+            0 => println!("(integer) {}", res.deleted),
+            _ => eprintln!(
+                "Delete failed with error code: {} after deleting {} keys",
+                res.error, res.deleted
+            ),
+        }
+    }
+
+    fn handle_exists_request(&self, _req: &ExistsRequest) -> ExistsResponse {
+        unimplemented!()
+    }
+
+    fn handle_exists_response(&self, res: &ExistsResponse) {
+        println!("(integer) {}", res.count);
+    }
+
+    fn handle_expire_request(&self, _req: &ExpireRequest) -> ExpireResponse {
+        unimplemented!()
+    }
+
+    fn handle_expire_response(&self, res: &ExpireResponse) {
+        match res.error {
+            0 => println!("(integer) {}", res.updated as u8),
+            _ => eprintln!("Expire failed with error code: {}", res.error),
+        }
+    }
+
+    fn handle_ttl_request(&self, _req: &TtlRequest) -> TtlResponse {
+        unimplemented!()
+    }
+
+    fn handle_ttl_response(&self, res: &TtlResponse) {
+        match res.ttl {
+            TTL_KEY_NOT_FOUND | TTL_NO_EXPIRY => println!("(integer) {}", res.ttl),
+            ttl => println!("(integer) {} ms", ttl),
+        }
+    }
+
+    fn handle_command_request(&self, _req: &CommandRequest) -> HandlerResponse<CommandResponse> {
+        unimplemented!()
+    }
+
+    fn handle_command_response(&self, res: &CommandResponse) {
+        println!("{}", res.reply);
+    }
+
+    fn handle_transaction_request(&self, _req: &TransactionRequest) -> TransactionResponse {
+        unimplemented!()
+    }
+
+    fn handle_transaction_response(&self, res: &TransactionResponse) {
+        println!("{}", res.reply);
+    }
+
+    fn handle_two_phase_request(&self, _req: &TwoPhaseRequest) -> TwoPhaseResponse {
+        unimplemented!()
+    }
+
+    fn handle_two_phase_response(&self, res: &TwoPhaseResponse) {
+        self.pending.deliver(res);
+    }
+
+    fn handle_replica_ack_request(&self, _req: &ReplicaAckRequest) -> ReplicaAckResponse {
+        unimplemented!()
+    }
+
+    fn handle_replica_ack_response(&self, _res: &ReplicaAckResponse) {
+        unimplemented!()
+    }
+
+    fn handle_sync_request(&self, _req: &SyncRequest) -> SyncResponse {
+        unimplemented!()
+    }
+
+    fn handle_sync_response(&self, _res: &SyncResponse) {
+        unimplemented!()
+    }
 }
 
 fn hash_key_to_shard(key: &str, num_shards: usize) -> usize {
@@ -159,12 +352,202 @@ fn hash_key_to_shard(key: &str, num_shards: usize) -> usize {
     (hasher.finish() as usize) % num_shards
 }
 
+/// Value and options of a set command
+struct SetOptions<'a> {
+    value: &'a str,
+    expiry_type: ExpiryType,
+    expiry: u64,
+    condition: WriteCondition,
+    expected_version: u64,
+}
+
+/// Splits the trailing `NX|XX`, `IFVERSION <version>` and `EX|PX|EXAT|PXAT <time>`
+/// options off the value of a set command
+fn parse_set_options(mut value: &str) -> Result<SetOptions<'_>, String> {
+    let mut options = SetOptions {
+        value,
+        expiry_type: ExpiryType::None,
+        expiry: 0,
+        condition: WriteCondition::Always,
+        expected_version: 0,
+    };
+    let set_condition = |options: &mut SetOptions, condition| {
+        if options.condition != WriteCondition::Always {
+            return Err("NX, XX and IFVERSION can't be combined".to_string());
+        }
+        options.condition = condition;
+        Ok(())
+    };
+
+    loop {
+        options.value = value;
+        let Some((rest, last)) = value.rsplit_once(' ') else {
+            return Ok(options);
+        };
+        match last.to_lowercase().as_str() {
+            "nx" => set_condition(&mut options, WriteCondition::IfAbsent)?,
+            "xx" => set_condition(&mut options, WriteCondition::IfPresent)?,
+            _ => {
+                let Some((rest, option)) = rest.rsplit_once(' ') else {
+                    return Ok(options);
+                };
+                let option = option.to_lowercase();
+                if option == "ifversion" {
+                    set_condition(&mut options, WriteCondition::IfVersion)?;
+                    options.expected_version = last
+                        .parse()
+                        .map_err(|_| format!("invalid version '{}'", last))?;
+                } else {
+                    let expiry_type = match option.as_str() {
+                        "ex" => ExpiryType::Ex,
+                        "px" => ExpiryType::Px,
+                        "exat" => ExpiryType::ExAt,
+                        "pxat" => ExpiryType::PxAt,
+                        _ => return Ok(options),
+                    };
+                    if options.expiry_type != ExpiryType::None {
+                        return Err("only one expiry can be given".to_string());
+                    }
+                    options.expiry_type = expiry_type;
+                    options.expiry = match last.parse::<u64>() {
+                        Ok(expiry) if expiry > 0 => expiry,
+                        _ => return Err(format!("invalid expire time '{}'", last)),
+                    };
+                }
+                value = rest;
+                continue;
+            }
+        }
+        value = rest;
+    }
+}
+
+/// Groups keys by the shard that owns them. Each shard answers with its own
+/// count, so a command spanning n shards prints n results.
+fn group_keys_by_shard<'a>(
+    keys: impl Iterator<Item = &'a str>,
+    shards: &[SocketAddrV6],
+    num_write_shards: usize,
+) -> HashMap<SocketAddrV6, Vec<Vec<u8>>> {
+    let mut groups: HashMap<SocketAddrV6, Vec<Vec<u8>>> = HashMap::new();
+    for key in keys {
+        let target = shards[hash_key_to_shard(key, num_write_shards)];
+        groups
+            .entry(target)
+            .or_default()
+            .push(key.as_bytes().to_vec());
+    }
+    groups
+}
+
+/// Splits a transaction into the parts each write shard owns, in the order
+/// the shards first come up. Every command has to keep its keys on one shard.
+fn group_transaction_by_shard(
+    commands: Vec<Vec<Vec<u8>>>,
+    watches: Vec<(Vec<u8>, u64)>,
+    shards: &[SocketAddrV6],
+    num_write_shards: usize,
+) -> Result<Vec<Participant>, String> {
+    let shard_of =
+        |key: &[u8]| shards[hash_key_to_shard(&String::from_utf8_lossy(key), num_write_shards)];
+    let mut participants: Vec<Participant> = Vec::new();
+    let mut part = |shard: SocketAddrV6| -> usize {
+        match participants.iter().position(|p| p.shard == shard) {
+            Some(i) => i,
+            None => {
+                participants.push(Participant {
+                    shard,
+                    transaction: TransactionRequest {
+                        watches: Vec::new(),
+                        commands: Vec::new(),
+                    },
+                    positions: Vec::new(),
+                });
+                participants.len() - 1
+            }
+        }
+    };
+
+    let mut parts = Vec::new();
+    for args in &commands {
+        let keys = commands::command_keys(args);
+        let Some(first) = keys.first() else {
+            return Err(format!(
+                "wrong number of arguments for '{}' command",
+                String::from_utf8_lossy(&args[0])
+            ));
+        };
+        let shard = shard_of(first);
+        if keys[1..].iter().any(|key| shard_of(key) != shard) {
+            return Err(format!(
+                "the keys of {} live on different shards",
+                String::from_utf8_lossy(&args[0])
+            ));
+        }
+        parts.push(part(shard));
+    }
+    let watch_parts: Vec<usize> = watches.iter().map(|(key, _)| part(shard_of(key))).collect();
+
+    for (position, (args, i)) in commands.into_iter().zip(parts).enumerate() {
+        participants[i].transaction.commands.push(args);
+        participants[i].positions.push(position);
+    }
+    for (watch, i) in watches.into_iter().zip(watch_parts) {
+        participants[i].transaction.watches.push(watch);
+    }
+    Ok(participants)
+}
+
+/// Splits a command line into arguments like redis-cli does: arguments are
+/// separated by whitespace unless quoted, and double quotes understand
+/// backslash escapes, so `json.set doc $ '{"a": 1}'` passes the JSON whole.
+fn split_args(input: &str) -> Result<Vec<Vec<u8>>, String> {
+    let mut args = Vec::new();
+    let mut chars = input.chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        if chars.peek().is_none() {
+            return Ok(args);
+        }
+        let mut arg = String::new();
+        while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+            match c {
+                '\'' => loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => arg.push(c),
+                        None => return Err("unbalanced quotes".to_string()),
+                    }
+                },
+                '"' => loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some('n') => arg.push('\n'),
+                            Some('r') => arg.push('\r'),
+                            Some('t') => arg.push('\t'),
+                            Some(c) => arg.push(c),
+                            None => return Err("unbalanced quotes".to_string()),
+                        },
+                        Some(c) => arg.push(c),
+                        None => return Err("unbalanced quotes".to_string()),
+                    }
+                },
+                c => arg.push(c),
+            }
+        }
+        args.push(arg.into_bytes());
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     // Create shared state
     let shard_state = Arc::new(Mutex::new(ClientState::default()));
+    let pending = PendingTransactions::default();
+    let (redirects, mut redirected) = mpsc::unbounded_channel();
     let client_router = Arc::new(RouterBuilder::new(
-        Client::new(Arc::clone(&shard_state)),
+        Client::new(Arc::clone(&shard_state), pending.clone(), redirects),
         None,
     ));
 
@@ -186,6 +569,32 @@ async fn main() -> Result<()> {
         }
     });
 
+    // reads a stale read shard turned away go to the key's write shard
+    let client_router_clone = Arc::clone(&client_router);
+    let redirect_state = Arc::clone(&shard_state);
+    tokio::spawn(async move {
+        while let Some(key) = redirected.recv().await {
+            let target = {
+                let shard_state = redirect_state.lock().unwrap();
+                shard_state.write_shard_info[hash_key_to_shard(
+                    &String::from_utf8_lossy(&key),
+                    shard_state.num_write_shards,
+                )]
+            };
+            let request = ReadRequest {
+                key,
+                min_version: 0,
+            };
+            if let Err(err) = client_router_clone
+                .get_router_client()
+                .queue_request::<ReadRequest>(request, target)
+                .await
+            {
+                eprintln!("Failed to redirect read request: {}", err);
+            }
+        }
+    });
+
     println!(
         "Connected to WriteShard database through Main Info Server at {}",
         main_info_server
@@ -204,7 +613,13 @@ async fn main() -> Result<()> {
     }
 
     println!("Shard information received. Now ready for commands!");
-    println!("Available commands: set <key> <value>, get <key>, exit");
+    println!(
+        "Available commands: set <key> <value> [NX|XX|IFVERSION <version>] [EX|PX|EXAT|PXAT <time>], setnx <key> <value>, get <key>, del <key>..., exists <key>..., expire|pexpire|expireat|pexpireat <key> <time>, persist <key>, ttl <key>, incr|decr <key>, incrby|decrby <key> <n>, incrbyfloat <key> <n>, append <key> <value>, getset <key> <value>, strlen <key>, lpush|rpush <key> <value>..., lpop|rpop <key> [count], lrange <key> <start> <stop>, llen <key>, lindex <key> <index>, ltrim <key> <start> <stop>, blpop|brpop <key>... <timeout>, hset <key> <field> <value>..., hget <key> <field>, hmget <key> <field>..., hdel <key> <field>..., hgetall <key>, hincrby <key> <field> <n>, hlen <key>, sadd|srem <key> <member>..., spop <key> [count], sismember <key> <member>, smembers <key>, scard <key>, sinter|sunion|sdiff <key>..., zadd <key> <score> <member>..., zincrby <key> <n> <member>, zrem <key> <member>..., zpopmin <key> [count], zscore <key> <member>, zrank <key> <member>, zrange <key> <start> <stop> [BYSCORE] [LIMIT <offset> <count>] [WITHSCORES], zcard <key>, xadd <key> [MAXLEN <n>] <id>|* <field> <value>..., xrange <key> <start> <end> [COUNT <n>], xread [COUNT <n>] STREAMS <key>... <id>..., xlen <key>, xtrim <key> MAXLEN <n>, xgroup CREATE <key> <group> <id>|$ [MKSTREAM], xgroup DESTROY <key> <group>, xreadgroup GROUP <group> <consumer> [COUNT <n>] STREAMS <key>... <id>..., xack <key> <group> <id>..., xpending <key> <group> [<start> <end> <count> [consumer]], pfadd <key> <element>..., pfcount <key>..., pfmerge <destination> <source>..., setbit <key> <offset> <0|1>, getbit <key> <offset>, bitcount <key> [<start> <end> [BYTE|BIT]], bitpos <key> <0|1> [<start> [<end> [BYTE|BIT]]], bitop AND|OR|XOR|NOT <destination> <key>..., bitfield <key> [GET <type> <offset>] [SET <type> <offset> <value>] [INCRBY <type> <offset> <increment>] [OVERFLOW WRAP|SAT|FAIL], geoadd <key> [NX|XX] [CH] <longitude> <latitude> <member>..., geopos <key> <member>..., geodist <key> <member> <member> [M|KM|FT|MI], geosearch <key> FROMMEMBER <member>|FROMLONLAT <longitude> <latitude> BYRADIUS <radius> <unit>|BYBOX <width> <height> <unit> [ASC|DESC] [COUNT <n> [ANY]] [WITHCOORD] [WITHDIST] [WITHHASH], json.set <key> <path> <json> [NX|XX], json.get <key> [path...], json.del <key> [path], json.numincrby <key> <path> <n>, json.arrappend <key> <path> <json>..., watch <key> <version>, unwatch, multi, exec, discard, bgsave, exit"
+    );
+
+    // commands queued since MULTI, and the keys WATCH checks when they run
+    let mut transaction: Option<Vec<Vec<Vec<u8>>>> = None;
+    let mut watches: Vec<(Vec<u8>, u64)> = Vec::new();
 
     loop {
         print!("> ");
@@ -219,9 +634,48 @@ async fn main() -> Result<()> {
         let key = parts.next();
         let value = parts.next();
 
+        // inside a transaction every command is queued until EXEC
+        if let Some(queued) = transaction.as_mut() {
+            if !matches!(
+                command.as_str(),
+                "multi" | "exec" | "discard" | "watch" | "unwatch" | "exit"
+            ) {
+                if commands::command_kind(command.as_bytes()).is_none() {
+                    println!("(error) ERR '{}' can't be used in a transaction", command);
+                    continue;
+                }
+                match split_args(input) {
+                    Ok(args) => {
+                        queued.push(args);
+                        println!("QUEUED");
+                    }
+                    Err(err) => println!("(error) ERR {}", err),
+                }
+                continue;
+            }
+        }
+
         match command.as_str() {
-            "set" => {
+            "set" | "setnx" => {
                 if let (Some(key), Some(value)) = (key, value) {
+                    let options = if command == "setnx" {
+                        Ok(SetOptions {
+                            value,
+                            expiry_type: ExpiryType::None,
+                            expiry: 0,
+                            condition: WriteCondition::IfAbsent,
+                            expected_version: 0,
+                        })
+                    } else {
+                        parse_set_options(value)
+                    };
+                    let options = match options {
+                        Ok(options) => options,
+                        Err(err) => {
+                            println!("{}", err);
+                            continue;
+                        }
+                    };
                     let shard_index;
                     let target;
                     {
@@ -236,7 +690,11 @@ async fn main() -> Result<()> {
 
                     let request = WriteRequest {
                         key: key.as_bytes().to_vec(),
-                        value: value.as_bytes().to_vec(),
+                        value: options.value.as_bytes().to_vec(),
+                        expiry_type: options.expiry_type as u8,
+                        expiry: options.expiry,
+                        condition: options.condition as u8,
+                        expected_version: options.expected_version,
                     };
 
                     let router_client = client_router.get_router_client();
@@ -249,13 +707,14 @@ async fn main() -> Result<()> {
                         println!("OK");
                     }
                 } else {
-                    println!("Usage: set <key> <value>");
+                    println!("Usage: set <key> <value> [NX|XX|IFVERSION version] [EX seconds|PX milliseconds|EXAT unix-time-seconds|PXAT unix-time-milliseconds]");
                 }
             }
             "get" => {
                 if let Some(key) = key {
                     let shard_index;
                     let target;
+                    let min_version;
                     {
                         let shard_state_lock = shard_state.lock().unwrap();
                         if shard_state_lock.num_write_shards == 0 {
@@ -264,10 +723,16 @@ async fn main() -> Result<()> {
                         }
                         shard_index = hash_key_to_shard(key, shard_state_lock.num_write_shards);
                         target = shard_state_lock.read_shard_info[shard_index];
+                        min_version = shard_state_lock
+                            .session_versions
+                            .get(&(shard_index as u16))
+                            .copied()
+                            .unwrap_or(0);
                     }
 
                     let request = ReadRequest {
                         key: key.as_bytes().to_vec(),
+                        min_version,
                     };
 
                     let router_client = client_router.get_router_client();
@@ -283,6 +748,222 @@ async fn main() -> Result<()> {
                     println!("Usage: get <key>");
                 }
             }
+            "del" | "exists" => {
+                let keys: Vec<&str> = input.split_whitespace().skip(1).collect();
+                if keys.is_empty() {
+                    println!("Usage: {} <key> [key ...]", command);
+                    continue;
+                }
+                let groups = {
+                    let shard_state_lock = shard_state.lock().unwrap();
+                    if shard_state_lock.num_write_shards == 0 {
+                        println!("No write shards available");
+                        continue;
+                    }
+                    // deletes go to the writers, existence checks to the readers
+                    let shards = if command == "del" {
+                        &shard_state_lock.write_shard_info
+                    } else {
+                        &shard_state_lock.read_shard_info
+                    };
+                    group_keys_by_shard(keys.into_iter(), shards, shard_state_lock.num_write_shards)
+                };
+
+                let router_client = client_router.get_router_client();
+                for (target, keys) in groups {
+                    let result = if command == "del" {
+                        router_client
+                            .queue_request::<DeleteRequest>(DeleteRequest { keys }, target)
+                            .await
+                    } else {
+                        router_client
+                            .queue_request::<ExistsRequest>(ExistsRequest { keys }, target)
+                            .await
+                    };
+                    if let Err(err) = result {
+                        eprintln!("Failed to queue {} request: {}", command, err);
+                    }
+                }
+            }
+            "expire" | "pexpire" | "expireat" | "pexpireat" | "persist" => {
+                let expiry_type = match command.as_str() {
+                    "expire" => ExpiryType::Ex,
+                    "pexpire" => ExpiryType::Px,
+                    "expireat" => ExpiryType::ExAt,
+                    "pexpireat" => ExpiryType::PxAt,
+                    _ => ExpiryType::None,
+                };
+                let expiry = match (expiry_type, value.map(str::parse::<u64>)) {
+                    (ExpiryType::None, None) => 0,
+                    (ExpiryType::None, Some(_)) | (_, None) => {
+                        println!(
+                            "Usage: {} <key>{}",
+                            command,
+                            if expiry_type == ExpiryType::None {
+                                ""
+                            } else {
+                                " <time>"
+                            }
+                        );
+                        continue;
+                    }
+                    (_, Some(Ok(expiry))) => expiry,
+                    (_, Some(Err(_))) => {
+                        println!("invalid expire time");
+                        continue;
+                    }
+                };
+                let Some(key) = key else {
+                    println!("Usage: {} <key>", command);
+                    continue;
+                };
+                let target = {
+                    let shard_state_lock = shard_state.lock().unwrap();
+                    if shard_state_lock.num_write_shards == 0 {
+                        println!("No write shards available");
+                        continue;
+                    }
+                    shard_state_lock.write_shard_info
+                        [hash_key_to_shard(key, shard_state_lock.num_write_shards)]
+                };
+
+                let request = ExpireRequest {
+                    key: key.as_bytes().to_vec(),
+                    expiry_type: expiry_type as u8,
+                    expiry,
+                };
+                let router_client = client_router.get_router_client();
+                if let Err(err) = router_client
+                    .queue_request::<ExpireRequest>(request, target)
+                    .await
+                {
+                    eprintln!("Failed to queue {} request: {}", command, err);
+                }
+            }
+            "ttl" => {
+                let Some(key) = key else {
+                    println!("Usage: ttl <key>");
+                    continue;
+                };
+                let target = {
+                    let shard_state_lock = shard_state.lock().unwrap();
+                    if shard_state_lock.num_write_shards == 0 {
+                        println!("No write shards available");
+                        continue;
+                    }
+                    shard_state_lock.read_shard_info
+                        [hash_key_to_shard(key, shard_state_lock.num_write_shards)]
+                };
+
+                let request = TtlRequest {
+                    key: key.as_bytes().to_vec(),
+                };
+                let router_client = client_router.get_router_client();
+                if let Err(err) = router_client
+                    .queue_request::<TtlRequest>(request, target)
+                    .await
+                {
+                    eprintln!("Failed to queue ttl request: {}", err);
+                }
+            }
+            "bgsave" => {
+                let write_shards = shard_state.lock().unwrap().write_shard_info.clone();
+                let router_client = client_router.get_router_client();
+                for target in write_shards {
+                    if let Err(err) = router_client
+                        .queue_request::<BgSaveRequest>(BgSaveRequest {}, target)
+                        .await
+                    {
+                        eprintln!("Failed to queue bgsave request: {}", err);
+                    }
+                }
+            }
+            "watch" => {
+                let version = value.and_then(|version| version.parse::<u64>().ok());
+                match (key, version) {
+                    (Some(key), Some(version)) if transaction.is_none() => {
+                        watches.push((key.as_bytes().to_vec(), version));
+                        println!("OK");
+                    }
+                    (Some(_), Some(_)) => println!("(error) ERR WATCH inside MULTI is not allowed"),
+                    _ => println!("Usage: watch <key> <version>"),
+                }
+            }
+            "unwatch" => {
+                watches.clear();
+                println!("OK");
+            }
+            "multi" => {
+                if transaction.is_some() {
+                    println!("(error) ERR MULTI calls can not be nested");
+                } else {
+                    transaction = Some(Vec::new());
+                    println!("OK");
+                }
+            }
+            "discard" => {
+                if transaction.take().is_some() {
+                    watches.clear();
+                    println!("OK");
+                } else {
+                    println!("(error) ERR DISCARD without MULTI");
+                }
+            }
+            "exec" => {
+                let Some(commands) = transaction.take() else {
+                    println!("(error) ERR EXEC without MULTI");
+                    continue;
+                };
+                let watches = std::mem::take(&mut watches);
+                let keys: Vec<String> = commands
+                    .iter()
+                    .flat_map(|args| commands::command_keys(args))
+                    .chain(watches.iter().map(|(key, _)| key))
+                    .map(|key| String::from_utf8_lossy(key).into_owned())
+                    .collect();
+                if keys.is_empty() {
+                    println!("(empty array)");
+                    continue;
+                }
+                let participants = {
+                    let shard_state_lock = shard_state.lock().unwrap();
+                    if shard_state_lock.num_write_shards == 0 {
+                        println!("No write shards available");
+                        continue;
+                    }
+                    group_transaction_by_shard(
+                        commands,
+                        watches,
+                        &shard_state_lock.write_shard_info,
+                        shard_state_lock.num_write_shards,
+                    )
+                };
+                let mut participants = match participants {
+                    Ok(participants) => participants,
+                    Err(err) => {
+                        println!("(error) ERR {}", err);
+                        continue;
+                    }
+                };
+
+                let router_client = client_router.get_router_client();
+                // keys on a single shard don't need two-phase commit
+                if participants.len() == 1 {
+                    let participant = participants.remove(0);
+                    if let Err(err) = router_client
+                        .queue_request::<TransactionRequest>(
+                            participant.transaction,
+                            participant.shard,
+                        )
+                        .await
+                    {
+                        eprintln!("Failed to queue transaction request: {}", err);
+                    }
+                } else {
+                    let reply = coordinator::execute(&router_client, &pending, participants).await;
+                    println!("{}", reply);
+                }
+            }
             "exit" => {
                 println!("Goodbye!");
                 break;
@@ -292,7 +973,58 @@ async fn main() -> Result<()> {
                 tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
             }
             _ => {
-                println!("Unknown command. Available commands: set, get, exit");
+                let Some(kind) = commands::command_kind(command.as_bytes()) else {
+                    println!(
+                        "Unknown command. Available commands: set, setnx, get, del, exists, expire, pexpire, expireat, pexpireat, persist, ttl, incr, decr, incrby, decrby, incrbyfloat, append, getset, strlen, lpush, rpush, lpop, rpop, lrange, llen, lindex, ltrim, blpop, brpop, hset, hget, hmget, hdel, hgetall, hincrby, hlen, sadd, srem, spop, sismember, smembers, scard, sinter, sunion, sdiff, zadd, zincrby, zrem, zpopmin, zscore, zrank, zrange, zcard, xadd, xrange, xread, xlen, xtrim, xgroup, xreadgroup, xack, xpending, pfadd, pfcount, pfmerge, setbit, getbit, bitcount, bitpos, bitop, bitfield, geoadd, geopos, geodist, geosearch, json.set, json.get, json.del, json.numincrby, json.arrappend, watch, unwatch, multi, exec, discard, bgsave, exit"
+                    );
+                    continue;
+                };
+                let request = match split_args(input) {
+                    Ok(args) => CommandRequest { args },
+                    Err(err) => {
+                        println!("(error) ERR {}", err);
+                        continue;
+                    }
+                };
+                let keys: Vec<String> = commands::command_keys(&request.args)
+                    .iter()
+                    .map(|key| String::from_utf8_lossy(key).into_owned())
+                    .collect();
+                if keys.is_empty() {
+                    println!("Usage: {} <key> [arg ...]", command);
+                    continue;
+                }
+                let target = {
+                    let shard_state_lock = shard_state.lock().unwrap();
+                    if shard_state_lock.num_write_shards == 0 {
+                        println!("No write shards available");
+                        continue;
+                    }
+                    let num_shards = shard_state_lock.num_write_shards;
+                    let shard = hash_key_to_shard(&keys[0], num_shards);
+                    if keys[1..]
+                        .iter()
+                        .any(|key| hash_key_to_shard(key, num_shards) != shard)
+                    {
+                        println!(
+                            "(error) ERR the keys of {} live on different shards",
+                            command
+                        );
+                        continue;
+                    }
+                    match kind {
+                        CommandKind::Write => shard_state_lock.write_shard_info[shard],
+                        CommandKind::Read => shard_state_lock.read_shard_info[shard],
+                    }
+                };
+
+                let router_client = client_router.get_router_client();
+                if let Err(err) = router_client
+                    .queue_request::<CommandRequest>(request, target)
+                    .await
+                {
+                    eprintln!("Failed to queue {} request: {}", command, err);
+                }
             }
         }
     }
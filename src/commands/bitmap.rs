@@ -0,0 +1,582 @@
+use super::{first_key, lookup, parse_int, store, CommandGroup, WriteOutcome};
+use crate::messages::responses::command_response::Reply;
+use crate::storage::keyspace::Keyspace;
+use crate::storage::mutation::Mutation;
+use crate::storage::value::{resolve_range, Value};
+use std::ops::RangeInclusive;
+
+pub const GROUP: CommandGroup = CommandGroup {
+    write_commands: &["setbit", "bitop", "bitfield"],
+    read_commands: &["getbit", "bitcount", "bitpos"],
+    keys,
+    execute_write,
+    execute_read,
+};
+
+/// Largest bit offset, which keeps values within the 512MB redis allows
+const MAX_BIT_OFFSET: u64 = (1 << 32) - 1;
+
+fn keys<'a>(name: &str, args: &'a [Vec<u8>]) -> &'a [Vec<u8>] {
+    match name {
+        // BITOP <operation> <destination> <source>...
+        "bitop" => args.get(1..).unwrap_or(&[]),
+        _ => first_key(name, args),
+    }
+}
+
+fn syntax_error() -> Reply {
+    Reply::error("ERR syntax error")
+}
+
+fn invalid_offset() -> Reply {
+    Reply::error("ERR bit offset is not an integer or out of range")
+}
+
+fn parse_offset(arg: &[u8]) -> Option<u64> {
+    u64::try_from(parse_int(arg)?)
+        .ok()
+        .filter(|&offset| offset <= MAX_BIT_OFFSET)
+}
+
+fn parse_bit(arg: &[u8]) -> Option<u8> {
+    match arg {
+        b"0" => Some(0),
+        b"1" => Some(1),
+        _ => None,
+    }
+}
+
+/// Bits are numbered from the most significant bit of the first byte, and
+/// bits past the end of the value read as 0
+fn get_bit(value: &[u8], offset: u64) -> u8 {
+    value
+        .get((offset / 8) as usize)
+        .map_or(0, |byte| (byte >> (7 - offset % 8)) & 1)
+}
+
+/// Sets a bit, growing the value with zero bytes if it is too short
+fn set_bit(value: &mut Vec<u8>, offset: u64, bit: u8) {
+    let index = (offset / 8) as usize;
+    if index >= value.len() {
+        value.resize(index + 1, 0);
+    }
+    let mask = 0x80 >> (offset % 8);
+    if bit == 1 {
+        value[index] |= mask;
+    } else {
+        value[index] &= !mask;
+    }
+}
+
+/// Resolves the optional [start end [BYTE|BIT]] arguments of BITCOUNT and
+/// BITPOS into an inclusive range of bits of a value of `len` bytes
+fn resolve_bit_range(
+    start: i64,
+    stop: i64,
+    unit: Option<&Vec<u8>>,
+    len: usize,
+) -> Result<Option<RangeInclusive<u64>>, Reply> {
+    match unit.map(|unit| unit.to_ascii_lowercase()).as_deref() {
+        None | Some(b"byte") => Ok(resolve_range(start, stop, len)
+            .map(|range| *range.start() as u64 * 8..=*range.end() as u64 * 8 + 7)),
+        Some(b"bit") => Ok(resolve_range(start, stop, len * 8)
+            .map(|range| *range.start() as u64..=*range.end() as u64)),
+        Some(_) => Err(syntax_error()),
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Overflow {
+    Wrap,
+    Sat,
+    Fail,
+}
+
+/// Integer type of a BITFIELD operation, like i8 or u16
+#[derive(Debug, Clone, Copy)]
+struct FieldType {
+    signed: bool,
+    bits: u32,
+}
+
+impl FieldType {
+    fn parse(arg: &[u8]) -> Option<Self> {
+        let (&sign, bits) = arg.split_first()?;
+        let bits: u32 = std::str::from_utf8(bits).ok()?.parse().ok()?;
+        let field = match sign.to_ascii_lowercase() {
+            b'i' if (1..=64).contains(&bits) => FieldType { signed: true, bits },
+            b'u' if (1..=63).contains(&bits) => FieldType {
+                signed: false,
+                bits,
+            },
+            _ => return None,
+        };
+        Some(field)
+    }
+
+    /// Parses an offset in bits, or in multiples of the type's width when it starts with #
+    fn parse_offset(&self, arg: &[u8]) -> Option<u64> {
+        let offset = match arg.strip_prefix(b"#") {
+            Some(index) => parse_offset(index)?.checked_mul(self.bits as u64)?,
+            None => parse_offset(arg)?,
+        };
+        (offset + self.bits as u64 - 1 <= MAX_BIT_OFFSET).then_some(offset)
+    }
+
+    fn range(&self) -> RangeInclusive<i128> {
+        if self.signed {
+            -(1 << (self.bits - 1))..=(1 << (self.bits - 1)) - 1
+        } else {
+            0..=(1 << self.bits) - 1
+        }
+    }
+
+    fn read(&self, value: &[u8], offset: u64) -> i128 {
+        let mut raw: u64 = 0;
+        for bit in 0..self.bits as u64 {
+            raw = (raw << 1) | get_bit(value, offset + bit) as u64;
+        }
+        if self.signed && raw >> (self.bits - 1) & 1 == 1 {
+            raw as i128 - (1 << self.bits)
+        } else {
+            raw as i128
+        }
+    }
+
+    fn write(&self, value: &mut Vec<u8>, offset: u64, field: i128) {
+        let raw = field as u128;
+        for bit in 0..self.bits {
+            let shift = self.bits - 1 - bit;
+            set_bit(value, offset + bit as u64, (raw >> shift & 1) as u8);
+        }
+    }
+
+    /// Brings `field` into the range of the type, or None if it doesn't fit
+    /// and overflows fail
+    fn fit(&self, field: i128, overflow: Overflow) -> Option<i128> {
+        let range = self.range();
+        if range.contains(&field) {
+            return Some(field);
+        }
+        match overflow {
+            Overflow::Wrap => {
+                Some((field - range.start()).rem_euclid(1 << self.bits) + range.start())
+            }
+            Overflow::Sat => Some(field.clamp(*range.start(), *range.end())),
+            Overflow::Fail => None,
+        }
+    }
+}
+
+/// BITFIELD <key> [GET <type> <offset>] [SET <type> <offset> <value>]
+/// [INCRBY <type> <offset> <increment>] [OVERFLOW WRAP|SAT|FAIL] ...
+/// Every operation runs against a copy of the value, so an error halfway
+/// leaves the key untouched.
+fn bitfield(keyspace: &Keyspace, key: &[u8], args: &[Vec<u8>], now: u64) -> WriteOutcome {
+    let current = match lookup(keyspace, key, now, Value::as_string) {
+        Ok(value) => value,
+        Err(reply) => return WriteOutcome::reply(reply),
+    };
+    let original: &[u8] = current.map_or(&[], |value| value);
+    let mut value = original.to_vec();
+    let mut overflow = Overflow::Wrap;
+    let mut replies = Vec::new();
+    let mut args = args.iter();
+    while let Some(operation) = args.next() {
+        let operation = operation.to_ascii_lowercase();
+        if operation == b"overflow" {
+            overflow = match args.next().map(|kind| kind.to_ascii_lowercase()).as_deref() {
+                Some(b"wrap") => Overflow::Wrap,
+                Some(b"sat") => Overflow::Sat,
+                Some(b"fail") => Overflow::Fail,
+                _ => {
+                    return WriteOutcome::reply(Reply::error("ERR Invalid OVERFLOW type specified"))
+                }
+            };
+            continue;
+        }
+        if !matches!(operation.as_slice(), b"get" | b"set" | b"incrby") {
+            return WriteOutcome::reply(syntax_error());
+        }
+        let (Some(field), Some(offset)) = (args.next(), args.next()) else {
+            return WriteOutcome::reply(syntax_error());
+        };
+        let Some(field) = FieldType::parse(field) else {
+            return WriteOutcome::reply(Reply::error(
+                "ERR Invalid bitfield type. Use something like i16 u8. Note that u64 is not supported but i64 is.",
+            ));
+        };
+        let Some(offset) = field.parse_offset(offset) else {
+            return WriteOutcome::reply(invalid_offset());
+        };
+        let old = field.read(&value, offset);
+        if operation == b"get" {
+            replies.push(Reply::Integer(old as i64));
+            continue;
+        }
+        let Some(argument) = args.next() else {
+            return WriteOutcome::reply(syntax_error());
+        };
+        let Some(argument) = parse_int(argument) else {
+            return WriteOutcome::reply(Reply::not_an_integer());
+        };
+        let (new, reply_with_old) = match operation.as_slice() {
+            b"set" => (argument as i128, true),
+            _ => (old + argument as i128, false),
+        };
+        match field.fit(new, overflow) {
+            Some(new) => {
+                field.write(&mut value, offset, new);
+                replies.push(Reply::Integer(if reply_with_old { old } else { new } as i64));
+            }
+            None => replies.push(Reply::Nil),
+        }
+    }
+    let reply = Reply::Array(replies);
+    if value == original {
+        return WriteOutcome::reply(reply);
+    }
+    WriteOutcome::mutation(reply, store(keyspace, key, value, now))
+}
+
+fn bitop(
+    keyspace: &Keyspace,
+    operation: &[u8],
+    destination: &[u8],
+    sources: &[Vec<u8>],
+    now: u64,
+) -> WriteOutcome {
+    let operation = operation.to_ascii_lowercase();
+    if !matches!(operation.as_slice(), b"and" | b"or" | b"xor" | b"not") {
+        return WriteOutcome::reply(syntax_error());
+    }
+    if operation == b"not" && sources.len() != 1 {
+        return WriteOutcome::reply(Reply::error(
+            "ERR BITOP NOT must be called with a single source key.",
+        ));
+    }
+    let mut values = Vec::new();
+    for source in sources {
+        match lookup(keyspace, source, now, Value::as_string) {
+            Ok(value) => values.push(value.map_or(&[][..], |value| value)),
+            Err(reply) => return WriteOutcome::reply(reply),
+        }
+    }
+
+    // shorter values are padded with zero bytes
+    let len = values.iter().map(|value| value.len()).max().unwrap_or(0);
+    let byte = |value: &[u8], index: usize| value.get(index).copied().unwrap_or(0);
+    let result: Vec<u8> = (0..len)
+        .map(|index| {
+            let mut bytes = values.iter().map(|value| byte(value, index));
+            let first = bytes.next().unwrap_or(0);
+            match operation.as_slice() {
+                b"and" => bytes.fold(first, |result, byte| result & byte),
+                b"or" => bytes.fold(first, |result, byte| result | byte),
+                b"xor" => bytes.fold(first, |result, byte| result ^ byte),
+                _ => !first,
+            }
+        })
+        .collect();
+
+    let reply = Reply::Integer(len as i64);
+    if result.is_empty() {
+        // an empty result deletes the destination, like it does in redis
+        if !keyspace.contains_key(destination, now) {
+            return WriteOutcome::reply(reply);
+        }
+        let mutation = Mutation::Delete {
+            key: destination.to_vec(),
+        };
+        return WriteOutcome::mutation(reply, mutation);
+    }
+    let mutation = Mutation::Set {
+        key: destination.to_vec(),
+        value: result,
+    };
+    WriteOutcome::mutation(reply, mutation)
+}
+
+fn execute_write(name: &str, keyspace: &Keyspace, args: &[Vec<u8>], now: u64) -> WriteOutcome {
+    match (name, args) {
+        ("setbit", [key, offset, bit]) => {
+            let Some(offset) = parse_offset(offset) else {
+                return WriteOutcome::reply(invalid_offset());
+            };
+            let Some(bit) = parse_bit(bit) else {
+                return WriteOutcome::reply(Reply::error(
+                    "ERR bit is not an integer or out of range",
+                ));
+            };
+            let original = match lookup(keyspace, key, now, Value::as_string) {
+                Ok(value) => value.map_or(&[][..], |value| value),
+                Err(reply) => return WriteOutcome::reply(reply),
+            };
+            let reply = Reply::Integer(get_bit(original, offset) as i64);
+            let mut value = original.to_vec();
+            set_bit(&mut value, offset, bit);
+            if value == original {
+                return WriteOutcome::reply(reply);
+            }
+            WriteOutcome::mutation(reply, store(keyspace, key, value, now))
+        }
+        ("bitop", [operation, destination, sources @ ..]) if !sources.is_empty() => {
+            bitop(keyspace, operation, destination, sources, now)
+        }
+        ("bitfield", [key, args @ ..]) => bitfield(keyspace, key, args, now),
+        _ => WriteOutcome::reply(Reply::wrong_arity(name)),
+    }
+}
+
+fn execute_read(name: &str, keyspace: &Keyspace, args: &[Vec<u8>], now: u64) -> Reply {
+    let (value, exists) = match args
+        .first()
+        .map(|key| lookup(keyspace, key, now, Value::as_string))
+    {
+        Some(Ok(value)) => (value.map_or(&[][..], |value| value), value.is_some()),
+        Some(Err(reply)) => return reply,
+        None => return Reply::wrong_arity(name),
+    };
+    match (name, args) {
+        ("getbit", [_, offset]) => match parse_offset(offset) {
+            Some(offset) => Reply::Integer(get_bit(value, offset) as i64),
+            None => invalid_offset(),
+        },
+        ("bitcount", [_]) => {
+            Reply::Integer(value.iter().map(|byte| byte.count_ones() as i64).sum())
+        }
+        ("bitcount", [_, start, stop, unit @ ..]) if unit.len() <= 1 => {
+            let (Some(start), Some(stop)) = (parse_int(start), parse_int(stop)) else {
+                return Reply::not_an_integer();
+            };
+            match resolve_bit_range(start, stop, unit.first(), value.len()) {
+                Ok(Some(range)) => Reply::Integer(
+                    range.filter(|&offset| get_bit(value, offset) == 1).count() as i64,
+                ),
+                Ok(None) => Reply::Integer(0),
+                Err(reply) => reply,
+            }
+        }
+        ("bitpos", [_, bit, range @ ..]) if range.len() <= 3 => {
+            let Some(bit) = parse_bit(bit) else {
+                return Reply::error("ERR The bit argument must be 1 or 0.");
+            };
+            if !exists {
+                return Reply::Integer(if bit == 0 { 0 } else { -1 });
+            }
+            let mut bounds = Vec::new();
+            for bound in range.iter().take(2) {
+                match parse_int(bound) {
+                    Some(bound) => bounds.push(bound),
+                    None => return Reply::not_an_integer(),
+                }
+            }
+            let start = bounds.first().copied().unwrap_or(0);
+            let stop = bounds.get(1).copied().unwrap_or(-1);
+            let range = match resolve_bit_range(start, stop, range.get(2), value.len()) {
+                Ok(Some(range)) => range,
+                Ok(None) => return Reply::Integer(-1),
+                Err(reply) => return reply,
+            };
+            let end = *range.end();
+            match range
+                .into_iter()
+                .find(|&offset| get_bit(value, offset) == bit)
+            {
+                Some(offset) => Reply::Integer(offset as i64),
+                // without an explicit end, the value counts as followed by zero bits
+                None if bit == 0 && bounds.len() < 2 => Reply::Integer(end as i64 + 1),
+                None => Reply::Integer(-1),
+            }
+        }
+        _ => Reply::wrong_arity(name),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands;
+    use crate::commands::test_support::{args, run};
+
+    fn integers(values: &[i64]) -> Reply {
+        Reply::Array(values.iter().copied().map(Reply::Integer).collect())
+    }
+
+    #[test]
+    fn test_bits() {
+        let mut keyspace = Keyspace::new();
+        assert_eq!(
+            run(&mut keyspace, &["setbit", "b", "7", "1"], 0),
+            Reply::Integer(0)
+        );
+        assert_eq!(
+            run(&mut keyspace, &["setbit", "b", "7", "1"], 0),
+            Reply::Integer(1)
+        );
+        assert_eq!(
+            run(&mut keyspace, &["setbit", "b", "17", "1"], 0),
+            Reply::Integer(0)
+        );
+        assert_eq!(
+            keyspace.get(b"b", 0),
+            Some(&Value::from(vec![0x01, 0x00, 0x40]))
+        );
+        assert_eq!(
+            run(&mut keyspace, &["getbit", "b", "17"], 0),
+            Reply::Integer(1)
+        );
+        assert_eq!(
+            run(&mut keyspace, &["getbit", "b", "1000"], 0),
+            Reply::Integer(0)
+        );
+        assert_eq!(run(&mut keyspace, &["bitcount", "b"], 0), Reply::Integer(2));
+        assert_eq!(
+            run(&mut keyspace, &["bitcount", "b", "1", "-1"], 0),
+            Reply::Integer(1)
+        );
+        assert_eq!(
+            run(&mut keyspace, &["bitcount", "b", "0", "7", "BIT"], 0),
+            Reply::Integer(1)
+        );
+        assert_eq!(
+            run(&mut keyspace, &["bitpos", "b", "1"], 0),
+            Reply::Integer(7)
+        );
+        assert_eq!(
+            run(&mut keyspace, &["bitpos", "b", "1", "1"], 0),
+            Reply::Integer(17)
+        );
+        assert_eq!(
+            run(&mut keyspace, &["bitpos", "b", "1", "8", "15", "BIT"], 0),
+            Reply::Integer(-1)
+        );
+        assert_eq!(
+            run(&mut keyspace, &["setbit", "b", "8", "2"], 0),
+            Reply::error("ERR bit is not an integer or out of range")
+        );
+        assert_eq!(
+            run(&mut keyspace, &["setbit", "b", "4294967296", "1"], 0),
+            Reply::error("ERR bit offset is not an integer or out of range")
+        );
+
+        // a value of all ones is followed by zero bits unless an end is given
+        keyspace.insert(b"ones".to_vec(), vec![0xff]);
+        assert_eq!(
+            run(&mut keyspace, &["bitpos", "ones", "0"], 0),
+            Reply::Integer(8)
+        );
+        assert_eq!(
+            run(&mut keyspace, &["bitpos", "ones", "0", "0", "-1"], 0),
+            Reply::Integer(-1)
+        );
+        assert_eq!(
+            run(&mut keyspace, &["bitpos", "missing", "0"], 0),
+            Reply::Integer(0)
+        );
+    }
+
+    #[test]
+    fn test_bitop() {
+        let mut keyspace = Keyspace::new();
+        keyspace.insert(b"a".to_vec(), vec![0b1100, 0xff]);
+        keyspace.insert(b"b".to_vec(), vec![0b1010]);
+        assert_eq!(
+            run(&mut keyspace, &["bitop", "AND", "and", "a", "b"], 0),
+            Reply::Integer(2)
+        );
+        assert_eq!(keyspace.get(b"and", 0), Some(&Value::from(vec![0b1000, 0])));
+        run(&mut keyspace, &["bitop", "OR", "or", "a", "b"], 0);
+        assert_eq!(
+            keyspace.get(b"or", 0),
+            Some(&Value::from(vec![0b1110, 0xff]))
+        );
+        run(&mut keyspace, &["bitop", "XOR", "xor", "a", "b"], 0);
+        assert_eq!(
+            keyspace.get(b"xor", 0),
+            Some(&Value::from(vec![0b0110, 0xff]))
+        );
+        run(&mut keyspace, &["bitop", "NOT", "not", "b"], 0);
+        assert_eq!(
+            keyspace.get(b"not", 0),
+            Some(&Value::from(vec![0b1111_0101]))
+        );
+        assert_eq!(
+            run(&mut keyspace, &["bitop", "NOT", "not", "a", "b"], 0),
+            Reply::error("ERR BITOP NOT must be called with a single source key.")
+        );
+
+        // an empty result deletes the destination
+        assert_eq!(
+            run(&mut keyspace, &["bitop", "AND", "and", "missing"], 0),
+            Reply::Integer(0)
+        );
+        assert!(!keyspace.contains_key(b"and", 0));
+    }
+
+    #[test]
+    fn test_bitfield() {
+        let mut keyspace = Keyspace::new();
+        assert_eq!(
+            run(
+                &mut keyspace,
+                &["bitfield", "f", "SET", "u8", "#1", "200", "GET", "u8", "8", "GET", "i8", "8"],
+                0
+            ),
+            integers(&[0, 200, -56])
+        );
+        assert_eq!(keyspace.get(b"f", 0), Some(&Value::from(vec![0, 200])));
+        assert_eq!(
+            run(
+                &mut keyspace,
+                &["bitfield", "f", "INCRBY", "u8", "8", "100"],
+                0
+            ),
+            integers(&[44])
+        );
+        assert_eq!(
+            run(
+                &mut keyspace,
+                &[
+                    "bitfield", "f", "OVERFLOW", "SAT", "INCRBY", "u8", "8", "300", "OVERFLOW",
+                    "FAIL", "INCRBY", "u8", "8", "1", "INCRBY", "i4", "0", "-9"
+                ],
+                0
+            ),
+            Reply::Array(vec![Reply::Integer(255), Reply::Nil, Reply::Nil])
+        );
+        assert_eq!(
+            run(
+                &mut keyspace,
+                &["bitfield", "f", "INCRBY", "i64", "0", "-1"],
+                0
+            ),
+            integers(&[0x00fe_ffff_ffff_ffff])
+        );
+
+        // an error halfway leaves the key untouched
+        let before = keyspace.get(b"f", 0).cloned();
+        assert_eq!(
+            run(&mut keyspace, &["bitfield", "f", "SET", "u8", "0", "1", "GET", "u64", "0"], 0),
+            Reply::error(
+                "ERR Invalid bitfield type. Use something like i16 u8. Note that u64 is not supported but i64 is."
+            )
+        );
+        assert_eq!(keyspace.get(b"f", 0).cloned(), before);
+
+        // reads alone replicate nothing
+        let outcome =
+            commands::execute_write(&keyspace, &args(&["bitfield", "f", "GET", "u4", "0"]), 0);
+        assert!(outcome.mutations.is_empty());
+    }
+
+    #[test]
+    fn test_keeps_expiry() {
+        let mut keyspace = Keyspace::new();
+        keyspace.insert(b"b".to_vec(), vec![0]);
+        keyspace.set_expiry(b"b", 100);
+        run(&mut keyspace, &["setbit", "b", "0", "1"], 0);
+        assert_eq!(keyspace.expires_at(b"b"), Some(100));
+        assert_eq!(keyspace.get(b"b", 0), Some(&Value::from(vec![0x80])));
+    }
+}
@@ -0,0 +1,124 @@
+//! Clients blocked on keys by commands like BLPOP. Each key keeps its
+//! waiters in the order they blocked, so when a value arrives the client
+//! that has waited longest gets it.
+
+use super::command_keys;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+
+/// A blocked command along with whatever answers the client that sent it
+#[derive(Debug)]
+pub struct Waiter<T> {
+    pub args: Vec<Vec<u8>>,
+    /// Time in unix milliseconds at which the command gives up, None for never
+    pub deadline: Option<u64>,
+    pub client: T,
+}
+
+#[derive(Debug)]
+pub struct BlockedClients<T> {
+    next_id: u64,
+    /// Waiters by id, which grows in the order they blocked
+    waiters: BTreeMap<u64, Waiter<T>>,
+    queues: HashMap<Vec<u8>, VecDeque<u64>>,
+}
+
+impl<T> Default for BlockedClients<T> {
+    fn default() -> Self {
+        BlockedClients {
+            next_id: 0,
+            waiters: BTreeMap::new(),
+            queues: HashMap::new(),
+        }
+    }
+}
+
+impl<T> BlockedClients<T> {
+    /// Blocks a command on its keys until it is unblocked or times out
+    pub fn block(&mut self, args: Vec<Vec<u8>>, deadline: Option<u64>, client: T) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        for key in command_keys(&args) {
+            self.queues.entry(key.clone()).or_default().push_back(id);
+        }
+        self.waiters.insert(
+            id,
+            Waiter {
+                args,
+                deadline,
+                client,
+            },
+        );
+        id
+    }
+
+    /// The waiter that has been blocked on `key` the longest
+    pub fn first(&self, key: &[u8]) -> Option<(u64, &Waiter<T>)> {
+        let id = *self.queues.get(key)?.front()?;
+        self.waiters.get(&id).map(|waiter| (id, waiter))
+    }
+
+    /// Takes a waiter off every key it is blocked on
+    pub fn unblock(&mut self, id: u64) -> Option<Waiter<T>> {
+        let waiter = self.waiters.remove(&id)?;
+        for key in command_keys(&waiter.args) {
+            if let Some(queue) = self.queues.get_mut(key) {
+                queue.retain(|&queued| queued != id);
+                if queue.is_empty() {
+                    self.queues.remove(key);
+                }
+            }
+        }
+        Some(waiter)
+    }
+
+    /// Unblocks and returns the waiters whose deadline has passed
+    pub fn time_out(&mut self, now: u64) -> Vec<Waiter<T>> {
+        let expired: Vec<u64> = self
+            .waiters
+            .iter()
+            .filter(|(_, waiter)| waiter.deadline.is_some_and(|deadline| deadline <= now))
+            .map(|(&id, _)| id)
+            .collect();
+        expired
+            .into_iter()
+            .filter_map(|id| self.unblock(id))
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.waiters.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.waiters.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::test_support::args;
+
+    #[test]
+    fn test_blocked_clients() {
+        let mut blocked = BlockedClients::default();
+        let first = blocked.block(args(&["blpop", "a", "b", "0"]), None, "first");
+        let second = blocked.block(args(&["brpop", "b", "1"]), Some(1000), "second");
+
+        // the longest waiting client comes first on every key
+        assert_eq!(blocked.first(b"b").map(|(id, _)| id), Some(first));
+        assert_eq!(blocked.first(b"a").unwrap().1.client, "first");
+        assert!(blocked.first(b"c").is_none());
+
+        assert!(blocked.time_out(999).is_empty());
+        assert_eq!(blocked.unblock(first).unwrap().client, "first");
+        assert!(blocked.first(b"a").is_none());
+        assert_eq!(blocked.first(b"b").map(|(id, _)| id), Some(second));
+
+        let timed_out = blocked.time_out(1000);
+        assert_eq!(timed_out.len(), 1);
+        assert_eq!(timed_out[0].client, "second");
+        assert!(blocked.is_empty());
+        assert!(blocked.queues.is_empty());
+    }
+}
@@ -0,0 +1,634 @@
+use super::{clear_expired, first_key, lookup, parse_int, CommandGroup, WriteOutcome};
+use crate::messages::responses::command_response::Reply;
+use crate::storage::geohash::{self, LAT_MAX, LAT_MIN, LON_MAX, LON_MIN};
+use crate::storage::keyspace::Keyspace;
+use crate::storage::mutation::Mutation;
+use crate::storage::sorted_set::{Score, SortedSet};
+use crate::storage::value::Value;
+use std::ops::Bound;
+
+pub const GROUP: CommandGroup = CommandGroup {
+    write_commands: &["geoadd"],
+    read_commands: &["geopos", "geodist", "geosearch"],
+    keys: first_key,
+    execute_write,
+    execute_read,
+};
+
+fn syntax_error() -> Reply {
+    Reply::error("ERR syntax error")
+}
+
+fn parse_float(arg: &[u8]) -> Option<f64> {
+    std::str::from_utf8(arg)
+        .ok()?
+        .parse::<f64>()
+        .ok()
+        .filter(|f| f.is_finite())
+}
+
+/// Meters in a distance unit
+fn parse_unit(arg: &[u8]) -> Option<f64> {
+    match arg.to_ascii_lowercase().as_slice() {
+        b"m" => Some(1.0),
+        b"km" => Some(1000.0),
+        b"ft" => Some(0.3048),
+        b"mi" => Some(1609.34),
+        _ => None,
+    }
+}
+
+fn unsupported_unit() -> Reply {
+    Reply::error("ERR unsupported unit provided. please use M, KM, FT, MI")
+}
+
+fn format_distance(meters: f64, unit: f64) -> Reply {
+    Reply::Bulk(format!("{:.4}", meters / unit).into_bytes())
+}
+
+fn position_reply(lon: f64, lat: f64) -> Reply {
+    Reply::Array(vec![
+        Reply::Bulk(lon.to_string().into_bytes()),
+        Reply::Bulk(lat.to_string().into_bytes()),
+    ])
+}
+
+/// Coordinates of a member, from the center of its geohash cell
+fn position(set: &SortedSet, member: &[u8]) -> Option<(f64, f64)> {
+    set.score(member).map(|score| geohash::decode(score as u64))
+}
+
+/// GEOADD <key> [NX|XX] [CH] <longitude> <latitude> <member> ...
+fn geoadd(keyspace: &Keyspace, key: &[u8], args: &[Vec<u8>], now: u64) -> WriteOutcome {
+    let set = match lookup(keyspace, key, now, Value::as_sorted_set) {
+        Ok(set) => set,
+        Err(reply) => return WriteOutcome::reply(reply),
+    };
+    let (mut only_new, mut only_existing, mut changed) = (false, false, false);
+    let mut args = args;
+    while let Some((option, rest)) = args.split_first() {
+        match option.to_ascii_lowercase().as_slice() {
+            b"nx" => only_new = true,
+            b"xx" => only_existing = true,
+            b"ch" => changed = true,
+            _ => break,
+        }
+        args = rest;
+    }
+    if only_new && only_existing {
+        return WriteOutcome::reply(Reply::error(
+            "ERR XX and NX options at the same time are not compatible",
+        ));
+    }
+    if args.is_empty() || !args.len().is_multiple_of(3) {
+        return WriteOutcome::reply(syntax_error());
+    }
+
+    let mut members: Vec<(Vec<u8>, Score)> = Vec::new();
+    for point in args.chunks(3) {
+        let (Some(lon), Some(lat)) = (parse_float(&point[0]), parse_float(&point[1])) else {
+            return WriteOutcome::reply(Reply::error("ERR value is not a valid float"));
+        };
+        let Some(hash) = geohash::encode(lon, lat) else {
+            return WriteOutcome::reply(Reply::Error(format!(
+                "ERR invalid longitude,latitude pair {:.6},{:.6}",
+                lon, lat
+            )));
+        };
+        let current = set.and_then(|set| set.score(&point[2]));
+        if (only_new && current.is_some()) || (only_existing && current.is_none()) {
+            continue;
+        }
+        // a later point for the same member replaces an earlier one
+        members.retain(|(member, _)| *member != point[2]);
+        members.push((point[2].clone(), Score(hash as f64)));
+    }
+
+    let count = members
+        .iter()
+        .filter(
+            |(member, score)| match set.and_then(|set| set.score(member)) {
+                None => true,
+                Some(current) => changed && current != score.0,
+            },
+        )
+        .count();
+    let reply = Reply::Integer(count as i64);
+    if members.is_empty() {
+        return WriteOutcome::reply(reply);
+    }
+    let mut mutations: Vec<Mutation> = clear_expired(keyspace, key, now).into_iter().collect();
+    mutations.push(Mutation::SetScores {
+        key: key.to_vec(),
+        members,
+    });
+    WriteOutcome { reply, mutations }
+}
+
+fn execute_write(name: &str, keyspace: &Keyspace, args: &[Vec<u8>], now: u64) -> WriteOutcome {
+    match (name, args) {
+        ("geoadd", [key, args @ ..]) => geoadd(keyspace, key, args, now),
+        _ => WriteOutcome::reply(Reply::wrong_arity(name)),
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Shape {
+    /// Points within a distance in meters of the center
+    Radius(f64),
+    /// Points within a box of a width and height in meters centered on the center
+    Box(f64, f64),
+}
+
+/// Options of a GEOSEARCH
+struct Search {
+    center: (f64, f64),
+    shape: Shape,
+    /// Meters in the unit distances are given and returned in
+    unit: f64,
+    descending: bool,
+    count: Option<usize>,
+    with_coord: bool,
+    with_dist: bool,
+    with_hash: bool,
+}
+
+/// Parses everything after the key of GEOSEARCH <key> FROMMEMBER <member> |
+/// FROMLONLAT <longitude> <latitude> BYRADIUS <radius> <unit> | BYBOX <width>
+/// <height> <unit> [ASC|DESC] [COUNT <count> [ANY]] [WITHCOORD] [WITHDIST] [WITHHASH]
+fn parse_search(set: Option<&SortedSet>, args: &[Vec<u8>]) -> Result<Search, Reply> {
+    let mut center = None;
+    let mut shape = None;
+    let mut unit = 1.0;
+    let mut search = Search {
+        center: (0.0, 0.0),
+        shape: Shape::Radius(0.0),
+        unit,
+        descending: false,
+        count: None,
+        with_coord: false,
+        with_dist: false,
+        with_hash: false,
+    };
+    let mut any = false;
+    let mut args = args.iter();
+    while let Some(option) = args.next() {
+        let mut next_float = || {
+            args.next()
+                .map(|arg| {
+                    parse_float(arg).ok_or_else(|| Reply::error("ERR value is not a valid float"))
+                })
+                .unwrap_or_else(|| Err(syntax_error()))
+        };
+        match option.to_ascii_lowercase().as_slice() {
+            b"frommember" if center.is_none() => {
+                let member = args.next().ok_or_else(syntax_error)?;
+                // an empty key has no members, so its search comes back empty later
+                center = Some(match set {
+                    Some(set) => position(set, member).ok_or_else(|| {
+                        Reply::error("ERR could not decode requested zset member")
+                    })?,
+                    None => (0.0, 0.0),
+                });
+            }
+            b"fromlonlat" if center.is_none() => {
+                let (lon, lat) = (next_float()?, next_float()?);
+                if !(LON_MIN..=LON_MAX).contains(&lon) || !(LAT_MIN..=LAT_MAX).contains(&lat) {
+                    return Err(Reply::Error(format!(
+                        "ERR invalid longitude,latitude pair {:.6},{:.6}",
+                        lon, lat
+                    )));
+                }
+                center = Some((lon, lat));
+            }
+            b"byradius" if shape.is_none() => {
+                shape = Some(Shape::Radius(next_float()?));
+                unit = args
+                    .next()
+                    .and_then(|arg| parse_unit(arg))
+                    .ok_or_else(unsupported_unit)?;
+            }
+            b"bybox" if shape.is_none() => {
+                shape = Some(Shape::Box(next_float()?, next_float()?));
+                unit = args
+                    .next()
+                    .and_then(|arg| parse_unit(arg))
+                    .ok_or_else(unsupported_unit)?;
+            }
+            b"asc" => search.descending = false,
+            b"desc" => search.descending = true,
+            b"count" => {
+                let count = args
+                    .next()
+                    .and_then(|arg| parse_int(arg))
+                    .ok_or_else(Reply::not_an_integer)?;
+                if count <= 0 {
+                    return Err(Reply::error("ERR COUNT must be > 0"));
+                }
+                search.count = Some(count as usize);
+            }
+            b"any" => any = true,
+            b"withcoord" => search.with_coord = true,
+            b"withdist" => search.with_dist = true,
+            b"withhash" => search.with_hash = true,
+            b"frommember" | b"fromlonlat" => {
+                return Err(Reply::error(
+                    "ERR exactly one of FROMMEMBER or FROMLONLAT can be specified for GEOSEARCH",
+                ))
+            }
+            b"byradius" | b"bybox" => {
+                return Err(Reply::error(
+                    "ERR exactly one of BYRADIUS and BYBOX can be specified for GEOSEARCH",
+                ))
+            }
+            _ => return Err(syntax_error()),
+        }
+    }
+    search.center = center.ok_or_else(|| {
+        Reply::error("ERR exactly one of FROMMEMBER or FROMLONLAT can be specified for GEOSEARCH")
+    })?;
+    let shape = shape.ok_or_else(|| {
+        Reply::error("ERR exactly one of BYRADIUS and BYBOX can be specified for GEOSEARCH")
+    })?;
+    if any && search.count.is_none() {
+        return Err(Reply::error("ERR the ANY argument requires COUNT argument"));
+    }
+    search.unit = unit;
+    search.shape = match shape {
+        Shape::Radius(radius) => Shape::Radius(radius * unit),
+        Shape::Box(width, height) => Shape::Box(width * unit, height * unit),
+    };
+    Ok(search)
+}
+
+/// Member found by a search, with its distance from the center, geohash and coordinates
+type Found = (Vec<u8>, f64, u64, (f64, f64));
+
+/// Finds the points of a search by scanning the geohash ranges around its
+/// shape and keeping the points that really are inside it. Points come back
+/// sorted by distance, ties broken by member.
+fn search(set: &SortedSet, search: &Search) -> Vec<Found> {
+    let (lon, lat) = search.center;
+    let (half_width, half_height) = match search.shape {
+        Shape::Radius(radius) => (radius, radius),
+        Shape::Box(width, height) => (width / 2.0, height / 2.0),
+    };
+    let (lon_delta, lat_delta) = geohash::box_around(lat, half_width, half_height);
+    let ranges = geohash::covering_ranges(
+        lon - lon_delta,
+        lon + lon_delta,
+        lat - lat_delta,
+        lat + lat_delta,
+    );
+
+    let mut points = Vec::new();
+    for range in ranges {
+        let scores = set.range_by_score(
+            Bound::Included(range.start as f64),
+            Bound::Excluded(range.end as f64),
+        );
+        for (member, score) in scores {
+            let hash = score as u64;
+            let (point_lon, point_lat) = geohash::decode(hash);
+            let distance = geohash::distance(lon, lat, point_lon, point_lat);
+            let inside = match search.shape {
+                Shape::Radius(radius) => distance <= radius,
+                // like redis, the width is measured along the point's latitude
+                Shape::Box(width, height) => {
+                    geohash::distance(lon, lat, lon, point_lat) <= height / 2.0
+                        && geohash::distance(lon, point_lat, point_lon, point_lat) <= width / 2.0
+                }
+            };
+            if inside {
+                points.push((member.clone(), distance, hash, (point_lon, point_lat)));
+            }
+        }
+    }
+    points.sort_by(|a, b| a.1.total_cmp(&b.1).then_with(|| a.0.cmp(&b.0)));
+    if search.descending {
+        points.reverse();
+    }
+    points.truncate(search.count.unwrap_or(usize::MAX));
+    points
+}
+
+fn execute_read(name: &str, keyspace: &Keyspace, args: &[Vec<u8>], now: u64) -> Reply {
+    let set = match args
+        .first()
+        .map(|key| lookup(keyspace, key, now, Value::as_sorted_set))
+    {
+        Some(Ok(set)) => set,
+        Some(Err(reply)) => return reply,
+        None => return Reply::wrong_arity(name),
+    };
+    match (name, args) {
+        ("geopos", [_, members @ ..]) if !members.is_empty() => Reply::Array(
+            members
+                .iter()
+                .map(|member| match set.and_then(|set| position(set, member)) {
+                    Some((lon, lat)) => position_reply(lon, lat),
+                    None => Reply::Nil,
+                })
+                .collect(),
+        ),
+        ("geodist", [_, first, second, unit @ ..]) if unit.len() <= 1 => {
+            let unit = match unit.first() {
+                Some(unit) => match parse_unit(unit) {
+                    Some(unit) => unit,
+                    None => return unsupported_unit(),
+                },
+                None => 1.0,
+            };
+            let positions =
+                set.and_then(|set| Some((position(set, first)?, position(set, second)?)));
+            match positions {
+                Some(((lon1, lat1), (lon2, lat2))) => {
+                    format_distance(geohash::distance(lon1, lat1, lon2, lat2), unit)
+                }
+                None => Reply::Nil,
+            }
+        }
+        ("geosearch", [_, options @ ..]) => {
+            let options = match parse_search(set, options) {
+                Ok(options) => options,
+                Err(reply) => return reply,
+            };
+            let Some(set) = set else {
+                return Reply::Array(Vec::new());
+            };
+            let points = search(set, &options);
+            let plain = !(options.with_coord || options.with_dist || options.with_hash);
+            Reply::Array(
+                points
+                    .into_iter()
+                    .map(|(member, distance, hash, (lon, lat))| {
+                        if plain {
+                            return Reply::Bulk(member);
+                        }
+                        let mut reply = vec![Reply::Bulk(member)];
+                        if options.with_dist {
+                            reply.push(format_distance(distance, options.unit));
+                        }
+                        if options.with_hash {
+                            reply.push(Reply::Integer(hash as i64));
+                        }
+                        if options.with_coord {
+                            reply.push(position_reply(lon, lat));
+                        }
+                        Reply::Array(reply)
+                    })
+                    .collect(),
+            )
+        }
+        _ => Reply::wrong_arity(name),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::test_support::{bulks, run};
+
+    fn sicily() -> Keyspace {
+        let mut keyspace = Keyspace::new();
+        let reply = run(
+            &mut keyspace,
+            &[
+                "geoadd",
+                "Sicily",
+                "13.361389",
+                "38.115556",
+                "Palermo",
+                "15.087269",
+                "37.502669",
+                "Catania",
+            ],
+            0,
+        );
+        assert_eq!(reply, Reply::Integer(2));
+        keyspace
+    }
+
+    #[test]
+    fn test_add_and_distance() {
+        let mut keyspace = sicily();
+        assert_eq!(
+            run(
+                &mut keyspace,
+                &["geodist", "Sicily", "Palermo", "Catania"],
+                0
+            ),
+            Reply::Bulk(b"166274.1516".to_vec())
+        );
+        assert_eq!(
+            run(
+                &mut keyspace,
+                &["geodist", "Sicily", "Palermo", "Catania", "km"],
+                0
+            ),
+            Reply::Bulk(b"166.2742".to_vec())
+        );
+        assert_eq!(
+            run(&mut keyspace, &["geodist", "Sicily", "Palermo", "Rome"], 0),
+            Reply::Nil
+        );
+        assert_eq!(
+            run(&mut keyspace, &["zscore", "Sicily", "Palermo"], 0),
+            Reply::Bulk(b"3479099956230698".to_vec())
+        );
+
+        let Reply::Array(positions) =
+            run(&mut keyspace, &["geopos", "Sicily", "Palermo", "Rome"], 0)
+        else {
+            panic!("expected an array");
+        };
+        let Reply::Array(palermo) = &positions[0] else {
+            panic!("expected a position");
+        };
+        let lon: f64 = String::from_utf8_lossy(match &palermo[0] {
+            Reply::Bulk(lon) => lon,
+            _ => panic!("expected a longitude"),
+        })
+        .parse()
+        .unwrap();
+        assert!((lon - 13.361389).abs() < 1e-5);
+        assert_eq!(positions[1], Reply::Nil);
+
+        // CH counts moved members and NX leaves existing ones alone
+        assert_eq!(
+            run(
+                &mut keyspace,
+                &["geoadd", "Sicily", "CH", "13.5", "38.1", "Palermo"],
+                0
+            ),
+            Reply::Integer(1)
+        );
+        assert_eq!(
+            run(
+                &mut keyspace,
+                &["geoadd", "Sicily", "NX", "13.6", "38.1", "Palermo"],
+                0
+            ),
+            Reply::Integer(0)
+        );
+        assert_eq!(
+            run(&mut keyspace, &["geoadd", "Sicily", "0", "86", "North"], 0),
+            Reply::error("ERR invalid longitude,latitude pair 0.000000,86.000000")
+        );
+    }
+
+    #[test]
+    fn test_search() {
+        let mut keyspace = sicily();
+        run(
+            &mut keyspace,
+            &[
+                "geoadd",
+                "Sicily",
+                "12.758489",
+                "38.788135",
+                "edge1",
+                "17.241510",
+                "38.788135",
+                "edge2",
+            ],
+            0,
+        );
+        assert_eq!(
+            run(
+                &mut keyspace,
+                &[
+                    "geosearch",
+                    "Sicily",
+                    "FROMLONLAT",
+                    "15",
+                    "37",
+                    "BYRADIUS",
+                    "200",
+                    "km",
+                    "ASC"
+                ],
+                0
+            ),
+            bulks(&["Catania", "Palermo"])
+        );
+        assert_eq!(
+            run(
+                &mut keyspace,
+                &[
+                    "geosearch",
+                    "Sicily",
+                    "FROMLONLAT",
+                    "15",
+                    "37",
+                    "BYBOX",
+                    "400",
+                    "400",
+                    "km",
+                    "DESC",
+                    "WITHDIST"
+                ],
+                0
+            ),
+            Reply::Array(vec![
+                Reply::Array(vec![
+                    Reply::Bulk(b"edge1".to_vec()),
+                    Reply::Bulk(b"279.7405".to_vec())
+                ]),
+                Reply::Array(vec![
+                    Reply::Bulk(b"edge2".to_vec()),
+                    Reply::Bulk(b"279.7403".to_vec())
+                ]),
+                Reply::Array(vec![
+                    Reply::Bulk(b"Palermo".to_vec()),
+                    Reply::Bulk(b"190.4424".to_vec())
+                ]),
+                Reply::Array(vec![
+                    Reply::Bulk(b"Catania".to_vec()),
+                    Reply::Bulk(b"56.4413".to_vec())
+                ]),
+            ])
+        );
+        assert_eq!(
+            run(
+                &mut keyspace,
+                &[
+                    "geosearch",
+                    "Sicily",
+                    "FROMMEMBER",
+                    "Palermo",
+                    "BYRADIUS",
+                    "10",
+                    "km"
+                ],
+                0
+            ),
+            bulks(&["Palermo"])
+        );
+        assert_eq!(
+            run(
+                &mut keyspace,
+                &[
+                    "geosearch",
+                    "Sicily",
+                    "FROMMEMBER",
+                    "Palermo",
+                    "BYRADIUS",
+                    "500",
+                    "km",
+                    "COUNT",
+                    "1",
+                    "WITHHASH"
+                ],
+                0
+            ),
+            Reply::Array(vec![Reply::Array(vec![
+                Reply::Bulk(b"Palermo".to_vec()),
+                Reply::Integer(3479099956230698),
+            ])])
+        );
+        assert_eq!(
+            run(
+                &mut keyspace,
+                &[
+                    "geosearch",
+                    "Sicily",
+                    "FROMMEMBER",
+                    "Rome",
+                    "BYRADIUS",
+                    "10",
+                    "km"
+                ],
+                0
+            ),
+            Reply::error("ERR could not decode requested zset member")
+        );
+        assert_eq!(
+            run(
+                &mut keyspace,
+                &["geosearch", "Sicily", "BYRADIUS", "10", "km"],
+                0
+            ),
+            Reply::error(
+                "ERR exactly one of FROMMEMBER or FROMLONLAT can be specified for GEOSEARCH"
+            )
+        );
+        assert_eq!(
+            run(
+                &mut keyspace,
+                &[
+                    "geosearch",
+                    "Nowhere",
+                    "FROMLONLAT",
+                    "15",
+                    "37",
+                    "BYRADIUS",
+                    "10",
+                    "km"
+                ],
+                0
+            ),
+            bulks(&[])
+        );
+    }
+}
@@ -0,0 +1,243 @@
+use super::{clear_expired, first_key, lookup, parse_int, CommandGroup, WriteOutcome};
+use crate::messages::responses::command_response::Reply;
+use crate::storage::keyspace::Keyspace;
+use crate::storage::mutation::Mutation;
+use crate::storage::value::Value;
+
+pub const GROUP: CommandGroup = CommandGroup {
+    write_commands: &["hset", "hdel", "hincrby"],
+    read_commands: &["hget", "hmget", "hgetall", "hlen"],
+    keys: first_key,
+    execute_write,
+    execute_read,
+};
+
+/// Sets fields of the hash at `key`, starting from nothing if it expired
+fn set_fields(
+    keyspace: &Keyspace,
+    key: &[u8],
+    fields: Vec<(Vec<u8>, Vec<u8>)>,
+    now: u64,
+    reply: Reply,
+) -> WriteOutcome {
+    let mut mutations: Vec<Mutation> = clear_expired(keyspace, key, now).into_iter().collect();
+    mutations.push(Mutation::SetFields {
+        key: key.to_vec(),
+        fields,
+    });
+    WriteOutcome { reply, mutations }
+}
+
+fn execute_write(name: &str, keyspace: &Keyspace, args: &[Vec<u8>], now: u64) -> WriteOutcome {
+    let hash = match args
+        .first()
+        .map(|key| lookup(keyspace, key, now, Value::as_hash))
+    {
+        Some(Ok(hash)) => hash,
+        Some(Err(reply)) => return WriteOutcome::reply(reply),
+        None => return WriteOutcome::reply(Reply::wrong_arity(name)),
+    };
+    match (name, args) {
+        ("hset", [key, pairs @ ..]) if !pairs.is_empty() && pairs.len() % 2 == 0 => {
+            let fields: Vec<(Vec<u8>, Vec<u8>)> = pairs
+                .chunks(2)
+                .map(|pair| (pair[0].clone(), pair[1].clone()))
+                .collect();
+            // a field given twice is only new once
+            let mut added: Vec<&Vec<u8>> = fields
+                .iter()
+                .map(|(field, _)| field)
+                .filter(|field| hash.is_none_or(|hash| !hash.contains_key(*field)))
+                .collect();
+            added.sort();
+            added.dedup();
+            let reply = Reply::Integer(added.len() as i64);
+            set_fields(keyspace, key, fields, now, reply)
+        }
+        ("hdel", [key, fields @ ..]) if !fields.is_empty() => {
+            let Some(hash) = hash else {
+                return WriteOutcome::reply(Reply::Integer(0));
+            };
+            let mut removed: Vec<Vec<u8>> = fields
+                .iter()
+                .filter(|field| hash.contains_key(*field))
+                .cloned()
+                .collect();
+            removed.sort();
+            removed.dedup();
+            if removed.is_empty() {
+                return WriteOutcome::reply(Reply::Integer(0));
+            }
+            let reply = Reply::Integer(removed.len() as i64);
+            let mutation = Mutation::DeleteFields {
+                key: key.clone(),
+                fields: removed,
+            };
+            WriteOutcome::mutation(reply, mutation)
+        }
+        ("hincrby", [key, field, delta]) => {
+            let Some(delta) = parse_int(delta) else {
+                return WriteOutcome::reply(Reply::not_an_integer());
+            };
+            let current = match hash.and_then(|hash| hash.get(field)) {
+                Some(value) => match parse_int(value) {
+                    Some(current) => current,
+                    None => {
+                        return WriteOutcome::reply(Reply::error(
+                            "ERR hash value is not an integer",
+                        ))
+                    }
+                },
+                None => 0,
+            };
+            let Some(new) = current.checked_add(delta) else {
+                return WriteOutcome::reply(Reply::error(
+                    "ERR increment or decrement would overflow",
+                ));
+            };
+            let fields = vec![(field.clone(), new.to_string().into_bytes())];
+            set_fields(keyspace, key, fields, now, Reply::Integer(new))
+        }
+        _ => WriteOutcome::reply(Reply::wrong_arity(name)),
+    }
+}
+
+fn execute_read(name: &str, keyspace: &Keyspace, args: &[Vec<u8>], now: u64) -> Reply {
+    let hash = match args
+        .first()
+        .map(|key| lookup(keyspace, key, now, Value::as_hash))
+    {
+        Some(Ok(hash)) => hash,
+        Some(Err(reply)) => return reply,
+        None => return Reply::wrong_arity(name),
+    };
+    let field = |field: &Vec<u8>| match hash.and_then(|hash| hash.get(field)) {
+        Some(value) => Reply::Bulk(value.clone()),
+        None => Reply::Nil,
+    };
+    match (name, args) {
+        ("hget", [_, name]) => field(name),
+        ("hmget", [_, fields @ ..]) if !fields.is_empty() => {
+            Reply::Array(fields.iter().map(field).collect())
+        }
+        ("hgetall", [_]) => {
+            let mut pairs: Vec<_> = hash.into_iter().flatten().collect();
+            // sorted so the reply doesn't depend on the order of the map
+            pairs.sort();
+            Reply::Array(
+                pairs
+                    .into_iter()
+                    .flat_map(|(field, value)| {
+                        [Reply::Bulk(field.clone()), Reply::Bulk(value.clone())]
+                    })
+                    .collect(),
+            )
+        }
+        ("hlen", [_]) => Reply::Integer(hash.map_or(0, |hash| hash.len() as i64)),
+        _ => Reply::wrong_arity(name),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands;
+    use crate::commands::test_support::{args, bulks, run};
+
+    #[test]
+    fn test_fields() {
+        let mut keyspace = Keyspace::new();
+        assert_eq!(
+            run(
+                &mut keyspace,
+                &["hset", "user", "name", "ann", "age", "30"],
+                0
+            ),
+            Reply::Integer(2)
+        );
+        assert_eq!(
+            run(
+                &mut keyspace,
+                &["hset", "user", "name", "bob", "city", "x", "city", "y"],
+                0
+            ),
+            Reply::Integer(1)
+        );
+        assert_eq!(
+            run(&mut keyspace, &["hget", "user", "name"], 0),
+            Reply::Bulk(b"bob".to_vec())
+        );
+        assert_eq!(
+            run(&mut keyspace, &["hmget", "user", "city", "missing"], 0),
+            Reply::Array(vec![Reply::Bulk(b"y".to_vec()), Reply::Nil])
+        );
+        assert_eq!(
+            run(&mut keyspace, &["hgetall", "user"], 0),
+            bulks(&["age", "30", "city", "y", "name", "bob"])
+        );
+        assert_eq!(run(&mut keyspace, &["hlen", "user"], 0), Reply::Integer(3));
+        assert_eq!(
+            run(&mut keyspace, &["hset", "user", "name"], 0),
+            Reply::wrong_arity("hset")
+        );
+    }
+
+    #[test]
+    fn test_hincrby_and_hdel() {
+        let mut keyspace = Keyspace::new();
+        assert_eq!(
+            run(&mut keyspace, &["hincrby", "user", "age", "30"], 0),
+            Reply::Integer(30)
+        );
+        assert_eq!(
+            run(&mut keyspace, &["hincrby", "user", "age", "-1"], 0),
+            Reply::Integer(29)
+        );
+        run(&mut keyspace, &["hset", "user", "name", "ann"], 0);
+        assert!(matches!(
+            run(&mut keyspace, &["hincrby", "user", "name", "1"], 0),
+            Reply::Error(_)
+        ));
+
+        assert_eq!(
+            run(&mut keyspace, &["hdel", "user", "age", "age", "missing"], 0),
+            Reply::Integer(1)
+        );
+        assert_eq!(
+            run(&mut keyspace, &["hdel", "user", "name"], 0),
+            Reply::Integer(1)
+        );
+        // the last field takes the hash with it
+        assert!(keyspace.is_empty());
+        assert_eq!(run(&mut keyspace, &["hgetall", "user"], 0), bulks(&[]));
+    }
+
+    #[test]
+    fn test_field_changes_are_deltas() {
+        let mut keyspace = Keyspace::new();
+        run(
+            &mut keyspace,
+            &["hset", "user", "name", "ann", "age", "30"],
+            0,
+        );
+        let outcome =
+            commands::execute_write(&keyspace, &args(&["hincrby", "user", "age", "1"]), 0);
+        assert_eq!(
+            outcome.mutations,
+            vec![Mutation::SetFields {
+                key: b"user".to_vec(),
+                fields: vec![(b"age".to_vec(), b"31".to_vec())],
+            }]
+        );
+
+        keyspace.insert(b"s".to_vec(), b"value".to_vec());
+        assert_eq!(
+            run(&mut keyspace, &["hset", "s", "f", "v"], 0),
+            Reply::wrong_type()
+        );
+        assert_eq!(
+            run(&mut keyspace, &["hget", "s", "f"], 0),
+            Reply::wrong_type()
+        );
+    }
+}
@@ -0,0 +1,200 @@
+use super::{clear_expired, first_key, lookup, CommandGroup, WriteOutcome};
+use crate::messages::responses::command_response::Reply;
+use crate::storage::hyperloglog::HyperLogLog;
+use crate::storage::keyspace::Keyspace;
+use crate::storage::mutation::Mutation;
+use crate::storage::value::Value;
+use std::collections::BTreeMap;
+
+pub const GROUP: CommandGroup = CommandGroup {
+    write_commands: &["pfadd", "pfmerge"],
+    read_commands: &["pfcount"],
+    keys,
+    execute_write,
+    execute_read,
+};
+
+fn keys<'a>(name: &str, args: &'a [Vec<u8>]) -> &'a [Vec<u8>] {
+    match name {
+        "pfcount" | "pfmerge" => args,
+        _ => first_key(name, args),
+    }
+}
+
+/// Raises the registers of `key` that are below `registers`. Only the ones
+/// that change are replicated, and a missing key is created even if none do.
+fn raise(
+    keyspace: &Keyspace,
+    key: &[u8],
+    registers: BTreeMap<u16, u8>,
+    now: u64,
+) -> Result<Option<Vec<Mutation>>, Reply> {
+    let current = lookup(keyspace, key, now, Value::as_hyperloglog)?;
+    let registers: Vec<(u16, u8)> = registers
+        .into_iter()
+        .filter(|&(index, value)| current.is_none_or(|hll| hll.register(index) < value))
+        .collect();
+    if current.is_some() && registers.is_empty() {
+        return Ok(None);
+    }
+    let mut mutations: Vec<Mutation> = clear_expired(keyspace, key, now).into_iter().collect();
+    mutations.push(Mutation::RaiseRegisters {
+        key: key.to_vec(),
+        registers,
+    });
+    Ok(Some(mutations))
+}
+
+fn execute_write(name: &str, keyspace: &Keyspace, args: &[Vec<u8>], now: u64) -> WriteOutcome {
+    match (name, args) {
+        ("pfadd", [key, elements @ ..]) => {
+            let mut registers = BTreeMap::new();
+            for element in elements {
+                let (index, value) = HyperLogLog::register_of(element);
+                let register = registers.entry(index).or_insert(0);
+                *register = value.max(*register);
+            }
+            match raise(keyspace, key, registers, now) {
+                Ok(Some(mutations)) => WriteOutcome {
+                    reply: Reply::Integer(1),
+                    mutations,
+                },
+                Ok(None) => WriteOutcome::reply(Reply::Integer(0)),
+                Err(reply) => WriteOutcome::reply(reply),
+            }
+        }
+        ("pfmerge", [destination, sources @ ..]) => {
+            let mut registers = BTreeMap::new();
+            for source in sources {
+                match lookup(keyspace, source, now, Value::as_hyperloglog) {
+                    Ok(Some(hll)) => {
+                        for (index, value) in hll.iter() {
+                            let register = registers.entry(index).or_insert(0);
+                            *register = value.max(*register);
+                        }
+                    }
+                    Ok(None) => {}
+                    Err(reply) => return WriteOutcome::reply(reply),
+                }
+            }
+            match raise(keyspace, destination, registers, now) {
+                Ok(mutations) => WriteOutcome {
+                    reply: Reply::ok(),
+                    mutations: mutations.unwrap_or_default(),
+                },
+                Err(reply) => WriteOutcome::reply(reply),
+            }
+        }
+        _ => WriteOutcome::reply(Reply::wrong_arity(name)),
+    }
+}
+
+fn execute_read(name: &str, keyspace: &Keyspace, args: &[Vec<u8>], now: u64) -> Reply {
+    match (name, args) {
+        ("pfcount", [key]) => match lookup(keyspace, key, now, Value::as_hyperloglog) {
+            Ok(hll) => Reply::Integer(hll.map_or(0, |hll| hll.count() as i64)),
+            Err(reply) => reply,
+        },
+        // the count of several keys is the count of their union
+        ("pfcount", [_, ..]) => {
+            let mut union = HyperLogLog::new();
+            for key in args {
+                match lookup(keyspace, key, now, Value::as_hyperloglog) {
+                    Ok(Some(hll)) => {
+                        for (index, value) in hll.iter() {
+                            union.raise(index, value);
+                        }
+                    }
+                    Ok(None) => {}
+                    Err(reply) => return reply,
+                }
+            }
+            Reply::Integer(union.count() as i64)
+        }
+        _ => Reply::wrong_arity(name),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands;
+    use crate::commands::test_support::{args, run};
+
+    #[test]
+    fn test_add_and_count() {
+        let mut keyspace = Keyspace::new();
+        assert_eq!(
+            run(&mut keyspace, &["pfadd", "visitors", "a", "b", "c"], 0),
+            Reply::Integer(1)
+        );
+        assert_eq!(
+            run(&mut keyspace, &["pfadd", "visitors", "b", "a"], 0),
+            Reply::Integer(0)
+        );
+        assert_eq!(
+            run(&mut keyspace, &["pfcount", "visitors"], 0),
+            Reply::Integer(3)
+        );
+
+        // adding nothing still creates the key
+        assert_eq!(
+            run(&mut keyspace, &["pfadd", "empty"], 0),
+            Reply::Integer(1)
+        );
+        assert_eq!(
+            run(&mut keyspace, &["pfadd", "empty"], 0),
+            Reply::Integer(0)
+        );
+        assert_eq!(
+            run(&mut keyspace, &["pfcount", "empty"], 0),
+            Reply::Integer(0)
+        );
+        assert_eq!(
+            run(&mut keyspace, &["pfcount", "missing"], 0),
+            Reply::Integer(0)
+        );
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut keyspace = Keyspace::new();
+        run(&mut keyspace, &["pfadd", "monday", "a", "b", "c"], 0);
+        run(&mut keyspace, &["pfadd", "tuesday", "c", "d"], 0);
+        assert_eq!(
+            run(&mut keyspace, &["pfcount", "monday", "tuesday"], 0),
+            Reply::Integer(4)
+        );
+        assert_eq!(
+            run(&mut keyspace, &["pfmerge", "week", "monday", "tuesday"], 0),
+            Reply::ok()
+        );
+        assert_eq!(
+            run(&mut keyspace, &["pfcount", "week"], 0),
+            Reply::Integer(4)
+        );
+
+        // merging what is already there replicates nothing
+        let outcome = commands::execute_write(&keyspace, &args(&["pfmerge", "week", "monday"]), 0);
+        assert_eq!(outcome.reply, Reply::ok());
+        assert!(outcome.mutations.is_empty());
+    }
+
+    #[test]
+    fn test_wrong_type() {
+        let mut keyspace = Keyspace::new();
+        keyspace.insert(b"s".to_vec(), b"value".to_vec());
+        assert_eq!(
+            run(&mut keyspace, &["pfadd", "s", "a"], 0),
+            Reply::wrong_type()
+        );
+        assert_eq!(
+            run(&mut keyspace, &["pfcount", "s"], 0),
+            Reply::wrong_type()
+        );
+        assert_eq!(
+            run(&mut keyspace, &["pfmerge", "h", "s"], 0),
+            Reply::wrong_type()
+        );
+    }
+}
@@ -0,0 +1,501 @@
+use super::{clear_expired, first_key, lookup, CommandGroup, WriteOutcome};
+use crate::messages::responses::command_response::Reply;
+use crate::storage::json::{self, Path, Step};
+use crate::storage::keyspace::Keyspace;
+use crate::storage::mutation::Mutation;
+use crate::storage::value::Value;
+use serde_json::Value as Json;
+
+pub const GROUP: CommandGroup = CommandGroup {
+    write_commands: &["json.set", "json.del", "json.numincrby", "json.arrappend"],
+    read_commands: &["json.get"],
+    keys: first_key,
+    execute_write,
+    execute_read,
+};
+
+fn parse_path(arg: &[u8]) -> Result<Path, Reply> {
+    std::str::from_utf8(arg)
+        .ok()
+        .and_then(|path| Path::parse(path).ok())
+        .ok_or_else(|| {
+            Reply::Error(format!(
+                "ERR invalid JSONPath '{}'",
+                String::from_utf8_lossy(arg)
+            ))
+        })
+}
+
+fn parse_json(arg: &[u8]) -> Result<Json, Reply> {
+    serde_json::from_slice(arg).map_err(|err| Reply::Error(format!("ERR {}", err)))
+}
+
+fn missing_path(path: &[u8]) -> Reply {
+    Reply::Error(format!(
+        "ERR Path '{}' does not exist",
+        String::from_utf8_lossy(path)
+    ))
+}
+
+fn missing_key() -> Reply {
+    Reply::error("ERR could not perform this operation on a key that doesn't exist")
+}
+
+fn wrong_value_type(expected: &str, found: &Json) -> Reply {
+    let found = match found {
+        Json::Null => "null",
+        Json::Bool(_) => "boolean",
+        Json::Number(number) if number.is_f64() => "number",
+        Json::Number(_) => "integer",
+        Json::String(_) => "string",
+        Json::Array(_) => "array",
+        Json::Object(_) => "object",
+    };
+    Reply::Error(format!(
+        "ERR wrong type of path value - expected {} but found {}",
+        expected, found
+    ))
+}
+
+fn json_reply(value: &Json) -> Reply {
+    Reply::Bulk(value.to_string().into_bytes())
+}
+
+/// Values a path matches, where a legacy path only ever matches the first one
+fn find(document: &Json, path: &Path) -> Vec<Vec<Step>> {
+    let mut matches = path.find(document);
+    if path.is_legacy() {
+        matches.truncate(1);
+    }
+    matches
+}
+
+/// JSON.SET <key> <path> <value> [NX|XX]
+fn set(
+    keyspace: &Keyspace,
+    key: &[u8],
+    path: &[u8],
+    value: &[u8],
+    condition: Option<&Vec<u8>>,
+    now: u64,
+) -> WriteOutcome {
+    let (only_new, only_existing) = match condition.map(|arg| arg.to_ascii_lowercase()).as_deref() {
+        None => (false, false),
+        Some(b"nx") => (true, false),
+        Some(b"xx") => (false, true),
+        Some(_) => return WriteOutcome::reply(Reply::error("ERR syntax error")),
+    };
+    let document = match lookup(keyspace, key, now, Value::as_json) {
+        Ok(document) => document,
+        Err(reply) => return WriteOutcome::reply(reply),
+    };
+    let (path, value) = match (parse_path(path), parse_json(value)) {
+        (Ok(path), Ok(value)) => (path, value),
+        (Err(reply), _) | (_, Err(reply)) => return WriteOutcome::reply(reply),
+    };
+
+    let Some(document) = document else {
+        if !path.is_root() {
+            return WriteOutcome::reply(Reply::error(
+                "ERR new objects must be created at the root",
+            ));
+        }
+        if only_existing {
+            return WriteOutcome::reply(Reply::Nil);
+        }
+        let mut mutations: Vec<Mutation> = clear_expired(keyspace, key, now).into_iter().collect();
+        mutations.push(Mutation::SetJsonPath {
+            key: key.to_vec(),
+            path: Vec::new(),
+            value,
+        });
+        return WriteOutcome {
+            reply: Reply::ok(),
+            mutations,
+        };
+    };
+
+    let matches = find(document, &path);
+    let targets: Vec<Vec<Step>> = if !matches.is_empty() {
+        if only_new {
+            return WriteOutcome::reply(Reply::Nil);
+        }
+        matches
+    } else if only_existing {
+        return WriteOutcome::reply(Reply::Nil);
+    } else {
+        // a path ending in a key that doesn't exist yet adds it to its parent objects
+        match path.split_last_key() {
+            Some((parent, name)) => find(document, &parent)
+                .into_iter()
+                .filter(|steps| matches!(json::get(document, steps), Some(Json::Object(_))))
+                .map(|mut steps| {
+                    steps.push(Step::Key(name.to_string()));
+                    steps
+                })
+                .collect(),
+            None => Vec::new(),
+        }
+    };
+    if targets.is_empty() {
+        return WriteOutcome::reply(Reply::Nil);
+    }
+    WriteOutcome {
+        reply: Reply::ok(),
+        mutations: targets
+            .into_iter()
+            .map(|path| Mutation::SetJsonPath {
+                key: key.to_vec(),
+                path,
+                value: value.clone(),
+            })
+            .collect(),
+    }
+}
+
+/// Adds two JSON numbers, staying an integer when both are and the sum fits
+fn add(current: &serde_json::Number, delta: &serde_json::Number) -> Option<Json> {
+    if let (Some(current), Some(delta)) = (current.as_i64(), delta.as_i64()) {
+        if let Some(sum) = current.checked_add(delta) {
+            return Some(Json::from(sum));
+        }
+    }
+    let sum = current.as_f64()? + delta.as_f64()?;
+    serde_json::Number::from_f64(sum).map(Json::Number)
+}
+
+fn execute_write(name: &str, keyspace: &Keyspace, args: &[Vec<u8>], now: u64) -> WriteOutcome {
+    let (key, path_arg) = match (name, args) {
+        ("json.set", [key, path, value, condition @ ..]) if condition.len() <= 1 => {
+            return set(keyspace, key, path, value, condition.first(), now)
+        }
+        ("json.del", [key]) => (key, &b"$"[..]),
+        ("json.del", [key, path])
+        | ("json.numincrby", [key, path, _])
+        | ("json.arrappend", [key, path, _, ..]) => (key, path.as_slice()),
+        _ => return WriteOutcome::reply(Reply::wrong_arity(name)),
+    };
+    let document = match lookup(keyspace, key, now, Value::as_json) {
+        Ok(document) => document,
+        Err(reply) => return WriteOutcome::reply(reply),
+    };
+    let path = match parse_path(path_arg) {
+        Ok(path) => path,
+        Err(reply) => return WriteOutcome::reply(reply),
+    };
+    let Some(document) = document else {
+        return WriteOutcome::reply(match name {
+            "json.del" => Reply::Integer(0),
+            _ => missing_key(),
+        });
+    };
+    let matches = find(document, &path);
+
+    match name {
+        "json.del" => {
+            if path.is_root() {
+                let mutation = Mutation::Delete { key: key.to_vec() };
+                return WriteOutcome::mutation(Reply::Integer(1), mutation);
+            }
+            // later array elements go first, so earlier indexes stay valid
+            let mut matches = matches;
+            matches.sort();
+            WriteOutcome {
+                reply: Reply::Integer(matches.len() as i64),
+                mutations: matches
+                    .into_iter()
+                    .rev()
+                    .map(|path| Mutation::DeleteJsonPath {
+                        key: key.to_vec(),
+                        path,
+                    })
+                    .collect(),
+            }
+        }
+        "json.numincrby" => {
+            let delta = match parse_json(&args[2]) {
+                Ok(Json::Number(delta)) => delta,
+                Ok(_) => return WriteOutcome::reply(Reply::error("ERR expected a number")),
+                Err(reply) => return WriteOutcome::reply(reply),
+            };
+            let mut results = Vec::new();
+            let mut mutations = Vec::new();
+            for steps in matches {
+                let sum = match json::get(document, &steps) {
+                    Some(Json::Number(current)) => match add(current, &delta) {
+                        Some(sum) => sum,
+                        None => {
+                            return WriteOutcome::reply(Reply::error("ERR result is not a number"))
+                        }
+                    },
+                    Some(other) if path.is_legacy() => {
+                        return WriteOutcome::reply(wrong_value_type("a number", other))
+                    }
+                    _ => {
+                        results.push(Json::Null);
+                        continue;
+                    }
+                };
+                results.push(sum.clone());
+                mutations.push(Mutation::SetJsonPath {
+                    key: key.to_vec(),
+                    path: steps,
+                    value: sum,
+                });
+            }
+            let reply = if path.is_legacy() {
+                match results.first() {
+                    Some(sum) => json_reply(sum),
+                    None => return WriteOutcome::reply(missing_path(path_arg)),
+                }
+            } else {
+                json_reply(&Json::Array(results))
+            };
+            WriteOutcome { reply, mutations }
+        }
+        _ => {
+            let mut values = Vec::new();
+            for value in &args[2..] {
+                match parse_json(value) {
+                    Ok(value) => values.push(value),
+                    Err(reply) => return WriteOutcome::reply(reply),
+                }
+            }
+            let mut lengths = Vec::new();
+            let mut mutations = Vec::new();
+            for steps in matches {
+                match json::get(document, &steps) {
+                    Some(Json::Array(array)) => {
+                        lengths.push(Reply::Integer((array.len() + values.len()) as i64));
+                        mutations.push(Mutation::AppendJsonArray {
+                            key: key.to_vec(),
+                            path: steps,
+                            values: values.clone(),
+                        });
+                    }
+                    Some(other) if path.is_legacy() => {
+                        return WriteOutcome::reply(wrong_value_type("an array", other))
+                    }
+                    _ => lengths.push(Reply::Nil),
+                }
+            }
+            let reply = if path.is_legacy() {
+                match lengths.pop() {
+                    Some(length) => length,
+                    None => return WriteOutcome::reply(missing_path(path_arg)),
+                }
+            } else {
+                Reply::Array(lengths)
+            };
+            WriteOutcome { reply, mutations }
+        }
+    }
+}
+
+/// Values at a path as JSON.GET returns them: an array of every match for
+/// paths starting with $, or the single match of a legacy path
+fn get(document: &Json, path_arg: &[u8]) -> Result<Json, Reply> {
+    let path = parse_path(path_arg)?;
+    let values = find(document, &path)
+        .into_iter()
+        .filter_map(|steps| json::get(document, &steps).cloned());
+    if path.is_legacy() {
+        return values
+            .into_iter()
+            .next()
+            .ok_or_else(|| missing_path(path_arg));
+    }
+    Ok(Json::Array(values.collect()))
+}
+
+fn execute_read(name: &str, keyspace: &Keyspace, args: &[Vec<u8>], now: u64) -> Reply {
+    let (key, paths) = match (name, args) {
+        ("json.get", [key, paths @ ..]) => (key, paths),
+        _ => return Reply::wrong_arity(name),
+    };
+    let document = match lookup(keyspace, key, now, Value::as_json) {
+        Ok(Some(document)) => document,
+        Ok(None) => return Reply::Nil,
+        Err(reply) => return reply,
+    };
+    let result = match paths {
+        [] => get(document, b"."),
+        [path] => get(document, path),
+        // several paths come back as an object keyed by path
+        _ => paths
+            .iter()
+            .map(|path| {
+                Ok((
+                    String::from_utf8_lossy(path).into_owned(),
+                    get(document, path)?,
+                ))
+            })
+            .collect::<Result<serde_json::Map<_, _>, Reply>>()
+            .map(Json::Object),
+    };
+    match result {
+        Ok(value) => json_reply(&value),
+        Err(reply) => reply,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands;
+    use crate::commands::test_support::{args, run};
+    use serde_json::json;
+
+    fn bulk(value: &str) -> Reply {
+        Reply::Bulk(value.as_bytes().to_vec())
+    }
+
+    fn document() -> Keyspace {
+        let mut keyspace = Keyspace::new();
+        let reply = run(
+            &mut keyspace,
+            &[
+                "json.set",
+                "doc",
+                "$",
+                r#"{"name":"edis","stars":10,"tags":["kv"],"owner":{"stars":1}}"#,
+            ],
+            0,
+        );
+        assert_eq!(reply, Reply::ok());
+        keyspace
+    }
+
+    #[test]
+    fn test_set_and_get() {
+        let mut keyspace = document();
+        assert_eq!(
+            run(&mut keyspace, &["json.get", "doc", "$.name"], 0),
+            bulk(r#"["edis"]"#)
+        );
+        assert_eq!(
+            run(&mut keyspace, &["json.get", "doc", ".owner.stars"], 0),
+            bulk("1")
+        );
+        assert_eq!(
+            run(&mut keyspace, &["json.get", "doc", "$..stars"], 0),
+            Reply::error("ERR invalid JSONPath '$..stars'")
+        );
+        assert_eq!(
+            run(&mut keyspace, &["json.get", "doc", ".missing"], 0),
+            Reply::error("ERR Path '.missing' does not exist")
+        );
+
+        // setting a missing key adds it, and the update is only that path
+        let outcome = commands::execute_write(
+            &keyspace,
+            &args(&["json.set", "doc", "$.owner.name", r#""ada""#]),
+            0,
+        );
+        assert_eq!(
+            outcome.mutations,
+            vec![Mutation::SetJsonPath {
+                key: b"doc".to_vec(),
+                path: vec![Step::Key("owner".into()), Step::Key("name".into())],
+                value: json!("ada"),
+            }]
+        );
+        run(
+            &mut keyspace,
+            &["json.set", "doc", "$.owner.name", r#""ada""#],
+            0,
+        );
+        assert_eq!(
+            run(&mut keyspace, &["json.get", "doc", "$.owner"], 0),
+            bulk(r#"[{"stars":1,"name":"ada"}]"#)
+        );
+        assert_eq!(
+            run(&mut keyspace, &["json.set", "doc", "$.name", "1", "NX"], 0),
+            Reply::Nil
+        );
+        assert_eq!(
+            run(&mut keyspace, &["json.set", "doc", "$.a.b", "1"], 0),
+            Reply::Nil
+        );
+        assert_eq!(
+            run(&mut keyspace, &["json.set", "other", "$.a", "1"], 0),
+            Reply::error("ERR new objects must be created at the root")
+        );
+        assert_eq!(
+            run(&mut keyspace, &["json.get", "doc", "$.name", ".stars"], 0),
+            bulk(r#"{"$.name":["edis"],".stars":10}"#)
+        );
+    }
+
+    #[test]
+    fn test_updates() {
+        let mut keyspace = document();
+        assert_eq!(
+            run(
+                &mut keyspace,
+                &["json.numincrby", "doc", "$.*.stars", "2"],
+                0
+            ),
+            bulk("[3]")
+        );
+        assert_eq!(
+            run(
+                &mut keyspace,
+                &["json.numincrby", "doc", ".stars", "0.5"],
+                0
+            ),
+            bulk("10.5")
+        );
+        assert_eq!(
+            run(&mut keyspace, &["json.numincrby", "doc", ".name", "1"], 0),
+            Reply::error("ERR wrong type of path value - expected a number but found string")
+        );
+        assert_eq!(
+            run(
+                &mut keyspace,
+                &["json.arrappend", "doc", "$.tags", r#""db""#, "1"],
+                0
+            ),
+            Reply::Array(vec![Reply::Integer(3)])
+        );
+        assert_eq!(
+            run(&mut keyspace, &["json.arrappend", "doc", "$.name", "1"], 0),
+            Reply::Array(vec![Reply::Nil])
+        );
+        assert_eq!(
+            run(&mut keyspace, &["json.del", "doc", "$.tags[*]"], 0),
+            Reply::Integer(3)
+        );
+        assert_eq!(
+            run(&mut keyspace, &["json.get", "doc"], 0),
+            bulk(r#"{"name":"edis","stars":10.5,"tags":[],"owner":{"stars":3}}"#)
+        );
+        assert_eq!(
+            run(&mut keyspace, &["json.del", "doc"], 0),
+            Reply::Integer(1)
+        );
+        assert!(keyspace.is_empty());
+        assert_eq!(
+            run(&mut keyspace, &["json.numincrby", "doc", "$", "1"], 0),
+            Reply::error("ERR could not perform this operation on a key that doesn't exist")
+        );
+    }
+
+    #[test]
+    fn test_wrong_type() {
+        let mut keyspace = Keyspace::new();
+        keyspace.insert(b"s".to_vec(), b"{}".to_vec());
+        assert_eq!(
+            run(&mut keyspace, &["json.get", "s"], 0),
+            Reply::wrong_type()
+        );
+        assert_eq!(
+            run(&mut keyspace, &["json.set", "s", "$", "{}"], 0),
+            Reply::wrong_type()
+        );
+        assert_eq!(
+            run(&mut keyspace, &["json.set", "new", "$", "{"], 0),
+            Reply::error("ERR EOF while parsing an object at line 1 column 1")
+        );
+    }
+}
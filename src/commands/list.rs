@@ -0,0 +1,362 @@
+use super::{clear_expired, first_key, lookup, parse_int, CommandGroup, WriteOutcome};
+use crate::messages::responses::command_response::Reply;
+use crate::storage::keyspace::Keyspace;
+use crate::storage::mutation::Mutation;
+use crate::storage::value::{resolve_range, ListEnd, Value};
+
+pub const GROUP: CommandGroup = CommandGroup {
+    write_commands: &["lpush", "rpush", "lpop", "rpop", "ltrim", "blpop", "brpop"],
+    read_commands: &["lrange", "llen", "lindex"],
+    keys,
+    execute_write,
+    execute_read,
+};
+
+/// Commands that wait for a value when every list they pop from is empty
+pub(super) const BLOCKING_COMMANDS: &[&str] = &["blpop", "brpop"];
+
+/// Keys of the blocking pops come before their timeout
+fn keys<'a>(name: &str, args: &'a [Vec<u8>]) -> &'a [Vec<u8>] {
+    match name {
+        "blpop" | "brpop" => args.split_last().map_or(&[], |(_, keys)| keys),
+        _ => first_key(name, args),
+    }
+}
+
+/// Parses the timeout of a blocking pop, given in seconds, into milliseconds
+pub(super) fn parse_timeout(arg: &[u8]) -> Result<u64, Reply> {
+    let timeout: f64 = std::str::from_utf8(arg)
+        .ok()
+        .and_then(|timeout| timeout.parse().ok())
+        .filter(|timeout: &f64| timeout.is_finite())
+        .ok_or_else(|| Reply::error("ERR timeout is not a float or out of range"))?;
+    if timeout < 0.0 {
+        return Err(Reply::error("ERR timeout is negative"));
+    }
+    Ok((timeout * 1000.0).ceil() as u64)
+}
+
+fn push(
+    keyspace: &Keyspace,
+    key: &[u8],
+    end: ListEnd,
+    values: &[Vec<u8>],
+    now: u64,
+) -> WriteOutcome {
+    let len = match lookup(keyspace, key, now, Value::as_list) {
+        Ok(list) => list.map_or(0, |list| list.len()),
+        Err(reply) => return WriteOutcome::reply(reply),
+    };
+    let mut mutations: Vec<Mutation> = clear_expired(keyspace, key, now).into_iter().collect();
+    mutations.push(Mutation::Push {
+        key: key.to_vec(),
+        end,
+        values: values.to_vec(),
+    });
+    WriteOutcome {
+        reply: Reply::Integer((len + values.len()) as i64),
+        mutations,
+    }
+}
+
+/// Pops one value, or up to `count` values as an array when a count is given
+fn pop(
+    keyspace: &Keyspace,
+    key: &[u8],
+    end: ListEnd,
+    count: Option<&Vec<u8>>,
+    now: u64,
+) -> WriteOutcome {
+    let count = match count.map(|count| parse_int(count)) {
+        None => None,
+        Some(Some(count)) if count >= 0 => Some(count as usize),
+        Some(_) => {
+            return WriteOutcome::reply(Reply::error("ERR value is out of range, must be positive"))
+        }
+    };
+    let list = match lookup(keyspace, key, now, Value::as_list) {
+        Ok(Some(list)) => list,
+        Ok(None) => return WriteOutcome::reply(Reply::Nil),
+        Err(reply) => return WriteOutcome::reply(reply),
+    };
+    let popped: Vec<Vec<u8>> = match end {
+        ListEnd::Left => list.iter().take(count.unwrap_or(1)).cloned().collect(),
+        ListEnd::Right => list
+            .iter()
+            .rev()
+            .take(count.unwrap_or(1))
+            .cloned()
+            .collect(),
+    };
+    let reply = match count {
+        Some(_) => Reply::Array(popped.iter().cloned().map(Reply::Bulk).collect()),
+        None => Reply::Bulk(popped[0].clone()),
+    };
+    if popped.is_empty() {
+        return WriteOutcome::reply(reply);
+    }
+    let mutation = Mutation::Pop {
+        key: key.to_vec(),
+        end,
+        count: popped.len() as u64,
+    };
+    WriteOutcome::mutation(reply, mutation)
+}
+
+/// Pops a value from the first of `keys` that holds a list. When they are
+/// all empty the reply is nil, and the write shard blocks the client until
+/// another command pushes to one of them.
+fn blocking_pop(
+    keyspace: &Keyspace,
+    keys: &[Vec<u8>],
+    end: ListEnd,
+    timeout: &[u8],
+    now: u64,
+) -> WriteOutcome {
+    if let Err(reply) = parse_timeout(timeout) {
+        return WriteOutcome::reply(reply);
+    }
+    for key in keys {
+        match lookup(keyspace, key, now, Value::as_list) {
+            // lists are never empty, popping the last value removes the key
+            Ok(Some(_)) => {
+                let outcome = pop(keyspace, key, end, None, now);
+                return WriteOutcome {
+                    reply: Reply::Array(vec![Reply::Bulk(key.clone()), outcome.reply]),
+                    mutations: outcome.mutations,
+                };
+            }
+            Ok(None) => {}
+            Err(reply) => return WriteOutcome::reply(reply),
+        }
+    }
+    WriteOutcome::reply(Reply::Nil)
+}
+
+fn execute_write(name: &str, keyspace: &Keyspace, args: &[Vec<u8>], now: u64) -> WriteOutcome {
+    match (name, args) {
+        ("blpop", [keys @ .., timeout]) if !keys.is_empty() => {
+            blocking_pop(keyspace, keys, ListEnd::Left, timeout, now)
+        }
+        ("brpop", [keys @ .., timeout]) if !keys.is_empty() => {
+            blocking_pop(keyspace, keys, ListEnd::Right, timeout, now)
+        }
+        ("lpush", [key, values @ ..]) if !values.is_empty() => {
+            push(keyspace, key, ListEnd::Left, values, now)
+        }
+        ("rpush", [key, values @ ..]) if !values.is_empty() => {
+            push(keyspace, key, ListEnd::Right, values, now)
+        }
+        ("lpop", [key]) => pop(keyspace, key, ListEnd::Left, None, now),
+        ("lpop", [key, count]) => pop(keyspace, key, ListEnd::Left, Some(count), now),
+        ("rpop", [key]) => pop(keyspace, key, ListEnd::Right, None, now),
+        ("rpop", [key, count]) => pop(keyspace, key, ListEnd::Right, Some(count), now),
+        ("ltrim", [key, start, stop]) => {
+            let (Some(start), Some(stop)) = (parse_int(start), parse_int(stop)) else {
+                return WriteOutcome::reply(Reply::not_an_integer());
+            };
+            match lookup(keyspace, key, now, Value::as_list) {
+                Ok(Some(_)) => {
+                    let mutation = Mutation::Trim {
+                        key: key.clone(),
+                        start,
+                        stop,
+                    };
+                    WriteOutcome::mutation(Reply::ok(), mutation)
+                }
+                Ok(None) => WriteOutcome::reply(Reply::ok()),
+                Err(reply) => WriteOutcome::reply(reply),
+            }
+        }
+        _ => WriteOutcome::reply(Reply::wrong_arity(name)),
+    }
+}
+
+fn execute_read(name: &str, keyspace: &Keyspace, args: &[Vec<u8>], now: u64) -> Reply {
+    let list = match args
+        .first()
+        .map(|key| lookup(keyspace, key, now, Value::as_list))
+    {
+        Some(Ok(list)) => list,
+        Some(Err(reply)) => return reply,
+        None => return Reply::wrong_arity(name),
+    };
+    match (name, args) {
+        ("lrange", [_, start, stop]) => {
+            let (Some(start), Some(stop)) = (parse_int(start), parse_int(stop)) else {
+                return Reply::not_an_integer();
+            };
+            let Some(list) = list else {
+                return Reply::Array(Vec::new());
+            };
+            match resolve_range(start, stop, list.len()) {
+                Some(range) => Reply::Array(list.range(range).cloned().map(Reply::Bulk).collect()),
+                None => Reply::Array(Vec::new()),
+            }
+        }
+        ("llen", [_]) => Reply::Integer(list.map_or(0, |list| list.len() as i64)),
+        ("lindex", [_, index]) => {
+            let Some(index) = parse_int(index) else {
+                return Reply::not_an_integer();
+            };
+            let Some(list) = list else {
+                return Reply::Nil;
+            };
+            let index = if index < 0 {
+                index + list.len() as i64
+            } else {
+                index
+            };
+            match usize::try_from(index)
+                .ok()
+                .and_then(|index| list.get(index))
+            {
+                Some(value) => Reply::Bulk(value.clone()),
+                None => Reply::Nil,
+            }
+        }
+        _ => Reply::wrong_arity(name),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands;
+    use crate::commands::test_support::{args, bulks, run};
+
+    #[test]
+    fn test_push_and_range() {
+        let mut keyspace = Keyspace::new();
+        assert_eq!(
+            run(&mut keyspace, &["rpush", "l", "b", "c"], 0),
+            Reply::Integer(2)
+        );
+        assert_eq!(
+            run(&mut keyspace, &["lpush", "l", "a", "z"], 0),
+            Reply::Integer(4)
+        );
+        assert_eq!(
+            run(&mut keyspace, &["lrange", "l", "0", "-1"], 0),
+            bulks(&["z", "a", "b", "c"])
+        );
+        assert_eq!(
+            run(&mut keyspace, &["lrange", "l", "-2", "100"], 0),
+            bulks(&["b", "c"])
+        );
+        assert_eq!(
+            run(&mut keyspace, &["lrange", "l", "3", "1"], 0),
+            bulks(&[])
+        );
+        assert_eq!(run(&mut keyspace, &["llen", "l"], 0), Reply::Integer(4));
+        assert_eq!(
+            run(&mut keyspace, &["lindex", "l", "-1"], 0),
+            Reply::Bulk(b"c".to_vec())
+        );
+        assert_eq!(run(&mut keyspace, &["lindex", "l", "4"], 0), Reply::Nil);
+        assert_eq!(
+            run(&mut keyspace, &["lpush", "l"], 0),
+            Reply::wrong_arity("lpush")
+        );
+    }
+
+    #[test]
+    fn test_pop_and_trim() {
+        let mut keyspace = Keyspace::new();
+        run(&mut keyspace, &["rpush", "l", "a", "b", "c", "d", "e"], 0);
+        assert_eq!(
+            run(&mut keyspace, &["lpop", "l"], 0),
+            Reply::Bulk(b"a".to_vec())
+        );
+        assert_eq!(
+            run(&mut keyspace, &["rpop", "l", "2"], 0),
+            bulks(&["e", "d"])
+        );
+        assert_eq!(
+            run(&mut keyspace, &["ltrim", "l", "1", "-1"], 0),
+            Reply::ok()
+        );
+        assert_eq!(
+            run(&mut keyspace, &["lrange", "l", "0", "-1"], 0),
+            bulks(&["c"])
+        );
+
+        // emptying a list removes the key
+        assert_eq!(run(&mut keyspace, &["lpop", "l", "10"], 0), bulks(&["c"]));
+        assert!(keyspace.is_empty());
+        assert_eq!(run(&mut keyspace, &["lpop", "l"], 0), Reply::Nil);
+        assert_eq!(run(&mut keyspace, &["llen", "l"], 0), Reply::Integer(0));
+    }
+
+    #[test]
+    fn test_blocking_pop() {
+        let mut keyspace = Keyspace::new();
+        run(&mut keyspace, &["rpush", "b", "1", "2"], 0);
+        assert_eq!(
+            run(&mut keyspace, &["blpop", "a", "b", "0"], 0),
+            bulks(&["b", "1"])
+        );
+        assert_eq!(
+            run(&mut keyspace, &["brpop", "b", "a", "1.5"], 0),
+            bulks(&["b", "2"])
+        );
+        // nothing to pop leaves it to the write shard to block
+        assert_eq!(run(&mut keyspace, &["blpop", "a", "b", "0"], 0), Reply::Nil);
+        assert_eq!(
+            run(&mut keyspace, &["blpop", "a", "-1"], 0),
+            Reply::error("ERR timeout is negative")
+        );
+        assert_eq!(
+            run(&mut keyspace, &["blpop", "a", "soon"], 0),
+            Reply::error("ERR timeout is not a float or out of range")
+        );
+        assert_eq!(
+            run(&mut keyspace, &["blpop", "0"], 0),
+            Reply::wrong_arity("blpop")
+        );
+
+        assert_eq!(
+            commands::command_keys(&args(&["BRPOP", "a", "b", "0"])),
+            &args(&["a", "b"])[..]
+        );
+        assert_eq!(
+            commands::block_timeout(&args(&["blpop", "a", "0.25"])),
+            Some(250)
+        );
+        assert_eq!(commands::block_timeout(&args(&["lpop", "a"])), None);
+    }
+
+    #[test]
+    fn test_wrong_type() {
+        let mut keyspace = Keyspace::new();
+        keyspace.insert(b"s".to_vec(), b"value".to_vec());
+        assert_eq!(
+            run(&mut keyspace, &["lpush", "s", "a"], 0),
+            Reply::wrong_type()
+        );
+        assert_eq!(
+            run(&mut keyspace, &["lrange", "s", "0", "-1"], 0),
+            Reply::wrong_type()
+        );
+
+        run(&mut keyspace, &["rpush", "l", "a"], 0);
+        assert_eq!(run(&mut keyspace, &["incr", "l"], 0), Reply::wrong_type());
+        assert_eq!(run(&mut keyspace, &["get", "l"], 0), Reply::wrong_type());
+    }
+
+    #[test]
+    fn test_push_to_expired_key() {
+        let mut keyspace = Keyspace::new();
+        run(&mut keyspace, &["rpush", "l", "old"], 0);
+        keyspace.set_expiry(b"l", 100);
+        assert_eq!(
+            run(&mut keyspace, &["rpush", "l", "new"], 100),
+            Reply::Integer(1)
+        );
+        assert_eq!(
+            run(&mut keyspace, &["lrange", "l", "0", "-1"], 100),
+            bulks(&["new"])
+        );
+        assert_eq!(keyspace.expires_at(b"l"), None);
+    }
+}
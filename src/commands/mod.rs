@@ -0,0 +1,368 @@
+//! Commands sent in a CommandRequest, grouped by the type of value they work on.
+//! Write commands run on the write shard that owns the key and turn into
+//! mutations in its version history; read commands run on read shards.
+
+mod bitmap;
+pub mod blocked;
+mod geo;
+mod hash;
+mod hyperloglog;
+mod json;
+mod list;
+mod set;
+mod stream;
+mod string;
+mod zset;
+
+use crate::messages::responses::command_response::Reply;
+use crate::messages::responses::read_response::{ReadResponse, ReadResponseError};
+use crate::storage::keyspace::Keyspace;
+use crate::storage::mutation::Mutation;
+use crate::storage::value::Value;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommandKind {
+    Write,
+    Read,
+}
+
+/// Picks the keys of a command out of the arguments after its name
+type KeysFn = for<'a> fn(&str, &'a [Vec<u8>]) -> &'a [Vec<u8>];
+
+/// The commands of one value type. `keys`, `execute_write` and `execute_read`
+/// get the lowercase command name and the arguments after it.
+struct CommandGroup {
+    write_commands: &'static [&'static str],
+    read_commands: &'static [&'static str],
+    /// Picks the keys out of the arguments of a command
+    keys: KeysFn,
+    execute_write: fn(&str, &Keyspace, &[Vec<u8>], u64) -> WriteOutcome,
+    execute_read: fn(&str, &Keyspace, &[Vec<u8>], u64) -> Reply,
+}
+
+const GROUPS: &[CommandGroup] = &[
+    string::GROUP,
+    list::GROUP,
+    hash::GROUP,
+    set::GROUP,
+    zset::GROUP,
+    stream::GROUP,
+    hyperloglog::GROUP,
+    bitmap::GROUP,
+    geo::GROUP,
+    json::GROUP,
+];
+
+fn find_group(name: &str) -> Option<(&'static CommandGroup, CommandKind)> {
+    GROUPS.iter().find_map(|group| {
+        if group.write_commands.contains(&name) {
+            Some((group, CommandKind::Write))
+        } else if group.read_commands.contains(&name) {
+            Some((group, CommandKind::Read))
+        } else {
+            None
+        }
+    })
+}
+
+/// Whether the command called `name` writes or reads, or None if there is no such command
+pub fn command_kind(name: &[u8]) -> Option<CommandKind> {
+    let name = String::from_utf8_lossy(name).to_lowercase();
+    find_group(&name).map(|(_, kind)| kind)
+}
+
+/// Keys in the arguments of a command, name included. Every key a command
+/// touches has to live on the same shard.
+pub fn command_keys(args: &[Vec<u8>]) -> &[Vec<u8>] {
+    let Some((name, _)) = split_name(args) else {
+        return &[];
+    };
+    match find_group(&name) {
+        Some((group, _)) => (group.keys)(&name, &args[1..]),
+        None => &[],
+    }
+}
+
+/// How long a blocking command waits when it finds nothing to return, in
+/// milliseconds where 0 waits forever, or None for commands that never block
+pub fn block_timeout(args: &[Vec<u8>]) -> Option<u64> {
+    let (name, args) = split_name(args)?;
+    if !list::BLOCKING_COMMANDS.contains(&name.as_str()) {
+        return None;
+    }
+    list::parse_timeout(args.last()?).ok()
+}
+
+/// Keys of the commands that take a single key as their first argument
+fn first_key<'a>(_name: &str, args: &'a [Vec<u8>]) -> &'a [Vec<u8>] {
+    args.get(..1).unwrap_or(&[])
+}
+
+/// What a write command did: the reply for the client and the mutations that
+/// record its effects, in the order they have to be committed
+#[derive(Debug, PartialEq, Eq)]
+pub struct WriteOutcome {
+    pub reply: Reply,
+    pub mutations: Vec<Mutation>,
+}
+
+impl WriteOutcome {
+    /// Outcome of a command that leaves the keyspace untouched
+    fn reply(reply: Reply) -> Self {
+        WriteOutcome {
+            reply,
+            mutations: Vec::new(),
+        }
+    }
+
+    fn mutation(reply: Reply, mutation: Mutation) -> Self {
+        WriteOutcome {
+            reply,
+            mutations: vec![mutation],
+        }
+    }
+}
+
+/// Value of `key` as the type `as_type` picks out, None if the key doesn't
+/// exist, or a WRONGTYPE error if it holds another type
+fn lookup<'a, T>(
+    keyspace: &'a Keyspace,
+    key: &[u8],
+    now: u64,
+    as_type: fn(&Value) -> Option<&T>,
+) -> Result<Option<&'a T>, Reply> {
+    match keyspace.get(key, now) {
+        Some(value) => as_type(value).map(Some).ok_or_else(Reply::wrong_type),
+        None => Ok(None),
+    }
+}
+
+/// Deletes `key` if it expired but the sweeper hasn't removed it yet, so
+/// mutations that build on the existing value start from nothing instead
+fn clear_expired(keyspace: &Keyspace, key: &[u8], now: u64) -> Option<Mutation> {
+    keyspace
+        .is_expired(key, now)
+        .then(|| Mutation::Delete { key: key.to_vec() })
+}
+
+/// Writes the result of an in-place change to `key`. Existing keys keep
+/// their expiry, like they do in redis.
+fn store(keyspace: &Keyspace, key: &[u8], value: Vec<u8>, now: u64) -> Mutation {
+    let key = key.to_vec();
+    if keyspace.contains_key(&key, now) {
+        Mutation::Update { key, value }
+    } else {
+        Mutation::Set { key, value }
+    }
+}
+
+fn parse_int(arg: &[u8]) -> Option<i64> {
+    std::str::from_utf8(arg).ok()?.parse().ok()
+}
+
+fn split_name(args: &[Vec<u8>]) -> Option<(String, &[Vec<u8>])> {
+    let (name, args) = args.split_first()?;
+    Some((String::from_utf8_lossy(name).to_lowercase(), args))
+}
+
+/// Runs a write command against the keyspace without changing it. The caller
+/// commits the returned mutations while still holding the keyspace's version lock.
+pub fn execute_write(keyspace: &Keyspace, args: &[Vec<u8>], now: u64) -> WriteOutcome {
+    let Some((name, args)) = split_name(args) else {
+        return WriteOutcome::reply(Reply::error("ERR empty command"));
+    };
+    match find_group(&name) {
+        Some((group, CommandKind::Write)) => (group.execute_write)(&name, keyspace, args, now),
+        Some((_, CommandKind::Read)) => WriteOutcome::reply(Reply::Error(format!(
+            "ERR '{}' is a read command, send it to a read shard",
+            name
+        ))),
+        None => WriteOutcome::reply(Reply::Error(format!("ERR unknown command '{}'", name))),
+    }
+}
+
+/// Runs a read command against the keyspace
+pub fn execute_read(keyspace: &Keyspace, args: &[Vec<u8>], now: u64) -> Reply {
+    let Some((name, args)) = split_name(args) else {
+        return Reply::error("ERR empty command");
+    };
+    match find_group(&name) {
+        Some((group, CommandKind::Read)) => (group.execute_read)(&name, keyspace, args, now),
+        Some((_, CommandKind::Write)) => Reply::Error(format!(
+            "ERR '{}' is a write command, send it to the write shard",
+            name
+        )),
+        None => Reply::Error(format!("ERR unknown command '{}'", name)),
+    }
+}
+
+/// Reads a string key for a ReadRequest. Expiries are absolute, so a lagging
+/// read shard still stops serving a key on time.
+pub fn read_key(keyspace: &Keyspace, key: &[u8], now: u64) -> ReadResponse {
+    let (error, value, version) = match keyspace.get(key, now) {
+        Some(Value::String(value)) => (
+            ReadResponseError::NoError,
+            value.clone(),
+            keyspace.version(key, now),
+        ),
+        Some(_) => (
+            ReadResponseError::WrongType,
+            Vec::new(),
+            keyspace.version(key, now),
+        ),
+        None => (ReadResponseError::KeyNotFound, Vec::new(), 0),
+    };
+    ReadResponse {
+        key: key.to_vec(),
+        value,
+        error: error as u8,
+        version,
+    }
+}
+
+/// Answer to a read whose key the storage engine failed to load
+pub fn read_failed(key: &[u8]) -> ReadResponse {
+    ReadResponse {
+        key: key.to_vec(),
+        value: Vec::new(),
+        error: ReadResponseError::StorageFailed as u8,
+        version: 0,
+    }
+}
+
+/// Runs the commands of a transaction one after the other without changing
+/// the keyspace, each seeing the effects of the ones before it. The reply
+/// holds the reply of every command, unless one of them fails: then the
+/// whole transaction is discarded with its error and no mutations.
+pub fn execute_transaction(
+    keyspace: &Keyspace,
+    commands: &[Vec<Vec<u8>>],
+    now: u64,
+) -> WriteOutcome {
+    // the commands run against a copy of just the keys they touch
+    let mut scratch = keyspace.subset(commands.iter().flat_map(|args| command_keys(args)));
+    let mut replies = Vec::new();
+    let mut mutations = Vec::new();
+    for (i, args) in commands.iter().enumerate() {
+        let kind = split_name(args).and_then(|(name, _)| find_group(&name));
+        let outcome = match kind {
+            Some((_, CommandKind::Read)) => WriteOutcome::reply(execute_read(&scratch, args, now)),
+            _ => execute_write(&scratch, args, now),
+        };
+        if let Reply::Error(message) = outcome.reply {
+            return WriteOutcome::reply(Reply::Error(format!(
+                "EXECABORT Transaction discarded because command {} failed: {}",
+                i + 1,
+                message
+            )));
+        }
+        for mutation in &outcome.mutations {
+            mutation.apply(&mut scratch, 0);
+        }
+        replies.push(outcome.reply);
+        mutations.extend(outcome.mutations);
+    }
+    WriteOutcome {
+        reply: Reply::Array(replies),
+        mutations,
+    }
+}
+
+/// Helpers the tests of every command module share
+#[cfg(test)]
+pub(crate) mod test_support {
+    use super::{command_kind, execute_read, execute_write, CommandKind};
+    use crate::messages::responses::command_response::Reply;
+    use crate::storage::keyspace::Keyspace;
+
+    pub fn args(args: &[&str]) -> Vec<Vec<u8>> {
+        args.iter().map(|arg| arg.as_bytes().to_vec()).collect()
+    }
+
+    pub fn bulks(values: &[&str]) -> Reply {
+        Reply::Array(
+            values
+                .iter()
+                .map(|value| Reply::Bulk(value.as_bytes().to_vec()))
+                .collect(),
+        )
+    }
+
+    /// Runs a command, applying the mutations of write commands like a write shard would
+    pub fn run(keyspace: &mut Keyspace, command: &[&str], now: u64) -> Reply {
+        let command = args(command);
+        if command_kind(&command[0]) == Some(CommandKind::Read) {
+            return execute_read(keyspace, &command, now);
+        }
+        let outcome = execute_write(keyspace, &command, now);
+        for mutation in outcome.mutations {
+            mutation.apply(keyspace, 1);
+        }
+        outcome.reply
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::test_support::args;
+    use super::*;
+
+    #[test]
+    fn test_command_keys() {
+        assert_eq!(
+            command_keys(&args(&["SADD", "s", "a", "b"])),
+            &args(&["s"])[..]
+        );
+        assert_eq!(
+            command_keys(&args(&["sinter", "x", "y"])),
+            &args(&["x", "y"])[..]
+        );
+        assert!(command_keys(&args(&["incr"])).is_empty());
+        assert!(command_keys(&[]).is_empty());
+        assert_eq!(command_kind(b"LPUSH"), Some(CommandKind::Write));
+        assert_eq!(command_kind(b"hgetall"), Some(CommandKind::Read));
+        assert_eq!(command_kind(b"nope"), None);
+    }
+
+    #[test]
+    fn test_transaction() {
+        let mut keyspace = Keyspace::new();
+        keyspace.insert(b"balance".to_vec(), b"10".to_vec());
+        let outcome = execute_transaction(
+            &keyspace,
+            &[
+                args(&["decrby", "balance", "4"]),
+                args(&["rpush", "log", "spent 4"]),
+                args(&["get", "balance"]),
+                args(&["incr", "balance"]),
+            ],
+            0,
+        );
+        assert_eq!(
+            outcome.reply,
+            Reply::Array(vec![
+                Reply::Integer(6),
+                Reply::Integer(1),
+                Reply::Bulk(b"6".to_vec()),
+                Reply::Integer(7),
+            ])
+        );
+        assert_eq!(outcome.mutations.len(), 3);
+        // the keyspace itself is left alone
+        assert_eq!(keyspace.len(), 1);
+
+        let outcome = execute_transaction(
+            &keyspace,
+            &[args(&["incr", "balance"]), args(&["lpush", "balance", "x"])],
+            0,
+        );
+        assert_eq!(
+            outcome.reply,
+            Reply::error(
+                "EXECABORT Transaction discarded because command 2 failed: \
+                 WRONGTYPE Operation against a key holding the wrong kind of value"
+            )
+        );
+        assert!(outcome.mutations.is_empty());
+    }
+}
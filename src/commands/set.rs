@@ -0,0 +1,264 @@
+use super::{clear_expired, first_key, lookup, parse_int, CommandGroup, WriteOutcome};
+use crate::messages::responses::command_response::Reply;
+use crate::storage::keyspace::Keyspace;
+use crate::storage::mutation::Mutation;
+use crate::storage::value::Value;
+use rand::seq::IteratorRandom;
+use std::collections::HashSet;
+
+pub const GROUP: CommandGroup = CommandGroup {
+    write_commands: &["sadd", "srem", "spop"],
+    read_commands: &[
+        "sismember",
+        "smembers",
+        "scard",
+        "sinter",
+        "sunion",
+        "sdiff",
+    ],
+    keys,
+    execute_write,
+    execute_read,
+};
+
+fn keys<'a>(name: &str, args: &'a [Vec<u8>]) -> &'a [Vec<u8>] {
+    match name {
+        "sinter" | "sunion" | "sdiff" => args,
+        _ => first_key(name, args),
+    }
+}
+
+/// Sorted so replies don't depend on the order of the set
+fn members_reply<'a>(members: impl IntoIterator<Item = &'a Vec<u8>>) -> Reply {
+    let mut members: Vec<&Vec<u8>> = members.into_iter().collect();
+    members.sort();
+    Reply::Array(members.into_iter().cloned().map(Reply::Bulk).collect())
+}
+
+fn distinct(members: &[Vec<u8>]) -> Vec<Vec<u8>> {
+    let mut members = members.to_vec();
+    members.sort();
+    members.dedup();
+    members
+}
+
+fn execute_write(name: &str, keyspace: &Keyspace, args: &[Vec<u8>], now: u64) -> WriteOutcome {
+    let set = match args
+        .first()
+        .map(|key| lookup(keyspace, key, now, Value::as_set))
+    {
+        Some(Ok(set)) => set,
+        Some(Err(reply)) => return WriteOutcome::reply(reply),
+        None => return WriteOutcome::reply(Reply::wrong_arity(name)),
+    };
+    match (name, args) {
+        ("sadd", [key, members @ ..]) if !members.is_empty() => {
+            let added = distinct(members)
+                .into_iter()
+                .filter(|member| set.is_none_or(|set| !set.contains(member)))
+                .count();
+            let mut mutations: Vec<Mutation> =
+                clear_expired(keyspace, key, now).into_iter().collect();
+            mutations.push(Mutation::AddMembers {
+                key: key.clone(),
+                members: members.to_vec(),
+            });
+            WriteOutcome {
+                reply: Reply::Integer(added as i64),
+                mutations,
+            }
+        }
+        ("srem", [key, members @ ..]) if !members.is_empty() => {
+            let removed: Vec<Vec<u8>> = distinct(members)
+                .into_iter()
+                .filter(|member| set.is_some_and(|set| set.contains(member)))
+                .collect();
+            if removed.is_empty() {
+                return WriteOutcome::reply(Reply::Integer(0));
+            }
+            let reply = Reply::Integer(removed.len() as i64);
+            let mutation = Mutation::RemoveMembers {
+                key: key.clone(),
+                members: removed,
+            };
+            WriteOutcome::mutation(reply, mutation)
+        }
+        ("spop", [key, count @ ..]) if count.len() <= 1 => {
+            let count = match count.first().map(|count| parse_int(count)) {
+                None => None,
+                Some(Some(count)) if count >= 0 => Some(count as usize),
+                Some(_) => {
+                    return WriteOutcome::reply(Reply::error(
+                        "ERR value is out of range, must be positive",
+                    ))
+                }
+            };
+            let Some(set) = set else {
+                return WriteOutcome::reply(match count {
+                    Some(_) => Reply::Array(Vec::new()),
+                    None => Reply::Nil,
+                });
+            };
+            // the write shard picks the members, read shards replay its choice
+            let popped: Vec<Vec<u8>> = set
+                .iter()
+                .cloned()
+                .choose_multiple(&mut rand::thread_rng(), count.unwrap_or(1));
+            let reply = match count {
+                Some(_) => members_reply(&popped),
+                None => Reply::Bulk(popped[0].clone()),
+            };
+            if popped.is_empty() {
+                return WriteOutcome::reply(reply);
+            }
+            let mutation = Mutation::RemoveMembers {
+                key: key.clone(),
+                members: popped,
+            };
+            WriteOutcome::mutation(reply, mutation)
+        }
+        _ => WriteOutcome::reply(Reply::wrong_arity(name)),
+    }
+}
+
+/// Combines the sets at `keys` for SINTER, SUNION and SDIFF. Missing keys count as empty sets.
+fn combine(name: &str, keyspace: &Keyspace, keys: &[Vec<u8>], now: u64) -> Reply {
+    let mut sets = Vec::new();
+    for key in keys {
+        match lookup(keyspace, key, now, Value::as_set) {
+            Ok(set) => sets.push(set),
+            Err(reply) => return reply,
+        }
+    }
+    let empty = HashSet::new();
+    let mut sets = sets.into_iter().map(|set| set.unwrap_or(&empty));
+    let Some(first) = sets.next() else {
+        return Reply::wrong_arity(name);
+    };
+    let mut result: HashSet<&Vec<u8>> = first.iter().collect();
+    for set in sets {
+        match name {
+            "sinter" => result.retain(|member| set.contains(*member)),
+            "sunion" => result.extend(set.iter()),
+            _ => result.retain(|member| !set.contains(*member)),
+        }
+    }
+    members_reply(result)
+}
+
+fn execute_read(name: &str, keyspace: &Keyspace, args: &[Vec<u8>], now: u64) -> Reply {
+    if let "sinter" | "sunion" | "sdiff" = name {
+        return combine(name, keyspace, args, now);
+    }
+    let set = match args
+        .first()
+        .map(|key| lookup(keyspace, key, now, Value::as_set))
+    {
+        Some(Ok(set)) => set,
+        Some(Err(reply)) => return reply,
+        None => return Reply::wrong_arity(name),
+    };
+    match (name, args) {
+        ("sismember", [_, member]) => {
+            Reply::Integer(set.is_some_and(|set| set.contains(member)) as i64)
+        }
+        ("smembers", [_]) => members_reply(set.into_iter().flatten()),
+        ("scard", [_]) => Reply::Integer(set.map_or(0, |set| set.len() as i64)),
+        _ => Reply::wrong_arity(name),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands;
+    use crate::commands::test_support::{args, bulks, run};
+
+    #[test]
+    fn test_members() {
+        let mut keyspace = Keyspace::new();
+        assert_eq!(
+            run(&mut keyspace, &["sadd", "s", "b", "a", "a"], 0),
+            Reply::Integer(2)
+        );
+        assert_eq!(
+            run(&mut keyspace, &["sadd", "s", "a", "c"], 0),
+            Reply::Integer(1)
+        );
+        assert_eq!(
+            run(&mut keyspace, &["smembers", "s"], 0),
+            bulks(&["a", "b", "c"])
+        );
+        assert_eq!(
+            run(&mut keyspace, &["sismember", "s", "b"], 0),
+            Reply::Integer(1)
+        );
+        assert_eq!(
+            run(&mut keyspace, &["sismember", "s", "z"], 0),
+            Reply::Integer(0)
+        );
+        assert_eq!(
+            run(&mut keyspace, &["srem", "s", "b", "z"], 0),
+            Reply::Integer(1)
+        );
+        assert_eq!(run(&mut keyspace, &["scard", "s"], 0), Reply::Integer(2));
+
+        assert_eq!(
+            run(&mut keyspace, &["spop", "s", "5"], 0),
+            bulks(&["a", "c"])
+        );
+        assert!(keyspace.is_empty());
+        assert_eq!(run(&mut keyspace, &["spop", "s"], 0), Reply::Nil);
+    }
+
+    #[test]
+    fn test_spop_replicates_its_choice() {
+        let mut keyspace = Keyspace::new();
+        run(&mut keyspace, &["sadd", "s", "a", "b", "c"], 0);
+        let outcome = commands::execute_write(&keyspace, &args(&["spop", "s"]), 0);
+        let Reply::Bulk(popped) = outcome.reply else {
+            panic!("expected a member, got {:?}", outcome.reply);
+        };
+        assert_eq!(
+            outcome.mutations,
+            vec![Mutation::RemoveMembers {
+                key: b"s".to_vec(),
+                members: vec![popped],
+            }]
+        );
+    }
+
+    #[test]
+    fn test_algebra() {
+        let mut keyspace = Keyspace::new();
+        run(&mut keyspace, &["sadd", "x", "a", "b", "c"], 0);
+        run(&mut keyspace, &["sadd", "y", "b", "c", "d"], 0);
+        assert_eq!(
+            run(&mut keyspace, &["sinter", "x", "y"], 0),
+            bulks(&["b", "c"])
+        );
+        assert_eq!(
+            run(&mut keyspace, &["sunion", "x", "y"], 0),
+            bulks(&["a", "b", "c", "d"])
+        );
+        assert_eq!(run(&mut keyspace, &["sdiff", "x", "y"], 0), bulks(&["a"]));
+        assert_eq!(
+            run(&mut keyspace, &["sinter", "x", "missing"], 0),
+            bulks(&[])
+        );
+        assert_eq!(
+            run(&mut keyspace, &["sdiff", "x"], 0),
+            bulks(&["a", "b", "c"])
+        );
+
+        keyspace.insert(b"str".to_vec(), b"value".to_vec());
+        assert_eq!(
+            run(&mut keyspace, &["sunion", "x", "str"], 0),
+            Reply::wrong_type()
+        );
+        assert_eq!(
+            run(&mut keyspace, &["sadd", "str", "a"], 0),
+            Reply::wrong_type()
+        );
+    }
+}
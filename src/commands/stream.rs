@@ -0,0 +1,750 @@
+use super::{clear_expired, first_key, lookup, parse_int, CommandGroup, WriteOutcome};
+use crate::messages::responses::command_response::Reply;
+use crate::storage::keyspace::Keyspace;
+use crate::storage::mutation::Mutation;
+use crate::storage::stream::{Fields, Stream, StreamId};
+use crate::storage::value::Value;
+
+pub const GROUP: CommandGroup = CommandGroup {
+    write_commands: &["xadd", "xtrim", "xgroup", "xreadgroup", "xack"],
+    read_commands: &["xrange", "xread", "xlen", "xpending"],
+    keys,
+    execute_write,
+    execute_read,
+};
+
+fn keys<'a>(name: &str, args: &'a [Vec<u8>]) -> &'a [Vec<u8>] {
+    match name {
+        // the keys are the first half of the arguments after STREAMS
+        "xread" | "xreadgroup" => match streams_position(args) {
+            Some(position) => {
+                let streams = &args[position + 1..];
+                &streams[..streams.len() / 2]
+            }
+            None => &[],
+        },
+        // XGROUP <subcommand> <key> ...
+        "xgroup" => args.get(1..2).unwrap_or(&[]),
+        _ => first_key(name, args),
+    }
+}
+
+fn streams_position(args: &[Vec<u8>]) -> Option<usize> {
+    args.iter()
+        .position(|arg| arg.eq_ignore_ascii_case(b"streams"))
+}
+
+fn syntax_error() -> Reply {
+    Reply::error("ERR syntax error")
+}
+
+fn invalid_id() -> Reply {
+    Reply::error("ERR Invalid stream ID specified as stream command argument")
+}
+
+fn no_group(key: &[u8], group: &[u8], command: &str) -> Reply {
+    Reply::Error(format!(
+        "NOGROUP No such key '{}' or consumer group '{}' in {}",
+        String::from_utf8_lossy(key),
+        String::from_utf8_lossy(group),
+        command
+    ))
+}
+
+fn entry_reply(id: &StreamId, fields: Option<&Fields>) -> Reply {
+    let fields = match fields {
+        Some(fields) => Reply::Array(
+            fields
+                .iter()
+                .flat_map(|(field, value)| [Reply::Bulk(field.clone()), Reply::Bulk(value.clone())])
+                .collect(),
+        ),
+        // the entry was trimmed after it was delivered
+        None => Reply::Nil,
+    };
+    Reply::Array(vec![Reply::Bulk(id.to_string().into_bytes()), fields])
+}
+
+/// Reply of XREAD and XREADGROUP: every stream that has entries with its
+/// entries, or nil when none of them do
+fn streams_reply(streams: Vec<(&Vec<u8>, Vec<Reply>)>) -> Reply {
+    let streams: Vec<Reply> = streams
+        .into_iter()
+        .filter(|(_, entries)| !entries.is_empty())
+        .map(|(key, entries)| Reply::Array(vec![Reply::Bulk(key.clone()), Reply::Array(entries)]))
+        .collect();
+    if streams.is_empty() {
+        Reply::Nil
+    } else {
+        Reply::Array(streams)
+    }
+}
+
+/// Parses MAXLEN [~|=] <count>, returning the count and how many arguments it took
+fn parse_max_len(args: &[Vec<u8>]) -> Option<(usize, usize)> {
+    if !args.first()?.eq_ignore_ascii_case(b"maxlen") {
+        return None;
+    }
+    let (count, taken) = match args.get(1)?.as_slice() {
+        // trimming is always exact, so "~" is accepted but doesn't change anything
+        b"~" | b"=" => (args.get(2)?, 3),
+        _ => (args.get(1)?, 2),
+    };
+    let count = usize::try_from(parse_int(count)?).ok()?;
+    Some((count, taken))
+}
+
+/// Pairs of stream keys and the ids to read after
+type StreamArgs<'a> = Vec<(&'a Vec<u8>, &'a Vec<u8>)>;
+
+/// Parses the [COUNT <count>] STREAMS <key>... <id>... tail of XREAD and XREADGROUP
+fn parse_read_args(args: &[Vec<u8>]) -> Result<(Option<usize>, StreamArgs<'_>), Reply> {
+    let Some(position) = streams_position(args) else {
+        return Err(syntax_error());
+    };
+    let mut count = None;
+    let mut options = args[..position].iter();
+    while let Some(option) = options.next() {
+        if !option.eq_ignore_ascii_case(b"count") {
+            return Err(syntax_error());
+        }
+        let value = options.next().and_then(|value| parse_int(value));
+        count = Some(value.map_or(0, |value| value.max(0) as usize));
+    }
+    let streams = &args[position + 1..];
+    if streams.is_empty() || !streams.len().is_multiple_of(2) {
+        return Err(Reply::error(
+            "ERR Unbalanced 'xread' list of streams: for each stream key an ID or '$' must be specified.",
+        ));
+    }
+    let (keys, ids) = streams.split_at(streams.len() / 2);
+    Ok((count, keys.iter().zip(ids).collect()))
+}
+
+fn xadd(keyspace: &Keyspace, args: &[Vec<u8>], now: u64) -> WriteOutcome {
+    let Some((key, args)) = args.split_first() else {
+        return WriteOutcome::reply(Reply::wrong_arity("xadd"));
+    };
+    let stream = match lookup(keyspace, key, now, Value::as_stream) {
+        Ok(stream) => stream,
+        Err(reply) => return WriteOutcome::reply(reply),
+    };
+    let (max_len, args) = match parse_max_len(args) {
+        Some((max_len, taken)) => (Some(max_len), &args[taken..]),
+        None => (None, args),
+    };
+    let Some((id, pairs)) = args.split_first() else {
+        return WriteOutcome::reply(Reply::wrong_arity("xadd"));
+    };
+    if pairs.is_empty() || !pairs.len().is_multiple_of(2) {
+        return WriteOutcome::reply(Reply::wrong_arity("xadd"));
+    }
+    let last_id = stream.map_or(StreamId::MIN, Stream::last_id);
+    let id = if id.as_slice() == b"*" {
+        let next = match stream {
+            Some(stream) => stream.next_id(now),
+            None => Some(StreamId { ms: now, seq: 0 }),
+        };
+        let Some(next) = next else {
+            return WriteOutcome::reply(Reply::error(
+                "ERR The stream has exhausted the last possible ID, unable to add more items",
+            ));
+        };
+        next
+    } else {
+        match StreamId::parse(id, 0) {
+            Some(id) if id > last_id => id,
+            Some(_) => return WriteOutcome::reply(Reply::error(
+                "ERR The ID specified in XADD is equal or smaller than the target stream top item",
+            )),
+            None => return WriteOutcome::reply(invalid_id()),
+        }
+    };
+
+    let fields = pairs
+        .chunks(2)
+        .map(|pair| (pair[0].clone(), pair[1].clone()))
+        .collect();
+    let mut mutations: Vec<Mutation> = clear_expired(keyspace, key, now).into_iter().collect();
+    mutations.push(Mutation::AddEntry {
+        key: key.clone(),
+        id,
+        fields,
+    });
+    let len = stream.map_or(0, Stream::len) + 1;
+    if let Some(max_len) = max_len.filter(|&max_len| len > max_len) {
+        mutations.push(Mutation::TrimStream {
+            key: key.clone(),
+            max_len: max_len as u64,
+        });
+    }
+    WriteOutcome {
+        reply: Reply::Bulk(id.to_string().into_bytes()),
+        mutations,
+    }
+}
+
+fn xgroup(keyspace: &Keyspace, args: &[Vec<u8>], now: u64) -> WriteOutcome {
+    let subcommand = args.first().map(|arg| arg.to_ascii_lowercase());
+    match (subcommand.as_deref(), args) {
+        (Some(b"create"), [_, key, group, id, options @ ..]) => {
+            let make_stream = match options {
+                [] => false,
+                [option] if option.eq_ignore_ascii_case(b"mkstream") => true,
+                _ => return WriteOutcome::reply(syntax_error()),
+            };
+            let stream = match lookup(keyspace, key, now, Value::as_stream) {
+                Ok(Some(stream)) => Some(stream),
+                Ok(None) if make_stream => None,
+                Ok(None) => {
+                    return WriteOutcome::reply(Reply::error(
+                        "ERR The XGROUP subcommand requires the key to exist. Note that for CREATE you may want to use the MKSTREAM option to create an empty stream automatically.",
+                    ))
+                }
+                Err(reply) => return WriteOutcome::reply(reply),
+            };
+            if stream.is_some_and(|stream| stream.group(group).is_some()) {
+                return WriteOutcome::reply(Reply::error(
+                    "BUSYGROUP Consumer Group name already exists",
+                ));
+            }
+            let last_delivered = if id.as_slice() == b"$" {
+                stream.map_or(StreamId::MIN, Stream::last_id)
+            } else {
+                match StreamId::parse(id, 0) {
+                    Some(id) => id,
+                    None => return WriteOutcome::reply(invalid_id()),
+                }
+            };
+            let mut mutations: Vec<Mutation> =
+                clear_expired(keyspace, key, now).into_iter().collect();
+            mutations.push(Mutation::CreateGroup {
+                key: key.clone(),
+                group: group.clone(),
+                last_delivered,
+            });
+            WriteOutcome {
+                reply: Reply::ok(),
+                mutations,
+            }
+        }
+        (Some(b"destroy"), [_, key, group]) => {
+            let stream = match lookup(keyspace, key, now, Value::as_stream) {
+                Ok(stream) => stream,
+                Err(reply) => return WriteOutcome::reply(reply),
+            };
+            if stream.is_none_or(|stream| stream.group(group).is_none()) {
+                return WriteOutcome::reply(Reply::Integer(0));
+            }
+            let mutation = Mutation::DestroyGroup {
+                key: key.clone(),
+                group: group.clone(),
+            };
+            WriteOutcome::mutation(Reply::Integer(1), mutation)
+        }
+        _ => WriteOutcome::reply(Reply::error(
+            "ERR unknown XGROUP subcommand or wrong number of arguments",
+        )),
+    }
+}
+
+/// XREADGROUP GROUP <group> <consumer> [COUNT <count>] STREAMS <key>... <id>...
+/// Reading from ">" delivers new entries to the consumer, any other id
+/// re-reads the consumer's pending entries after it.
+fn xreadgroup(keyspace: &Keyspace, args: &[Vec<u8>], now: u64) -> WriteOutcome {
+    let [option, group, consumer, args @ ..] = args else {
+        return WriteOutcome::reply(Reply::wrong_arity("xreadgroup"));
+    };
+    if !option.eq_ignore_ascii_case(b"group") {
+        return WriteOutcome::reply(syntax_error());
+    }
+    let (count, streams) = match parse_read_args(args) {
+        Ok(parsed) => parsed,
+        Err(reply) => return WriteOutcome::reply(reply),
+    };
+    let count = count.unwrap_or(usize::MAX);
+
+    let mut mutations = Vec::new();
+    let mut replies = Vec::new();
+    for (key, id) in streams {
+        let stream = match lookup(keyspace, key, now, Value::as_stream) {
+            Ok(stream) => stream,
+            Err(reply) => return WriteOutcome::reply(reply),
+        };
+        let Some((stream, group_state)) =
+            stream.and_then(|stream| Some((stream, stream.group(group)?)))
+        else {
+            return WriteOutcome::reply(no_group(key, group, "XREADGROUP with GROUP option"));
+        };
+        let entries: Vec<Reply> = if id.as_slice() == b">" {
+            let new: Vec<(&StreamId, &Fields)> = stream
+                .range(group_state.last_delivered, StreamId::MAX)
+                .filter(|(entry_id, _)| **entry_id > group_state.last_delivered)
+                .take(count)
+                .collect();
+            if !new.is_empty() {
+                mutations.push(Mutation::DeliverEntries {
+                    key: key.clone(),
+                    group: group.clone(),
+                    consumer: consumer.clone(),
+                    ids: new.iter().map(|(entry_id, _)| **entry_id).collect(),
+                    delivered_at: now,
+                });
+            }
+            new.into_iter()
+                .map(|(entry_id, fields)| entry_reply(entry_id, Some(fields)))
+                .collect()
+        } else {
+            let Some(after) = StreamId::parse(id, 0) else {
+                return WriteOutcome::reply(invalid_id());
+            };
+            group_state
+                .pending
+                .range(after..)
+                .filter(|(entry_id, pending)| **entry_id > after && pending.consumer == *consumer)
+                .take(count)
+                .map(|(entry_id, _)| entry_reply(entry_id, stream.get(entry_id)))
+                .collect()
+        };
+        replies.push((key, entries));
+    }
+    WriteOutcome {
+        reply: streams_reply(replies),
+        mutations,
+    }
+}
+
+fn execute_write(name: &str, keyspace: &Keyspace, args: &[Vec<u8>], now: u64) -> WriteOutcome {
+    match (name, args) {
+        ("xadd", _) => xadd(keyspace, args, now),
+        ("xtrim", [key, rest @ ..]) => {
+            let Some((max_len, _)) = parse_max_len(rest).filter(|(_, taken)| *taken == rest.len())
+            else {
+                return WriteOutcome::reply(syntax_error());
+            };
+            let len = match lookup(keyspace, key, now, Value::as_stream) {
+                Ok(stream) => stream.map_or(0, Stream::len),
+                Err(reply) => return WriteOutcome::reply(reply),
+            };
+            let removed = len.saturating_sub(max_len);
+            if removed == 0 {
+                return WriteOutcome::reply(Reply::Integer(0));
+            }
+            let mutation = Mutation::TrimStream {
+                key: key.clone(),
+                max_len: max_len as u64,
+            };
+            WriteOutcome::mutation(Reply::Integer(removed as i64), mutation)
+        }
+        ("xgroup", _) => xgroup(keyspace, args, now),
+        ("xreadgroup", _) => xreadgroup(keyspace, args, now),
+        ("xack", [key, group, ids @ ..]) if !ids.is_empty() => {
+            let mut parsed = Vec::new();
+            for id in ids {
+                match StreamId::parse(id, 0) {
+                    Some(id) => parsed.push(id),
+                    None => return WriteOutcome::reply(invalid_id()),
+                }
+            }
+            let pending = match lookup(keyspace, key, now, Value::as_stream) {
+                Ok(stream) => stream
+                    .and_then(|stream| stream.group(group))
+                    .map(|group| &group.pending),
+                Err(reply) => return WriteOutcome::reply(reply),
+            };
+            parsed.sort();
+            parsed.dedup();
+            parsed.retain(|id| pending.is_some_and(|pending| pending.contains_key(id)));
+            if parsed.is_empty() {
+                return WriteOutcome::reply(Reply::Integer(0));
+            }
+            let reply = Reply::Integer(parsed.len() as i64);
+            let mutation = Mutation::AckEntries {
+                key: key.clone(),
+                group: group.clone(),
+                ids: parsed,
+            };
+            WriteOutcome::mutation(reply, mutation)
+        }
+        _ => WriteOutcome::reply(Reply::wrong_arity(name)),
+    }
+}
+
+/// Parses an XRANGE bound, where "-" and "+" are the smallest and largest ids
+/// and an id without a sequence number covers the whole millisecond
+fn parse_range_bound(arg: &[u8], default_seq: u64) -> Option<StreamId> {
+    match arg {
+        b"-" => Some(StreamId::MIN),
+        b"+" => Some(StreamId::MAX),
+        _ => StreamId::parse(arg, default_seq),
+    }
+}
+
+fn xpending(stream: &Stream, key: &[u8], args: &[Vec<u8>], now: u64) -> Reply {
+    let Some((group, args)) = args.split_first() else {
+        return Reply::wrong_arity("xpending");
+    };
+    let Some(group) = stream.group(group) else {
+        return no_group(key, group, "XPENDING");
+    };
+    match args {
+        [] => {
+            let mut consumers: Vec<(&Vec<u8>, i64)> = Vec::new();
+            for pending in group.pending.values() {
+                match consumers
+                    .iter_mut()
+                    .find(|(consumer, _)| *consumer == &pending.consumer)
+                {
+                    Some((_, count)) => *count += 1,
+                    None => consumers.push((&pending.consumer, 1)),
+                }
+            }
+            consumers.sort();
+            let bound = |id: Option<&StreamId>| match id {
+                Some(id) => Reply::Bulk(id.to_string().into_bytes()),
+                None => Reply::Nil,
+            };
+            Reply::Array(vec![
+                Reply::Integer(group.pending.len() as i64),
+                bound(group.pending.keys().next()),
+                bound(group.pending.keys().next_back()),
+                if consumers.is_empty() {
+                    Reply::Nil
+                } else {
+                    Reply::Array(
+                        consumers
+                            .into_iter()
+                            .map(|(consumer, count)| {
+                                Reply::Array(vec![
+                                    Reply::Bulk(consumer.clone()),
+                                    Reply::Bulk(count.to_string().into_bytes()),
+                                ])
+                            })
+                            .collect(),
+                    )
+                },
+            ])
+        }
+        [start, end, count, consumer @ ..] if consumer.len() <= 1 => {
+            let (Some(start), Some(end)) = (
+                parse_range_bound(start, 0),
+                parse_range_bound(end, u64::MAX),
+            ) else {
+                return invalid_id();
+            };
+            let Some(count) = parse_int(count) else {
+                return Reply::not_an_integer();
+            };
+            if start > end {
+                return Reply::Array(Vec::new());
+            }
+            Reply::Array(
+                group
+                    .pending
+                    .range(start..=end)
+                    .filter(|(_, pending)| {
+                        consumer
+                            .first()
+                            .is_none_or(|consumer| pending.consumer == *consumer)
+                    })
+                    .take(count.max(0) as usize)
+                    .map(|(id, pending)| {
+                        Reply::Array(vec![
+                            Reply::Bulk(id.to_string().into_bytes()),
+                            Reply::Bulk(pending.consumer.clone()),
+                            Reply::Integer(now.saturating_sub(pending.delivered_at) as i64),
+                            Reply::Integer(pending.deliveries as i64),
+                        ])
+                    })
+                    .collect(),
+            )
+        }
+        _ => syntax_error(),
+    }
+}
+
+fn execute_read(name: &str, keyspace: &Keyspace, args: &[Vec<u8>], now: u64) -> Reply {
+    if name == "xread" {
+        let (count, streams) = match parse_read_args(args) {
+            Ok(parsed) => parsed,
+            Err(reply) => return reply,
+        };
+        let mut replies = Vec::new();
+        for (key, id) in streams {
+            let stream = match lookup(keyspace, key, now, Value::as_stream) {
+                Ok(stream) => stream,
+                Err(reply) => return reply,
+            };
+            let after = if id.as_slice() == b"$" {
+                stream.map_or(StreamId::MIN, Stream::last_id)
+            } else {
+                match StreamId::parse(id, 0) {
+                    Some(after) => after,
+                    None => return invalid_id(),
+                }
+            };
+            let entries = stream
+                .into_iter()
+                .flat_map(|stream| stream.range(after, StreamId::MAX))
+                .filter(|(entry_id, _)| **entry_id > after)
+                .take(count.unwrap_or(usize::MAX))
+                .map(|(entry_id, fields)| entry_reply(entry_id, Some(fields)))
+                .collect();
+            replies.push((key, entries));
+        }
+        return streams_reply(replies);
+    }
+
+    let Some((key, rest)) = args.split_first() else {
+        return Reply::wrong_arity(name);
+    };
+    let stream = match lookup(keyspace, key, now, Value::as_stream) {
+        Ok(stream) => stream,
+        Err(reply) => return reply,
+    };
+    match (name, rest) {
+        ("xrange", [start, end, options @ ..]) => {
+            let count = match options {
+                [] => usize::MAX,
+                [option, count] if option.eq_ignore_ascii_case(b"count") => {
+                    match parse_int(count) {
+                        Some(count) => count.max(0) as usize,
+                        None => return Reply::not_an_integer(),
+                    }
+                }
+                _ => return syntax_error(),
+            };
+            let (Some(start), Some(end)) = (
+                parse_range_bound(start, 0),
+                parse_range_bound(end, u64::MAX),
+            ) else {
+                return invalid_id();
+            };
+            Reply::Array(
+                stream
+                    .into_iter()
+                    .flat_map(|stream| stream.range(start, end))
+                    .take(count)
+                    .map(|(id, fields)| entry_reply(id, Some(fields)))
+                    .collect(),
+            )
+        }
+        ("xlen", []) => Reply::Integer(stream.map_or(0, Stream::len) as i64),
+        ("xpending", [_, ..]) => match stream {
+            Some(stream) => xpending(stream, key, rest, now),
+            None => no_group(key, &rest[0], "XPENDING"),
+        },
+        _ => Reply::wrong_arity(name),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::command_keys;
+    use crate::commands::test_support::{args, run};
+
+    fn bulk(value: &str) -> Reply {
+        Reply::Bulk(value.as_bytes().to_vec())
+    }
+
+    fn entry(id: &str, fields: &[&str]) -> Reply {
+        Reply::Array(vec![
+            bulk(id),
+            Reply::Array(fields.iter().map(|field| bulk(field)).collect()),
+        ])
+    }
+
+    #[test]
+    fn test_add_and_range() {
+        let mut keyspace = Keyspace::new();
+        assert_eq!(
+            run(&mut keyspace, &["xadd", "s", "*", "temp", "20"], 1000),
+            bulk("1000-0")
+        );
+        assert_eq!(
+            run(&mut keyspace, &["xadd", "s", "*", "temp", "21"], 1000),
+            bulk("1000-1")
+        );
+        assert_eq!(
+            run(&mut keyspace, &["xadd", "s", "1000-1", "temp", "22"], 1000),
+            Reply::error(
+                "ERR The ID specified in XADD is equal or smaller than the target stream top item"
+            )
+        );
+        assert_eq!(
+            run(&mut keyspace, &["xadd", "s", "2000-5", "temp", "22"], 1000),
+            bulk("2000-5")
+        );
+        assert_eq!(run(&mut keyspace, &["xlen", "s"], 0), Reply::Integer(3));
+        assert_eq!(
+            run(
+                &mut keyspace,
+                &["xrange", "s", "1000", "+", "COUNT", "2"],
+                0
+            ),
+            Reply::Array(vec![
+                entry("1000-0", &["temp", "20"]),
+                entry("1000-1", &["temp", "21"]),
+            ])
+        );
+        assert_eq!(
+            run(&mut keyspace, &["xread", "STREAMS", "s", "1000-1"], 0),
+            Reply::Array(vec![Reply::Array(vec![
+                bulk("s"),
+                Reply::Array(vec![entry("2000-5", &["temp", "22"])]),
+            ])])
+        );
+        assert_eq!(
+            run(&mut keyspace, &["xread", "STREAMS", "s", "$"], 0),
+            Reply::Nil
+        );
+
+        assert_eq!(
+            run(
+                &mut keyspace,
+                &["xadd", "s", "MAXLEN", "2", "*", "temp", "23"],
+                3000
+            ),
+            bulk("3000-0")
+        );
+        assert_eq!(run(&mut keyspace, &["xlen", "s"], 0), Reply::Integer(2));
+        assert_eq!(
+            run(&mut keyspace, &["xtrim", "s", "MAXLEN", "1"], 0),
+            Reply::Integer(1)
+        );
+        assert_eq!(
+            run(&mut keyspace, &["xrange", "s", "-", "+"], 0),
+            Reply::Array(vec![entry("3000-0", &["temp", "23"])])
+        );
+    }
+
+    #[test]
+    fn test_consumer_groups() {
+        let mut keyspace = Keyspace::new();
+        assert_eq!(
+            run(&mut keyspace, &["xgroup", "CREATE", "s", "g", "$"], 0),
+            Reply::error("ERR The XGROUP subcommand requires the key to exist. Note that for CREATE you may want to use the MKSTREAM option to create an empty stream automatically.")
+        );
+        assert_eq!(
+            run(
+                &mut keyspace,
+                &["xgroup", "CREATE", "s", "g", "$", "MKSTREAM"],
+                0
+            ),
+            Reply::ok()
+        );
+        assert_eq!(
+            run(&mut keyspace, &["xgroup", "CREATE", "s", "g", "0"], 0),
+            Reply::error("BUSYGROUP Consumer Group name already exists")
+        );
+        run(&mut keyspace, &["xadd", "s", "1-0", "job", "a"], 0);
+        run(&mut keyspace, &["xadd", "s", "2-0", "job", "b"], 0);
+
+        let read = [
+            "xreadgroup",
+            "GROUP",
+            "g",
+            "alice",
+            "COUNT",
+            "1",
+            "STREAMS",
+            "s",
+            ">",
+        ];
+        assert_eq!(
+            run(&mut keyspace, &read, 100),
+            Reply::Array(vec![Reply::Array(vec![
+                bulk("s"),
+                Reply::Array(vec![entry("1-0", &["job", "a"])]),
+            ])])
+        );
+        run(
+            &mut keyspace,
+            &["xreadgroup", "GROUP", "g", "bob", "STREAMS", "s", ">"],
+            100,
+        );
+        assert_eq!(
+            run(
+                &mut keyspace,
+                &["xreadgroup", "GROUP", "g", "bob", "STREAMS", "s", ">"],
+                100
+            ),
+            Reply::Nil
+        );
+
+        // reading from an id re-reads the consumer's own pending entries
+        assert_eq!(
+            run(
+                &mut keyspace,
+                &["xreadgroup", "GROUP", "g", "alice", "STREAMS", "s", "0"],
+                100
+            ),
+            Reply::Array(vec![Reply::Array(vec![
+                bulk("s"),
+                Reply::Array(vec![entry("1-0", &["job", "a"])]),
+            ])])
+        );
+        assert_eq!(
+            run(&mut keyspace, &["xpending", "s", "g"], 150),
+            Reply::Array(vec![
+                Reply::Integer(2),
+                bulk("1-0"),
+                bulk("2-0"),
+                Reply::Array(vec![
+                    Reply::Array(vec![bulk("alice"), bulk("1")]),
+                    Reply::Array(vec![bulk("bob"), bulk("1")]),
+                ]),
+            ])
+        );
+        assert_eq!(
+            run(
+                &mut keyspace,
+                &["xpending", "s", "g", "-", "+", "10", "bob"],
+                150
+            ),
+            Reply::Array(vec![Reply::Array(vec![
+                bulk("2-0"),
+                bulk("bob"),
+                Reply::Integer(50),
+                Reply::Integer(1),
+            ])])
+        );
+
+        assert_eq!(
+            run(&mut keyspace, &["xack", "s", "g", "1-0", "1-0", "9-0"], 0),
+            Reply::Integer(1)
+        );
+        assert_eq!(
+            run(&mut keyspace, &["xpending", "s", "g"], 0),
+            Reply::Array(vec![
+                Reply::Integer(1),
+                bulk("2-0"),
+                bulk("2-0"),
+                Reply::Array(vec![Reply::Array(vec![bulk("bob"), bulk("1")])]),
+            ])
+        );
+        assert_eq!(
+            run(&mut keyspace, &["xgroup", "DESTROY", "s", "g"], 0),
+            Reply::Integer(1)
+        );
+        assert_eq!(
+            run(&mut keyspace, &["xpending", "s", "g"], 0),
+            Reply::error("NOGROUP No such key 's' or consumer group 'g' in XPENDING")
+        );
+    }
+
+    #[test]
+    fn test_keys() {
+        assert_eq!(
+            command_keys(&args(&[
+                "xread", "COUNT", "2", "STREAMS", "a", "b", "0", "0"
+            ])),
+            &args(&["a", "b"])[..]
+        );
+        assert_eq!(
+            command_keys(&args(&["xgroup", "CREATE", "s", "g", "$"])),
+            &args(&["s"])[..]
+        );
+    }
+}
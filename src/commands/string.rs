@@ -0,0 +1,231 @@
+use super::{first_key, lookup, parse_int, store, CommandGroup, WriteOutcome};
+use crate::messages::responses::command_response::Reply;
+use crate::storage::keyspace::Keyspace;
+use crate::storage::mutation::Mutation;
+use crate::storage::value::Value;
+
+pub const GROUP: CommandGroup = CommandGroup {
+    write_commands: &[
+        "set",
+        "incr",
+        "decr",
+        "incrby",
+        "decrby",
+        "incrbyfloat",
+        "append",
+        "getset",
+    ],
+    read_commands: &["get", "strlen"],
+    keys: first_key,
+    execute_write,
+    execute_read,
+};
+
+fn parse_float(arg: &[u8]) -> Option<f64> {
+    std::str::from_utf8(arg)
+        .ok()?
+        .parse::<f64>()
+        .ok()
+        .filter(|f| f.is_finite())
+}
+
+fn incr_by(keyspace: &Keyspace, key: &[u8], delta: i64, now: u64) -> WriteOutcome {
+    let current = match lookup(keyspace, key, now, Value::as_string) {
+        Ok(Some(value)) => match parse_int(value) {
+            Some(current) => current,
+            None => return WriteOutcome::reply(Reply::not_an_integer()),
+        },
+        Ok(None) => 0,
+        Err(reply) => return WriteOutcome::reply(reply),
+    };
+    let Some(new) = current.checked_add(delta) else {
+        return WriteOutcome::reply(Reply::error("ERR increment or decrement would overflow"));
+    };
+    let mutation = store(keyspace, key, new.to_string().into_bytes(), now);
+    WriteOutcome::mutation(Reply::Integer(new), mutation)
+}
+
+fn incr_by_float(keyspace: &Keyspace, key: &[u8], delta: f64, now: u64) -> WriteOutcome {
+    let current = match lookup(keyspace, key, now, Value::as_string) {
+        Ok(Some(value)) => match parse_float(value) {
+            Some(current) => current,
+            None => return WriteOutcome::reply(Reply::not_a_float()),
+        },
+        Ok(None) => 0.0,
+        Err(reply) => return WriteOutcome::reply(reply),
+    };
+    let new = current + delta;
+    if !new.is_finite() {
+        return WriteOutcome::reply(Reply::error("ERR increment would produce NaN or Infinity"));
+    }
+    let new = new.to_string().into_bytes();
+    let mutation = store(keyspace, key, new.clone(), now);
+    WriteOutcome::mutation(Reply::Bulk(new), mutation)
+}
+
+fn execute_write(name: &str, keyspace: &Keyspace, args: &[Vec<u8>], now: u64) -> WriteOutcome {
+    match (name, args) {
+        // the client sends a top-level set as a WriteRequest, this one is for transactions
+        ("set", [key, value]) => {
+            let mutation = Mutation::Set {
+                key: key.clone(),
+                value: value.clone(),
+            };
+            WriteOutcome::mutation(Reply::ok(), mutation)
+        }
+        ("incr", [key]) => incr_by(keyspace, key, 1, now),
+        ("decr", [key]) => incr_by(keyspace, key, -1, now),
+        ("incrby", [key, delta]) => match parse_int(delta) {
+            Some(delta) => incr_by(keyspace, key, delta, now),
+            None => WriteOutcome::reply(Reply::not_an_integer()),
+        },
+        ("decrby", [key, delta]) => match parse_int(delta).and_then(i64::checked_neg) {
+            Some(delta) => incr_by(keyspace, key, delta, now),
+            None => WriteOutcome::reply(Reply::not_an_integer()),
+        },
+        ("incrbyfloat", [key, delta]) => match parse_float(delta) {
+            Some(delta) => incr_by_float(keyspace, key, delta, now),
+            None => WriteOutcome::reply(Reply::not_a_float()),
+        },
+        ("append", [key, suffix]) => {
+            let mut value = match lookup(keyspace, key, now, Value::as_string) {
+                Ok(value) => value.cloned().unwrap_or_default(),
+                Err(reply) => return WriteOutcome::reply(reply),
+            };
+            value.extend_from_slice(suffix);
+            let len = value.len() as i64;
+            WriteOutcome::mutation(Reply::Integer(len), store(keyspace, key, value, now))
+        }
+        ("getset", [key, value]) => {
+            let old = match lookup(keyspace, key, now, Value::as_string) {
+                Ok(Some(old)) => Reply::Bulk(old.clone()),
+                Ok(None) => Reply::Nil,
+                Err(reply) => return WriteOutcome::reply(reply),
+            };
+            // a plain set, so any expiry is cleared
+            let mutation = Mutation::Set {
+                key: key.clone(),
+                value: value.clone(),
+            };
+            WriteOutcome::mutation(old, mutation)
+        }
+        _ => WriteOutcome::reply(Reply::wrong_arity(name)),
+    }
+}
+
+fn execute_read(name: &str, keyspace: &Keyspace, args: &[Vec<u8>], now: u64) -> Reply {
+    match (name, args) {
+        ("get", [key]) => match lookup(keyspace, key, now, Value::as_string) {
+            Ok(Some(value)) => Reply::Bulk(value.clone()),
+            Ok(None) => Reply::Nil,
+            Err(reply) => reply,
+        },
+        ("strlen", [key]) => match lookup(keyspace, key, now, Value::as_string) {
+            Ok(value) => Reply::Integer(value.map_or(0, |value| value.len() as i64)),
+            Err(reply) => reply,
+        },
+        _ => Reply::wrong_arity(name),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::test_support::{args, run};
+
+    #[test]
+    fn test_incr() {
+        let mut keyspace = Keyspace::new();
+        assert_eq!(run(&mut keyspace, &["incr", "n"], 0), Reply::Integer(1));
+        assert_eq!(
+            run(&mut keyspace, &["incrby", "n", "10"], 0),
+            Reply::Integer(11)
+        );
+        assert_eq!(
+            run(&mut keyspace, &["decrby", "n", "20"], 0),
+            Reply::Integer(-9)
+        );
+        assert_eq!(run(&mut keyspace, &["decr", "n"], 0), Reply::Integer(-10));
+        assert_eq!(keyspace.get(b"n", 0), Some(&Value::from(b"-10".to_vec())));
+
+        assert_eq!(
+            run(&mut keyspace, &["incrby", "n", "ten"], 0),
+            Reply::not_an_integer()
+        );
+        keyspace.insert(b"s".to_vec(), b"abc".to_vec());
+        assert_eq!(
+            run(&mut keyspace, &["incr", "s"], 0),
+            Reply::not_an_integer()
+        );
+
+        keyspace.insert(b"max".to_vec(), i64::MAX.to_string().into_bytes());
+        assert!(matches!(
+            run(&mut keyspace, &["incr", "max"], 0),
+            Reply::Error(_)
+        ));
+        assert_eq!(run(&mut keyspace, &["incr"], 0), Reply::wrong_arity("incr"));
+    }
+
+    #[test]
+    fn test_incr_keeps_expiry() {
+        let mut keyspace = Keyspace::new();
+        keyspace.insert(b"n".to_vec(), b"1".to_vec());
+        keyspace.set_expiry(b"n", 100);
+        assert_eq!(run(&mut keyspace, &["incr", "n"], 0), Reply::Integer(2));
+        assert_eq!(keyspace.expires_at(b"n"), Some(100));
+    }
+
+    #[test]
+    fn test_incrbyfloat() {
+        let mut keyspace = Keyspace::new();
+        assert_eq!(
+            run(&mut keyspace, &["incrbyfloat", "f", "10.5"], 0),
+            Reply::Bulk(b"10.5".to_vec())
+        );
+        assert_eq!(
+            run(&mut keyspace, &["incrbyfloat", "f", "-0.5"], 0),
+            Reply::Bulk(b"10".to_vec())
+        );
+        assert_eq!(
+            run(&mut keyspace, &["incrbyfloat", "f", "nan"], 0),
+            Reply::not_a_float()
+        );
+    }
+
+    #[test]
+    fn test_append_and_getset() {
+        let mut keyspace = Keyspace::new();
+        assert_eq!(
+            run(&mut keyspace, &["append", "s", "foo"], 0),
+            Reply::Integer(3)
+        );
+        assert_eq!(
+            run(&mut keyspace, &["append", "s", "bar"], 0),
+            Reply::Integer(6)
+        );
+        assert_eq!(
+            execute_read("get", &keyspace, &args(&["s"]), 0),
+            Reply::Bulk(b"foobar".to_vec())
+        );
+        assert_eq!(
+            execute_read("strlen", &keyspace, &args(&["s"]), 0),
+            Reply::Integer(6)
+        );
+
+        keyspace.set_expiry(b"s", 100);
+        assert_eq!(
+            run(&mut keyspace, &["getset", "s", "new"], 0),
+            Reply::Bulk(b"foobar".to_vec())
+        );
+        assert_eq!(keyspace.expires_at(b"s"), None);
+        assert_eq!(run(&mut keyspace, &["getset", "t", "new"], 0), Reply::Nil);
+
+        keyspace.set_expiry(b"t", 100);
+        assert_eq!(run(&mut keyspace, &["set", "t", "newer"], 0), Reply::ok());
+        assert_eq!(keyspace.expires_at(b"t"), None);
+        assert_eq!(
+            execute_read("get", &keyspace, &args(&["t"]), 0),
+            Reply::Bulk(b"newer".to_vec())
+        );
+    }
+}
@@ -0,0 +1,371 @@
+use super::{clear_expired, first_key, lookup, parse_int, CommandGroup, WriteOutcome};
+use crate::messages::responses::command_response::Reply;
+use crate::storage::keyspace::Keyspace;
+use crate::storage::mutation::Mutation;
+use crate::storage::sorted_set::{Score, SortedSet};
+use crate::storage::value::{resolve_range, Value};
+use std::ops::Bound;
+
+pub const GROUP: CommandGroup = CommandGroup {
+    write_commands: &["zadd", "zincrby", "zrem", "zpopmin"],
+    read_commands: &["zscore", "zrank", "zrange", "zcard"],
+    keys: first_key,
+    execute_write,
+    execute_read,
+};
+
+/// Parses a score, where "inf", "+inf" and "-inf" are allowed but NaN is not
+fn parse_score(arg: &[u8]) -> Option<f64> {
+    std::str::from_utf8(arg)
+        .ok()?
+        .parse::<f64>()
+        .ok()
+        .filter(|score| !score.is_nan())
+}
+
+/// Parses a score bound of ZRANGE BYSCORE, exclusive when prefixed with "("
+fn parse_bound(arg: &[u8]) -> Option<Bound<f64>> {
+    match arg.strip_prefix(b"(") {
+        Some(score) => parse_score(score).map(Bound::Excluded),
+        None => parse_score(arg).map(Bound::Included),
+    }
+}
+
+fn format_score(score: f64) -> Vec<u8> {
+    score.to_string().into_bytes()
+}
+
+fn not_a_score() -> Reply {
+    Reply::error("ERR value is not a valid float")
+}
+
+/// Reply listing members, each followed by its score when `with_scores` is set
+fn members_reply<'a>(
+    members: impl Iterator<Item = (&'a Vec<u8>, f64)>,
+    with_scores: bool,
+) -> Reply {
+    let mut replies = Vec::new();
+    for (member, score) in members {
+        replies.push(Reply::Bulk(member.clone()));
+        if with_scores {
+            replies.push(Reply::Bulk(format_score(score)));
+        }
+    }
+    Reply::Array(replies)
+}
+
+fn set_scores(
+    keyspace: &Keyspace,
+    key: &[u8],
+    members: Vec<(Vec<u8>, Score)>,
+    now: u64,
+    reply: Reply,
+) -> WriteOutcome {
+    let mut mutations: Vec<Mutation> = clear_expired(keyspace, key, now).into_iter().collect();
+    mutations.push(Mutation::SetScores {
+        key: key.to_vec(),
+        members,
+    });
+    WriteOutcome { reply, mutations }
+}
+
+fn execute_write(name: &str, keyspace: &Keyspace, args: &[Vec<u8>], now: u64) -> WriteOutcome {
+    let set = match args
+        .first()
+        .map(|key| lookup(keyspace, key, now, Value::as_sorted_set))
+    {
+        Some(Ok(set)) => set,
+        Some(Err(reply)) => return WriteOutcome::reply(reply),
+        None => return WriteOutcome::reply(Reply::wrong_arity(name)),
+    };
+    match (name, args) {
+        ("zadd", [key, pairs @ ..]) if !pairs.is_empty() && pairs.len() % 2 == 0 => {
+            let mut members = Vec::new();
+            for pair in pairs.chunks(2) {
+                let Some(score) = parse_score(&pair[0]) else {
+                    return WriteOutcome::reply(not_a_score());
+                };
+                members.push((pair[1].clone(), Score(score)));
+            }
+            let mut added: Vec<&Vec<u8>> = members
+                .iter()
+                .map(|(member, _)| member)
+                .filter(|member| set.is_none_or(|set| set.score(member).is_none()))
+                .collect();
+            added.sort();
+            added.dedup();
+            let reply = Reply::Integer(added.len() as i64);
+            set_scores(keyspace, key, members, now, reply)
+        }
+        ("zincrby", [key, delta, member]) => {
+            let Some(delta) = parse_score(delta) else {
+                return WriteOutcome::reply(not_a_score());
+            };
+            let score = set.and_then(|set| set.score(member)).unwrap_or(0.0) + delta;
+            if score.is_nan() {
+                return WriteOutcome::reply(Reply::error(
+                    "ERR resulting score is not a number (NaN)",
+                ));
+            }
+            let members = vec![(member.clone(), Score(score))];
+            set_scores(
+                keyspace,
+                key,
+                members,
+                now,
+                Reply::Bulk(format_score(score)),
+            )
+        }
+        ("zrem", [key, members @ ..]) if !members.is_empty() => {
+            let mut removed: Vec<Vec<u8>> = members
+                .iter()
+                .filter(|member| set.is_some_and(|set| set.score(member).is_some()))
+                .cloned()
+                .collect();
+            removed.sort();
+            removed.dedup();
+            if removed.is_empty() {
+                return WriteOutcome::reply(Reply::Integer(0));
+            }
+            let reply = Reply::Integer(removed.len() as i64);
+            let mutation = Mutation::RemoveMembers {
+                key: key.clone(),
+                members: removed,
+            };
+            WriteOutcome::mutation(reply, mutation)
+        }
+        ("zpopmin", [key, count @ ..]) if count.len() <= 1 => {
+            let count = match count.first().map(|count| parse_int(count)) {
+                None => 1,
+                Some(Some(count)) if count >= 0 => count as usize,
+                Some(_) => {
+                    return WriteOutcome::reply(Reply::error(
+                        "ERR value is out of range, must be positive",
+                    ))
+                }
+            };
+            let Some(set) = set else {
+                return WriteOutcome::reply(Reply::Array(Vec::new()));
+            };
+            let popped: Vec<(&Vec<u8>, f64)> = set.iter().take(count).collect();
+            let reply = members_reply(popped.iter().copied(), true);
+            if popped.is_empty() {
+                return WriteOutcome::reply(reply);
+            }
+            let mutation = Mutation::RemoveMembers {
+                key: key.clone(),
+                members: popped
+                    .into_iter()
+                    .map(|(member, _)| member.clone())
+                    .collect(),
+            };
+            WriteOutcome::mutation(reply, mutation)
+        }
+        _ => WriteOutcome::reply(Reply::wrong_arity(name)),
+    }
+}
+
+/// ZRANGE key start stop [BYSCORE] [LIMIT offset count] [WITHSCORES]
+fn zrange(set: Option<&SortedSet>, args: &[Vec<u8>]) -> Reply {
+    let [start, stop, options @ ..] = args else {
+        return Reply::wrong_arity("zrange");
+    };
+    let mut by_score = false;
+    let mut with_scores = false;
+    let mut limit: Option<(usize, Option<usize>)> = None;
+    let mut options = options.iter();
+    while let Some(option) = options.next() {
+        match option.to_ascii_lowercase().as_slice() {
+            b"byscore" => by_score = true,
+            b"withscores" => with_scores = true,
+            b"limit" => {
+                let (Some(offset), Some(count)) = (
+                    options.next().and_then(|arg| parse_int(arg)),
+                    options.next().and_then(|arg| parse_int(arg)),
+                ) else {
+                    return Reply::error("ERR syntax error");
+                };
+                // a negative offset selects nothing and a negative count everything
+                let offset = usize::try_from(offset).unwrap_or(usize::MAX);
+                limit = Some((offset, usize::try_from(count).ok()));
+            }
+            _ => return Reply::error("ERR syntax error"),
+        }
+    }
+    if limit.is_some() && !by_score {
+        return Reply::error(
+            "ERR syntax error, LIMIT is only supported in combination with BYSCORE",
+        );
+    }
+    let Some(set) = set else {
+        return Reply::Array(Vec::new());
+    };
+    if by_score {
+        let (Some(min), Some(max)) = (parse_bound(start), parse_bound(stop)) else {
+            return Reply::error("ERR min or max is not a float");
+        };
+        let (offset, count) = limit.unwrap_or((0, None));
+        let members = set
+            .range_by_score(min, max)
+            .skip(offset)
+            .take(count.unwrap_or(usize::MAX));
+        return members_reply(members, with_scores);
+    }
+    let (Some(start), Some(stop)) = (parse_int(start), parse_int(stop)) else {
+        return Reply::not_an_integer();
+    };
+    match resolve_range(start, stop, set.len()) {
+        Some(range) => members_reply(set.range_by_rank(range), with_scores),
+        None => Reply::Array(Vec::new()),
+    }
+}
+
+fn execute_read(name: &str, keyspace: &Keyspace, args: &[Vec<u8>], now: u64) -> Reply {
+    let set = match args
+        .first()
+        .map(|key| lookup(keyspace, key, now, Value::as_sorted_set))
+    {
+        Some(Ok(set)) => set,
+        Some(Err(reply)) => return reply,
+        None => return Reply::wrong_arity(name),
+    };
+    match (name, args) {
+        ("zscore", [_, member]) => match set.and_then(|set| set.score(member)) {
+            Some(score) => Reply::Bulk(format_score(score)),
+            None => Reply::Nil,
+        },
+        ("zrank", [_, member]) => match set.and_then(|set| set.rank(member)) {
+            Some(rank) => Reply::Integer(rank as i64),
+            None => Reply::Nil,
+        },
+        ("zrange", [_, rest @ ..]) => zrange(set, rest),
+        ("zcard", [_]) => Reply::Integer(set.map_or(0, |set| set.len() as i64)),
+        _ => Reply::wrong_arity(name),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::test_support::{bulks, run};
+
+    fn leaderboard() -> Keyspace {
+        let mut keyspace = Keyspace::new();
+        let reply = run(
+            &mut keyspace,
+            &[
+                "zadd", "board", "30", "carol", "10", "alice", "20", "bob", "20", "dave",
+            ],
+            0,
+        );
+        assert_eq!(reply, Reply::Integer(4));
+        keyspace
+    }
+
+    #[test]
+    fn test_scores_and_ranks() {
+        let mut keyspace = leaderboard();
+        assert_eq!(
+            run(&mut keyspace, &["zadd", "board", "5", "bob"], 0),
+            Reply::Integer(0)
+        );
+        assert_eq!(
+            run(&mut keyspace, &["zincrby", "board", "2.5", "bob"], 0),
+            Reply::Bulk(b"7.5".to_vec())
+        );
+        assert_eq!(
+            run(&mut keyspace, &["zscore", "board", "bob"], 0),
+            Reply::Bulk(b"7.5".to_vec())
+        );
+        assert_eq!(
+            run(&mut keyspace, &["zrank", "board", "bob"], 0),
+            Reply::Integer(0)
+        );
+        assert_eq!(
+            run(&mut keyspace, &["zrank", "board", "carol"], 0),
+            Reply::Integer(3)
+        );
+        assert_eq!(
+            run(&mut keyspace, &["zrank", "board", "nobody"], 0),
+            Reply::Nil
+        );
+        assert_eq!(
+            run(&mut keyspace, &["zadd", "board", "nan", "x"], 0),
+            not_a_score()
+        );
+        assert_eq!(
+            run(&mut keyspace, &["zrem", "board", "bob", "nobody"], 0),
+            Reply::Integer(1)
+        );
+        assert_eq!(
+            run(&mut keyspace, &["zcard", "board"], 0),
+            Reply::Integer(3)
+        );
+    }
+
+    #[test]
+    fn test_zrange() {
+        let mut keyspace = leaderboard();
+        assert_eq!(
+            run(&mut keyspace, &["zrange", "board", "0", "-1"], 0),
+            bulks(&["alice", "bob", "dave", "carol"])
+        );
+        assert_eq!(
+            run(
+                &mut keyspace,
+                &["zrange", "board", "-2", "-1", "WITHSCORES"],
+                0
+            ),
+            bulks(&["dave", "20", "carol", "30"])
+        );
+        assert_eq!(
+            run(
+                &mut keyspace,
+                &["zrange", "board", "(10", "+inf", "BYSCORE"],
+                0
+            ),
+            bulks(&["bob", "dave", "carol"])
+        );
+        assert_eq!(
+            run(
+                &mut keyspace,
+                &["zrange", "board", "-inf", "20", "BYSCORE", "LIMIT", "1", "1"],
+                0
+            ),
+            bulks(&["bob"])
+        );
+        assert!(matches!(
+            run(
+                &mut keyspace,
+                &["zrange", "board", "0", "1", "LIMIT", "0", "1"],
+                0
+            ),
+            Reply::Error(_)
+        ));
+        assert_eq!(
+            run(&mut keyspace, &["zrange", "missing", "0", "-1"], 0),
+            bulks(&[])
+        );
+    }
+
+    #[test]
+    fn test_zpopmin() {
+        let mut keyspace = leaderboard();
+        assert_eq!(
+            run(&mut keyspace, &["zpopmin", "board"], 0),
+            bulks(&["alice", "10"])
+        );
+        assert_eq!(
+            run(&mut keyspace, &["zpopmin", "board", "5"], 0),
+            bulks(&["bob", "20", "dave", "20", "carol", "30"])
+        );
+        assert!(keyspace.is_empty());
+        assert_eq!(run(&mut keyspace, &["zpopmin", "board"], 0), bulks(&[]));
+
+        keyspace.insert(b"s".to_vec(), b"value".to_vec());
+        assert_eq!(
+            run(&mut keyspace, &["zadd", "s", "1", "a"], 0),
+            Reply::wrong_type()
+        );
+    }
+}
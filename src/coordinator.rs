@@ -0,0 +1,215 @@
+//! Coordinator for transactions whose keys live on several write shards. It
+//! runs two-phase commit: every shard involved prepares its part and votes,
+//! and only when all of them vote to commit is the transaction committed,
+//! on the primary first. The primary's commit is the point of no return, so
+//! shards the coordinator never gets back to ask the primary how it ended.
+
+use anyhow::Result;
+use rand::Rng;
+use std::collections::HashMap;
+use std::net::SocketAddrV6;
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc;
+use tokio::time::{timeout, Duration};
+
+use crate::io::router::{RouterClient, RouterHandler};
+use crate::messages::requests::transaction_request::TransactionRequest;
+use crate::messages::requests::two_phase_request::{Phase, TwoPhaseRequest};
+use crate::messages::responses::command_response::Reply;
+use crate::messages::responses::two_phase_response::{TwoPhaseOutcome, TwoPhaseResponse};
+
+/// How long the coordinator waits for a shard to answer
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// The part of a transaction one write shard owns
+pub struct Participant {
+    pub shard: SocketAddrV6,
+    pub transaction: TransactionRequest,
+    /// Where each of the shard's commands sits in the whole transaction
+    pub positions: Vec<usize>,
+}
+
+/// Routes the responses to two-phase requests to the coordinator waiting on
+/// them. The router handler calls `deliver` for every response it gets.
+#[derive(Debug, Default, Clone)]
+pub struct PendingTransactions {
+    senders: Arc<Mutex<HashMap<u128, mpsc::UnboundedSender<TwoPhaseResponse>>>>,
+}
+
+impl PendingTransactions {
+    fn register(&self, txn_id: u128) -> mpsc::UnboundedReceiver<TwoPhaseResponse> {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        self.senders.lock().unwrap().insert(txn_id, sender);
+        receiver
+    }
+
+    fn unregister(&self, txn_id: u128) {
+        self.senders.lock().unwrap().remove(&txn_id);
+    }
+
+    /// Hands a response to its transaction's coordinator, dropping it if the
+    /// coordinator stopped waiting
+    pub fn deliver(&self, res: &TwoPhaseResponse) {
+        if let Some(sender) = self.senders.lock().unwrap().get(&res.txn_id) {
+            let _ = sender.send(res.clone());
+        }
+    }
+}
+
+/// Sends one request and waits for its response. The coordinator only ever
+/// has one request of a transaction outstanding, so the next response on the
+/// channel is the answer to it.
+async fn request<H: RouterHandler>(
+    router_client: &RouterClient<H>,
+    responses: &mut mpsc::UnboundedReceiver<TwoPhaseResponse>,
+    request: TwoPhaseRequest,
+    shard: SocketAddrV6,
+) -> Result<TwoPhaseResponse> {
+    router_client
+        .queue_request::<TwoPhaseRequest>(request, shard)
+        .await?;
+    match timeout(RESPONSE_TIMEOUT, responses.recv()).await {
+        Ok(Some(res)) => Ok(res),
+        _ => anyhow::bail!("{} did not answer in time", shard),
+    }
+}
+
+/// Runs a transaction across the shards of `participants` and returns the
+/// reply to EXEC: the reply of every command in order, nil if a watched key
+/// changed, or an error if the transaction was aborted. The first participant
+/// is the primary.
+pub async fn execute<H: RouterHandler>(
+    router_client: &RouterClient<H>,
+    pending: &PendingTransactions,
+    participants: Vec<Participant>,
+) -> Reply {
+    let txn_id: u128 = rand::thread_rng().gen();
+    let mut responses = pending.register(txn_id);
+    let reply = run(router_client, &mut responses, txn_id, participants).await;
+    pending.unregister(txn_id);
+    reply
+}
+
+async fn run<H: RouterHandler>(
+    router_client: &RouterClient<H>,
+    responses: &mut mpsc::UnboundedReceiver<TwoPhaseResponse>,
+    txn_id: u128,
+    participants: Vec<Participant>,
+) -> Reply {
+    let primary = participants[0].shard;
+    let command_count: usize = participants.iter().map(|p| p.positions.len()).sum();
+    let mut shards = Vec::new();
+
+    // phase one, every shard checks its part and locks its keys
+    let mut vote = Ok(());
+    for (i, participant) in participants.into_iter().enumerate() {
+        let (primary_ip, primary_port) = match i {
+            0 => (0, 0),
+            _ => (primary.ip().to_bits(), primary.port()),
+        };
+        shards.push((
+            participant.shard,
+            primary_ip,
+            primary_port,
+            participant.positions,
+        ));
+        let prepare = TwoPhaseRequest {
+            txn_id,
+            phase: Phase::Prepare as u8,
+            primary_ip,
+            primary_port,
+            transaction: participant.transaction,
+        };
+        vote = match request(router_client, responses, prepare, participant.shard).await {
+            Ok(res) if res.outcome == TwoPhaseOutcome::Prepared as u8 => Ok(()),
+            Ok(res) => Err(match res.reply {
+                Reply::Error(message) => Reply::Error(message),
+                // a watched key changed
+                _ => Reply::Nil,
+            }),
+            Err(e) => Err(Reply::error(format!("ERR {}", e))),
+        };
+        if vote.is_err() {
+            break;
+        }
+    }
+
+    // phase two, the primary's commit decides the outcome
+    let decision = match vote {
+        Ok(()) => {
+            let commit = TwoPhaseRequest::new(txn_id, Phase::Commit, 0, 0);
+            match request(router_client, responses, commit, primary).await {
+                Ok(res) if res.outcome == TwoPhaseOutcome::Committed as u8 => Ok(res.reply),
+                Ok(res) => Err(match res.reply {
+                    Reply::Error(message) => Reply::Error(message),
+                    _ => Reply::error("ERR transaction aborted by the primary"),
+                }),
+                // the other shards find out from the primary once it answers them
+                Err(e) => {
+                    return Reply::error(format!(
+                        "ERR outcome unknown, {}, the shards will settle it",
+                        e
+                    ))
+                }
+            }
+        }
+        Err(reply) => Err(reply),
+    };
+
+    let primary_reply = match decision {
+        Ok(reply) => reply,
+        Err(reply) => {
+            for (shard, primary_ip, primary_port, _) in shards {
+                let abort = TwoPhaseRequest::new(txn_id, Phase::Abort, primary_ip, primary_port);
+                if let Err(e) = router_client
+                    .queue_request::<TwoPhaseRequest>(abort, shard)
+                    .await
+                {
+                    eprintln!("Failed to queue abort for {}: {}", shard, e);
+                }
+            }
+            return reply;
+        }
+    };
+
+    let mut replies = vec![Reply::Nil; command_count];
+    let mut place = |positions: &[usize], reply: Reply| match reply {
+        Reply::Array(shard_replies) => {
+            for (&position, reply) in positions.iter().zip(shard_replies) {
+                replies[position] = reply;
+            }
+        }
+        reply => {
+            for &position in positions {
+                replies[position] = reply.clone();
+            }
+        }
+    };
+    place(&shards[0].3, primary_reply);
+
+    // once a shard misses its answer, a late one could be mistaken for the
+    // next shard's, so the rest are committed without waiting
+    let mut answering = true;
+    for (shard, primary_ip, primary_port, positions) in &shards[1..] {
+        let commit = TwoPhaseRequest::new(txn_id, Phase::Commit, *primary_ip, *primary_port);
+        let res = if answering {
+            request(router_client, responses, commit, *shard).await
+        } else {
+            router_client
+                .queue_request::<TwoPhaseRequest>(commit, *shard)
+                .await
+                .and_then(|_| anyhow::bail!("{} was not waited for", shard))
+        };
+        match res {
+            Ok(res) => place(positions, res.reply),
+            Err(e) => {
+                answering = false;
+                place(
+                    positions,
+                    Reply::error(format!("ERR committed, but the reply was lost: {}", e)),
+                );
+            }
+        }
+    }
+    Reply::Array(replies)
+}
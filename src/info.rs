@@ -3,22 +3,42 @@ pub mod io;
 pub mod messages;
 pub mod utils;
 
-use crate::io::router::{RouterBuilder, RouterHandler};
+use crate::io::router::{HandlerResponse, RouterBuilder, RouterHandler};
 
 use clap::Parser;
 use messages::requests::announce_shard_request::{AnnounceShardRequest, ShardType};
+use messages::requests::bg_save_request::BgSaveRequest;
+use messages::requests::command_request::CommandRequest;
+use messages::requests::delete_request::DeleteRequest;
+use messages::requests::exists_request::ExistsRequest;
+use messages::requests::expire_request::ExpireRequest;
 use messages::requests::get_client_shard_info_request::GetClientShardInfoRequest;
 use messages::requests::get_shared_peers_request::GetSharedPeersRequest;
 use messages::requests::get_version_request::GetVersionRequest;
 use messages::requests::query_version_request::QueryVersionRequest;
+use messages::requests::replica_ack_request::ReplicaAckRequest;
+use messages::requests::sync_request::SyncRequest;
+use messages::requests::transaction_request::TransactionRequest;
+use messages::requests::ttl_request::TtlRequest;
+use messages::requests::two_phase_request::TwoPhaseRequest;
 
 use messages::requests::read_request::ReadRequest;
 use messages::requests::write_request::WriteRequest;
 
 use messages::responses::announce_shard_response::AnnounceShardResponse;
+use messages::responses::bg_save_response::BgSaveResponse;
+use messages::responses::command_response::CommandResponse;
+use messages::responses::delete_response::DeleteResponse;
+use messages::responses::exists_response::ExistsResponse;
+use messages::responses::expire_response::ExpireResponse;
 use messages::responses::get_client_shard_info_response::GetClientShardInfoResponse;
 use messages::responses::get_shared_peers_response::GetSharedPeersResponse;
 use messages::responses::get_version_response::GetVersionResponse;
+use messages::responses::replica_ack_response::ReplicaAckResponse;
+use messages::responses::sync_response::SyncResponse;
+use messages::responses::transaction_response::TransactionResponse;
+use messages::responses::ttl_response::TtlResponse;
+use messages::responses::two_phase_response::TwoPhaseResponse;
 
 use messages::responses::query_version_response::QueryVersionResponse;
 use messages::responses::read_response::ReadResponse;
@@ -215,15 +235,33 @@ impl RouterHandler for InfoRouter {
     fn handle_query_version_request(&self, _req: &QueryVersionRequest) -> QueryVersionResponse {
         unimplemented!()
     }
-    fn handle_read_request(&self, _req: &ReadRequest) -> ReadResponse {
+    fn handle_read_request(&self, _req: &ReadRequest) -> HandlerResponse<ReadResponse> {
         unimplemented!()
     }
-    fn handle_write_request(&self, _req: &WriteRequest) -> WriteResponse {
+    fn handle_write_request(&self, _req: &WriteRequest) -> HandlerResponse<WriteResponse> {
         unimplemented!()
     }
     fn handle_get_version_request(&self, _req: &GetVersionRequest) -> GetVersionResponse {
         unimplemented!();
     }
+    fn handle_bg_save_request(&self, _req: &BgSaveRequest) -> BgSaveResponse {
+        unimplemented!()
+    }
+    fn handle_delete_request(&self, _req: &DeleteRequest) -> DeleteResponse {
+        unimplemented!()
+    }
+    fn handle_exists_request(&self, _req: &ExistsRequest) -> ExistsResponse {
+        unimplemented!()
+    }
+    fn handle_expire_request(&self, _req: &ExpireRequest) -> ExpireResponse {
+        unimplemented!()
+    }
+    fn handle_ttl_request(&self, _req: &TtlRequest) -> TtlResponse {
+        unimplemented!()
+    }
+    fn handle_command_request(&self, _req: &CommandRequest) -> HandlerResponse<CommandResponse> {
+        unimplemented!()
+    }
 
     // Unused responses
     fn handle_announce_shard_response(&self, _res: &AnnounceShardResponse) {
@@ -245,6 +283,56 @@ impl RouterHandler for InfoRouter {
     fn handle_get_version_response(&self, _res: &GetVersionResponse) {
         unimplemented!()
     }
+    fn handle_bg_save_response(&self, _res: &BgSaveResponse) {
+        unimplemented!()
+    }
+    fn handle_delete_response(&self, _res: &DeleteResponse) {
+        unimplemented!()
+    }
+    fn handle_exists_response(&self, _res: &ExistsResponse) {
+        unimplemented!()
+    }
+    fn handle_expire_response(&self, _res: &ExpireResponse) {
+        unimplemented!()
+    }
+    fn handle_ttl_response(&self, _res: &TtlResponse) {
+        unimplemented!()
+    }
+    fn handle_command_response(&self, _res: &CommandResponse) {
+        unimplemented!()
+    }
+
+    fn handle_transaction_request(&self, _req: &TransactionRequest) -> TransactionResponse {
+        unimplemented!()
+    }
+
+    fn handle_transaction_response(&self, _res: &TransactionResponse) {
+        unimplemented!()
+    }
+
+    fn handle_two_phase_request(&self, _req: &TwoPhaseRequest) -> TwoPhaseResponse {
+        unimplemented!()
+    }
+
+    fn handle_two_phase_response(&self, _res: &TwoPhaseResponse) {
+        unimplemented!()
+    }
+
+    fn handle_replica_ack_request(&self, _req: &ReplicaAckRequest) -> ReplicaAckResponse {
+        unimplemented!()
+    }
+
+    fn handle_replica_ack_response(&self, _res: &ReplicaAckResponse) {
+        unimplemented!()
+    }
+
+    fn handle_sync_request(&self, _req: &SyncRequest) -> SyncResponse {
+        unimplemented!()
+    }
+
+    fn handle_sync_response(&self, _res: &SyncResponse) {
+        unimplemented!()
+    }
 }
 
 #[derive(Parser, Debug)]
@@ -371,51 +459,53 @@ mod tests {
         tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
 
         // assert responses
-        let client_shard_info_responses = test_router_client
-            .get_client_shard_info_responses
-            .lock()
-            .unwrap();
-        let shared_peers_responses = test_router_client
-            .get_shared_peers_responses
-            .lock()
-            .unwrap();
-
-        assert_eq!(shared_peers_responses.len(), write_shards as usize);
-        for i in 0..(write_shards as usize) {
-            assert_eq!(shared_peers_responses[i].peer_ips[0].0, i as u128);
-            assert_eq!(shared_peers_responses[i].peer_ips[0].1, i as u16);
-            for j in 0..(read_shards as usize) {
-                assert!(shared_peers_responses[i].peer_ips[1 + j].0 >= 100 as u128);
-                assert!(shared_peers_responses[i].peer_ips[1 + j].1 >= 100 as u16);
+        {
+            let client_shard_info_responses = test_router_client
+                .get_client_shard_info_responses
+                .lock()
+                .unwrap();
+            let shared_peers_responses = test_router_client
+                .get_shared_peers_responses
+                .lock()
+                .unwrap();
+
+            assert_eq!(shared_peers_responses.len(), write_shards as usize);
+            for i in 0..(write_shards as usize) {
+                assert_eq!(shared_peers_responses[i].peer_ips[0].0, i as u128);
+                assert_eq!(shared_peers_responses[i].peer_ips[0].1, i as u16);
+                for j in 0..(read_shards as usize) {
+                    assert!(shared_peers_responses[i].peer_ips[1 + j].0 >= 100u128);
+                    assert!(shared_peers_responses[i].peer_ips[1 + j].1 >= 100u16);
+                }
             }
-        }
 
-        assert_eq!(
-            client_shard_info_responses.len(),
-            num_get_client_shard_info_requests as usize
-        );
-        assert_eq!(
-            client_shard_info_responses[0].num_write_shards,
-            write_shards as u16
-        );
-        assert_eq!(
-            client_shard_info_responses[0].write_shard_info.len(),
-            write_shards as usize
-        );
-        for i in 0..(write_shards as usize) {
             assert_eq!(
-                client_shard_info_responses[0].write_shard_info[i].0,
-                i as u128
+                client_shard_info_responses.len(),
+                num_get_client_shard_info_requests as usize
+            );
+            assert_eq!(
+                client_shard_info_responses[0].num_write_shards,
+                write_shards as u16
             );
             assert_eq!(
-                client_shard_info_responses[0].write_shard_info[i].1,
-                i as u16
+                client_shard_info_responses[0].write_shard_info.len(),
+                write_shards as usize
+            );
+            for i in 0..(write_shards as usize) {
+                assert_eq!(
+                    client_shard_info_responses[0].write_shard_info[i].0,
+                    i as u128
+                );
+                assert_eq!(
+                    client_shard_info_responses[0].write_shard_info[i].1,
+                    i as u16
+                );
+            }
+            assert_eq!(
+                client_shard_info_responses[0].read_shard_info.len(),
+                write_shards as usize
             );
         }
-        assert_eq!(
-            client_shard_info_responses[0].read_shard_info.len(),
-            write_shards as usize
-        );
 
         crate::integration::test_setup::test_teardown().await;
     }
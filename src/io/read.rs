@@ -3,10 +3,12 @@ use tokio::{io::AsyncReadExt, net::tcp::OwnedReadHalf};
 
 use crate::messages::message::{bytes_as_message, MessagePayload};
 
-pub async fn read_message(stream: &mut OwnedReadHalf) -> Result<Box<dyn MessagePayload>> {
-    let mut buffer = [0; 4096];
-    let mut buffer_idx = 0;
+/// totlen, msgtype and is_req
+const HEADER_LENGTH: usize = 6;
+/// Upper bound on a single message, so a corrupt length can't exhaust memory
+const MAX_MESSAGE_LENGTH: usize = 512 * 1024 * 1024;
 
+pub async fn read_message(stream: &mut OwnedReadHalf) -> Result<Box<dyn MessagePayload>> {
     // read the total length and then proceed with the rest once the message size is known
     let total_length = match stream.read_u32_le().await {
         Ok(n) => n,
@@ -14,41 +16,26 @@ pub async fn read_message(stream: &mut OwnedReadHalf) -> Result<Box<dyn MessageP
             return Err(anyhow::anyhow!("failed to read total length: {}", e));
         }
     };
-
-    buffer[0..4].copy_from_slice(&total_length.to_le_bytes());
-    buffer_idx += 4;
     let total_length = total_length as usize;
+    if !(HEADER_LENGTH..=MAX_MESSAGE_LENGTH).contains(&total_length) {
+        return Err(anyhow::anyhow!("invalid message length {}", total_length));
+    }
 
     //println!("reading message length: {total_length}");
 
+    // messages can carry arbitrarily large values, so size the buffer to fit
+    let mut buffer = vec![0; total_length];
+    buffer[0..4].copy_from_slice(&(total_length as u32).to_le_bytes());
+
     // read the rest of the message
-    while let Ok(bytes_read) = stream.read(&mut buffer[buffer_idx..total_length]).await {
-        match bytes_read {
-            0 => {
-                return Err(anyhow::anyhow!("connection closed"));
-            }
-            _ => {
-                match (buffer_idx + bytes_read).cmp(&total_length) {
-                    std::cmp::Ordering::Greater => {
-                        return Err(anyhow::anyhow!("invalid message length"));
-                    }
-                    std::cmp::Ordering::Equal => {
-                        // all good
-                        break;
-                    }
-                    std::cmp::Ordering::Less => {
-                        // keep reading
-                        buffer_idx += bytes_read;
-                    }
-                }
-            }
-        }
+    if let Err(e) = stream.read_exact(&mut buffer[4..]).await {
+        return Err(anyhow::anyhow!("connection closed: {}", e));
     }
 
     //println!("finished reading message length: {total_length}");
 
     // deserialize the message
-    let message = bytes_as_message(&buffer[0..total_length])?;
+    let message = bytes_as_message(&buffer)?;
 
     //println!("finished deserializing message length: {total_length}");
     Ok(message)
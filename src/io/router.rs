@@ -5,16 +5,25 @@ use crate::messages::responses::get_shared_peers_response::GetSharedPeersRespons
 use crate::messages::{
     message::{MessagePayload, MessageType},
     requests::{
-        announce_shard_request::AnnounceShardRequest,
+        announce_shard_request::AnnounceShardRequest, bg_save_request::BgSaveRequest,
+        command_request::CommandRequest, delete_request::DeleteRequest,
+        exists_request::ExistsRequest, expire_request::ExpireRequest,
         get_client_shard_info_request::GetClientShardInfoRequest,
         get_version_request::GetVersionRequest, query_version_request::QueryVersionRequest,
-        read_request::ReadRequest, write_request::WriteRequest,
+        read_request::ReadRequest, replica_ack_request::ReplicaAckRequest,
+        sync_request::SyncRequest, transaction_request::TransactionRequest,
+        ttl_request::TtlRequest, two_phase_request::TwoPhaseRequest, write_request::WriteRequest,
     },
     responses::{
-        announce_shard_response::AnnounceShardResponse,
+        announce_shard_response::AnnounceShardResponse, bg_save_response::BgSaveResponse,
+        command_response::CommandResponse, delete_response::DeleteResponse,
+        exists_response::ExistsResponse, expire_response::ExpireResponse,
         get_client_shard_info_response::GetClientShardInfoResponse,
         get_version_response::GetVersionResponse, query_version_response::QueryVersionResponse,
-        read_response::ReadResponse, write_response::WriteResponse,
+        read_response::ReadResponse, replica_ack_response::ReplicaAckResponse,
+        sync_response::SyncResponse, transaction_response::TransactionResponse,
+        ttl_response::TtlResponse, two_phase_response::TwoPhaseResponse,
+        write_response::WriteResponse,
     },
 };
 use anyhow::{Ok, Result};
@@ -24,9 +33,27 @@ use std::net::SocketAddr::{V4, V6};
 use std::net::{Ipv6Addr, SocketAddrV6};
 use std::sync::Arc;
 use tokio::net::{tcp::OwnedReadHalf, TcpListener, TcpStream};
+use tokio::sync::oneshot;
 
 use super::read::read_message;
 
+/// Response a handler gives to a request, either right away or later on, as
+/// a blocking command does once there is data for it. The router sends a
+/// deferred response whenever it arrives, without holding up the requests
+/// that come after it on the same connection.
+pub enum HandlerResponse<T> {
+    Ready(T),
+    /// Dropping the sender drops the request without responding
+    #[allow(unused)]
+    Deferred(oneshot::Receiver<T>),
+}
+
+impl<T> From<T> for HandlerResponse<T> {
+    fn from(res: T) -> Self {
+        HandlerResponse::Ready(res)
+    }
+}
+
 /// Trait for handling callbacks to requests/responses from peers
 pub trait RouterHandler: Send + Sync + 'static {
     /// Callback for handling new requests
@@ -39,9 +66,9 @@ pub trait RouterHandler: Send + Sync + 'static {
 
     fn handle_query_version_request(&self, req: &QueryVersionRequest) -> QueryVersionResponse;
 
-    fn handle_read_request(&self, req: &ReadRequest) -> ReadResponse;
+    fn handle_read_request(&self, req: &ReadRequest) -> HandlerResponse<ReadResponse>;
 
-    fn handle_write_request(&self, req: &WriteRequest) -> WriteResponse;
+    fn handle_write_request(&self, req: &WriteRequest) -> HandlerResponse<WriteResponse>;
 
     fn handle_get_shared_peers_request(
         &self,
@@ -50,6 +77,26 @@ pub trait RouterHandler: Send + Sync + 'static {
 
     fn handle_get_version_request(&self, req: &GetVersionRequest) -> GetVersionResponse;
 
+    fn handle_bg_save_request(&self, req: &BgSaveRequest) -> BgSaveResponse;
+
+    fn handle_delete_request(&self, req: &DeleteRequest) -> DeleteResponse;
+
+    fn handle_exists_request(&self, req: &ExistsRequest) -> ExistsResponse;
+
+    fn handle_expire_request(&self, req: &ExpireRequest) -> ExpireResponse;
+
+    fn handle_ttl_request(&self, req: &TtlRequest) -> TtlResponse;
+
+    fn handle_command_request(&self, req: &CommandRequest) -> HandlerResponse<CommandResponse>;
+
+    fn handle_transaction_request(&self, req: &TransactionRequest) -> TransactionResponse;
+
+    fn handle_two_phase_request(&self, req: &TwoPhaseRequest) -> TwoPhaseResponse;
+
+    fn handle_replica_ack_request(&self, req: &ReplicaAckRequest) -> ReplicaAckResponse;
+
+    fn handle_sync_request(&self, req: &SyncRequest) -> SyncResponse;
+
     /// Callbacks for handling responses to outbound requests
     fn handle_announce_shard_response(&self, res: &AnnounceShardResponse);
 
@@ -64,6 +111,26 @@ pub trait RouterHandler: Send + Sync + 'static {
     fn handle_write_response(&self, res: &WriteResponse);
 
     fn handle_get_shared_peers_response(&self, res: &GetSharedPeersResponse);
+
+    fn handle_bg_save_response(&self, res: &BgSaveResponse);
+
+    fn handle_delete_response(&self, res: &DeleteResponse);
+
+    fn handle_exists_response(&self, res: &ExistsResponse);
+
+    fn handle_expire_response(&self, res: &ExpireResponse);
+
+    fn handle_ttl_response(&self, res: &TtlResponse);
+
+    fn handle_command_response(&self, res: &CommandResponse);
+
+    fn handle_transaction_response(&self, res: &TransactionResponse);
+
+    fn handle_two_phase_response(&self, res: &TwoPhaseResponse);
+
+    fn handle_replica_ack_response(&self, res: &ReplicaAckResponse);
+
+    fn handle_sync_response(&self, res: &SyncResponse);
 }
 
 pub struct RouterBuilder<H: RouterHandler> {
@@ -155,6 +222,30 @@ impl<H: RouterHandler> RouterBuilder<H> {
         Ok(())
     }
 
+    /// Queues a handler's response, waiting for it in the background when
+    /// the handler deferred it
+    async fn queue_handler_response<M: MessagePayload>(
+        write_sockets: Arc<HashMap<SocketAddrV6, tokio::net::tcp::OwnedWriteHalf>>,
+        handler: Arc<H>,
+        res: HandlerResponse<M>,
+        peer: SocketAddrV6,
+    ) -> Result<()> {
+        match res {
+            HandlerResponse::Ready(res) => {
+                Self::queue_response::<M>(write_sockets, handler, res, peer).await
+            }
+            HandlerResponse::Deferred(receiver) => {
+                tokio::spawn(async move {
+                    if let std::result::Result::Ok(res) = receiver.await {
+                        Self::queue_response::<M>(write_sockets, handler, res, peer).await?;
+                    }
+                    Ok(())
+                });
+                Ok(())
+            }
+        }
+    }
+
     /// Creates a write socket for a peer if it doesn't exist
     /// I don't understand the async recursion problem but something to do with how async builds state machines
     /// https://www.reddit.com/r/rust/comments/kbu6bs/async_recursive_function_in_rust_using_futures/
@@ -252,7 +343,7 @@ impl<H: RouterHandler> RouterBuilder<H> {
                                         .as_any()
                                         .downcast_ref::<ReadRequest>()
                                         .unwrap();
-                                    Self::queue_response::<ReadResponse>(
+                                    Self::queue_handler_response::<ReadResponse>(
                                         write_sockets.clone(),
                                         handler.clone(),
                                         handler.handle_read_request(req),
@@ -266,7 +357,7 @@ impl<H: RouterHandler> RouterBuilder<H> {
                                         .as_any()
                                         .downcast_ref::<WriteRequest>()
                                         .unwrap();
-                                    Self::queue_response::<WriteResponse>(
+                                    Self::queue_handler_response::<WriteResponse>(
                                         write_sockets.clone(),
                                         handler.clone(),
                                         handler.handle_write_request(req),
@@ -302,6 +393,146 @@ impl<H: RouterHandler> RouterBuilder<H> {
                                     )
                                     .await?;
                                 }
+                                MessageType::BgSave => {
+                                    let req = message
+                                        .as_ref()
+                                        .as_any()
+                                        .downcast_ref::<BgSaveRequest>()
+                                        .unwrap();
+                                    Self::queue_response::<BgSaveResponse>(
+                                        write_sockets.clone(),
+                                        handler.clone(),
+                                        handler.handle_bg_save_request(req),
+                                        peer,
+                                    )
+                                    .await?;
+                                }
+                                MessageType::Delete => {
+                                    let req = message
+                                        .as_ref()
+                                        .as_any()
+                                        .downcast_ref::<DeleteRequest>()
+                                        .unwrap();
+                                    Self::queue_response::<DeleteResponse>(
+                                        write_sockets.clone(),
+                                        handler.clone(),
+                                        handler.handle_delete_request(req),
+                                        peer,
+                                    )
+                                    .await?;
+                                }
+                                MessageType::Exists => {
+                                    let req = message
+                                        .as_ref()
+                                        .as_any()
+                                        .downcast_ref::<ExistsRequest>()
+                                        .unwrap();
+                                    Self::queue_response::<ExistsResponse>(
+                                        write_sockets.clone(),
+                                        handler.clone(),
+                                        handler.handle_exists_request(req),
+                                        peer,
+                                    )
+                                    .await?;
+                                }
+                                MessageType::Expire => {
+                                    let req = message
+                                        .as_ref()
+                                        .as_any()
+                                        .downcast_ref::<ExpireRequest>()
+                                        .unwrap();
+                                    Self::queue_response::<ExpireResponse>(
+                                        write_sockets.clone(),
+                                        handler.clone(),
+                                        handler.handle_expire_request(req),
+                                        peer,
+                                    )
+                                    .await?;
+                                }
+                                MessageType::Ttl => {
+                                    let req = message
+                                        .as_ref()
+                                        .as_any()
+                                        .downcast_ref::<TtlRequest>()
+                                        .unwrap();
+                                    Self::queue_response::<TtlResponse>(
+                                        write_sockets.clone(),
+                                        handler.clone(),
+                                        handler.handle_ttl_request(req),
+                                        peer,
+                                    )
+                                    .await?;
+                                }
+                                MessageType::Command => {
+                                    let req = message
+                                        .as_ref()
+                                        .as_any()
+                                        .downcast_ref::<CommandRequest>()
+                                        .unwrap();
+                                    Self::queue_handler_response::<CommandResponse>(
+                                        write_sockets.clone(),
+                                        handler.clone(),
+                                        handler.handle_command_request(req),
+                                        peer,
+                                    )
+                                    .await?;
+                                }
+                                MessageType::Transaction => {
+                                    let req = message
+                                        .as_ref()
+                                        .as_any()
+                                        .downcast_ref::<TransactionRequest>()
+                                        .unwrap();
+                                    Self::queue_response::<TransactionResponse>(
+                                        write_sockets.clone(),
+                                        handler.clone(),
+                                        handler.handle_transaction_request(req),
+                                        peer,
+                                    )
+                                    .await?;
+                                }
+                                MessageType::TwoPhase => {
+                                    let req = message
+                                        .as_ref()
+                                        .as_any()
+                                        .downcast_ref::<TwoPhaseRequest>()
+                                        .unwrap();
+                                    Self::queue_response::<TwoPhaseResponse>(
+                                        write_sockets.clone(),
+                                        handler.clone(),
+                                        handler.handle_two_phase_request(req),
+                                        peer,
+                                    )
+                                    .await?;
+                                }
+                                MessageType::ReplicaAck => {
+                                    let req = message
+                                        .as_ref()
+                                        .as_any()
+                                        .downcast_ref::<ReplicaAckRequest>()
+                                        .unwrap();
+                                    Self::queue_response::<ReplicaAckResponse>(
+                                        write_sockets.clone(),
+                                        handler.clone(),
+                                        handler.handle_replica_ack_request(req),
+                                        peer,
+                                    )
+                                    .await?;
+                                }
+                                MessageType::Sync => {
+                                    let req = message
+                                        .as_ref()
+                                        .as_any()
+                                        .downcast_ref::<SyncRequest>()
+                                        .unwrap();
+                                    Self::queue_response::<SyncResponse>(
+                                        write_sockets.clone(),
+                                        handler.clone(),
+                                        handler.handle_sync_request(req),
+                                        peer,
+                                    )
+                                    .await?;
+                                }
                             };
                         }
                         false => match message.get_message_type() {
@@ -362,6 +593,86 @@ impl<H: RouterHandler> RouterBuilder<H> {
                                     .unwrap();
                                 handler.handle_get_shared_peers_response(res)
                             }
+                            MessageType::BgSave => {
+                                let res = message
+                                    .as_ref()
+                                    .as_any()
+                                    .downcast_ref::<BgSaveResponse>()
+                                    .unwrap();
+                                handler.handle_bg_save_response(res)
+                            }
+                            MessageType::Delete => {
+                                let res = message
+                                    .as_ref()
+                                    .as_any()
+                                    .downcast_ref::<DeleteResponse>()
+                                    .unwrap();
+                                handler.handle_delete_response(res)
+                            }
+                            MessageType::Exists => {
+                                let res = message
+                                    .as_ref()
+                                    .as_any()
+                                    .downcast_ref::<ExistsResponse>()
+                                    .unwrap();
+                                handler.handle_exists_response(res)
+                            }
+                            MessageType::Expire => {
+                                let res = message
+                                    .as_ref()
+                                    .as_any()
+                                    .downcast_ref::<ExpireResponse>()
+                                    .unwrap();
+                                handler.handle_expire_response(res)
+                            }
+                            MessageType::Ttl => {
+                                let res = message
+                                    .as_ref()
+                                    .as_any()
+                                    .downcast_ref::<TtlResponse>()
+                                    .unwrap();
+                                handler.handle_ttl_response(res)
+                            }
+                            MessageType::Command => {
+                                let res = message
+                                    .as_ref()
+                                    .as_any()
+                                    .downcast_ref::<CommandResponse>()
+                                    .unwrap();
+                                handler.handle_command_response(res)
+                            }
+                            MessageType::Transaction => {
+                                let res = message
+                                    .as_ref()
+                                    .as_any()
+                                    .downcast_ref::<TransactionResponse>()
+                                    .unwrap();
+                                handler.handle_transaction_response(res)
+                            }
+                            MessageType::TwoPhase => {
+                                let res = message
+                                    .as_ref()
+                                    .as_any()
+                                    .downcast_ref::<TwoPhaseResponse>()
+                                    .unwrap();
+                                handler.handle_two_phase_response(res)
+                            }
+                            MessageType::ReplicaAck => {
+                                let res = message
+                                    .as_ref()
+                                    .as_any()
+                                    .downcast_ref::<ReplicaAckResponse>()
+                                    .unwrap();
+                                handler.handle_replica_ack_response(res)
+                            }
+                            MessageType::Sync => {
+                                let res = message
+                                    .as_ref()
+                                    .as_any()
+                                    .downcast_ref::<SyncResponse>()
+                                    .unwrap();
+                                handler.handle_sync_response(res)
+                            }
                         },
                     };
                 }
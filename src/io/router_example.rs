@@ -44,15 +44,23 @@ mod test {
             unimplemented!()
         }
 
-        fn handle_read_request(&self, _req: &ReadRequest) -> ReadResponse {
+        fn handle_read_request(
+            &self,
+            _req: &ReadRequest,
+        ) -> crate::io::router::HandlerResponse<ReadResponse> {
             ReadResponse {
                 value: vec![1, 2, 3, 4],
                 key: b"testkey".to_vec(),
                 error: 0,
+                version: 1,
             }
+            .into()
         }
 
-        fn handle_write_request(&self, _req: &WriteRequest) -> WriteResponse {
+        fn handle_write_request(
+            &self,
+            _req: &WriteRequest,
+        ) -> crate::io::router::HandlerResponse<WriteResponse> {
             unimplemented!()
         }
 
@@ -101,6 +109,148 @@ mod test {
         ) {
             unimplemented!()
         }
+
+        fn handle_bg_save_request(
+            &self,
+            _req: &crate::messages::requests::bg_save_request::BgSaveRequest,
+        ) -> crate::messages::responses::bg_save_response::BgSaveResponse {
+            unimplemented!()
+        }
+
+        fn handle_bg_save_response(
+            &self,
+            _res: &crate::messages::responses::bg_save_response::BgSaveResponse,
+        ) {
+            unimplemented!()
+        }
+
+        fn handle_delete_request(
+            &self,
+            _req: &crate::messages::requests::delete_request::DeleteRequest,
+        ) -> crate::messages::responses::delete_response::DeleteResponse {
+            unimplemented!()
+        }
+
+        fn handle_delete_response(
+            &self,
+            _res: &crate::messages::responses::delete_response::DeleteResponse,
+        ) {
+            unimplemented!()
+        }
+
+        fn handle_exists_request(
+            &self,
+            _req: &crate::messages::requests::exists_request::ExistsRequest,
+        ) -> crate::messages::responses::exists_response::ExistsResponse {
+            unimplemented!()
+        }
+
+        fn handle_exists_response(
+            &self,
+            _res: &crate::messages::responses::exists_response::ExistsResponse,
+        ) {
+            unimplemented!()
+        }
+
+        fn handle_expire_request(
+            &self,
+            _req: &crate::messages::requests::expire_request::ExpireRequest,
+        ) -> crate::messages::responses::expire_response::ExpireResponse {
+            unimplemented!()
+        }
+
+        fn handle_expire_response(
+            &self,
+            _res: &crate::messages::responses::expire_response::ExpireResponse,
+        ) {
+            unimplemented!()
+        }
+
+        fn handle_ttl_request(
+            &self,
+            _req: &crate::messages::requests::ttl_request::TtlRequest,
+        ) -> crate::messages::responses::ttl_response::TtlResponse {
+            unimplemented!()
+        }
+
+        fn handle_ttl_response(
+            &self,
+            _res: &crate::messages::responses::ttl_response::TtlResponse,
+        ) {
+            unimplemented!()
+        }
+
+        fn handle_command_request(
+            &self,
+            _req: &crate::messages::requests::command_request::CommandRequest,
+        ) -> crate::io::router::HandlerResponse<
+            crate::messages::responses::command_response::CommandResponse,
+        > {
+            unimplemented!()
+        }
+
+        fn handle_command_response(
+            &self,
+            _res: &crate::messages::responses::command_response::CommandResponse,
+        ) {
+            unimplemented!()
+        }
+
+        fn handle_transaction_request(
+            &self,
+            _req: &crate::messages::requests::transaction_request::TransactionRequest,
+        ) -> crate::messages::responses::transaction_response::TransactionResponse {
+            unimplemented!()
+        }
+
+        fn handle_transaction_response(
+            &self,
+            _res: &crate::messages::responses::transaction_response::TransactionResponse,
+        ) {
+            unimplemented!()
+        }
+
+        fn handle_two_phase_request(
+            &self,
+            _req: &crate::messages::requests::two_phase_request::TwoPhaseRequest,
+        ) -> crate::messages::responses::two_phase_response::TwoPhaseResponse {
+            unimplemented!()
+        }
+
+        fn handle_two_phase_response(
+            &self,
+            _res: &crate::messages::responses::two_phase_response::TwoPhaseResponse,
+        ) {
+            unimplemented!()
+        }
+
+        fn handle_replica_ack_request(
+            &self,
+            _req: &crate::messages::requests::replica_ack_request::ReplicaAckRequest,
+        ) -> crate::messages::responses::replica_ack_response::ReplicaAckResponse {
+            unimplemented!()
+        }
+
+        fn handle_replica_ack_response(
+            &self,
+            _res: &crate::messages::responses::replica_ack_response::ReplicaAckResponse,
+        ) {
+            unimplemented!()
+        }
+
+        fn handle_sync_request(
+            &self,
+            _req: &crate::messages::requests::sync_request::SyncRequest,
+        ) -> crate::messages::responses::sync_response::SyncResponse {
+            unimplemented!()
+        }
+
+        fn handle_sync_response(
+            &self,
+            _res: &crate::messages::responses::sync_response::SyncResponse,
+        ) {
+            unimplemented!()
+        }
     }
 
     #[tokio::test]
@@ -138,6 +288,7 @@ mod test {
                 .queue_request::<ReadRequest>(
                     ReadRequest {
                         key: "test".as_bytes().to_vec(),
+                        min_version: 0,
                     },
                     SocketAddrV6::new(Ipv6Addr::LOCALHOST, 8081, 0, 0),
                 )
@@ -146,9 +297,8 @@ mod test {
         }
         tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
 
-        let debug_out1 = debug_out1.read().unwrap();
         assert_eq!(
-            *debug_out1,
+            *debug_out1.read().unwrap(),
             vec![vec![1, 2, 3, 4], vec![1, 2, 3, 4], vec![1, 2, 3, 4]]
         );
 
@@ -1,4 +1,7 @@
+pub mod commands;
+pub mod coordinator;
 pub mod integration;
 pub mod io;
 pub mod messages;
+pub mod storage;
 pub mod utils;
@@ -3,8 +3,18 @@ use int_enum::IntEnum;
 use std::any::Any;
 
 use super::requests::announce_shard_request::AnnounceShardRequest;
+use super::requests::bg_save_request::BgSaveRequest;
+use super::requests::command_request::CommandRequest;
+use super::requests::delete_request::DeleteRequest;
+use super::requests::exists_request::ExistsRequest;
+use super::requests::expire_request::ExpireRequest;
 use super::requests::get_shared_peers_request::GetSharedPeersRequest;
 use super::requests::get_version_request::GetVersionRequest;
+use super::requests::replica_ack_request::ReplicaAckRequest;
+use super::requests::sync_request::SyncRequest;
+use super::requests::transaction_request::TransactionRequest;
+use super::requests::ttl_request::TtlRequest;
+use super::requests::two_phase_request::TwoPhaseRequest;
 use super::requests::{
     get_client_shard_info_request::GetClientShardInfoRequest,
     query_version_request::QueryVersionRequest, read_request::ReadRequest,
@@ -12,9 +22,19 @@ use super::requests::{
 };
 
 use super::responses::announce_shard_response::AnnounceShardResponse;
+use super::responses::bg_save_response::BgSaveResponse;
+use super::responses::command_response::CommandResponse;
+use super::responses::delete_response::DeleteResponse;
+use super::responses::exists_response::ExistsResponse;
+use super::responses::expire_response::ExpireResponse;
 use super::responses::get_shared_peers_response::GetSharedPeersResponse;
 use super::responses::get_version_response::GetVersionResponse;
 use super::responses::read_response::ReadResponse;
+use super::responses::replica_ack_response::ReplicaAckResponse;
+use super::responses::sync_response::SyncResponse;
+use super::responses::transaction_response::TransactionResponse;
+use super::responses::ttl_response::TtlResponse;
+use super::responses::two_phase_response::TwoPhaseResponse;
 use super::responses::write_response::WriteResponse;
 use super::responses::{
     get_client_shard_info_response::GetClientShardInfoResponse,
@@ -31,6 +51,16 @@ pub enum MessageType {
     GetVersion = 4,         // 4 - read key-value for a version number
     AnnounceShard = 5,      // 5 - announce a shard
     GetSharedPeers = 6,     // 6 - get shared peers
+    BgSave = 7,             // 7 - snapshot the keyspace in the background
+    Delete = 8,             // 8 - delete one or more keys
+    Exists = 9,             // 9 - count how many keys exist
+    Expire = 10,            // 10 - set or remove the expiry of a key
+    Ttl = 11,               // 11 - time to live of a key
+    Command = 12,           // 12 - run a command on the shard that owns its key
+    Transaction = 13,       // 13 - run several commands atomically on one write shard
+    TwoPhase = 14,          // 14 - prepare, commit or abort part of a cross-shard transaction
+    ReplicaAck = 15,        // 15 - a read shard confirms the versions it has applied
+    Sync = 16,              // 16 - transfer a full snapshot to a read shard that fell behind
 }
 
 pub trait MessagePayload: AsAny + Send {
@@ -95,6 +125,46 @@ pub fn bytes_as_message(buffer: &[u8]) -> Result<Box<dyn MessagePayload>> {
             true => Box::new(Message::<GetVersionRequest>::deserialize(buffer)?.message_payload),
             false => Box::new(Message::<GetVersionResponse>::deserialize(buffer)?.message_payload),
         },
+        MessageType::BgSave => match is_request {
+            true => Box::new(Message::<BgSaveRequest>::deserialize(buffer)?.message_payload),
+            false => Box::new(Message::<BgSaveResponse>::deserialize(buffer)?.message_payload),
+        },
+        MessageType::Delete => match is_request {
+            true => Box::new(Message::<DeleteRequest>::deserialize(buffer)?.message_payload),
+            false => Box::new(Message::<DeleteResponse>::deserialize(buffer)?.message_payload),
+        },
+        MessageType::Exists => match is_request {
+            true => Box::new(Message::<ExistsRequest>::deserialize(buffer)?.message_payload),
+            false => Box::new(Message::<ExistsResponse>::deserialize(buffer)?.message_payload),
+        },
+        MessageType::Expire => match is_request {
+            true => Box::new(Message::<ExpireRequest>::deserialize(buffer)?.message_payload),
+            false => Box::new(Message::<ExpireResponse>::deserialize(buffer)?.message_payload),
+        },
+        MessageType::Ttl => match is_request {
+            true => Box::new(Message::<TtlRequest>::deserialize(buffer)?.message_payload),
+            false => Box::new(Message::<TtlResponse>::deserialize(buffer)?.message_payload),
+        },
+        MessageType::Command => match is_request {
+            true => Box::new(Message::<CommandRequest>::deserialize(buffer)?.message_payload),
+            false => Box::new(Message::<CommandResponse>::deserialize(buffer)?.message_payload),
+        },
+        MessageType::Transaction => match is_request {
+            true => Box::new(Message::<TransactionRequest>::deserialize(buffer)?.message_payload),
+            false => Box::new(Message::<TransactionResponse>::deserialize(buffer)?.message_payload),
+        },
+        MessageType::TwoPhase => match is_request {
+            true => Box::new(Message::<TwoPhaseRequest>::deserialize(buffer)?.message_payload),
+            false => Box::new(Message::<TwoPhaseResponse>::deserialize(buffer)?.message_payload),
+        },
+        MessageType::ReplicaAck => match is_request {
+            true => Box::new(Message::<ReplicaAckRequest>::deserialize(buffer)?.message_payload),
+            false => Box::new(Message::<ReplicaAckResponse>::deserialize(buffer)?.message_payload),
+        },
+        MessageType::Sync => match is_request {
+            true => Box::new(Message::<SyncRequest>::deserialize(buffer)?.message_payload),
+            false => Box::new(Message::<SyncResponse>::deserialize(buffer)?.message_payload),
+        },
     };
     Ok(result)
 }
@@ -181,6 +251,10 @@ mod tests {
             message_payload: WriteRequest {
                 key: b"test".to_vec(),
                 value: b"test".to_vec(),
+                expiry_type: 0,
+                expiry: 0,
+                condition: 0,
+                expected_version: 0,
             },
         };
         let serialized = message.serialize().unwrap();
@@ -0,0 +1,22 @@
+use crate::messages::message::{MessagePayload, MessageType};
+use anyhow::Result;
+
+pub struct BgSaveRequest {}
+
+impl MessagePayload for BgSaveRequest {
+    fn is_request(&self) -> bool {
+        true
+    }
+
+    fn get_message_type(&self) -> MessageType {
+        MessageType::BgSave
+    }
+
+    fn serialize(&self) -> Result<Vec<u8>> {
+        Ok(Vec::new())
+    }
+
+    fn deserialize(_buffer: &[u8]) -> Result<Self> {
+        Ok(BgSaveRequest {})
+    }
+}
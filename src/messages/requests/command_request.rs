@@ -0,0 +1,97 @@
+use crate::messages::message::{MessagePayload, MessageType};
+use anyhow::{Context, Result};
+
+/// A command given as its name followed by its arguments, like a redis
+/// command line. Write commands go to the write shard that owns the key and
+/// read commands to one of its read shards.
+pub struct CommandRequest {
+    pub args: Vec<Vec<u8>>,
+}
+
+/// Layout of the CommandRequest
+/// | 2 bytes | 4 bytes | N bytes | ... |
+/// | argc    | arglen  |   arg   | ... |
+/// Integers are always encoded in little-endian order
+impl MessagePayload for CommandRequest {
+    fn get_message_type(&self) -> MessageType {
+        MessageType::Command
+    }
+
+    fn is_request(&self) -> bool {
+        true
+    }
+
+    fn serialize(&self) -> Result<Vec<u8>> {
+        let mut buffer = Vec::new();
+        let argc = u16::try_from(self.args.len()).context("too many arguments")?;
+        buffer.extend_from_slice(&argc.to_le_bytes());
+        for arg in &self.args {
+            let arg_len = u32::try_from(arg.len()).context("argument length overflow")?;
+            buffer.extend_from_slice(&arg_len.to_le_bytes());
+            buffer.extend_from_slice(arg);
+        }
+        Ok(buffer)
+    }
+
+    fn deserialize(buffer: &[u8]) -> Result<Self> {
+        let argc = u16::from_le_bytes(
+            buffer
+                .get(0..2)
+                .context("failed to get argument count")?
+                .try_into()?,
+        );
+        let mut offset = 2;
+        let mut args = Vec::new();
+        for _ in 0..argc {
+            let arg_len = u32::from_le_bytes(
+                buffer
+                    .get(offset..offset + 4)
+                    .context("failed to get argument length")?
+                    .try_into()?,
+            ) as usize;
+            offset += 4;
+            let arg = buffer
+                .get(offset..offset + arg_len)
+                .context("failed to get argument")?
+                .to_vec();
+            offset += arg_len;
+            args.push(arg);
+        }
+        Ok(CommandRequest { args })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::Rng;
+
+    #[test]
+    fn test_roundtrip_basic() {
+        let original = CommandRequest {
+            args: vec![b"incrby".to_vec(), b"counter".to_vec(), b"5".to_vec()],
+        };
+        let serialized = original.serialize().unwrap();
+        let deserialized = CommandRequest::deserialize(&serialized).unwrap();
+        assert_eq!(original.args, deserialized.args);
+    }
+
+    #[test]
+    fn test_roundtrip_random() {
+        for _ in 0..1000 {
+            let mut rng = rand::thread_rng();
+            let argc = rng.gen_range(0..10);
+            let args: Vec<Vec<u8>> = (0..argc)
+                .map(|_| {
+                    let arg_len = rng.gen_range(0..101);
+                    (0..arg_len).map(|_| rng.gen()).collect()
+                })
+                .collect();
+
+            let original = CommandRequest { args };
+            let serialized = original.serialize().unwrap();
+            let deserialized = CommandRequest::deserialize(&serialized).unwrap();
+            assert_eq!(original.args, deserialized.args);
+        }
+    }
+}
@@ -0,0 +1,94 @@
+use crate::messages::message::{MessagePayload, MessageType};
+use anyhow::{Context, Result};
+
+pub struct ExistsRequest {
+    pub keys: Vec<Vec<u8>>,
+}
+
+/// Layout of the ExistsRequest
+/// | 2 bytes  | 2 bytes | N bytes | ... |
+/// | numkeys  | keylen  |   key   | ... |
+/// Integers are always encoded in little-endian order
+impl MessagePayload for ExistsRequest {
+    fn get_message_type(&self) -> MessageType {
+        MessageType::Exists
+    }
+
+    fn is_request(&self) -> bool {
+        true
+    }
+
+    fn serialize(&self) -> Result<Vec<u8>> {
+        let mut buffer = Vec::new();
+        let num_keys = u16::try_from(self.keys.len()).context("too many keys")?;
+        buffer.extend_from_slice(&num_keys.to_le_bytes());
+        for key in &self.keys {
+            let key_len = u16::try_from(key.len()).context("key length overflow")?;
+            buffer.extend_from_slice(&key_len.to_le_bytes());
+            buffer.extend_from_slice(key);
+        }
+        Ok(buffer)
+    }
+
+    fn deserialize(buffer: &[u8]) -> Result<Self> {
+        let num_keys = u16::from_le_bytes(
+            buffer
+                .get(0..2)
+                .context("failed to get number of keys")?
+                .try_into()?,
+        );
+        let mut offset = 2;
+        let mut keys = Vec::new();
+        for _ in 0..num_keys {
+            let key_len = u16::from_le_bytes(
+                buffer
+                    .get(offset..offset + 2)
+                    .context("failed to get key length")?
+                    .try_into()?,
+            ) as usize;
+            offset += 2;
+            let key = buffer
+                .get(offset..offset + key_len)
+                .context("failed to get key")?
+                .to_vec();
+            offset += key_len;
+            keys.push(key);
+        }
+        Ok(ExistsRequest { keys })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::Rng;
+
+    #[test]
+    fn test_roundtrip_basic() {
+        let original = ExistsRequest {
+            keys: vec![b"key1".to_vec(), b"key2".to_vec()],
+        };
+        let serialized = original.serialize().unwrap();
+        let deserialized = ExistsRequest::deserialize(&serialized).unwrap();
+        assert_eq!(original.keys, deserialized.keys);
+    }
+
+    #[test]
+    fn test_roundtrip_random() {
+        for _ in 0..1000 {
+            let mut rng = rand::thread_rng();
+            let num_keys = rng.gen_range(0..10);
+            let keys: Vec<Vec<u8>> = (0..num_keys)
+                .map(|_| {
+                    let key_len = rng.gen_range(0..101);
+                    (0..key_len).map(|_| rng.gen()).collect()
+                })
+                .collect();
+
+            let original = ExistsRequest { keys };
+            let serialized = original.serialize().unwrap();
+            let deserialized = ExistsRequest::deserialize(&serialized).unwrap();
+            assert_eq!(original.keys, deserialized.keys);
+        }
+    }
+}
@@ -0,0 +1,81 @@
+use crate::messages::message::{MessagePayload, MessageType};
+use anyhow::{Context, Result};
+
+/// Sets or removes the expiry of an existing key. An expiry type of
+/// ExpiryType::None removes the expiry, like PERSIST.
+pub struct ExpireRequest {
+    pub key: Vec<u8>,
+    pub expiry_type: u8,
+    pub expiry: u64,
+}
+
+/// Layout of the ExpireRequest
+/// | 2 bytes | N bytes | 1 byte      | 8 bytes |
+/// | keylen  |   key   | expiry type | expiry  |
+/// Integers are always encoded in little-endian order
+impl MessagePayload for ExpireRequest {
+    fn get_message_type(&self) -> MessageType {
+        MessageType::Expire
+    }
+
+    fn is_request(&self) -> bool {
+        true
+    }
+
+    fn serialize(&self) -> Result<Vec<u8>> {
+        let key_len = u16::try_from(self.key.len()).context("key length overflow")?;
+        let mut buffer = Vec::new();
+        buffer.extend_from_slice(&key_len.to_le_bytes());
+        buffer.extend_from_slice(&self.key);
+        buffer.push(self.expiry_type);
+        buffer.extend_from_slice(&self.expiry.to_le_bytes());
+        Ok(buffer)
+    }
+
+    fn deserialize(buffer: &[u8]) -> Result<Self> {
+        let key_len = u16::from_le_bytes(
+            buffer
+                .get(0..2)
+                .context("failed to get key length")?
+                .try_into()?,
+        ) as usize;
+        let key = buffer
+            .get(2..2 + key_len)
+            .context("failed to get key")?
+            .to_vec();
+        let expiry_type = *buffer
+            .get(2 + key_len)
+            .context("failed to get expiry type")?;
+        let expiry = u64::from_le_bytes(
+            buffer
+                .get(3 + key_len..11 + key_len)
+                .context("failed to get expiry")?
+                .try_into()?,
+        );
+        Ok(ExpireRequest {
+            key,
+            expiry_type,
+            expiry,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::requests::write_request::ExpiryType;
+
+    #[test]
+    fn test_roundtrip_basic() {
+        let original = ExpireRequest {
+            key: b"session".to_vec(),
+            expiry_type: ExpiryType::Px as u8,
+            expiry: 1500,
+        };
+        let serialized = original.serialize().unwrap();
+        let deserialized = ExpireRequest::deserialize(&serialized).unwrap();
+        assert_eq!(original.key, deserialized.key);
+        assert_eq!(original.expiry_type, deserialized.expiry_type);
+        assert_eq!(original.expiry, deserialized.expiry);
+    }
+}
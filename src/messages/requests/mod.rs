@@ -1,7 +1,17 @@
 pub mod announce_shard_request;
+pub mod bg_save_request;
+pub mod command_request;
+pub mod delete_request;
+pub mod exists_request;
+pub mod expire_request;
 pub mod get_client_shard_info_request;
 pub mod get_shared_peers_request;
 pub mod get_version_request;
 pub mod query_version_request;
 pub mod read_request;
+pub mod replica_ack_request;
+pub mod sync_request;
+pub mod transaction_request;
+pub mod ttl_request;
+pub mod two_phase_request;
 pub mod write_request;
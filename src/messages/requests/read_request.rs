@@ -3,11 +3,15 @@ use anyhow::{Context, Result};
 
 pub struct ReadRequest {
     pub key: Vec<u8>,
+    /// Session token, the version of the key's write shard the reader must
+    /// have replicated before it answers. 0 reads whatever the shard has.
+    pub min_version: u64,
 }
 
 /// Layout of the ReadRequest
-/// | 2 bytes | N bytes |
-/// | keylen  |   key   |
+/// | 2 bytes | N bytes | 8 bytes     |
+/// | keylen  |   key   | min version |
+/// Integers are always encoded in little-endian order
 impl MessagePayload for ReadRequest {
     fn get_message_type(&self) -> MessageType {
         MessageType::Read
@@ -22,6 +26,7 @@ impl MessagePayload for ReadRequest {
         let mut buffer = Vec::new();
         buffer.extend_from_slice(&key_len.to_le_bytes());
         buffer.extend_from_slice(&self.key);
+        buffer.extend_from_slice(&self.min_version.to_le_bytes());
         Ok(buffer)
     }
 
@@ -36,7 +41,13 @@ impl MessagePayload for ReadRequest {
             .get(2..2 + key_len)
             .context("failed to get key")?
             .to_vec();
-        Ok(ReadRequest { key })
+        let min_version = u64::from_le_bytes(
+            buffer
+                .get(2 + key_len..2 + key_len + 8)
+                .context("failed to get min version")?
+                .try_into()?,
+        );
+        Ok(ReadRequest { key, min_version })
     }
 }
 
@@ -49,10 +60,12 @@ mod tests {
     fn test_roundtrip_basic() {
         let original = ReadRequest {
             key: b"key".to_vec(),
+            min_version: 17,
         };
         let serialized = original.serialize().unwrap();
         let deserialized = ReadRequest::deserialize(&serialized).unwrap();
         assert_eq!(original.key, deserialized.key);
+        assert_eq!(original.min_version, deserialized.min_version);
     }
 
     #[test]
@@ -62,11 +75,15 @@ mod tests {
             let key_len = rng.gen_range(0..1001);
             let key: Vec<u8> = (0..key_len).map(|_| rng.gen()).collect();
 
-            let original = ReadRequest { key };
+            let original = ReadRequest {
+                key,
+                min_version: rng.gen(),
+            };
             let serialized = original.serialize().unwrap();
             let deserialized = ReadRequest::deserialize(&serialized).unwrap();
 
             assert_eq!(original.key, deserialized.key);
+            assert_eq!(original.min_version, deserialized.min_version);
         }
     }
 }
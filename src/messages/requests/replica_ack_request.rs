@@ -0,0 +1,71 @@
+use crate::messages::message::{MessagePayload, MessageType};
+use anyhow::{Context, Result};
+
+/// Sent by a read shard to its write shard once it has applied a version, so
+/// writes waiting for a quorum of read shards can be acknowledged
+pub struct ReplicaAckRequest {
+    /// Address the read shard announced itself with
+    pub ip: u128,
+    pub port: u16,
+    /// Latest version the read shard has applied
+    pub version: u64,
+}
+
+/// Layout of the ReplicaAckRequest
+/// | 16 bytes | 2 bytes | 8 bytes |
+/// | ip       | port    | version |
+/// Integers are always encoded in little-endian order
+impl MessagePayload for ReplicaAckRequest {
+    fn get_message_type(&self) -> MessageType {
+        MessageType::ReplicaAck
+    }
+
+    fn is_request(&self) -> bool {
+        true
+    }
+
+    fn serialize(&self) -> Result<Vec<u8>> {
+        let mut buffer = Vec::new();
+        buffer.extend_from_slice(&self.ip.to_le_bytes());
+        buffer.extend_from_slice(&self.port.to_le_bytes());
+        buffer.extend_from_slice(&self.version.to_le_bytes());
+        Ok(buffer)
+    }
+
+    fn deserialize(buffer: &[u8]) -> Result<Self> {
+        let ip = u128::from_le_bytes(buffer.get(0..16).context("failed to get ip")?.try_into()?);
+        let port = u16::from_le_bytes(
+            buffer
+                .get(16..18)
+                .context("failed to get port")?
+                .try_into()?,
+        );
+        let version = u64::from_le_bytes(
+            buffer
+                .get(18..26)
+                .context("failed to get version")?
+                .try_into()?,
+        );
+        Ok(ReplicaAckRequest { ip, port, version })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip_basic() {
+        let original = ReplicaAckRequest {
+            ip: 1,
+            port: 8084,
+            version: 42,
+        };
+        let serialized = original.serialize().unwrap();
+        let deserialized = ReplicaAckRequest::deserialize(&serialized).unwrap();
+        assert_eq!(original.ip, deserialized.ip);
+        assert_eq!(original.port, deserialized.port);
+        assert_eq!(original.version, deserialized.version);
+        assert!(ReplicaAckRequest::deserialize(&serialized[..20]).is_err());
+    }
+}
@@ -0,0 +1,49 @@
+use crate::messages::message::{MessagePayload, MessageType};
+use anyhow::{Context, Result};
+
+/// Sent by a read shard that fell behind its peer's version history, to get
+/// a chunk of a full snapshot of the peer's keyspace to resume replication
+/// from. A sync starts at offset 0, which has the peer take a new snapshot,
+/// and carries on from the version and offset the last chunk ended at.
+pub struct SyncRequest {
+    /// Version of the snapshot the chunk comes from, ignored at offset 0
+    pub version: u64,
+    /// Number of entries of the snapshot already received
+    pub offset: u64,
+}
+
+/// Layout of the SyncRequest
+/// | 8 bytes | 8 bytes |
+/// | version | offset  |
+impl MessagePayload for SyncRequest {
+    fn is_request(&self) -> bool {
+        true
+    }
+
+    fn get_message_type(&self) -> MessageType {
+        MessageType::Sync
+    }
+
+    fn serialize(&self) -> Result<Vec<u8>> {
+        let mut buffer = Vec::new();
+        buffer.extend_from_slice(&self.version.to_le_bytes());
+        buffer.extend_from_slice(&self.offset.to_le_bytes());
+        Ok(buffer)
+    }
+
+    fn deserialize(buffer: &[u8]) -> Result<Self> {
+        let version = u64::from_le_bytes(
+            buffer
+                .get(0..8)
+                .context("failed to get version")?
+                .try_into()?,
+        );
+        let offset = u64::from_le_bytes(
+            buffer
+                .get(8..16)
+                .context("failed to get offset")?
+                .try_into()?,
+        );
+        Ok(SyncRequest { version, offset })
+    }
+}
@@ -0,0 +1,102 @@
+use crate::messages::message::{MessagePayload, MessageType};
+use anyhow::{Context, Result};
+
+/// Commands to run all-or-nothing on the write shard that owns their keys,
+/// like a redis MULTI/EXEC block. The transaction is discarded unless every
+/// watched key is still at the version the client last saw it at.
+pub struct TransactionRequest {
+    /// Keys and the version each must be at, where 0 means the key must not exist
+    pub watches: Vec<(Vec<u8>, u64)>,
+    pub commands: Vec<Vec<Vec<u8>>>,
+}
+
+/// Layout of the TransactionRequest
+/// | 2 bytes | 4 bytes | N bytes | 8 bytes | ... | 2 bytes  | 2 bytes | 4 bytes | N bytes | ... |
+/// | watches | keylen  |   key   | version | ... | commands | argc    | arglen  |   arg   | ... |
+/// Integers are always encoded in little-endian order
+impl MessagePayload for TransactionRequest {
+    fn get_message_type(&self) -> MessageType {
+        MessageType::Transaction
+    }
+
+    fn is_request(&self) -> bool {
+        true
+    }
+
+    fn serialize(&self) -> Result<Vec<u8>> {
+        let mut buffer = Vec::new();
+        let watches = u16::try_from(self.watches.len()).context("too many watched keys")?;
+        buffer.extend_from_slice(&watches.to_le_bytes());
+        for (key, version) in &self.watches {
+            let key_len = u32::try_from(key.len()).context("key length overflow")?;
+            buffer.extend_from_slice(&key_len.to_le_bytes());
+            buffer.extend_from_slice(key);
+            buffer.extend_from_slice(&version.to_le_bytes());
+        }
+        let commands = u16::try_from(self.commands.len()).context("too many commands")?;
+        buffer.extend_from_slice(&commands.to_le_bytes());
+        for args in &self.commands {
+            let argc = u16::try_from(args.len()).context("too many arguments")?;
+            buffer.extend_from_slice(&argc.to_le_bytes());
+            for arg in args {
+                let arg_len = u32::try_from(arg.len()).context("argument length overflow")?;
+                buffer.extend_from_slice(&arg_len.to_le_bytes());
+                buffer.extend_from_slice(arg);
+            }
+        }
+        Ok(buffer)
+    }
+
+    fn deserialize(buffer: &[u8]) -> Result<Self> {
+        let mut offset = 0;
+        let mut read = |len: usize, field: &str| -> Result<&[u8]> {
+            let bytes = buffer
+                .get(offset..offset + len)
+                .with_context(|| format!("failed to get {}", field))?;
+            offset += len;
+            Ok(bytes)
+        };
+
+        let watch_count = u16::from_le_bytes(read(2, "watch count")?.try_into()?);
+        let mut watches = Vec::new();
+        for _ in 0..watch_count {
+            let key_len = u32::from_le_bytes(read(4, "key length")?.try_into()?) as usize;
+            let key = read(key_len, "key")?.to_vec();
+            let version = u64::from_le_bytes(read(8, "version")?.try_into()?);
+            watches.push((key, version));
+        }
+        let command_count = u16::from_le_bytes(read(2, "command count")?.try_into()?);
+        let mut commands = Vec::new();
+        for _ in 0..command_count {
+            let argc = u16::from_le_bytes(read(2, "argument count")?.try_into()?);
+            let mut args = Vec::new();
+            for _ in 0..argc {
+                let arg_len = u32::from_le_bytes(read(4, "argument length")?.try_into()?) as usize;
+                args.push(read(arg_len, "argument")?.to_vec());
+            }
+            commands.push(args);
+        }
+        Ok(TransactionRequest { watches, commands })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip_basic() {
+        let original = TransactionRequest {
+            watches: vec![(b"balance".to_vec(), 7), (b"missing".to_vec(), 0)],
+            commands: vec![
+                vec![b"decrby".to_vec(), b"balance".to_vec(), b"5".to_vec()],
+                vec![b"rpush".to_vec(), b"log".to_vec(), vec![0xff, 0x00]],
+            ],
+        };
+        let serialized = original.serialize().unwrap();
+        let deserialized = TransactionRequest::deserialize(&serialized).unwrap();
+        assert_eq!(original.watches, deserialized.watches);
+        assert_eq!(original.commands, deserialized.commands);
+        assert!(TransactionRequest::deserialize(&serialized[..serialized.len() - 1]).is_err());
+    }
+}
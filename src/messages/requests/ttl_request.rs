@@ -0,0 +1,72 @@
+use crate::messages::message::{MessagePayload, MessageType};
+use anyhow::{Context, Result};
+
+pub struct TtlRequest {
+    pub key: Vec<u8>,
+}
+
+/// Layout of the TtlRequest
+/// | 2 bytes | N bytes |
+/// | keylen  |   key   |
+impl MessagePayload for TtlRequest {
+    fn get_message_type(&self) -> MessageType {
+        MessageType::Ttl
+    }
+
+    fn is_request(&self) -> bool {
+        true
+    }
+
+    fn serialize(&self) -> Result<Vec<u8>> {
+        let key_len = u16::try_from(self.key.len()).context("key length overflow")?;
+        let mut buffer = Vec::new();
+        buffer.extend_from_slice(&key_len.to_le_bytes());
+        buffer.extend_from_slice(&self.key);
+        Ok(buffer)
+    }
+
+    fn deserialize(buffer: &[u8]) -> Result<Self> {
+        let key_len = u16::from_le_bytes(
+            buffer
+                .get(0..2)
+                .context("failed to get key length")?
+                .try_into()?,
+        ) as usize;
+        let key = buffer
+            .get(2..2 + key_len)
+            .context("failed to get key")?
+            .to_vec();
+        Ok(TtlRequest { key })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::Rng;
+
+    #[test]
+    fn test_roundtrip_basic() {
+        let original = TtlRequest {
+            key: b"key".to_vec(),
+        };
+        let serialized = original.serialize().unwrap();
+        let deserialized = TtlRequest::deserialize(&serialized).unwrap();
+        assert_eq!(original.key, deserialized.key);
+    }
+
+    #[test]
+    fn test_roundtrip_random() {
+        for _ in 0..1000 {
+            let mut rng = rand::thread_rng();
+            let key_len = rng.gen_range(0..1001);
+            let key: Vec<u8> = (0..key_len).map(|_| rng.gen()).collect();
+
+            let original = TtlRequest { key };
+            let serialized = original.serialize().unwrap();
+            let deserialized = TtlRequest::deserialize(&serialized).unwrap();
+
+            assert_eq!(original.key, deserialized.key);
+        }
+    }
+}
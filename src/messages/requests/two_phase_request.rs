@@ -0,0 +1,147 @@
+use crate::messages::message::{MessagePayload, MessageType};
+use crate::messages::requests::transaction_request::TransactionRequest;
+use anyhow::{Context, Result};
+use int_enum::IntEnum;
+
+/// Step of a two-phase commit a request asks a write shard to take
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, IntEnum, PartialEq, Eq)]
+pub enum Phase {
+    /// Check the shard's part of the transaction and lock its keys until the outcome is known
+    Prepare = 0,
+    Commit = 1,
+    Abort = 2,
+    /// Ask the primary how a transaction ended, sent by shards left in doubt
+    Status = 3,
+}
+
+/// One step of a transaction whose keys live on several write shards. The
+/// coordinator prepares the part of the transaction each shard owns, and
+/// once every shard has voted to commit it commits on the primary first.
+/// That commit decides the outcome, so shards left in doubt ask the primary.
+pub struct TwoPhaseRequest {
+    pub txn_id: u128,
+    pub phase: u8,
+    /// Address of the primary, 0 for both when the request is sent to the primary itself
+    pub primary_ip: u128,
+    pub primary_port: u16,
+    /// The shard's part of the transaction, empty unless preparing
+    pub transaction: TransactionRequest,
+}
+
+// only write shards and the coordinator build and check these
+#[allow(unused)]
+impl TwoPhaseRequest {
+    /// Builds a request for a step that doesn't carry the transaction
+    pub fn new(txn_id: u128, phase: Phase, primary_ip: u128, primary_port: u16) -> Self {
+        TwoPhaseRequest {
+            txn_id,
+            phase: phase as u8,
+            primary_ip,
+            primary_port,
+            transaction: TransactionRequest {
+                watches: Vec::new(),
+                commands: Vec::new(),
+            },
+        }
+    }
+
+    pub fn is_primary(&self) -> bool {
+        self.primary_port == 0
+    }
+}
+
+/// Layout of the TwoPhaseRequest
+/// | 16 bytes | 1 byte | 16 bytes   | 2 bytes      | ...         |
+/// | txn id   | phase  | primary ip | primary port | transaction |
+/// The transaction is laid out the same way as a TransactionRequest
+/// Integers are always encoded in little-endian order
+impl MessagePayload for TwoPhaseRequest {
+    fn get_message_type(&self) -> MessageType {
+        MessageType::TwoPhase
+    }
+
+    fn is_request(&self) -> bool {
+        true
+    }
+
+    fn serialize(&self) -> Result<Vec<u8>> {
+        let mut buffer = Vec::new();
+        buffer.extend_from_slice(&self.txn_id.to_le_bytes());
+        buffer.push(self.phase);
+        buffer.extend_from_slice(&self.primary_ip.to_le_bytes());
+        buffer.extend_from_slice(&self.primary_port.to_le_bytes());
+        buffer.extend_from_slice(&self.transaction.serialize()?);
+        Ok(buffer)
+    }
+
+    fn deserialize(buffer: &[u8]) -> Result<Self> {
+        let txn_id = u128::from_le_bytes(
+            buffer
+                .get(0..16)
+                .context("failed to get txn id")?
+                .try_into()?,
+        );
+        let phase = *buffer.get(16).context("failed to get phase")?;
+        let primary_ip = u128::from_le_bytes(
+            buffer
+                .get(17..33)
+                .context("failed to get primary ip")?
+                .try_into()?,
+        );
+        let primary_port = u16::from_le_bytes(
+            buffer
+                .get(33..35)
+                .context("failed to get primary port")?
+                .try_into()?,
+        );
+        let transaction = TransactionRequest::deserialize(&buffer[35..])?;
+        Ok(TwoPhaseRequest {
+            txn_id,
+            phase,
+            primary_ip,
+            primary_port,
+            transaction,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip_basic() {
+        let original = TwoPhaseRequest {
+            txn_id: u128::MAX - 7,
+            phase: Phase::Prepare as u8,
+            primary_ip: 1,
+            primary_port: 8081,
+            transaction: TransactionRequest {
+                watches: vec![(b"balance".to_vec(), 3)],
+                commands: vec![vec![b"incr".to_vec(), b"balance".to_vec()]],
+            },
+        };
+        let serialized = original.serialize().unwrap();
+        let deserialized = TwoPhaseRequest::deserialize(&serialized).unwrap();
+        assert_eq!(original.txn_id, deserialized.txn_id);
+        assert_eq!(original.phase, deserialized.phase);
+        assert_eq!(original.primary_ip, deserialized.primary_ip);
+        assert_eq!(original.primary_port, deserialized.primary_port);
+        assert!(!deserialized.is_primary());
+        assert_eq!(
+            original.transaction.commands,
+            deserialized.transaction.commands
+        );
+        assert_eq!(
+            original.transaction.watches,
+            deserialized.transaction.watches
+        );
+
+        let status = TwoPhaseRequest::new(9, Phase::Status, 0, 0);
+        let deserialized = TwoPhaseRequest::deserialize(&status.serialize().unwrap()).unwrap();
+        assert!(deserialized.is_primary());
+        assert!(deserialized.transaction.commands.is_empty());
+        assert!(TwoPhaseRequest::deserialize(&[0; 20]).is_err());
+    }
+}
@@ -1,14 +1,76 @@
 use crate::messages::message::{MessagePayload, MessageType};
 use anyhow::{Context, Result};
+use int_enum::IntEnum;
+
+/// How the expiry of a write or expire request is given
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, IntEnum, PartialEq, Eq)]
+pub enum ExpiryType {
+    /// No expiry, the key lives until it is deleted
+    None = 0,
+    /// Seconds from now
+    Ex = 1,
+    /// Milliseconds from now
+    Px = 2,
+    /// Unix time in seconds
+    ExAt = 3,
+    /// Unix time in milliseconds
+    PxAt = 4,
+}
+
+impl ExpiryType {
+    /// Resolves an expiry to unix milliseconds, relative to `now` in unix milliseconds
+    pub fn expires_at(self, expiry: u64, now: u64) -> Option<u64> {
+        match self {
+            ExpiryType::None => None,
+            ExpiryType::Ex => Some(now.saturating_add(expiry.saturating_mul(1000))),
+            ExpiryType::Px => Some(now.saturating_add(expiry)),
+            ExpiryType::ExAt => Some(expiry.saturating_mul(1000)),
+            ExpiryType::PxAt => Some(expiry),
+        }
+    }
+}
+
+/// When a write is allowed to go through
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, IntEnum, PartialEq, Eq)]
+pub enum WriteCondition {
+    Always = 0,
+    /// Only if the key doesn't exist, like SET NX
+    IfAbsent = 1,
+    /// Only if the key exists, like SET XX
+    IfPresent = 2,
+    /// Only if the key was last written at the expected version. An expected
+    /// version of 0 means the key must not exist.
+    IfVersion = 3,
+}
+
+impl WriteCondition {
+    /// Checks the condition against the version of the key's last write, 0 if it doesn't exist
+    pub fn holds(self, key_version: u64, expected_version: u64) -> bool {
+        match self {
+            WriteCondition::Always => true,
+            WriteCondition::IfAbsent => key_version == 0,
+            WriteCondition::IfPresent => key_version != 0,
+            WriteCondition::IfVersion => key_version == expected_version,
+        }
+    }
+}
 
 pub struct WriteRequest {
     pub key: Vec<u8>,
     pub value: Vec<u8>,
+    pub expiry_type: u8,
+    pub expiry: u64,
+    pub condition: u8,
+    pub expected_version: u64,
 }
 
 /// Layout of the WriteRequest as described in architecture
-/// | 2 bytes | N bytes | 2 bytes | M bytes |
-/// | keylen  |   key   | valuelen|  value  |
+/// | 2 bytes | N bytes | 4 bytes | M bytes | 1 byte      | 8 bytes | 1 byte    | 8 bytes          |
+/// | keylen  |   key   | valuelen|  value  | expiry type | expiry  | condition | expected version |
+/// expiry is interpreted according to ExpiryType and ignored for ExpiryType::None
+/// expected version is only used by WriteCondition::IfVersion
 /// Integers are are always encoded in little-endian order
 impl MessagePayload for WriteRequest {
     fn get_message_type(&self) -> MessageType {
@@ -22,11 +84,15 @@ impl MessagePayload for WriteRequest {
     fn serialize(&self) -> Result<Vec<u8>> {
         let mut buffer = Vec::new();
         let key_len = u16::try_from(self.key.len()).context("key length overflow")?;
-        let value_len = u16::try_from(self.value.len()).context("value length overflow")?;
+        let value_len = u32::try_from(self.value.len()).context("value length overflow")?;
         buffer.extend_from_slice(&key_len.to_le_bytes());
         buffer.extend_from_slice(&self.key);
         buffer.extend_from_slice(&value_len.to_le_bytes());
         buffer.extend_from_slice(&self.value);
+        buffer.push(self.expiry_type);
+        buffer.extend_from_slice(&self.expiry.to_le_bytes());
+        buffer.push(self.condition);
+        buffer.extend_from_slice(&self.expected_version.to_le_bytes());
         Ok(buffer)
     }
 
@@ -41,17 +107,41 @@ impl MessagePayload for WriteRequest {
             .get(2..2 + key_len)
             .context("failed to get key")?
             .to_vec();
-        let value_len = u16::from_le_bytes(
+        let value_len = u32::from_le_bytes(
             buffer
-                .get(2 + key_len..2 + key_len + 2)
+                .get(2 + key_len..2 + key_len + 4)
                 .context("failed to get value length")?
                 .try_into()?,
         ) as usize;
+        let value_end = 2 + key_len + 4 + value_len;
         let value = buffer
-            .get(2 + key_len + 2..2 + key_len + 2 + value_len)
+            .get(2 + key_len + 4..value_end)
             .context("failed to get value")?
             .to_vec();
-        Ok(WriteRequest { key, value })
+        let expiry_type = *buffer.get(value_end).context("failed to get expiry type")?;
+        let expiry = u64::from_le_bytes(
+            buffer
+                .get(value_end + 1..value_end + 9)
+                .context("failed to get expiry")?
+                .try_into()?,
+        );
+        let condition = *buffer
+            .get(value_end + 9)
+            .context("failed to get condition")?;
+        let expected_version = u64::from_le_bytes(
+            buffer
+                .get(value_end + 10..value_end + 18)
+                .context("failed to get expected version")?
+                .try_into()?,
+        );
+        Ok(WriteRequest {
+            key,
+            value,
+            expiry_type,
+            expiry,
+            condition,
+            expected_version,
+        })
     }
 }
 
@@ -65,11 +155,19 @@ mod tests {
         let original = WriteRequest {
             key: b"key".to_vec(),
             value: b"value".to_vec(),
+            expiry_type: ExpiryType::Ex as u8,
+            expiry: 60,
+            condition: WriteCondition::IfVersion as u8,
+            expected_version: 7,
         };
         let serialized = original.serialize().unwrap();
         let deserialized = WriteRequest::deserialize(&serialized).unwrap();
         assert_eq!(original.key, deserialized.key);
         assert_eq!(original.value, deserialized.value);
+        assert_eq!(original.expiry_type, deserialized.expiry_type);
+        assert_eq!(original.expiry, deserialized.expiry);
+        assert_eq!(original.condition, deserialized.condition);
+        assert_eq!(original.expected_version, deserialized.expected_version);
     }
 
     #[test]
@@ -82,12 +180,60 @@ mod tests {
             let value_len = rng.gen_range(0..1001);
             let value: Vec<u8> = (0..value_len).map(|_| rng.gen()).collect();
 
-            let original = WriteRequest { key, value };
+            let original = WriteRequest {
+                key,
+                value,
+                expiry_type: rng.gen_range(0..5),
+                expiry: rng.gen(),
+                condition: rng.gen_range(0..4),
+                expected_version: rng.gen(),
+            };
             let serialized = original.serialize().unwrap();
             let deserialized = WriteRequest::deserialize(&serialized).unwrap();
 
             assert_eq!(original.key, deserialized.key);
             assert_eq!(original.value, deserialized.value);
+            assert_eq!(original.expiry_type, deserialized.expiry_type);
+            assert_eq!(original.expiry, deserialized.expiry);
+            assert_eq!(original.condition, deserialized.condition);
+            assert_eq!(original.expected_version, deserialized.expected_version);
         }
     }
+
+    #[test]
+    fn test_roundtrip_large_value() {
+        let original = WriteRequest {
+            key: b"image".to_vec(),
+            value: (0..200_000).map(|i| (i % 256) as u8).collect(),
+            expiry_type: ExpiryType::None as u8,
+            expiry: 0,
+            condition: WriteCondition::Always as u8,
+            expected_version: 0,
+        };
+        let serialized = original.serialize().unwrap();
+        let deserialized = WriteRequest::deserialize(&serialized).unwrap();
+        assert_eq!(original.value, deserialized.value);
+    }
+
+    #[test]
+    fn test_expires_at() {
+        let now = 1_700_000_000_000;
+        assert_eq!(ExpiryType::None.expires_at(10, now), None);
+        assert_eq!(ExpiryType::Ex.expires_at(10, now), Some(now + 10_000));
+        assert_eq!(ExpiryType::Px.expires_at(10, now), Some(now + 10));
+        assert_eq!(ExpiryType::ExAt.expires_at(10, now), Some(10_000));
+        assert_eq!(ExpiryType::PxAt.expires_at(10, now), Some(10));
+    }
+
+    #[test]
+    fn test_condition_holds() {
+        assert!(WriteCondition::Always.holds(3, 0));
+        assert!(WriteCondition::IfAbsent.holds(0, 0));
+        assert!(!WriteCondition::IfAbsent.holds(3, 0));
+        assert!(WriteCondition::IfPresent.holds(3, 0));
+        assert!(!WriteCondition::IfPresent.holds(0, 0));
+        assert!(WriteCondition::IfVersion.holds(3, 3));
+        assert!(!WriteCondition::IfVersion.holds(4, 3));
+        assert!(WriteCondition::IfVersion.holds(0, 0));
+    }
 }
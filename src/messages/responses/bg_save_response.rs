@@ -0,0 +1,66 @@
+use crate::messages::message::{MessagePayload, MessageType};
+use anyhow::{Context, Result};
+use int_enum::IntEnum;
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, IntEnum, PartialEq, Eq)]
+pub enum BgSaveResponseError {
+    NoError = 0,
+    AlreadyInProgress = 1,
+    SnapshotsDisabled = 2,
+    SnapshotFailed = 3,
+}
+
+#[derive(Clone)]
+pub struct BgSaveResponse {
+    pub error: u8,
+    pub version: u64,
+}
+
+/// Layout of the BgSaveResponse
+/// | 1 byte | 8 bytes |
+/// | error  | version |
+/// version is the version the started snapshot will contain
+impl MessagePayload for BgSaveResponse {
+    fn is_request(&self) -> bool {
+        false
+    }
+
+    fn get_message_type(&self) -> MessageType {
+        MessageType::BgSave
+    }
+
+    fn serialize(&self) -> Result<Vec<u8>> {
+        let mut buffer = vec![self.error];
+        buffer.extend_from_slice(&self.version.to_le_bytes());
+        Ok(buffer)
+    }
+
+    fn deserialize(buffer: &[u8]) -> Result<Self> {
+        let error = *buffer.first().context("failed to get error")?;
+        let version = u64::from_le_bytes(
+            buffer
+                .get(1..9)
+                .context("failed to get version")?
+                .try_into()?,
+        );
+        Ok(BgSaveResponse { error, version })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip_basic() {
+        let original = BgSaveResponse {
+            error: BgSaveResponseError::NoError as u8,
+            version: 12,
+        };
+        let serialized = original.serialize().unwrap();
+        let deserialized = BgSaveResponse::deserialize(&serialized).unwrap();
+        assert_eq!(original.error, deserialized.error);
+        assert_eq!(original.version, deserialized.version);
+    }
+}
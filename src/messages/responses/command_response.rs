@@ -0,0 +1,273 @@
+use crate::messages::message::{MessagePayload, MessageType};
+use anyhow::{Context, Result};
+use int_enum::IntEnum;
+use std::fmt;
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, IntEnum, PartialEq, Eq)]
+pub enum ReplyType {
+    Nil = 0,
+    Status = 1,
+    Error = 2,
+    Integer = 3,
+    Bulk = 4,
+    Array = 5,
+}
+
+/// Reply to a command, modelled on the redis protocol's reply types
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Reply {
+    Nil,
+    /// Short status message such as OK
+    Status(String),
+    Error(String),
+    Integer(i64),
+    Bulk(Vec<u8>),
+    Array(Vec<Reply>),
+}
+
+impl Reply {
+    pub fn ok() -> Self {
+        Reply::Status("OK".to_string())
+    }
+
+    pub fn error(message: impl Into<String>) -> Self {
+        Reply::Error(message.into())
+    }
+
+    pub fn wrong_type() -> Self {
+        Reply::error("WRONGTYPE Operation against a key holding the wrong kind of value")
+    }
+
+    pub fn wrong_arity(command: &str) -> Self {
+        Reply::Error(format!(
+            "ERR wrong number of arguments for '{}' command",
+            command
+        ))
+    }
+
+    pub fn not_an_integer() -> Self {
+        Reply::error("ERR value is not an integer or out of range")
+    }
+
+    pub fn not_a_float() -> Self {
+        Reply::error("ERR value is not a valid float")
+    }
+
+    #[allow(unused)]
+    pub fn locked() -> Self {
+        Reply::error("LOCKED a key is held by a pending cross-shard transaction")
+    }
+
+    #[allow(unused)]
+    pub fn out_of_memory() -> Self {
+        Reply::error("OOM command not allowed when used memory > 'maxmemory'.")
+    }
+
+    fn reply_type(&self) -> ReplyType {
+        match self {
+            Reply::Nil => ReplyType::Nil,
+            Reply::Status(_) => ReplyType::Status,
+            Reply::Error(_) => ReplyType::Error,
+            Reply::Integer(_) => ReplyType::Integer,
+            Reply::Bulk(_) => ReplyType::Bulk,
+            Reply::Array(_) => ReplyType::Array,
+        }
+    }
+
+    pub(crate) fn serialize_into(&self, buffer: &mut Vec<u8>) -> Result<()> {
+        buffer.push(self.reply_type() as u8);
+        match self {
+            Reply::Nil => {}
+            Reply::Status(message) | Reply::Error(message) => {
+                let len = u32::try_from(message.len()).context("message length overflow")?;
+                buffer.extend_from_slice(&len.to_le_bytes());
+                buffer.extend_from_slice(message.as_bytes());
+            }
+            Reply::Integer(n) => buffer.extend_from_slice(&n.to_le_bytes()),
+            Reply::Bulk(bytes) => {
+                let len = u32::try_from(bytes.len()).context("bulk length overflow")?;
+                buffer.extend_from_slice(&len.to_le_bytes());
+                buffer.extend_from_slice(bytes);
+            }
+            Reply::Array(replies) => {
+                let len = u32::try_from(replies.len()).context("array length overflow")?;
+                buffer.extend_from_slice(&len.to_le_bytes());
+                for reply in replies {
+                    reply.serialize_into(buffer)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn deserialize_from(buffer: &[u8], offset: &mut usize) -> Result<Self> {
+        let reply_type = *buffer.get(*offset).context("failed to get reply type")?;
+        *offset += 1;
+        let reply_type = ReplyType::try_from(reply_type)
+            .map_err(|_| anyhow::anyhow!("invalid reply type {}", reply_type))?;
+
+        let read_u32 = |offset: &mut usize| -> Result<usize> {
+            let n = u32::from_le_bytes(
+                buffer
+                    .get(*offset..*offset + 4)
+                    .context("failed to get length")?
+                    .try_into()?,
+            );
+            *offset += 4;
+            Ok(n as usize)
+        };
+        let read_bytes = |offset: &mut usize, len: usize| -> Result<Vec<u8>> {
+            let bytes = buffer
+                .get(*offset..*offset + len)
+                .context("failed to get bytes")?
+                .to_vec();
+            *offset += len;
+            Ok(bytes)
+        };
+
+        Ok(match reply_type {
+            ReplyType::Nil => Reply::Nil,
+            ReplyType::Status | ReplyType::Error => {
+                let len = read_u32(offset)?;
+                let message = String::from_utf8(read_bytes(offset, len)?)?;
+                if reply_type == ReplyType::Status {
+                    Reply::Status(message)
+                } else {
+                    Reply::Error(message)
+                }
+            }
+            ReplyType::Integer => {
+                let bytes = read_bytes(offset, 8)?;
+                Reply::Integer(i64::from_le_bytes(bytes.as_slice().try_into()?))
+            }
+            ReplyType::Bulk => {
+                let len = read_u32(offset)?;
+                Reply::Bulk(read_bytes(offset, len)?)
+            }
+            ReplyType::Array => {
+                let len = read_u32(offset)?;
+                let mut replies = Vec::new();
+                for _ in 0..len {
+                    replies.push(Reply::deserialize_from(buffer, offset)?);
+                }
+                Reply::Array(replies)
+            }
+        })
+    }
+}
+
+/// Quotes binary values the way redis-cli does, escaping every byte that
+/// isn't printable ascii
+fn escape(bytes: &[u8]) -> String {
+    let mut escaped = String::with_capacity(bytes.len());
+    for &byte in bytes {
+        match byte {
+            b'"' => escaped.push_str("\\\""),
+            b'\\' => escaped.push_str("\\\\"),
+            b'\n' => escaped.push_str("\\n"),
+            b'\r' => escaped.push_str("\\r"),
+            b'\t' => escaped.push_str("\\t"),
+            b' '..=b'~' => escaped.push(byte as char),
+            _ => escaped.push_str(&format!("\\x{:02x}", byte)),
+        }
+    }
+    escaped
+}
+
+/// Formats the reply the way redis-cli prints it
+impl fmt::Display for Reply {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Reply::Nil => write!(f, "(nil)"),
+            Reply::Status(message) => write!(f, "{}", message),
+            Reply::Error(message) => write!(f, "(error) {}", message),
+            Reply::Integer(n) => write!(f, "(integer) {}", n),
+            Reply::Bulk(bytes) => write!(f, "\"{}\"", escape(bytes)),
+            Reply::Array(replies) if replies.is_empty() => write!(f, "(empty array)"),
+            Reply::Array(replies) => {
+                for (i, reply) in replies.iter().enumerate() {
+                    if i > 0 {
+                        writeln!(f)?;
+                    }
+                    write!(f, "{}) {}", i + 1, reply)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CommandResponse {
+    pub reply: Reply,
+}
+
+/// Layout of the CommandResponse
+/// | 1 byte     | ...   |
+/// | reply type | reply |
+/// Layout of the reply depending on its type
+/// Nil: nothing
+/// Status, Error: | 4 bytes len | utf-8 message |
+/// Integer: | 8 bytes i64 |
+/// Bulk: | 4 bytes len | bytes |
+/// Array: | 4 bytes count | count replies, each with its own type byte |
+/// Integers are always encoded in little-endian order
+impl MessagePayload for CommandResponse {
+    fn is_request(&self) -> bool {
+        false
+    }
+
+    fn get_message_type(&self) -> MessageType {
+        MessageType::Command
+    }
+
+    fn serialize(&self) -> Result<Vec<u8>> {
+        let mut buffer = Vec::new();
+        self.reply.serialize_into(&mut buffer)?;
+        Ok(buffer)
+    }
+
+    fn deserialize(buffer: &[u8]) -> Result<Self> {
+        let mut offset = 0;
+        let reply = Reply::deserialize_from(buffer, &mut offset)?;
+        Ok(CommandResponse { reply })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip_basic() {
+        let replies = vec![
+            Reply::Nil,
+            Reply::Status("OK".to_string()),
+            Reply::not_an_integer(),
+            Reply::Integer(-42),
+            Reply::Bulk(vec![0xff, 0x00]),
+            Reply::Array(vec![
+                Reply::Bulk(b"a".to_vec()),
+                Reply::Nil,
+                Reply::Array(vec![Reply::Integer(1)]),
+            ]),
+        ];
+        for reply in replies {
+            let original = CommandResponse { reply };
+            let serialized = original.serialize().unwrap();
+            let deserialized = CommandResponse::deserialize(&serialized).unwrap();
+            assert_eq!(original.reply, deserialized.reply);
+        }
+    }
+
+    #[test]
+    fn test_display() {
+        let reply = Reply::Array(vec![Reply::Bulk(b"a".to_vec()), Reply::Integer(2)]);
+        assert_eq!(reply.to_string(), "1) \"a\"\n2) (integer) 2");
+        assert_eq!(
+            Reply::Bulk(vec![b'a', b'"', 0x80, b'\n']).to_string(),
+            "\"a\\\"\\x80\\n\""
+        );
+    }
+}
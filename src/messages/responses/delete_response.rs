@@ -0,0 +1,66 @@
+use crate::messages::message::{MessagePayload, MessageType};
+use anyhow::{Context, Result};
+use int_enum::IntEnum;
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, IntEnum, PartialEq, Eq)]
+pub enum DeleteResponseError {
+    NoError = 0,
+    Error = 1,
+    /// A key is held by a prepared cross-shard transaction, nothing was written
+    Locked = 2,
+}
+
+#[derive(Clone)]
+pub struct DeleteResponse {
+    pub error: u8,
+    pub deleted: u64,
+}
+
+/// Layout of the DeleteResponse
+/// | 1 byte | 8 bytes |
+/// | error  | deleted |
+/// deleted is the number of keys that existed and were removed
+impl MessagePayload for DeleteResponse {
+    fn is_request(&self) -> bool {
+        false
+    }
+
+    fn get_message_type(&self) -> MessageType {
+        MessageType::Delete
+    }
+
+    fn serialize(&self) -> Result<Vec<u8>> {
+        let mut buffer = vec![self.error];
+        buffer.extend_from_slice(&self.deleted.to_le_bytes());
+        Ok(buffer)
+    }
+
+    fn deserialize(buffer: &[u8]) -> Result<Self> {
+        let error = *buffer.first().context("failed to get error")?;
+        let deleted = u64::from_le_bytes(
+            buffer
+                .get(1..9)
+                .context("failed to get deleted count")?
+                .try_into()?,
+        );
+        Ok(DeleteResponse { error, deleted })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip_basic() {
+        let original = DeleteResponse {
+            error: DeleteResponseError::NoError as u8,
+            deleted: 3,
+        };
+        let serialized = original.serialize().unwrap();
+        let deserialized = DeleteResponse::deserialize(&serialized).unwrap();
+        assert_eq!(original.error, deserialized.error);
+        assert_eq!(original.deleted, deserialized.deleted);
+    }
+}
@@ -0,0 +1,45 @@
+use crate::messages::message::{MessagePayload, MessageType};
+use anyhow::{Context, Result};
+
+#[derive(Clone)]
+pub struct ExistsResponse {
+    pub count: u64,
+}
+
+/// Layout of the ExistsResponse
+/// | 8 bytes |
+/// | count   |
+/// count is how many of the requested keys are present, a key named
+/// twice in the request counts twice
+impl MessagePayload for ExistsResponse {
+    fn is_request(&self) -> bool {
+        false
+    }
+
+    fn get_message_type(&self) -> MessageType {
+        MessageType::Exists
+    }
+
+    fn serialize(&self) -> Result<Vec<u8>> {
+        Ok(self.count.to_le_bytes().to_vec())
+    }
+
+    fn deserialize(buffer: &[u8]) -> Result<Self> {
+        Ok(ExistsResponse {
+            count: u64::from_le_bytes(buffer.try_into().context("Invalid buffer size")?),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip_basic() {
+        let response = ExistsResponse { count: 2 };
+        let serialized = response.serialize().unwrap();
+        let deserialized = ExistsResponse::deserialize(&serialized).unwrap();
+        assert_eq!(response.count, deserialized.count);
+    }
+}
@@ -0,0 +1,60 @@
+use crate::messages::message::{MessagePayload, MessageType};
+use anyhow::{Context, Result};
+use int_enum::IntEnum;
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, IntEnum, PartialEq, Eq)]
+pub enum ExpireResponseError {
+    NoError = 0,
+    Error = 1,
+    /// A key is held by a prepared cross-shard transaction, nothing was written
+    Locked = 2,
+}
+
+#[derive(Clone)]
+pub struct ExpireResponse {
+    pub error: u8,
+    /// Whether the expiry was changed. False when the key doesn't exist,
+    /// or when removing the expiry of a key that has none.
+    pub updated: bool,
+}
+
+/// Layout of the ExpireResponse
+/// | 1 byte | 1 byte  |
+/// | error  | updated |
+impl MessagePayload for ExpireResponse {
+    fn is_request(&self) -> bool {
+        false
+    }
+
+    fn get_message_type(&self) -> MessageType {
+        MessageType::Expire
+    }
+
+    fn serialize(&self) -> Result<Vec<u8>> {
+        Ok(vec![self.error, self.updated as u8])
+    }
+
+    fn deserialize(buffer: &[u8]) -> Result<Self> {
+        let error = *buffer.first().context("failed to get error")?;
+        let updated = *buffer.get(1).context("failed to get updated")? == 1;
+        Ok(ExpireResponse { error, updated })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip_basic() {
+        let original = ExpireResponse {
+            error: ExpireResponseError::NoError as u8,
+            updated: true,
+        };
+        let serialized = original.serialize().unwrap();
+        let deserialized = ExpireResponse::deserialize(&serialized).unwrap();
+        assert_eq!(original.error, deserialized.error);
+        assert_eq!(original.updated, deserialized.updated);
+    }
+}
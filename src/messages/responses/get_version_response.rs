@@ -7,18 +7,23 @@ use int_enum::IntEnum;
 pub enum GetVersionResponseError {
     NoError = 0,
     KeyNotFound = 1,
+    /// The version is no longer in the peer's history, so the reader has
+    /// to resync from a full snapshot of the peer
+    FullResyncRequired = 2,
 }
 
 pub struct GetVersionResponse {
     pub version: u64,
+    pub mutation_type: u8,
     pub key: Vec<u8>,
     pub value: Vec<u8>,
     pub error: u8,
 }
 
 /// Layout of the GetVersionResponse as described in architecture
-/// | 1 byte  | 8 bytes | 2 bytes | N bytes | 2 bytes | M bytes |
-/// | error   | version | keylen  |   key   | valuelen|  value  |
+/// | 1 byte  | 8 bytes | 1 byte        | 2 bytes | N bytes | 4 bytes | M bytes |
+/// | error   | version | mutation type | keylen  |   key   | valuelen|  value  |
+/// value is the mutation specific payload, e.g. empty for a delete
 /// Integers are are always encoded in little-endian order
 impl MessagePayload for GetVersionResponse {
     fn get_message_type(&self) -> MessageType {
@@ -33,8 +38,9 @@ impl MessagePayload for GetVersionResponse {
         let mut buffer = Vec::new();
         buffer.push(self.error);
         let key_len = u16::try_from(self.key.len()).context("key length overflow")?;
-        let value_len = u16::try_from(self.value.len()).context("value length overflow")?;
+        let value_len = u32::try_from(self.value.len()).context("value length overflow")?;
         buffer.extend_from_slice(&self.version.to_le_bytes());
+        buffer.push(self.mutation_type);
         buffer.extend_from_slice(&key_len.to_le_bytes());
         buffer.extend_from_slice(&self.key);
         buffer.extend_from_slice(&value_len.to_le_bytes());
@@ -50,29 +56,31 @@ impl MessagePayload for GetVersionResponse {
                 .context("failed to get version")?
                 .try_into()?,
         );
+        let mutation_type = *buffer.get(9).context("failed to get mutation type")?;
         let key_len = u16::from_le_bytes(
             buffer
-                .get(9..11)
+                .get(10..12)
                 .context("failed to get key length")?
                 .try_into()?,
         ) as usize;
         let key = buffer
-            .get(11..11 + key_len)
+            .get(12..12 + key_len)
             .context("failed to get key")?
             .to_vec();
-        let value_len = u16::from_le_bytes(
+        let value_len = u32::from_le_bytes(
             buffer
-                .get(11 + key_len..11 + key_len + 2)
+                .get(12 + key_len..12 + key_len + 4)
                 .context("failed to get value length")?
                 .try_into()?,
         ) as usize;
         let value = buffer
-            .get(11 + key_len + 2..11 + key_len + 2 + value_len)
+            .get(12 + key_len + 4..12 + key_len + 4 + value_len)
             .context("failed to get value")?
             .to_vec();
 
         Ok(GetVersionResponse {
             version,
+            mutation_type,
             error,
             key,
             value,
@@ -90,12 +98,14 @@ mod tests {
         let original = GetVersionResponse {
             error: GetVersionResponseError::NoError as u8,
             version: 1,
+            mutation_type: 0,
             key: b"key".to_vec(),
             value: b"value".to_vec(),
         };
         let serialized = original.serialize().unwrap();
         let deserialized = GetVersionResponse::deserialize(&serialized).unwrap();
         assert_eq!(original.version, deserialized.version);
+        assert_eq!(original.mutation_type, deserialized.mutation_type);
         assert_eq!(original.key, deserialized.key);
         assert_eq!(original.value, deserialized.value);
     }
@@ -113,6 +123,7 @@ mod tests {
             let original = GetVersionResponse {
                 error: GetVersionResponseError::NoError as u8,
                 version: rng.gen(),
+                mutation_type: rng.gen(),
                 key,
                 value,
             };
@@ -121,6 +132,7 @@ mod tests {
 
             assert_eq!(original.error, deserialized.error);
             assert_eq!(original.version, deserialized.version);
+            assert_eq!(original.mutation_type, deserialized.mutation_type);
             assert_eq!(original.key, deserialized.key);
             assert_eq!(original.value, deserialized.value);
         }
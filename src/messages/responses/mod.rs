@@ -1,7 +1,17 @@
 pub mod announce_shard_response;
+pub mod bg_save_response;
+pub mod command_response;
+pub mod delete_response;
+pub mod exists_response;
+pub mod expire_response;
 pub mod get_client_shard_info_response;
 pub mod get_shared_peers_response;
 pub mod get_version_response;
 pub mod query_version_response;
 pub mod read_response;
+pub mod replica_ack_response;
+pub mod sync_response;
+pub mod transaction_response;
+pub mod ttl_response;
+pub mod two_phase_response;
 pub mod write_response;
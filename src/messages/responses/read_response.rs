@@ -7,18 +7,28 @@ use int_enum::IntEnum;
 pub enum ReadResponseError {
     NoError = 0,
     KeyNotFound = 1,
+    /// The key holds a value that isn't a string
+    WrongType = 2,
+    /// The shard did not replicate the session's version in time, read from
+    /// the write shard instead
+    Stale = 3,
+    /// The shard's storage engine failed to load the key
+    StorageFailed = 4,
 }
 
-#[derive(Clone)]
+#[derive(Debug, Clone)]
 pub struct ReadResponse {
     pub key: Vec<u8>,
     pub value: Vec<u8>,
     pub error: u8,
+    /// Version of the last write to the key, 0 if it doesn't exist. A stale
+    /// shard sends the version it has replicated up to instead.
+    pub version: u64,
 }
 
 /// Layout of the ReadResponse
-/// | 1 byte | 2 bytes | N bytes| 2 bytes | M bytes |
-/// | error  | keylen  |   key  | valuelen|  value  |
+/// | 1 byte | 2 bytes | N bytes| 4 bytes | M bytes | 8 bytes |
+/// | error  | keylen  |   key  | valuelen|  value  | version |
 /// Integers are are always encoded in little-endian order
 impl MessagePayload for ReadResponse {
     fn is_request(&self) -> bool {
@@ -33,11 +43,12 @@ impl MessagePayload for ReadResponse {
         let mut buffer = Vec::new();
         buffer.push(self.error);
         let key_len = u16::try_from(self.key.len()).context("key length overflow")?;
-        let value_len = u16::try_from(self.value.len()).context("value length overflow")?;
+        let value_len = u32::try_from(self.value.len()).context("value length overflow")?;
         buffer.extend_from_slice(&key_len.to_le_bytes());
         buffer.extend_from_slice(&self.key);
         buffer.extend_from_slice(&value_len.to_le_bytes());
         buffer.extend_from_slice(&self.value);
+        buffer.extend_from_slice(&self.version.to_le_bytes());
         Ok(buffer)
     }
 
@@ -53,16 +64,28 @@ impl MessagePayload for ReadResponse {
             .get(3..3 + key_len)
             .context("failed to get key")?
             .to_vec();
-        let value_len = u16::from_le_bytes(
+        let value_len = u32::from_le_bytes(
             buffer
-                .get(3 + key_len..3 + key_len + 2)
+                .get(3 + key_len..3 + key_len + 4)
                 .context("failed to get value length")?
                 .try_into()?,
         ) as usize;
+        let value_end = 3 + key_len + 4 + value_len;
         let value = buffer
-            .get(3 + key_len + 2..3 + key_len + 2 + value_len)
+            .get(3 + key_len + 4..value_end)
             .context("failed to get value")?
             .to_vec();
-        Ok(ReadResponse { error, key, value })
+        let version = u64::from_le_bytes(
+            buffer
+                .get(value_end..value_end + 8)
+                .context("failed to get version")?
+                .try_into()?,
+        );
+        Ok(ReadResponse {
+            error,
+            key,
+            value,
+            version,
+        })
     }
 }
@@ -0,0 +1,35 @@
+use crate::messages::message::{MessagePayload, MessageType};
+use anyhow::Result;
+
+#[derive(Clone)]
+pub struct ReplicaAckResponse {}
+
+impl MessagePayload for ReplicaAckResponse {
+    fn is_request(&self) -> bool {
+        false
+    }
+
+    fn get_message_type(&self) -> MessageType {
+        MessageType::ReplicaAck
+    }
+
+    fn serialize(&self) -> Result<Vec<u8>> {
+        Ok(Vec::new())
+    }
+
+    fn deserialize(_buffer: &[u8]) -> Result<Self> {
+        Ok(ReplicaAckResponse {})
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip() {
+        let original = ReplicaAckResponse {};
+        let serialized = original.serialize().unwrap();
+        let _deserialized = ReplicaAckResponse::deserialize(&serialized).unwrap();
+    }
+}
@@ -0,0 +1,108 @@
+use crate::messages::message::{MessagePayload, MessageType};
+use anyhow::{Context, Result};
+use int_enum::IntEnum;
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, IntEnum, PartialEq, Eq)]
+pub enum SyncResponseError {
+    NoError = 0,
+    /// The peer failed to snapshot its keyspace
+    SnapshotFailed = 1,
+    /// The peer no longer keeps the snapshot the chunk was asked from, so
+    /// the sync has to start over
+    SnapshotGone = 2,
+}
+
+/// Chunk of a full snapshot of the peer's keyspace. The chunks of a
+/// snapshot all carry its version, and the reader puts them together
+/// before it replaces its keyspace.
+#[derive(Clone)]
+pub struct SyncResponse {
+    pub error: u8,
+    /// Version the snapshot is at
+    pub version: u64,
+    /// Number of entries of the snapshot before the ones in the chunk
+    pub offset: u64,
+    /// Number of entries in the whole snapshot
+    pub total: u64,
+    /// Whether this is the last chunk of the snapshot
+    pub done: bool,
+    /// Entries laid out as in the snapshot file format
+    pub entries: Vec<u8>,
+}
+
+/// Layout of the SyncResponse
+/// | 1 byte | 8 bytes | 8 bytes | 8 bytes | 1 byte | N bytes |
+/// | error  | version | offset  | total   | done   | entries |
+/// entries is empty when error is set
+/// Integers are always encoded in little-endian order
+impl MessagePayload for SyncResponse {
+    fn is_request(&self) -> bool {
+        false
+    }
+
+    fn get_message_type(&self) -> MessageType {
+        MessageType::Sync
+    }
+
+    fn serialize(&self) -> Result<Vec<u8>> {
+        let mut buffer = vec![self.error];
+        buffer.extend_from_slice(&self.version.to_le_bytes());
+        buffer.extend_from_slice(&self.offset.to_le_bytes());
+        buffer.extend_from_slice(&self.total.to_le_bytes());
+        buffer.push(self.done as u8);
+        buffer.extend_from_slice(&self.entries);
+        Ok(buffer)
+    }
+
+    fn deserialize(buffer: &[u8]) -> Result<Self> {
+        let error = *buffer.first().context("failed to get error")?;
+        let u64_at = |start: usize, field: &str| -> Result<u64> {
+            Ok(u64::from_le_bytes(
+                buffer
+                    .get(start..start + 8)
+                    .with_context(|| format!("failed to get {}", field))?
+                    .try_into()?,
+            ))
+        };
+        let version = u64_at(1, "version")?;
+        let offset = u64_at(9, "offset")?;
+        let total = u64_at(17, "total")?;
+        let done = *buffer.get(25).context("failed to get done")? != 0;
+        Ok(SyncResponse {
+            error,
+            version,
+            offset,
+            total,
+            done,
+            entries: buffer[26..].to_vec(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip_basic() {
+        let original = SyncResponse {
+            error: SyncResponseError::NoError as u8,
+            version: 7,
+            offset: 100,
+            total: 250,
+            done: false,
+            entries: b"entries".to_vec(),
+        };
+        let serialized = original.serialize().unwrap();
+        let deserialized = SyncResponse::deserialize(&serialized).unwrap();
+        assert_eq!(original.error, deserialized.error);
+        assert_eq!(original.version, deserialized.version);
+        assert_eq!(original.offset, deserialized.offset);
+        assert_eq!(original.total, deserialized.total);
+        assert_eq!(original.done, deserialized.done);
+        assert_eq!(original.entries, deserialized.entries);
+        assert!(SyncResponse::deserialize(&[]).is_err());
+        assert!(SyncResponse::deserialize(&serialized[..20]).is_err());
+    }
+}
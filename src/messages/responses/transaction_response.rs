@@ -0,0 +1,55 @@
+use crate::messages::message::{MessagePayload, MessageType};
+use crate::messages::responses::command_response::Reply;
+use anyhow::Result;
+
+/// Reply to a transaction: an array with the reply of each command, nil if
+/// a watched key changed, or an error if a command failed and nothing was written
+#[derive(Debug, Clone)]
+pub struct TransactionResponse {
+    pub reply: Reply,
+}
+
+/// Layout of the TransactionResponse
+/// | 1 byte     | ...   |
+/// | reply type | reply |
+/// The reply is laid out the same way as in a CommandResponse
+impl MessagePayload for TransactionResponse {
+    fn is_request(&self) -> bool {
+        false
+    }
+
+    fn get_message_type(&self) -> MessageType {
+        MessageType::Transaction
+    }
+
+    fn serialize(&self) -> Result<Vec<u8>> {
+        let mut buffer = Vec::new();
+        self.reply.serialize_into(&mut buffer)?;
+        Ok(buffer)
+    }
+
+    fn deserialize(buffer: &[u8]) -> Result<Self> {
+        let mut offset = 0;
+        let reply = Reply::deserialize_from(buffer, &mut offset)?;
+        Ok(TransactionResponse { reply })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip_basic() {
+        for reply in [
+            Reply::Nil,
+            Reply::error("EXECABORT Transaction discarded"),
+            Reply::Array(vec![Reply::Integer(2), Reply::ok()]),
+        ] {
+            let original = TransactionResponse { reply };
+            let serialized = original.serialize().unwrap();
+            let deserialized = TransactionResponse::deserialize(&serialized).unwrap();
+            assert_eq!(original.reply, deserialized.reply);
+        }
+    }
+}
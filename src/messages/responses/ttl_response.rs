@@ -0,0 +1,54 @@
+use crate::messages::message::{MessagePayload, MessageType};
+use anyhow::{Context, Result};
+
+/// ttl of a key that doesn't exist
+#[allow(unused)]
+pub const TTL_KEY_NOT_FOUND: i64 = -2;
+/// ttl of a key that never expires
+#[allow(unused)]
+pub const TTL_NO_EXPIRY: i64 = -1;
+
+#[derive(Clone)]
+pub struct TtlResponse {
+    /// Milliseconds until the key expires, or one of the negative TTL_ constants
+    pub ttl: i64,
+}
+
+/// Layout of the TtlResponse
+/// | 8 bytes |
+/// | ttl     |
+/// Integers are always encoded in little-endian order
+impl MessagePayload for TtlResponse {
+    fn is_request(&self) -> bool {
+        false
+    }
+
+    fn get_message_type(&self) -> MessageType {
+        MessageType::Ttl
+    }
+
+    fn serialize(&self) -> Result<Vec<u8>> {
+        Ok(self.ttl.to_le_bytes().to_vec())
+    }
+
+    fn deserialize(buffer: &[u8]) -> Result<Self> {
+        Ok(TtlResponse {
+            ttl: i64::from_le_bytes(buffer.try_into().context("Invalid buffer size")?),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip_basic() {
+        for ttl in [TTL_KEY_NOT_FOUND, TTL_NO_EXPIRY, 0, 1500] {
+            let response = TtlResponse { ttl };
+            let serialized = response.serialize().unwrap();
+            let deserialized = TtlResponse::deserialize(&serialized).unwrap();
+            assert_eq!(response.ttl, deserialized.ttl);
+        }
+    }
+}
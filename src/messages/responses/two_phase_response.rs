@@ -0,0 +1,94 @@
+use crate::messages::message::{MessagePayload, MessageType};
+use crate::messages::responses::command_response::Reply;
+use anyhow::{Context, Result};
+use int_enum::IntEnum;
+
+/// Where a write shard's part of a cross-shard transaction stands
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, IntEnum, PartialEq, Eq)]
+pub enum TwoPhaseOutcome {
+    /// Voted to commit and holding its keys, the outcome isn't known yet
+    Prepared = 0,
+    Committed = 1,
+    Aborted = 2,
+    /// Was asked to commit a transaction it has no record of, so it can't
+    /// tell whether its part was ever applied
+    Unknown = 3,
+}
+
+#[derive(Debug, Clone)]
+pub struct TwoPhaseResponse {
+    pub txn_id: u128,
+    pub outcome: u8,
+    /// Replies of the shard's commands once committed, or why it voted to abort:
+    /// nil if a watched key changed, an error if a command failed or a key was locked
+    pub reply: Reply,
+}
+
+/// Layout of the TwoPhaseResponse
+/// | 16 bytes | 1 byte  | 1 byte     | ...   |
+/// | txn id   | outcome | reply type | reply |
+/// The reply is laid out the same way as in a CommandResponse
+impl MessagePayload for TwoPhaseResponse {
+    fn is_request(&self) -> bool {
+        false
+    }
+
+    fn get_message_type(&self) -> MessageType {
+        MessageType::TwoPhase
+    }
+
+    fn serialize(&self) -> Result<Vec<u8>> {
+        let mut buffer = Vec::new();
+        buffer.extend_from_slice(&self.txn_id.to_le_bytes());
+        buffer.push(self.outcome);
+        self.reply.serialize_into(&mut buffer)?;
+        Ok(buffer)
+    }
+
+    fn deserialize(buffer: &[u8]) -> Result<Self> {
+        let txn_id = u128::from_le_bytes(
+            buffer
+                .get(0..16)
+                .context("failed to get txn id")?
+                .try_into()?,
+        );
+        let outcome = *buffer.get(16).context("failed to get outcome")?;
+        let mut offset = 17;
+        let reply = Reply::deserialize_from(buffer, &mut offset)?;
+        Ok(TwoPhaseResponse {
+            txn_id,
+            outcome,
+            reply,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip_basic() {
+        for (outcome, reply) in [
+            (TwoPhaseOutcome::Prepared, Reply::Nil),
+            (TwoPhaseOutcome::Aborted, Reply::error("LOCKED")),
+            (TwoPhaseOutcome::Unknown, Reply::error("ERR unknown")),
+            (
+                TwoPhaseOutcome::Committed,
+                Reply::Array(vec![Reply::Integer(1), Reply::ok()]),
+            ),
+        ] {
+            let original = TwoPhaseResponse {
+                txn_id: 42,
+                outcome: outcome as u8,
+                reply,
+            };
+            let serialized = original.serialize().unwrap();
+            let deserialized = TwoPhaseResponse::deserialize(&serialized).unwrap();
+            assert_eq!(original.txn_id, deserialized.txn_id);
+            assert_eq!(original.outcome, deserialized.outcome);
+            assert_eq!(original.reply, deserialized.reply);
+        }
+    }
+}
@@ -7,16 +7,33 @@ use int_enum::IntEnum;
 pub enum WriteResponseError {
     NoError = 0,
     Error = 1,
+    /// The write's condition did not hold, nothing was written
+    PreconditionFailed = 2,
+    /// A key is held by a prepared cross-shard transaction, nothing was written
+    Locked = 3,
+    /// The write was committed, but fewer read shards than the write quorum
+    /// confirmed applying it in time
+    QuorumTimeout = 4,
+    /// The shard is over its memory limit and has nothing it may evict,
+    /// nothing was written
+    OutOfMemory = 5,
 }
 
-#[derive(Clone)]
+#[derive(Debug, Clone)]
 pub struct WriteResponse {
     pub error: u8,
+    /// Version of the write on success. When a precondition fails it is the
+    /// key's current version instead, 0 if the key doesn't exist.
+    pub version: u64,
+    /// Number the info server gave the write shard, so a client knows whose
+    /// version a successful write became and can read its own writes
+    pub writer_number: u16,
 }
 
 /// Layout of the WriteResponse
-/// | 1 byte |
-/// | error |
+/// | 1 byte | 8 bytes | 2 bytes       |
+/// | error  | version | writer number |
+/// Integers are always encoded in little-endian order
 impl MessagePayload for WriteResponse {
     fn is_request(&self) -> bool {
         false
@@ -27,12 +44,49 @@ impl MessagePayload for WriteResponse {
     }
 
     fn serialize(&self) -> Result<Vec<u8>> {
-        let buffer = vec![self.error];
+        let mut buffer = vec![self.error];
+        buffer.extend_from_slice(&self.version.to_le_bytes());
+        buffer.extend_from_slice(&self.writer_number.to_le_bytes());
         Ok(buffer)
     }
 
     fn deserialize(buffer: &[u8]) -> Result<Self> {
         let error = *buffer.first().context("failed to get error")?;
-        Ok(WriteResponse { error })
+        let version = u64::from_le_bytes(
+            buffer
+                .get(1..9)
+                .context("failed to get version")?
+                .try_into()?,
+        );
+        let writer_number = u16::from_le_bytes(
+            buffer
+                .get(9..11)
+                .context("failed to get writer number")?
+                .try_into()?,
+        );
+        Ok(WriteResponse {
+            error,
+            version,
+            writer_number,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip_basic() {
+        let original = WriteResponse {
+            error: WriteResponseError::PreconditionFailed as u8,
+            version: 42,
+            writer_number: 3,
+        };
+        let serialized = original.serialize().unwrap();
+        let deserialized = WriteResponse::deserialize(&serialized).unwrap();
+        assert_eq!(original.error, deserialized.error);
+        assert_eq!(original.version, deserialized.version);
+        assert_eq!(original.writer_number, deserialized.writer_number);
     }
 }
@@ -1,42 +1,144 @@
-use crate::io::router::{RouterBuilder, RouterHandler};
+use crate::io::router::{HandlerResponse, RouterBuilder, RouterHandler};
 use anyhow::{Ok, Result};
+use clap::Parser;
 use messages::requests::announce_shard_request::ShardType;
 use messages::requests::get_client_shard_info_request::GetClientShardInfoRequest;
 use messages::requests::write_request::WriteRequest;
 use messages::responses::get_client_shard_info_response::GetClientShardInfoResponse;
 use messages::responses::write_response::WriteResponse;
 use rand::Rng;
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 use std::net::{Ipv6Addr, SocketAddrV6};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
+use tokio::sync::oneshot;
 use tokio::time;
+#[allow(unused)]
+mod commands;
 mod integration;
 mod io;
 mod messages;
+mod storage;
 mod utils;
 use crate::messages::{
     requests::{
-        announce_shard_request::AnnounceShardRequest,
+        announce_shard_request::AnnounceShardRequest, bg_save_request::BgSaveRequest,
+        command_request::CommandRequest, delete_request::DeleteRequest,
+        exists_request::ExistsRequest, expire_request::ExpireRequest,
         get_shared_peers_request::GetSharedPeersRequest, get_version_request::GetVersionRequest,
         query_version_request::QueryVersionRequest, read_request::ReadRequest,
+        replica_ack_request::ReplicaAckRequest, sync_request::SyncRequest,
+        transaction_request::TransactionRequest, ttl_request::TtlRequest,
+        two_phase_request::TwoPhaseRequest,
     },
     responses::{
         announce_shard_response::AnnounceShardResponse,
+        bg_save_response::{BgSaveResponse, BgSaveResponseError},
+        command_response::{CommandResponse, Reply},
+        delete_response::DeleteResponse,
+        exists_response::ExistsResponse,
+        expire_response::ExpireResponse,
         get_shared_peers_response::GetSharedPeersResponse,
-        get_version_response::GetVersionResponse, query_version_response::QueryVersionResponse,
-        read_response::ReadResponse,
+        get_version_response::{GetVersionResponse, GetVersionResponseError},
+        query_version_response::QueryVersionResponse,
+        read_response::{ReadResponse, ReadResponseError},
+        replica_ack_response::ReplicaAckResponse,
+        sync_response::{SyncResponse, SyncResponseError},
+        transaction_response::TransactionResponse,
+        ttl_response::{TtlResponse, TTL_KEY_NOT_FOUND, TTL_NO_EXPIRY},
+        two_phase_response::TwoPhaseResponse,
     },
 };
+use crate::storage::engine::{open_engine, EngineKind};
+use crate::storage::history::VersionHistory;
+use crate::storage::keyspace::{now_millis, Keyspace};
+use crate::storage::mutation::Mutation;
+use crate::storage::snapshot::{Snapshot, Snapshotter};
+use crate::storage::store::Store;
 use crate::utils::constants::MAIN_INSTANCE_IP_PORT;
 
+#[derive(Parser, Debug)]
+pub struct ReadShardArgs {
+    /// Path of the snapshot file; snapshots are disabled when unset
+    #[arg(long)]
+    snapshot_path: Option<PathBuf>,
+
+    /// Seconds between automatic snapshots
+    #[arg(long, default_value_t = 300)]
+    snapshot_interval: u64,
+
+    /// Engine the replicated keys are kept in
+    #[arg(long, value_enum, default_value_t = EngineKind::Memory)]
+    storage_engine: EngineKind,
+
+    /// Path of the disk engine's data file
+    #[arg(long)]
+    data_path: Option<PathBuf>,
+
+    /// Number of versions kept for other read shards to catch up from; a
+    /// read shard that falls further behind resyncs from a full snapshot
+    #[arg(long, default_value_t = 100_000)]
+    backlog_size: usize,
+}
+
+/// How long a read waits for the shard to replicate the session's version
+/// before the client is told to read from the write shard
+const READ_WAIT_TIMEOUT_MS: u64 = 1_000;
+
+/// How long a read shard waits for the snapshot it asked for before asking again
+const SYNC_TIMEOUT_MS: u64 = 10_000;
+
+/// Most versions fetched from a peer in one replication tick
+const MAX_FETCHES_PER_TICK: usize = 1_000;
+
+/// Where a read shard is in resyncing from a full snapshot, which it has to
+/// do once the next version is gone from its peers' history
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum Resync {
+    #[default]
+    Idle,
+    /// Replication is paused until a snapshot is asked for
+    Needed,
+    /// A snapshot was asked for at the given unix milliseconds
+    Requested { at: u64 },
+}
+
+/// Chunks of a snapshot received so far in a resync, put together into the
+/// keyspace that replaces the shard's once the last one arrives
+#[derive(Debug, Default)]
+struct SyncAssembly {
+    version: u64,
+    /// Number of entries received, which the next chunk starts after
+    received: u64,
+    keyspace: Keyspace,
+}
+
+/// Read whose session token is ahead of the versions the shard has replicated
+#[derive(Debug)]
+struct WaitingRead {
+    key: Vec<u8>,
+    min_version: u64,
+    deadline: u64,
+    sender: oneshot::Sender<ReadResponse>,
+}
+
 #[derive(Clone, Debug)]
 pub struct ReadShard {
     writer_id: Arc<Mutex<u16>>,
     peers: Arc<Mutex<Vec<SocketAddrV6>>>,
-    requested_version: Arc<Mutex<u64>>,
-    current_version: Arc<Mutex<u64>>,
-    history: Arc<Mutex<Vec<(String, String)>>>,
-    data: Arc<Mutex<HashMap<String, String>>>,
+    /// Latest version a peer said it has, which replication catches up to
+    requested_version: Arc<AtomicU64>,
+    /// Replicated keys and version history; only replication writes to it
+    store: Arc<Store>,
+    snapshotter: Option<Arc<Snapshotter>>,
+    /// Reads parked until replication reaches their session's version
+    waiting_reads: Arc<Mutex<Vec<WaitingRead>>>,
+    resync: Arc<Mutex<Resync>>,
+    /// Snapshot being resynced from, until its last chunk arrives
+    assembly: Arc<Mutex<Option<SyncAssembly>>>,
+    /// Versions that arrived ahead of the next one to apply
+    fetched: Arc<Mutex<BTreeMap<u64, Mutation>>>,
 }
 
 impl RouterHandler for ReadShard {
@@ -46,25 +148,31 @@ impl RouterHandler for ReadShard {
         *writer_id = writer_number;
     }
 
-    fn handle_read_request(&self, req: &ReadRequest) -> ReadResponse {
+    fn handle_read_request(&self, req: &ReadRequest) -> HandlerResponse<ReadResponse> {
         println!(
             "handling request for key: {}",
             String::from_utf8_lossy(&req.key)
         );
-        let key = String::from_utf8_lossy(&req.key).into_owned();
-        let value = self.data.lock().unwrap().get(&key).cloned();
-        match value {
-            Some(value) => ReadResponse {
-                error: 0,
-                key: req.key.clone(),
-                value: value.into_bytes(),
-            },
-            None => ReadResponse {
-                error: 1,
-                key: req.key.clone(),
-                value: Vec::new(),
-            },
+        // the keyspace fell behind what the peers still have history for, so
+        // reads go to the write shard until it is resynced from a snapshot
+        if self.is_resyncing() {
+            return HandlerResponse::Ready(self.stale(req.key.clone()));
+        }
+        // replication serves parked reads under this lock after publishing a
+        // version, so a read checked under it cannot miss the version it waits for
+        let mut waiting_reads = self.waiting_reads.lock().unwrap();
+        if req.min_version <= self.store.version() {
+            drop(waiting_reads);
+            return HandlerResponse::Ready(self.read(&req.key));
         }
+        let (sender, receiver) = oneshot::channel();
+        waiting_reads.push(WaitingRead {
+            key: req.key.clone(),
+            min_version: req.min_version,
+            deadline: now_millis() + READ_WAIT_TIMEOUT_MS,
+            sender,
+        });
+        HandlerResponse::Deferred(receiver)
     }
 
     fn handle_get_shared_peers_response(&self, res: &GetSharedPeersResponse) {
@@ -78,33 +186,58 @@ impl RouterHandler for ReadShard {
 
     fn handle_query_version_response(&self, res: &QueryVersionResponse) {
         println!("---received version: {}", res.version);
-        let mut requested_version = self.requested_version.lock().unwrap();
-        *requested_version = res.version;
+        self.requested_version.store(res.version, Ordering::Relaxed);
     }
 
     fn handle_get_version_response(&self, res: &GetVersionResponse) {
-        let mut current_version = self.current_version.lock().unwrap();
-
-        if res.error == 0 && res.version == *current_version + 1 {
-            *current_version = res.version;
-            let mut history = self.history.lock().unwrap();
-            let mut data = self.data.lock().unwrap();
-            data.insert(
-                String::from_utf8_lossy(&res.key).into_owned(),
-                String::from_utf8_lossy(&res.value).into_owned(),
-            );
-            println!(
-                "-- caught up key: {}, value: {}",
-                String::from_utf8_lossy(&res.key),
-                String::from_utf8_lossy(&res.value)
-            );
-            history.push((
-                String::from_utf8_lossy(&res.key).into_owned(),
-                String::from_utf8_lossy(&res.value).into_owned(),
-            ));
-
-            let mut requested_version = self.requested_version.lock().unwrap();
-            *requested_version = *current_version;
+        if res.error == GetVersionResponseError::FullResyncRequired as u8 {
+            let mut resync = self.resync.lock().unwrap();
+            if *resync == Resync::Idle {
+                println!(
+                    "version {} is gone from the peer's history, resyncing from a snapshot",
+                    res.version
+                );
+                *resync = Resync::Needed;
+            }
+            return;
+        }
+        if res.error != 0 || res.version <= self.store.version() {
+            return;
+        }
+        let mutation =
+            match Mutation::from_parts(res.mutation_type, res.key.clone(), res.value.clone()) {
+                std::result::Result::Ok(mutation) => mutation,
+                Err(e) => {
+                    eprintln!("Failed to decode version {}: {:?}", res.version, e);
+                    return;
+                }
+            };
+        println!(
+            "-- caught up {:?} key: {}, value: {}",
+            mutation.mutation_type(),
+            String::from_utf8_lossy(&res.key),
+            String::from_utf8_lossy(&res.value)
+        );
+        let mut fetched = self.fetched.lock().unwrap();
+        fetched.insert(res.version, mutation);
+
+        // versions are fetched several at a time and can arrive in any order.
+        // Replication is the only writer, and holding every key keeps a
+        // resync from moving the version in between.
+        let mut writer = self.store.exclusive();
+        let applied = writer.version();
+        while let Some(mutation) = fetched.remove(&(writer.version() + 1)) {
+            if let Err(e) = writer.commit(mutation) {
+                eprintln!("Failed to apply version {}: {:?}", writer.version() + 1, e);
+                break;
+            }
+        }
+        let version = writer.version();
+        drop(writer);
+        drop(fetched);
+        if version > applied {
+            self.requested_version.fetch_max(version, Ordering::Relaxed);
+            self.serve_waiting_reads(now_millis());
         }
     }
 
@@ -121,33 +254,64 @@ impl RouterHandler for ReadShard {
 
     fn handle_query_version_request(&self, _req: &QueryVersionRequest) -> QueryVersionResponse {
         QueryVersionResponse {
-            version: *self.current_version.lock().unwrap(),
+            version: self.store.version(),
         }
     }
 
     fn handle_get_version_request(&self, req: &GetVersionRequest) -> GetVersionResponse {
-        let res = self.history.lock().unwrap();
+        let res = self.store.history();
 
         println!("updating version: {}", req.version);
 
-        if req.version <= *self.current_version.lock().unwrap() {
-            GetVersionResponse {
+        match res.get(req.version) {
+            Some(mutation) => GetVersionResponse {
                 error: 0,
-                key: (res[req.version as usize - 1].0).as_bytes().to_vec(),
-                value: (res[req.version as usize - 1].1).as_bytes().to_vec(),
+                mutation_type: mutation.mutation_type() as u8,
+                key: mutation.key().to_vec(),
+                value: mutation.value(),
                 version: req.version,
-            }
-        } else {
-            GetVersionResponse {
-                error: 1,
+            },
+            None => GetVersionResponse {
+                error: if res.is_compacted(req.version) {
+                    GetVersionResponseError::FullResyncRequired as u8
+                } else {
+                    GetVersionResponseError::KeyNotFound as u8
+                },
+                mutation_type: 0,
                 key: Vec::new(),
                 value: Vec::new(),
                 version: req.version,
+            },
+        }
+    }
+
+    fn handle_bg_save_request(&self, _req: &BgSaveRequest) -> BgSaveResponse {
+        self.bg_save()
+    }
+
+    fn handle_exists_request(&self, req: &ExistsRequest) -> ExistsResponse {
+        let data = match self.store.view(req.keys.iter().map(Vec::as_slice)) {
+            std::result::Result::Ok(data) => data,
+            Err(e) => {
+                eprintln!("Failed to load keys: {:?}", e);
+                return ExistsResponse { count: 0 };
             }
+        };
+        let now = now_millis();
+        ExistsResponse {
+            count: req
+                .keys
+                .iter()
+                .filter(|key| data.contains_key(key, now))
+                .count() as u64,
         }
     }
 
-    fn handle_write_request(&self, _req: &WriteRequest) -> WriteResponse {
+    fn handle_delete_request(&self, _req: &DeleteRequest) -> DeleteResponse {
+        unimplemented!()
+    }
+
+    fn handle_write_request(&self, _req: &WriteRequest) -> HandlerResponse<WriteResponse> {
         unimplemented!()
     }
 
@@ -169,6 +333,141 @@ impl RouterHandler for ReadShard {
     fn handle_write_response(&self, _res: &WriteResponse) {
         unimplemented!()
     }
+
+    fn handle_bg_save_response(&self, _res: &BgSaveResponse) {
+        unimplemented!()
+    }
+
+    fn handle_delete_response(&self, _res: &DeleteResponse) {
+        unimplemented!()
+    }
+
+    fn handle_exists_response(&self, _res: &ExistsResponse) {
+        unimplemented!()
+    }
+
+    fn handle_expire_request(&self, _req: &ExpireRequest) -> ExpireResponse {
+        unimplemented!()
+    }
+
+    fn handle_expire_response(&self, _res: &ExpireResponse) {
+        unimplemented!()
+    }
+
+    fn handle_ttl_request(&self, req: &TtlRequest) -> TtlResponse {
+        let data = match self.store.view([req.key.as_slice()]) {
+            std::result::Result::Ok(data) => data,
+            Err(e) => {
+                eprintln!("Failed to load key: {:?}", e);
+                return TtlResponse {
+                    ttl: TTL_KEY_NOT_FOUND,
+                };
+            }
+        };
+        let now = now_millis();
+        let ttl = if !data.contains_key(&req.key, now) {
+            TTL_KEY_NOT_FOUND
+        } else {
+            match data.expires_at(&req.key) {
+                Some(expires_at) => i64::try_from(expires_at - now).unwrap_or(i64::MAX),
+                None => TTL_NO_EXPIRY,
+            }
+        };
+        TtlResponse { ttl }
+    }
+
+    fn handle_ttl_response(&self, _res: &TtlResponse) {
+        unimplemented!()
+    }
+
+    fn handle_command_request(&self, req: &CommandRequest) -> HandlerResponse<CommandResponse> {
+        let keys = commands::command_keys(&req.args);
+        let reply = match self.store.view(keys.iter().map(Vec::as_slice)) {
+            std::result::Result::Ok(data) => commands::execute_read(&data, &req.args, now_millis()),
+            Err(e) => {
+                eprintln!("Failed to load keys: {:?}", e);
+                Reply::error("ERR failed to load keys")
+            }
+        };
+        HandlerResponse::Ready(CommandResponse { reply })
+    }
+
+    fn handle_command_response(&self, _res: &CommandResponse) {
+        unimplemented!()
+    }
+
+    fn handle_transaction_request(&self, _req: &TransactionRequest) -> TransactionResponse {
+        unimplemented!()
+    }
+
+    fn handle_transaction_response(&self, _res: &TransactionResponse) {
+        unimplemented!()
+    }
+
+    fn handle_two_phase_request(&self, _req: &TwoPhaseRequest) -> TwoPhaseResponse {
+        unimplemented!()
+    }
+
+    fn handle_two_phase_response(&self, _res: &TwoPhaseResponse) {
+        unimplemented!()
+    }
+
+    fn handle_replica_ack_request(&self, _req: &ReplicaAckRequest) -> ReplicaAckResponse {
+        unimplemented!()
+    }
+
+    fn handle_replica_ack_response(&self, _res: &ReplicaAckResponse) {
+        // nothing to do
+    }
+
+    fn handle_sync_request(&self, req: &SyncRequest) -> SyncResponse {
+        let failed = |error: SyncResponseError| SyncResponse {
+            error: error as u8,
+            version: req.version,
+            offset: req.offset,
+            total: 0,
+            done: false,
+            entries: Vec::new(),
+        };
+        match self
+            .store
+            .snapshot_chunk(req.version, req.offset, now_millis())
+        {
+            std::result::Result::Ok(Some(chunk)) => SyncResponse {
+                error: SyncResponseError::NoError as u8,
+                version: chunk.version,
+                offset: chunk.offset,
+                total: chunk.total,
+                done: chunk.done,
+                entries: chunk.entries,
+            },
+            std::result::Result::Ok(None) => failed(SyncResponseError::SnapshotGone),
+            Err(e) => {
+                eprintln!("Failed to snapshot keyspace: {:?}", e);
+                failed(SyncResponseError::SnapshotFailed)
+            }
+        }
+    }
+
+    fn handle_sync_response(&self, res: &SyncResponse) {
+        // a chunk asked for again after it went unanswered may turn up late
+        if !self.is_resyncing() {
+            return;
+        }
+        match self.resync_from(res) {
+            std::result::Result::Ok(true) => {
+                *self.resync.lock().unwrap() = Resync::Idle;
+                self.serve_waiting_reads(now_millis());
+            }
+            // the next chunk is asked for on the next tick
+            std::result::Result::Ok(false) => *self.resync.lock().unwrap() = Resync::Needed,
+            Err(e) => {
+                eprintln!("Failed to resync: {:?}", e);
+                *self.assembly.lock().unwrap() = None;
+                *self.resync.lock().unwrap() = Resync::Needed;
+            }
+        }
+    }
 }
 
 impl Default for ReadShard {
@@ -182,23 +481,260 @@ impl ReadShard {
         ReadShard {
             writer_id: Arc::new(Mutex::new(0)),
             peers: Arc::new(Mutex::new(Vec::new())),
-            requested_version: Arc::new(Mutex::new(0)),
-            current_version: Arc::new(Mutex::new(0)),
-            history: Arc::new(Mutex::new(Vec::new())),
-            data: Arc::new(Mutex::new(HashMap::new())),
+            requested_version: Arc::default(),
+            store: Arc::default(),
+            snapshotter: None,
+            waiting_reads: Arc::default(),
+            resync: Arc::default(),
+            assembly: Arc::default(),
+            fetched: Arc::default(),
+        }
+    }
+
+    /// Restores the shard from its storage engine or its latest snapshot,
+    /// whichever is further along. Replication then resumes from there.
+    pub fn open(args: &ReadShardArgs) -> Result<ReadShard> {
+        let engine = open_engine(args.storage_engine, args.data_path.as_deref())?;
+        let mut version = engine.durable_version();
+        if let Some(snapshot_path) = &args.snapshot_path {
+            let snapshot = Snapshot::load(snapshot_path)?.unwrap_or_default();
+            if version < snapshot.version {
+                engine.restore(snapshot.keyspace, snapshot.version)?;
+                version = snapshot.version;
+            }
+        }
+        println!("restored read shard at version {}", version);
+
+        Ok(ReadShard {
+            writer_id: Arc::new(Mutex::new(0)),
+            peers: Arc::new(Mutex::new(Vec::new())),
+            requested_version: Arc::new(AtomicU64::new(version)),
+            store: Arc::new(Store::new(
+                engine,
+                VersionHistory::with_capacity(version + 1, args.backlog_size),
+            )),
+            snapshotter: args
+                .snapshot_path
+                .clone()
+                .map(|snapshot_path| Arc::new(Snapshotter::new(snapshot_path))),
+            waiting_reads: Arc::default(),
+            resync: Arc::default(),
+            assembly: Arc::default(),
+            fetched: Arc::default(),
+        })
+    }
+
+    /// Chunk of a snapshot to ask the write shard for now, if any: the shard
+    /// fell behind its peers' history or got the chunk before and hasn't
+    /// asked yet, or its last ask went unanswered
+    fn sync_request(&self, now: u64) -> Option<SyncRequest> {
+        let mut resync = self.resync.lock().unwrap();
+        match *resync {
+            Resync::Idle => return None,
+            Resync::Requested { at } if now < at + SYNC_TIMEOUT_MS => return None,
+            Resync::Needed | Resync::Requested { .. } => {}
+        }
+        *resync = Resync::Requested { at: now };
+        Some(match &*self.assembly.lock().unwrap() {
+            Some(assembly) => SyncRequest {
+                version: assembly.version,
+                offset: assembly.received,
+            },
+            None => SyncRequest {
+                version: 0,
+                offset: 0,
+            },
+        })
+    }
+
+    /// Versions to fetch from a peer, those after the applied one up to the
+    /// latest a peer has that haven't arrived yet
+    fn versions_to_fetch(&self) -> Vec<u64> {
+        let fetched = self.fetched.lock().unwrap();
+        let current_version = self.store.version();
+        let requested_version = self.requested_version.load(Ordering::Relaxed);
+        (current_version + 1..=requested_version)
+            .filter(|version| !fetched.contains_key(version))
+            .take(MAX_FETCHES_PER_TICK)
+            .collect()
+    }
+
+    /// Whether replication is paused for a resync from a snapshot
+    fn is_resyncing(&self) -> bool {
+        *self.resync.lock().unwrap() != Resync::Idle
+    }
+
+    /// Adds a chunk of the snapshot a peer sent to the ones before it. Once
+    /// the last one is in, the keyspace is replaced with the snapshot, unless
+    /// replication already got further while it was on its way. Returns
+    /// whether the resync is over.
+    fn resync_from(&self, res: &SyncResponse) -> Result<bool> {
+        if res.error != SyncResponseError::NoError as u8 {
+            anyhow::bail!("peer failed to snapshot with error code {}", res.error);
+        }
+        let mut assembly = self.assembly.lock().unwrap();
+        if res.offset == 0 {
+            *assembly = Some(SyncAssembly {
+                version: res.version,
+                ..SyncAssembly::default()
+            });
+        }
+        // chunks that aren't the next one are asked for again
+        let Some(next) = assembly
+            .as_mut()
+            .filter(|next| next.version == res.version && next.received == res.offset)
+        else {
+            return Ok(false);
+        };
+        next.received += Snapshot::read_entries(&res.entries, &mut next.keyspace)? as u64;
+        println!(
+            "received {} of {} snapshot entries at version {}",
+            next.received, res.total, res.version
+        );
+        if !res.done {
+            return Ok(false);
+        }
+        let SyncAssembly {
+            version,
+            received,
+            keyspace,
+        } = assembly.take().unwrap();
+        drop(assembly);
+        if received != res.total {
+            anyhow::bail!(
+                "snapshot at version {} has {} entries but {} arrived",
+                version,
+                res.total,
+                received
+            );
+        }
+
+        let mut writer = self.store.exclusive();
+        if version <= writer.version() {
+            return Ok(true);
+        }
+        writer.restore(keyspace, version)?;
+        self.requested_version.fetch_max(version, Ordering::Relaxed);
+        drop(writer);
+        let mut fetched = self.fetched.lock().unwrap();
+        *fetched = fetched.split_off(&(version + 1));
+        drop(fetched);
+        println!("resynced read shard at version {}", version);
+        // the snapshot on disk no longer leads up to the keyspace
+        if self.snapshotter.is_some() {
+            self.bg_save();
+        }
+        Ok(true)
+    }
+
+    fn read(&self, key: &[u8]) -> ReadResponse {
+        match self.store.view([key]) {
+            std::result::Result::Ok(data) => commands::read_key(&data, key, now_millis()),
+            Err(e) => {
+                eprintln!("Failed to load key: {:?}", e);
+                commands::read_failed(key)
+            }
+        }
+    }
+
+    /// Answers the parked reads whose version has been replicated, and tells
+    /// the ones that waited too long that the shard is stale
+    fn serve_waiting_reads(&self, now: u64) {
+        let mut waiting_reads = self.waiting_reads.lock().unwrap();
+        let current_version = self.store.version();
+        for waiting in std::mem::take(&mut *waiting_reads) {
+            if waiting.sender.is_closed() {
+                continue;
+            }
+            if waiting.min_version <= current_version {
+                let _ = waiting.sender.send(self.read(&waiting.key));
+            } else if waiting.deadline <= now {
+                let _ = waiting.sender.send(self.stale(waiting.key));
+            } else {
+                waiting_reads.push(waiting);
+            }
+        }
+    }
+
+    /// Tells a client to read `key` from the write shard instead
+    fn stale(&self, key: Vec<u8>) -> ReadResponse {
+        ReadResponse {
+            key,
+            value: Vec::new(),
+            error: ReadResponseError::Stale as u8,
+            version: self.store.version(),
+        }
+    }
+
+    /// Whether any versions have been applied since the last snapshot
+    fn has_unsaved_versions(&self) -> bool {
+        let history = self.store.history();
+        history.last_version() >= history.first_version()
+    }
+
+    /// Snapshots the replicated keyspace on a background thread and
+    /// compacts the history up to the snapshot version once it is on disk
+    fn bg_save(&self) -> BgSaveResponse {
+        let Some(snapshotter) = &self.snapshotter else {
+            return BgSaveResponse {
+                error: BgSaveResponseError::SnapshotsDisabled as u8,
+                version: 0,
+            };
+        };
+
+        let (version, source) = match self.store.snapshot() {
+            std::result::Result::Ok(snapshot) => snapshot,
+            Err(e) => {
+                eprintln!("Failed to snapshot keyspace: {:?}", e);
+                return BgSaveResponse {
+                    error: BgSaveResponseError::SnapshotFailed as u8,
+                    version: 0,
+                };
+            }
+        };
+
+        let store = self.store.clone();
+        let started = snapshotter.save_in_background(version, source, move |version| {
+            store.exclusive().history_mut().truncate_through(version);
+        });
+
+        BgSaveResponse {
+            error: if started {
+                BgSaveResponseError::NoError as u8
+            } else {
+                BgSaveResponseError::AlreadyInProgress as u8
+            },
+            version,
         }
     }
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let read_shard_router = ReadShard::new();
+    let args = ReadShardArgs::parse();
+    let read_shard_router = ReadShard::open(&args)?;
     let mut read_shard_server = RouterBuilder::new(read_shard_router, None);
     let read_shard_router = read_shard_server.get_handler_arc();
     let reader_ip_port = read_shard_server.bind().await?;
 
     println!("hi from read shard!");
 
+    if args.snapshot_path.is_some() {
+        let read_shard = read_shard_router.clone();
+        tokio::spawn(async move {
+            let mut interval =
+                time::interval(time::Duration::from_secs(args.snapshot_interval.max(1)));
+            // the first tick completes immediately
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                if read_shard.has_unsaved_versions() {
+                    read_shard.bg_save();
+                }
+            }
+        });
+    }
+
     let shard_id = rand::thread_rng().gen();
 
     let client1 = read_shard_server.get_router_client();
@@ -256,10 +792,13 @@ async fn main() -> Result<()> {
     tokio::spawn({
         async move {
             let mut interval = time::interval(time::Duration::from_millis(100));
+            // latest version the write shard has been told this shard applied
+            let mut acked_version = 0;
             loop {
                 interval.tick().await;
+                router_clone_3.serve_waiting_reads(now_millis());
 
-                let peer_ip_port = {
+                let (peer_ip_port, writer_ip_port) = {
                     let peers = router_clone_3.peers.lock().unwrap();
                     if peers.is_empty() {
                         eprintln!("No peers available for query.");
@@ -267,11 +806,41 @@ async fn main() -> Result<()> {
                     }
                     let mut rng = rand::thread_rng();
                     let index = rng.gen_range(0..peers.len());
-                    peers[index]
+                    // the info server lists the write shard first
+                    (peers[index], peers[0])
                 };
 
-                if *router_clone_3.requested_version.lock().unwrap()
-                    <= *router_clone_3.current_version.lock().unwrap()
+                let applied_version = router_clone_3.store.version();
+                if applied_version > acked_version {
+                    let ack = ReplicaAckRequest {
+                        ip: reader_ip_port.ip().to_bits(),
+                        port: reader_ip_port.port(),
+                        version: applied_version,
+                    };
+                    match client3
+                        .queue_request::<ReplicaAckRequest>(ack, writer_ip_port)
+                        .await
+                    {
+                        std::result::Result::Ok(()) => acked_version = applied_version,
+                        Err(e) => eprintln!("Failed to send ReplicaAckRequest: {:?}", e),
+                    }
+                }
+
+                if let Some(sync_request) = router_clone_3.sync_request(now_millis()) {
+                    if let Err(e) = client3
+                        .queue_request::<SyncRequest>(sync_request, writer_ip_port)
+                        .await
+                    {
+                        eprintln!("Failed to send SyncRequest: {:?}", e);
+                    }
+                }
+                // versions before the snapshot can't be fetched one at a time anymore
+                if router_clone_3.is_resyncing() {
+                    continue;
+                }
+
+                if router_clone_3.requested_version.load(Ordering::Relaxed)
+                    <= router_clone_3.store.version()
                 {
                     let query_version_request = QueryVersionRequest {};
                     if let Err(e) = client3
@@ -282,28 +851,25 @@ async fn main() -> Result<()> {
                     }
                 }
 
-                let (current_version, requested_version) = {
-                    let curr_ver = *router_clone_3.current_version.lock().unwrap();
-                    let req_ver = *router_clone_3.requested_version.lock().unwrap();
-                    (curr_ver, req_ver)
-                };
-
-                if requested_version > current_version {
-                    let get_version_request = GetVersionRequest {
-                        version: current_version + 1,
-                    };
-
+                // every version the peer has is fetched at once, so a burst of
+                // writes is applied and acknowledged within a tick or two
+                let versions = router_clone_3.versions_to_fetch();
+                if versions.is_empty() {
+                    println!(
+                        "current version: {}, requested version: {}",
+                        router_clone_3.store.version(),
+                        router_clone_3.requested_version.load(Ordering::Relaxed)
+                    );
+                }
+                for version in versions {
+                    let get_version_request = GetVersionRequest { version };
                     if let Err(e) = client3
                         .queue_request::<GetVersionRequest>(get_version_request, peer_ip_port)
                         .await
                     {
                         eprintln!("Failed to send GetVersionRequest: {:?}", e);
+                        break;
                     }
-                } else {
-                    println!(
-                        "current version: {}, requested version: {}",
-                        current_version, requested_version
-                    );
                 }
             }
         }
@@ -323,27 +889,68 @@ async fn main() -> Result<()> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::storage::engine::MemoryEngine;
+    use crate::storage::mutation::MutationType;
+    use crate::storage::value::ListEnd;
 
     #[test]
     fn test_handle_read_request() {
         let read_shard = ReadShard::new();
 
         read_shard
-            .data
-            .lock()
-            .unwrap()
-            .insert("key1".to_string(), "value1".to_string());
+            .store
+            .exclusive()
+            .commit(Mutation::Set {
+                key: b"key1".to_vec(),
+                value: b"value1".to_vec(),
+            })
+            .unwrap();
 
         let read_request = ReadRequest {
             key: "key1".to_string().into_bytes(),
+            min_version: 0,
         };
 
-        let response = read_shard.handle_read_request(&read_request);
+        let HandlerResponse::Ready(response) = read_shard.handle_read_request(&read_request) else {
+            panic!("reads without a session token never wait");
+        };
 
         assert_eq!(response.error, 0);
         assert_eq!(response.value, "value1".to_string().into_bytes());
     }
 
+    #[test]
+    fn test_read_waits_for_session_version() {
+        let read_shard = ReadShard::new();
+        let read = |min_version| match read_shard.handle_read_request(&ReadRequest {
+            key: b"key".to_vec(),
+            min_version,
+        }) {
+            HandlerResponse::Deferred(receiver) => receiver,
+            HandlerResponse::Ready(_) => panic!("the shard is behind the session"),
+        };
+        let mut caught_up = read(1);
+        let mut behind = read(2);
+
+        read_shard.handle_get_version_response(&GetVersionResponse {
+            key: b"key".to_vec(),
+            value: b"value".to_vec(),
+            error: 0,
+            version: 1,
+            mutation_type: MutationType::Set as u8,
+        });
+        let res = caught_up.try_recv().unwrap();
+        assert_eq!(res.value, b"value".to_vec());
+        assert_eq!(res.version, 1);
+        assert!(behind.try_recv().is_err());
+
+        read_shard.serve_waiting_reads(now_millis() + READ_WAIT_TIMEOUT_MS);
+        let res = behind.try_recv().unwrap();
+        assert_eq!(res.error, ReadResponseError::Stale as u8);
+        assert_eq!(res.version, 1);
+        assert!(read_shard.waiting_reads.lock().unwrap().is_empty());
+    }
+
     #[test]
     fn test_handle_announce_shard_response() {
         let read_shard = ReadShard::new();
@@ -386,8 +993,7 @@ mod tests {
 
         read_shard.handle_query_version_response(&res);
 
-        let requested_version = read_shard.requested_version.lock().unwrap();
-        assert_eq!(*requested_version, 1);
+        assert_eq!(read_shard.requested_version.load(Ordering::Relaxed), 1);
     }
 
     #[test]
@@ -399,12 +1005,12 @@ mod tests {
             value: b"value".to_vec(),
             error: 0,
             version: 1,
+            mutation_type: MutationType::Set as u8,
         };
 
         read_shard.handle_get_version_response(&get_version_response);
 
-        let current_version = read_shard.current_version.lock().unwrap();
-        assert_eq!(*current_version, 1);
+        assert_eq!(read_shard.store.version(), 1);
     }
 
     #[test]
@@ -423,17 +1029,13 @@ mod tests {
         let read_shard = ReadShard::new();
 
         read_shard
-            .data
-            .lock()
-            .unwrap()
-            .insert("key".to_string(), "value".to_string());
-        read_shard
-            .history
-            .lock()
-            .unwrap()
-            .push(("key".to_string(), "value".to_string()));
-
-        *read_shard.current_version.lock().unwrap() = 1;
+            .store
+            .exclusive()
+            .commit(Mutation::Set {
+                key: b"key".to_vec(),
+                value: b"value".to_vec(),
+            })
+            .unwrap();
 
         let req = GetVersionRequest { version: 1 };
 
@@ -444,4 +1046,347 @@ mod tests {
         assert_eq!(res.value, b"value".to_vec());
         assert_eq!(res.version, 1);
     }
+
+    #[test]
+    fn test_handle_get_version_request_compacted() {
+        let read_shard = ReadShard::new();
+
+        {
+            let mut writer = read_shard.store.exclusive();
+            writer
+                .commit(Mutation::Set {
+                    key: b"key".to_vec(),
+                    value: b"value".to_vec(),
+                })
+                .unwrap();
+            writer
+                .commit(Mutation::Set {
+                    key: b"key".to_vec(),
+                    value: b"value2".to_vec(),
+                })
+                .unwrap();
+            writer.history_mut().truncate_through(1);
+        }
+
+        let res = read_shard.handle_get_version_request(&GetVersionRequest { version: 1 });
+        assert_eq!(res.error, GetVersionResponseError::FullResyncRequired as u8);
+
+        let res = read_shard.handle_get_version_request(&GetVersionRequest { version: 2 });
+        assert_eq!(res.error, 0);
+        assert_eq!(res.value, b"value2".to_vec());
+    }
+
+    #[test]
+    fn test_burst_is_applied_in_one_tick() {
+        let peer = ReadShard::new();
+        for i in 0..50 {
+            peer.store
+                .exclusive()
+                .commit(Mutation::Set {
+                    key: format!("key{}", i).into_bytes(),
+                    value: b"value".to_vec(),
+                })
+                .unwrap();
+        }
+
+        let read_shard = ReadShard::new();
+        read_shard.handle_query_version_response(&QueryVersionResponse {
+            version: peer.store.version(),
+        });
+        let versions = read_shard.versions_to_fetch();
+        assert_eq!(versions, (1..=50).collect::<Vec<_>>());
+        // responses can come back in any order
+        for &version in versions.iter().rev() {
+            assert_eq!(read_shard.store.version(), 0);
+            read_shard.handle_get_version_response(
+                &peer.handle_get_version_request(&GetVersionRequest { version }),
+            );
+        }
+        // the whole burst can be acknowledged at once for the write quorum
+        assert_eq!(read_shard.store.version(), 50);
+        assert!(read_shard.fetched.lock().unwrap().is_empty());
+        assert!(read_shard.versions_to_fetch().is_empty());
+    }
+
+    #[test]
+    fn test_resync_from_snapshot() {
+        let mut peer = ReadShard::new();
+        peer.store = Arc::new(Store::new(
+            Box::new(MemoryEngine::new()),
+            VersionHistory::with_capacity(1, 2),
+        ));
+        for value in ["a", "b", "c"] {
+            peer.store
+                .exclusive()
+                .commit(Mutation::Set {
+                    key: b"key".to_vec(),
+                    value: value.as_bytes().to_vec(),
+                })
+                .unwrap();
+        }
+        // version 1 fell out of the peer's backlog
+        let res = peer.handle_get_version_request(&GetVersionRequest { version: 1 });
+        assert_eq!(res.error, GetVersionResponseError::FullResyncRequired as u8);
+        let res = peer.handle_get_version_request(&GetVersionRequest { version: 0 });
+        assert_eq!(res.error, GetVersionResponseError::KeyNotFound as u8);
+
+        let read_shard = ReadShard::new();
+        read_shard.handle_get_version_response(
+            &peer.handle_get_version_request(&GetVersionRequest { version: 1 }),
+        );
+        assert!(read_shard.is_resyncing());
+        let HandlerResponse::Ready(res) = read_shard.handle_read_request(&ReadRequest {
+            key: b"key".to_vec(),
+            min_version: 0,
+        }) else {
+            panic!("read was parked while resyncing");
+        };
+        assert_eq!(res.error, ReadResponseError::Stale as u8);
+        let now = now_millis();
+        let first = read_shard.sync_request(now).unwrap();
+        assert_eq!((first.version, first.offset), (0, 0));
+        assert!(read_shard.sync_request(now + 1).is_none());
+        // the snapshot never came, so it is asked for again
+        assert!(read_shard.sync_request(now + SYNC_TIMEOUT_MS).is_some());
+
+        read_shard.handle_sync_response(&SyncResponse {
+            error: SyncResponseError::SnapshotFailed as u8,
+            version: 0,
+            offset: 0,
+            total: 0,
+            done: false,
+            entries: Vec::new(),
+        });
+        assert!(read_shard.sync_request(now).is_some());
+
+        read_shard.handle_sync_response(&peer.handle_sync_request(&first));
+        assert!(!read_shard.is_resyncing());
+        assert_eq!(read_shard.store.version(), 3);
+        assert_eq!(read_shard.requested_version.load(Ordering::Relaxed), 3);
+        assert_eq!(read_shard.read(b"key").value, b"c".to_vec());
+
+        // replication picks up right after the snapshot
+        read_shard.handle_get_version_response(&GetVersionResponse {
+            key: b"key".to_vec(),
+            value: b"d".to_vec(),
+            error: 0,
+            version: 4,
+            mutation_type: MutationType::Set as u8,
+        });
+        assert_eq!(read_shard.store.version(), 4);
+    }
+
+    #[test]
+    fn test_resync_assembles_chunks() {
+        let mut snapshot = Keyspace::new();
+        for i in 0..6 {
+            snapshot.insert(format!("key{}", i).into_bytes(), vec![b'v'; 100]);
+        }
+        // chunks of two entries each, as a write shard would send them
+        let chunk = |offset: u64| {
+            let mut entries = Vec::new();
+            let count =
+                Snapshot::write_entries(&mut entries, &snapshot, offset as usize, 250).unwrap();
+            SyncResponse {
+                error: SyncResponseError::NoError as u8,
+                version: 7,
+                offset,
+                total: 6,
+                done: offset as usize + count >= 6,
+                entries,
+            }
+        };
+
+        let read_shard = ReadShard::new();
+        *read_shard.resync.lock().unwrap() = Resync::Needed;
+        let now = now_millis();
+        read_shard.sync_request(now).unwrap();
+        read_shard.handle_sync_response(&chunk(0));
+        // the shard keeps serving its own keyspace until the last chunk is in
+        assert!(read_shard.is_resyncing());
+        assert_eq!(read_shard.store.version(), 0);
+        let next = read_shard.sync_request(now).unwrap();
+        assert_eq!((next.version, next.offset), (7, 2));
+
+        // a chunk that isn't the next one is asked for again
+        read_shard.handle_sync_response(&chunk(4));
+        let next = read_shard.sync_request(now).unwrap();
+        assert_eq!((next.version, next.offset), (7, 2));
+
+        read_shard.handle_sync_response(&chunk(2));
+        read_shard.sync_request(now).unwrap();
+        read_shard.handle_sync_response(&chunk(4));
+        assert!(!read_shard.is_resyncing());
+        assert_eq!(read_shard.store.version(), 7);
+        assert_eq!(read_shard.store.engine().len(), 6);
+        assert!(read_shard.assembly.lock().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_handle_get_version_response_binary() {
+        let read_shard = ReadShard::new();
+
+        let key = vec![0xff, 0x00, 0xfe];
+        let value: Vec<u8> = (0..=255).collect();
+        read_shard.handle_get_version_response(&GetVersionResponse {
+            key: key.clone(),
+            value: value.clone(),
+            error: 0,
+            version: 1,
+            mutation_type: MutationType::Set as u8,
+        });
+
+        let res = read_shard.read(&key);
+        assert_eq!(res.error, 0);
+        assert_eq!(res.value, value);
+        assert_eq!(res.version, 1);
+
+        let res = read_shard.handle_get_version_request(&GetVersionRequest { version: 1 });
+        assert_eq!(res.key, key);
+        assert_eq!(res.value, value);
+    }
+
+    #[test]
+    fn test_handle_get_version_response_tombstone() {
+        let read_shard = ReadShard::new();
+
+        read_shard.handle_get_version_response(&GetVersionResponse {
+            key: b"key".to_vec(),
+            value: b"value".to_vec(),
+            error: 0,
+            version: 1,
+            mutation_type: MutationType::Set as u8,
+        });
+        let res = read_shard.handle_exists_request(&ExistsRequest {
+            keys: vec![b"key".to_vec(), b"key".to_vec(), b"missing".to_vec()],
+        });
+        assert_eq!(res.count, 2);
+
+        read_shard.handle_get_version_response(&GetVersionResponse {
+            key: b"key".to_vec(),
+            value: Vec::new(),
+            error: 0,
+            version: 2,
+            mutation_type: MutationType::Delete as u8,
+        });
+        assert_eq!(read_shard.store.version(), 2);
+        let res = read_shard.read(b"key");
+        assert_eq!(res.error, 1);
+
+        // the tombstone is served to other readers catching up
+        let res = read_shard.handle_get_version_request(&GetVersionRequest { version: 2 });
+        assert_eq!(res.mutation_type, MutationType::Delete as u8);
+        assert_eq!(res.key, b"key".to_vec());
+    }
+
+    #[test]
+    fn test_expired_keys_are_not_served() {
+        let read_shard = ReadShard::new();
+        let now = now_millis();
+
+        // the writer has not replicated the deletion of an expired key yet
+        for (version, key, expires_at) in [(1, b"stale", now - 1), (2, b"fresh", now + 60_000)] {
+            let mut value = expires_at.to_le_bytes().to_vec();
+            value.extend_from_slice(b"token");
+            read_shard.handle_get_version_response(&GetVersionResponse {
+                key: key.to_vec(),
+                value,
+                error: 0,
+                version,
+                mutation_type: MutationType::SetWithExpiry as u8,
+            });
+        }
+
+        let res = read_shard.read(b"stale");
+        assert_eq!(res.error, 1);
+        let res = read_shard.read(b"fresh");
+        assert_eq!(res.value, b"token".to_vec());
+
+        let res = read_shard.handle_ttl_request(&TtlRequest {
+            key: b"stale".to_vec(),
+        });
+        assert_eq!(res.ttl, TTL_KEY_NOT_FOUND);
+        let res = read_shard.handle_ttl_request(&TtlRequest {
+            key: b"fresh".to_vec(),
+        });
+        assert!(res.ttl > 0 && res.ttl <= 60_000);
+        let res = read_shard.handle_exists_request(&ExistsRequest {
+            keys: vec![b"stale".to_vec(), b"fresh".to_vec()],
+        });
+        assert_eq!(res.count, 1);
+    }
+
+    #[test]
+    fn test_far_future_ttl_does_not_wrap() {
+        let read_shard = ReadShard::new();
+        // set with PXAT u64::MAX
+        let mut value = u64::MAX.to_le_bytes().to_vec();
+        value.extend_from_slice(b"token");
+        read_shard.handle_get_version_response(&GetVersionResponse {
+            key: b"key".to_vec(),
+            value,
+            error: 0,
+            version: 1,
+            mutation_type: MutationType::SetWithExpiry as u8,
+        });
+
+        let res = read_shard.handle_ttl_request(&TtlRequest {
+            key: b"key".to_vec(),
+        });
+        assert_eq!(res.ttl, i64::MAX);
+    }
+
+    #[test]
+    fn test_replicated_lists() {
+        let read_shard = ReadShard::new();
+        let mutations = [
+            Mutation::Push {
+                key: b"list".to_vec(),
+                end: ListEnd::Right,
+                values: vec![b"a".to_vec(), b"b".to_vec(), b"c".to_vec()],
+            },
+            Mutation::Pop {
+                key: b"list".to_vec(),
+                end: ListEnd::Left,
+                count: 1,
+            },
+            Mutation::Push {
+                key: b"list".to_vec(),
+                end: ListEnd::Left,
+                values: vec![b"z".to_vec()],
+            },
+            Mutation::Trim {
+                key: b"list".to_vec(),
+                start: 0,
+                stop: -2,
+            },
+        ];
+        for (version, mutation) in (1..).zip(mutations) {
+            read_shard.handle_get_version_response(&GetVersionResponse {
+                key: mutation.key().to_vec(),
+                value: mutation.value(),
+                error: 0,
+                version,
+                mutation_type: mutation.mutation_type() as u8,
+            });
+        }
+
+        let HandlerResponse::Ready(res) = read_shard.handle_command_request(&CommandRequest {
+            args: vec![
+                b"lrange".to_vec(),
+                b"list".to_vec(),
+                b"0".to_vec(),
+                b"-1".to_vec(),
+            ],
+        }) else {
+            panic!("read commands never block");
+        };
+        assert_eq!(
+            res.reply,
+            Reply::Array(vec![Reply::Bulk(b"z".to_vec()), Reply::Bulk(b"b".to_vec())])
+        );
+        let res = read_shard.read(b"list");
+        assert_eq!(res.error, ReadResponseError::WrongType as u8);
+        assert_eq!(res.version, 4);
+    }
 }
@@ -0,0 +1,437 @@
+use anyhow::{Context, Result};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use super::mutation::Mutation;
+
+/// Controls how often the append-only file is flushed to stable storage
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum FsyncPolicy {
+    /// fsync after every write, before the response is sent
+    Always,
+    /// fsync once a second from a background task
+    Everysec,
+    /// leave flushing to the operating system
+    No,
+}
+
+/// A single applied mutation as recorded in the append-only file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AofEntry {
+    pub version: u64,
+    pub mutation: Mutation,
+}
+
+/// Size of the crc32 and body length fields that prefix every record
+const RECORD_HEADER_LEN: usize = 8;
+
+/// Largest record body a log is expected to hold, so a damaged length
+/// field can't pass for a record torn at the tail
+pub(super) const MAX_RECORD_LEN: usize = 512 * 1024 * 1024;
+
+/// Prefixes a record body with its crc32 and length
+pub(super) fn frame_record(body: &[u8]) -> Result<Vec<u8>> {
+    let body_len = u32::try_from(body.len()).context("record length overflow")?;
+    let mut buffer = Vec::with_capacity(RECORD_HEADER_LEN + body.len());
+    buffer.extend_from_slice(&crc32fast::hash(body).to_le_bytes());
+    buffer.extend_from_slice(&body_len.to_le_bytes());
+    buffer.extend_from_slice(body);
+    Ok(buffer)
+}
+
+/// Whether `tail`, which starts with a record that runs past the end of the
+/// log, can be a record torn by a crash mid-write: its length is plausible
+/// and no complete record follows it. Otherwise its length field is damaged.
+/// The bodies hashed looking for a complete record add up to at most the
+/// length of the tail; a tail that needs more is taken to be damaged, so a
+/// large one can't make startup quadratic.
+pub(super) fn is_torn_tail(tail: &[u8]) -> bool {
+    let Some(header) = tail.get(..RECORD_HEADER_LEN) else {
+        return true;
+    };
+    let body_len = u32::from_le_bytes(header[4..8].try_into().unwrap()) as usize;
+    if body_len > MAX_RECORD_LEN {
+        return false;
+    }
+    let mut budget = tail.len();
+    for start in 1..tail.len() {
+        let Some(header) = tail.get(start..start + RECORD_HEADER_LEN) else {
+            break;
+        };
+        let crc = u32::from_le_bytes(header[0..4].try_into().unwrap());
+        let body_len = u32::from_le_bytes(header[4..8].try_into().unwrap()) as usize;
+        let body_start = start + RECORD_HEADER_LEN;
+        // every record has a body, so zeroes left by the crash don't count as one
+        if body_len == 0 || body_len > MAX_RECORD_LEN {
+            continue;
+        }
+        let Some(body) = tail.get(body_start..body_start + body_len) else {
+            continue;
+        };
+        let Some(left) = budget.checked_sub(body.len()) else {
+            return false;
+        };
+        budget = left;
+        if crc32fast::hash(body) == crc {
+            return false;
+        }
+    }
+    true
+}
+
+/// Splits the contents of a log into record bodies along with their offsets.
+/// A torn record at the tail, left behind by a crash mid-write, is truncated
+/// away. A damaged record followed by further data is treated as corruption.
+pub(super) fn read_records<'a>(
+    file: &mut File,
+    buffer: &'a [u8],
+    name: &str,
+    path: &Path,
+) -> Result<Vec<(usize, &'a [u8])>> {
+    let mut records = Vec::new();
+    let mut offset = 0;
+    while offset < buffer.len() {
+        let Some(header) = buffer.get(offset..offset + RECORD_HEADER_LEN) else {
+            break;
+        };
+        let crc = u32::from_le_bytes(header[0..4].try_into()?);
+        let body_len = u32::from_le_bytes(header[4..8].try_into()?) as usize;
+        let body_start = offset + RECORD_HEADER_LEN;
+        let Some(body) = buffer.get(body_start..body_start + body_len) else {
+            if is_torn_tail(&buffer[offset..]) {
+                break;
+            }
+            anyhow::bail!(
+                "{} {} has a damaged record length at offset {}, refusing to load it",
+                name,
+                path.display(),
+                offset
+            );
+        };
+        let record_end = body_start + body_len;
+        if crc32fast::hash(body) != crc {
+            if record_end == buffer.len() {
+                break;
+            }
+            anyhow::bail!(
+                "{} {} is corrupt at offset {}, refusing to load it",
+                name,
+                path.display(),
+                offset
+            );
+        }
+        records.push((offset, body));
+        offset = record_end;
+    }
+
+    if offset < buffer.len() {
+        println!(
+            "truncating torn {} tail: {} bytes at offset {}",
+            name,
+            buffer.len() - offset,
+            offset
+        );
+        file.set_len(offset as u64)?;
+        file.sync_data()?;
+    }
+    Ok(records)
+}
+
+/// Layout of an AofEntry record
+/// | 4 bytes | 4 bytes | 8 bytes | N bytes  |
+/// | crc32   | bodylen | version | mutation |
+/// mutation is laid out as described on Mutation
+/// crc32 covers the body, i.e. everything after the bodylen field
+/// Integers are always encoded in little-endian order
+impl AofEntry {
+    fn serialize(&self) -> Result<Vec<u8>> {
+        let mut body = Vec::new();
+        body.extend_from_slice(&self.version.to_le_bytes());
+        body.extend_from_slice(&self.mutation.serialize()?);
+        frame_record(&body)
+    }
+
+    fn deserialize_body(body: &[u8]) -> Result<Self> {
+        let version = u64::from_le_bytes(
+            body.get(0..8)
+                .context("failed to get version")?
+                .try_into()?,
+        );
+        let mutation = Mutation::deserialize(body.get(8..).context("failed to get mutation")?)?;
+        Ok(AofEntry { version, mutation })
+    }
+}
+
+/// New log being written alongside the one in use, see
+/// [`AppendOnlyFile::begin_rewrite`]
+#[derive(Debug)]
+pub struct AofRewrite {
+    file: File,
+    path: PathBuf,
+    /// Length of the log in use when the rewrite began
+    offset: u64,
+}
+
+#[allow(unused)]
+impl AofRewrite {
+    /// Writes `entries` to the new log, which has to hold every version
+    /// up to the point the rewrite began that isn't in a snapshot
+    pub fn write(&mut self, entries: &[AofEntry]) -> Result<()> {
+        for entry in entries {
+            self.file.write_all(&entry.serialize()?)?;
+        }
+        self.file.sync_data()?;
+        Ok(())
+    }
+}
+
+/// Append-only log of every write applied by a write shard
+#[allow(unused)]
+#[derive(Debug)]
+pub struct AppendOnlyFile {
+    file: File,
+    path: PathBuf,
+    policy: FsyncPolicy,
+}
+
+#[allow(unused)]
+impl AppendOnlyFile {
+    /// Opens (or creates) the log at `path` and returns every entry it holds,
+    /// truncating a torn record at the tail as `read_records` describes
+    pub fn open(path: &Path, policy: FsyncPolicy) -> Result<(Self, Vec<AofEntry>)> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)
+            .with_context(|| format!("failed to open aof at {}", path.display()))?;
+
+        let mut buffer = Vec::new();
+        file.read_to_end(&mut buffer)?;
+
+        let mut entries = Vec::new();
+        for (offset, body) in read_records(&mut file, &buffer, "aof", path)? {
+            entries.push(AofEntry::deserialize_body(body).with_context(|| {
+                format!(
+                    "aof {} has a malformed record at offset {}",
+                    path.display(),
+                    offset
+                )
+            })?);
+        }
+
+        Ok((
+            AppendOnlyFile {
+                file,
+                path: path.to_path_buf(),
+                policy,
+            },
+            entries,
+        ))
+    }
+
+    /// Appends an entry, syncing it to disk first if the policy is `Always`.
+    /// If that fails the log is cut back to where it was, so a partial
+    /// record doesn't end up in the middle of it once later appends succeed.
+    pub fn append(&mut self, entry: &AofEntry) -> Result<()> {
+        let record = entry.serialize()?;
+        let len = self.file.metadata()?.len();
+        let appended = self.file.write_all(&record).and_then(|_| {
+            if self.policy == FsyncPolicy::Always {
+                self.file.sync_data()?;
+            }
+            Ok(())
+        });
+        if let Err(e) = appended {
+            self.file.set_len(len)?;
+            self.file.seek(SeekFrom::Start(len))?;
+            return Err(e)
+                .with_context(|| format!("failed to append to aof at {}", self.path.display()));
+        }
+        Ok(())
+    }
+
+    /// Starts replacing the log with just the entries a snapshot doesn't
+    /// cover. Records appended from here on are carried over when the
+    /// rewrite is finished, so the caller only has to hold off writes while
+    /// it is started and finished, not while the new log is written.
+    pub fn begin_rewrite(&self) -> Result<AofRewrite> {
+        let tmp_path = self.path.with_extension("rewrite");
+        let file = File::create(&tmp_path)
+            .with_context(|| format!("failed to create {}", tmp_path.display()))?;
+        Ok(AofRewrite {
+            file,
+            path: tmp_path,
+            offset: self.file.metadata()?.len(),
+        })
+    }
+
+    /// Copies the records appended since `rewrite` began into it and
+    /// renames it into place
+    pub fn finish_rewrite(&mut self, mut rewrite: AofRewrite) -> Result<()> {
+        let mut source = File::open(&self.path)?;
+        source.seek(SeekFrom::Start(rewrite.offset))?;
+        io::copy(&mut source, &mut rewrite.file)?;
+        rewrite.file.sync_all()?;
+        fs::rename(&rewrite.path, &self.path)
+            .with_context(|| format!("failed to move rewritten aof to {}", self.path.display()))?;
+        self.file = OpenOptions::new().append(true).open(&self.path)?;
+        Ok(())
+    }
+
+    /// Returns a second handle to the log file so it can be fsynced
+    /// from a background task without holding the writer's lock
+    pub fn sync_handle(&self) -> Result<File> {
+        Ok(self.file.try_clone()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::Rng;
+
+    fn temp_aof_path() -> PathBuf {
+        std::env::temp_dir().join(format!("rust-edis-{}.aof", rand::thread_rng().gen::<u64>()))
+    }
+
+    fn entry(version: u64) -> AofEntry {
+        let key = format!("key{}", version).into_bytes();
+        AofEntry {
+            version,
+            mutation: if version.is_multiple_of(2) {
+                Mutation::Delete { key }
+            } else {
+                Mutation::Set {
+                    key,
+                    value: vec![0xff, 0x00, version as u8],
+                }
+            },
+        }
+    }
+
+    #[test]
+    fn test_append_and_replay() {
+        let path = temp_aof_path();
+        {
+            let (mut aof, entries) = AppendOnlyFile::open(&path, FsyncPolicy::Always).unwrap();
+            assert!(entries.is_empty());
+            for version in 1..=3 {
+                aof.append(&entry(version)).unwrap();
+            }
+        }
+
+        let (_, entries) = AppendOnlyFile::open(&path, FsyncPolicy::Always).unwrap();
+        assert_eq!(entries, vec![entry(1), entry(2), entry(3)]);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_torn_tail_is_truncated() {
+        let path = temp_aof_path();
+        {
+            let (mut aof, _) = AppendOnlyFile::open(&path, FsyncPolicy::No).unwrap();
+            aof.append(&entry(1)).unwrap();
+            aof.append(&entry(2)).unwrap();
+        }
+        let full_len = fs::metadata(&path).unwrap().len();
+        let torn_len = full_len - 3;
+        OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_len(torn_len)
+            .unwrap();
+
+        {
+            let (mut aof, entries) = AppendOnlyFile::open(&path, FsyncPolicy::No).unwrap();
+            assert_eq!(entries, vec![entry(1)]);
+            aof.append(&entry(2)).unwrap();
+        }
+
+        let (_, entries) = AppendOnlyFile::open(&path, FsyncPolicy::No).unwrap();
+        assert_eq!(entries, vec![entry(1), entry(2)]);
+        assert_eq!(fs::metadata(&path).unwrap().len(), full_len);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_rewrite_keeps_only_given_entries() {
+        let path = temp_aof_path();
+        {
+            let (mut aof, _) = AppendOnlyFile::open(&path, FsyncPolicy::No).unwrap();
+            for version in 1..=3 {
+                aof.append(&entry(version)).unwrap();
+            }
+            let mut rewrite = aof.begin_rewrite().unwrap();
+            // appended while the new log is written, carried over when it is swapped in
+            aof.append(&entry(4)).unwrap();
+            rewrite.write(&[entry(3)]).unwrap();
+            aof.finish_rewrite(rewrite).unwrap();
+            aof.append(&entry(5)).unwrap();
+        }
+
+        let (_, entries) = AppendOnlyFile::open(&path, FsyncPolicy::No).unwrap();
+        assert_eq!(entries, vec![entry(3), entry(4), entry(5)]);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_corrupt_record_is_rejected() {
+        let path = temp_aof_path();
+        {
+            let (mut aof, _) = AppendOnlyFile::open(&path, FsyncPolicy::No).unwrap();
+            aof.append(&entry(1)).unwrap();
+            aof.append(&entry(2)).unwrap();
+        }
+        let mut bytes = fs::read(&path).unwrap();
+        bytes[RECORD_HEADER_LEN + 2] ^= 0xff;
+        fs::write(&path, bytes).unwrap();
+
+        assert!(AppendOnlyFile::open(&path, FsyncPolicy::No).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_damaged_length_is_rejected() {
+        let path = temp_aof_path();
+        {
+            let (mut aof, _) = AppendOnlyFile::open(&path, FsyncPolicy::No).unwrap();
+            for version in 1..=3 {
+                aof.append(&entry(version)).unwrap();
+            }
+        }
+        let bytes = fs::read(&path).unwrap();
+        let first_len =
+            RECORD_HEADER_LEN + u32::from_le_bytes(bytes[4..8].try_into().unwrap()) as usize;
+
+        // a length pointing past the end of the file, with records after it
+        let mut damaged = bytes.clone();
+        damaged[first_len + 4..first_len + 8].copy_from_slice(&10_000u32.to_le_bytes());
+        fs::write(&path, &damaged).unwrap();
+        assert!(AppendOnlyFile::open(&path, FsyncPolicy::No).is_err());
+        // nothing was truncated
+        assert_eq!(fs::read(&path).unwrap(), damaged);
+
+        // a length no record could have, even in the last record
+        let mut damaged = bytes.clone();
+        damaged[first_len + 4..first_len + 8].copy_from_slice(&u32::MAX.to_le_bytes());
+        damaged.truncate(first_len + RECORD_HEADER_LEN + 4);
+        fs::write(&path, &damaged).unwrap();
+        assert!(AppendOnlyFile::open(&path, FsyncPolicy::No).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_torn_tail_scan_is_bounded() {
+        let mut tail = 1_000u32.to_le_bytes().repeat(2);
+        assert!(is_torn_tail(&tail));
+        // every 8 bytes looks like the header of a record that fits, so
+        // checking them all would hash far more than the tail holds
+        for _ in 0..100 {
+            tail.extend_from_slice(&[0, 0, 0, 0, 0, 1, 0, 0]);
+        }
+        assert!(!is_torn_tail(&tail));
+    }
+}
@@ -0,0 +1,708 @@
+//! Storage engine that keeps values in a log file on disk, laid out like
+//! bitcask: every write appends the new values of the keys it touched, and
+//! an in-memory directory remembers where the latest value of each key is.
+//! Only keys, expiries and versions take up memory, so a shard can hold more
+//! data than fits in it. Values that have been overwritten or deleted are
+//! dropped by rewriting the log once they make up most of it.
+
+use super::aof::{frame_record, is_torn_tail, MAX_RECORD_LEN};
+use super::engine::{EngineSnapshot, Entry, Scan, StorageEngine, View};
+use super::keyspace::Keyspace;
+use super::mutation::Mutation;
+use super::value::Value;
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+
+/// Size of the crc32 and body length fields that prefix every record
+const RECORD_HEADER_LEN: u64 = 8;
+/// Bytes of dead values the log holds before it may be rewritten. It is
+/// only rewritten once they also outweigh the live ones.
+const COMPACT_MIN_DEAD_BYTES: u64 = 64 << 20;
+
+const OP_PUT: u8 = 0;
+const OP_DELETE: u8 = 1;
+
+/// Bytes of an op before its key: the op and the key length
+const KEY_OP_OVERHEAD: u64 = (size_of::<u8>() + size_of::<u32>()) as u64;
+/// Bytes of a put op besides its key and value: those of every op along
+/// with the value type, expiry, key version and value length
+const PUT_OP_OVERHEAD: u64 =
+    KEY_OP_OVERHEAD + (size_of::<u8>() + 2 * size_of::<u64>() + size_of::<u32>()) as u64;
+
+/// Where the latest value of a key sits in the log
+#[derive(Debug, Clone, Copy)]
+struct Slot {
+    offset: u64,
+    len: u32,
+    value_type: u8,
+    expires_at: Option<u64>,
+    version: u64,
+}
+
+fn read_value(file: &File, slot: &Slot) -> Result<Value> {
+    let mut bytes = vec![0; slot.len as usize];
+    file.read_exact_at(&mut bytes, slot.offset)
+        .context("failed to read value from the disk engine log")?;
+    Value::decode(slot.value_type, bytes)
+}
+
+/// Layout of a record in the log, which holds the values one write left behind
+/// | 4 bytes | 4 bytes | 8 bytes | 4 bytes | ...        |
+/// | crc32   | bodylen | version | count   | count ops  |
+/// Layout of each op
+/// | 1 byte | 4 bytes | N bytes | 1 byte | 8 bytes    | 8 bytes     | 4 bytes  | M bytes |
+/// | op     | keylen  |   key   | type   | expires at | key version | valuelen |  value  |
+/// Deletes end after the key. version is the version the engine was at once
+/// the write was applied. expires at is 0 for keys without an expiry.
+/// crc32 covers the body, i.e. everything after the bodylen field
+/// Integers are always encoded in little-endian order
+struct RecordWriter {
+    body: Vec<u8>,
+    /// Ops written so far, with puts pointing at where their value starts in the body
+    record: Record,
+}
+
+impl RecordWriter {
+    fn new(version: u64) -> Self {
+        let mut body = Vec::new();
+        body.extend_from_slice(&version.to_le_bytes());
+        body.extend_from_slice(&0u32.to_le_bytes());
+        RecordWriter {
+            body,
+            record: Record {
+                version,
+                ops: Vec::new(),
+            },
+        }
+    }
+
+    fn put(&mut self, key: &[u8], entry: &Entry) -> Result<()> {
+        let encoded = entry.value.encode();
+        self.push_key(OP_PUT, key)?;
+        self.body.push(entry.value.value_type() as u8);
+        self.body
+            .extend_from_slice(&entry.expires_at.unwrap_or(0).to_le_bytes());
+        self.body.extend_from_slice(&entry.version.to_le_bytes());
+        let len = u32::try_from(encoded.len()).context("value length overflow")?;
+        self.body.extend_from_slice(&len.to_le_bytes());
+        let slot = Slot {
+            offset: self.body.len() as u64,
+            len,
+            value_type: entry.value.value_type() as u8,
+            expires_at: entry.expires_at,
+            version: entry.version,
+        };
+        self.body.extend_from_slice(&encoded);
+        self.record.ops.push((key.to_vec(), Some(slot)));
+        Ok(())
+    }
+
+    fn delete(&mut self, key: &[u8]) -> Result<()> {
+        self.push_key(OP_DELETE, key)?;
+        self.record.ops.push((key.to_vec(), None));
+        Ok(())
+    }
+
+    fn push_key(&mut self, op: u8, key: &[u8]) -> Result<()> {
+        self.body.push(op);
+        let len = u32::try_from(key.len()).context("key length overflow")?;
+        self.body.extend_from_slice(&len.to_le_bytes());
+        self.body.extend_from_slice(key);
+        Ok(())
+    }
+
+    /// Frames the record to be written at `at` in the log, and returns it
+    /// along with the ops it holds, pointing at where their values end up
+    fn finish(mut self, at: u64) -> Result<(Vec<u8>, Record)> {
+        let count = u32::try_from(self.record.ops.len()).context("op count overflow")?;
+        self.body[8..12].copy_from_slice(&count.to_le_bytes());
+        for slot in self
+            .record
+            .ops
+            .iter_mut()
+            .filter_map(|(_, slot)| slot.as_mut())
+        {
+            slot.offset += at + RECORD_HEADER_LEN;
+        }
+        Ok((frame_record(&self.body)?, self.record))
+    }
+}
+
+/// What a record in the log did
+struct Record {
+    version: u64,
+    ops: Vec<(Vec<u8>, Option<Slot>)>,
+}
+
+/// Reads the ops of the record body starting at `at` in the log
+fn parse_record(body: &[u8], at: u64) -> Result<Record> {
+    fn take<'a>(body: &'a [u8], offset: &mut usize, len: usize, field: &str) -> Result<&'a [u8]> {
+        let bytes = body
+            .get(*offset..*offset + len)
+            .with_context(|| format!("failed to get {}", field))?;
+        *offset += len;
+        Ok(bytes)
+    }
+    let u64_at = |bytes: &[u8]| u64::from_le_bytes(bytes.try_into().unwrap());
+    let u32_at = |bytes: &[u8]| u32::from_le_bytes(bytes.try_into().unwrap());
+
+    let mut offset = 0;
+    let version = u64_at(take(body, &mut offset, 8, "version")?);
+    let count = u32_at(take(body, &mut offset, 4, "op count")?);
+    let mut ops = Vec::new();
+    for _ in 0..count {
+        let op = take(body, &mut offset, 1, "op")?[0];
+        let key_len = u32_at(take(body, &mut offset, 4, "key length")?) as usize;
+        let key = take(body, &mut offset, key_len, "key")?.to_vec();
+        match op {
+            OP_PUT => {
+                let value_type = take(body, &mut offset, 1, "value type")?[0];
+                let expires_at = u64_at(take(body, &mut offset, 8, "expiry")?);
+                let version = u64_at(take(body, &mut offset, 8, "key version")?);
+                let len = u32_at(take(body, &mut offset, 4, "value length")?);
+                let slot = Slot {
+                    offset: at + RECORD_HEADER_LEN + offset as u64,
+                    len,
+                    value_type,
+                    expires_at: (expires_at != 0).then_some(expires_at),
+                    version,
+                };
+                take(body, &mut offset, len as usize, "value")?;
+                ops.push((key, Some(slot)));
+            }
+            OP_DELETE => ops.push((key, None)),
+            op => anyhow::bail!("unknown op {}", op),
+        }
+    }
+    if offset != body.len() {
+        anyhow::bail!("record has trailing bytes");
+    }
+    Ok(Record { version, ops })
+}
+
+#[derive(Debug)]
+pub struct DiskEngine {
+    path: PathBuf,
+    /// Held while a record is appended or the log is rewritten, so the log
+    /// has one writer at a time. Reads never take it.
+    appending: Mutex<()>,
+    /// Only held to look keys up or point them at new values, never while a
+    /// value is read from or written to the log
+    state: RwLock<DiskState>,
+}
+
+#[derive(Debug)]
+struct DiskState {
+    /// Shared with the snapshots taken of the engine, which keep reading the
+    /// log they were taken from after it is rewritten
+    file: Arc<File>,
+    /// Length of the log, where the next record goes
+    end: u64,
+    keydir: HashMap<Vec<u8>, Slot>,
+    /// Keys with an expiry, so the sweeper doesn't walk every key
+    expires: HashMap<Vec<u8>, u64>,
+    version: u64,
+    /// Bytes of the log taken up by values that were overwritten or deleted since
+    dead_bytes: u64,
+}
+
+impl DiskState {
+    /// Points the key directory at the values a record wrote
+    fn index(&mut self, record: Record) {
+        self.version = record.version;
+        for (key, slot) in record.ops {
+            // the op fields and key around a value are counted as part of it
+            if let Some(old) = self.keydir.remove(&key) {
+                self.dead_bytes += PUT_OP_OVERHEAD + key.len() as u64 + old.len as u64;
+            }
+            self.expires.remove(&key);
+            match slot {
+                Some(slot) => {
+                    if let Some(at) = slot.expires_at {
+                        self.expires.insert(key.clone(), at);
+                    }
+                    self.keydir.insert(key, slot);
+                }
+                // a delete supersedes the key's last put, and a rewrite
+                // drops it along with that put, so it is dead as soon as it is written
+                None => self.dead_bytes += KEY_OP_OVERHEAD + key.len() as u64,
+            }
+        }
+    }
+
+    fn snapshot(&self) -> DiskSnapshot {
+        DiskSnapshot {
+            file: self.file.clone(),
+            slots: self
+                .keydir
+                .iter()
+                .map(|(key, slot)| (key.clone(), *slot))
+                .collect(),
+        }
+    }
+}
+
+impl DiskEngine {
+    /// Opens (or creates) the log at `path` and rebuilds the key directory
+    /// from it. A torn record at the tail, left behind by a crash mid-write,
+    /// is truncated away. A damaged record followed by more data is corruption.
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)
+            .with_context(|| format!("failed to open disk engine log at {}", path.display()))?;
+        let len = file.metadata()?.len();
+
+        let mut state = DiskState {
+            file: Arc::new(file),
+            end: 0,
+            keydir: HashMap::new(),
+            expires: HashMap::new(),
+            version: 0,
+            dead_bytes: 0,
+        };
+        // records are read one at a time, the log can be larger than memory
+        let file = state.file.clone();
+        let mut reader = BufReader::new(file.as_ref());
+        let mut header = [0; RECORD_HEADER_LEN as usize];
+        while state.end < len {
+            if reader.read_exact(&mut header).is_err() {
+                break;
+            }
+            let crc = u32::from_le_bytes(header[0..4].try_into()?);
+            let body_len = u32::from_le_bytes(header[4..8].try_into()?) as u64;
+            let record_end = state.end + RECORD_HEADER_LEN + body_len;
+            if record_end > len {
+                // a plausible length leaves at most one record's worth to check
+                if body_len <= MAX_RECORD_LEN as u64 {
+                    let mut tail = header.to_vec();
+                    reader.read_to_end(&mut tail)?;
+                    if is_torn_tail(&tail) {
+                        break;
+                    }
+                }
+                anyhow::bail!(
+                    "disk engine log {} has a damaged record length at offset {}, refusing to load it",
+                    path.display(),
+                    state.end
+                );
+            }
+            let mut body = vec![0; body_len as usize];
+            reader.read_exact(&mut body)?;
+            if crc32fast::hash(&body) != crc {
+                if record_end == len {
+                    break;
+                }
+                anyhow::bail!(
+                    "disk engine log {} is corrupt at offset {}, refusing to load it",
+                    path.display(),
+                    state.end
+                );
+            }
+            let record = parse_record(&body, state.end).with_context(|| {
+                format!(
+                    "disk engine log {} has a malformed record at offset {}",
+                    path.display(),
+                    state.end
+                )
+            })?;
+            state.end = record_end;
+            state.index(record);
+        }
+
+        if state.end < len {
+            println!(
+                "truncating torn disk engine log tail: {} bytes at offset {}",
+                len - state.end,
+                state.end
+            );
+            state.file.set_len(state.end)?;
+            state.file.sync_data()?;
+        }
+        println!(
+            "opened disk engine at version {} with {} keys",
+            state.version,
+            state.keydir.len()
+        );
+        Ok(DiskEngine {
+            path: path.to_path_buf(),
+            appending: Mutex::new(()),
+            state: RwLock::new(state),
+        })
+    }
+
+    /// Appends a record of new values for `ops`' keys as `version`, or
+    /// deletes the ones without an entry. The caller holds `appending`.
+    fn write(&self, version: u64, ops: &[(&[u8], Option<Entry>)]) -> Result<()> {
+        let mut writer = RecordWriter::new(version);
+        for (key, entry) in ops {
+            match entry {
+                Some(entry) => writer.put(key, entry)?,
+                None => writer.delete(key)?,
+            }
+        }
+        let (file, end) = {
+            let state = self.state.read().unwrap();
+            (state.file.clone(), state.end)
+        };
+        let (bytes, record) = writer.finish(end)?;
+        (&*file)
+            .write_all(&bytes)
+            .with_context(|| format!("failed to append to {}", self.path.display()))?;
+        let mut state = self.state.write().unwrap();
+        state.end += bytes.len() as u64;
+        state.index(record);
+        drop(state);
+        self.compact_if_needed()
+    }
+
+    /// The caller holds `appending`
+    fn compact_if_needed(&self) -> Result<()> {
+        let (live, version) = {
+            let state = self.state.read().unwrap();
+            let live_bytes = state.end - state.dead_bytes.min(state.end);
+            if state.dead_bytes < COMPACT_MIN_DEAD_BYTES || state.dead_bytes < live_bytes {
+                return Ok(());
+            }
+            (state.snapshot(), state.version)
+        };
+        self.rewrite(&live, version)
+    }
+
+    /// Replaces the log with one holding just the keys of `source` at
+    /// `version`. The new log is built in a temporary file and renamed into
+    /// place, and the caller holds `appending` throughout.
+    fn rewrite(&self, source: &dyn EngineSnapshot, version: u64) -> Result<()> {
+        let tmp_path = self.path.with_extension("rewrite");
+        let tmp_file = File::create(&tmp_path)
+            .with_context(|| format!("failed to create {}", tmp_path.display()))?;
+        let mut out = BufWriter::new(&tmp_file);
+        let mut end = 0;
+        let mut keydir = HashMap::new();
+        let mut expires = HashMap::new();
+        // an empty record first, so the version survives even without any keys
+        let (bytes, _) = RecordWriter::new(version).finish(end)?;
+        out.write_all(&bytes)?;
+        end += bytes.len() as u64;
+        for entry in source.scan() {
+            let (key, entry) = entry?;
+            let mut writer = RecordWriter::new(version);
+            writer.put(&key, &entry)?;
+            let (bytes, record) = writer.finish(end)?;
+            out.write_all(&bytes)?;
+            end += bytes.len() as u64;
+            for (key, slot) in record.ops {
+                let Some(slot) = slot else {
+                    continue;
+                };
+                if let Some(at) = slot.expires_at {
+                    expires.insert(key.clone(), at);
+                }
+                keydir.insert(key, slot);
+            }
+        }
+        out.flush()?;
+        drop(out);
+        tmp_file.sync_all()?;
+        fs::rename(&tmp_path, &self.path).with_context(|| {
+            format!(
+                "failed to move rewritten disk engine log to {}",
+                self.path.display()
+            )
+        })?;
+
+        let file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .open(&self.path)?;
+        // readers still holding the old log finish against it
+        *self.state.write().unwrap() = DiskState {
+            file: Arc::new(file),
+            end,
+            keydir,
+            expires,
+            version,
+            dead_bytes: 0,
+        };
+        Ok(())
+    }
+}
+
+impl StorageEngine for DiskEngine {
+    fn get(&self, key: &[u8]) -> Result<Option<Entry>> {
+        let (file, slot) = {
+            let state = self.state.read().unwrap();
+            let Some(slot) = state.keydir.get(key) else {
+                return Ok(None);
+            };
+            (state.file.clone(), *slot)
+        };
+        Ok(Some(Entry {
+            value: read_value(&file, &slot)?,
+            expires_at: slot.expires_at,
+            version: slot.version,
+        }))
+    }
+
+    fn snapshot(&self) -> Result<Box<dyn EngineSnapshot>> {
+        Ok(Box::new(self.state.read().unwrap().snapshot()))
+    }
+
+    fn len(&self) -> usize {
+        self.state.read().unwrap().keydir.len()
+    }
+
+    fn visit_expiries(&self, visit: &mut dyn FnMut(&[u8], u64)) {
+        for (key, &at) in &self.state.read().unwrap().expires {
+            visit(key, at);
+        }
+    }
+
+    fn restore(&self, keyspace: Keyspace, version: u64) -> Result<()> {
+        let _appending = self.appending.lock().unwrap();
+        self.rewrite(&keyspace, version)
+    }
+
+    fn durable_version(&self) -> u64 {
+        self.state.read().unwrap().version
+    }
+
+    /// Looks every key up at once, so the view never shows half of a record
+    fn view(&self, keys: &[&[u8]]) -> Result<View<'_>> {
+        let (file, slots) = {
+            let state = self.state.read().unwrap();
+            let slots: Vec<_> = keys
+                .iter()
+                .filter_map(|&key| Some((key, *state.keydir.get(key)?)))
+                .collect();
+            (state.file.clone(), slots)
+        };
+        let mut keyspace = Keyspace::new();
+        for (key, slot) in slots {
+            let entry = Entry {
+                value: read_value(&file, &slot)?,
+                expires_at: slot.expires_at,
+                version: slot.version,
+            };
+            keyspace.insert_entry(key.to_vec(), entry);
+        }
+        Ok(View::Owned(keyspace))
+    }
+
+    /// Writes every key the mutation touched in a single record, so a crash
+    /// never leaves half of it applied
+    fn apply(&self, mutation: &Mutation, version: u64) -> Result<()> {
+        let _appending = self.appending.lock().unwrap();
+        let mut keys = mutation.keys();
+        keys.sort();
+        keys.dedup();
+        let mut view = self.view(&keys)?.into_owned();
+        mutation.apply(&mut view, version);
+        let ops: Vec<(&[u8], Option<Entry>)> =
+            keys.into_iter().map(|key| (key, view.entry(key))).collect();
+        self.write(version, &ops)
+    }
+}
+
+/// Key directory of a disk engine at the time of the snapshot, along with
+/// the log its values are in
+#[derive(Debug)]
+struct DiskSnapshot {
+    file: Arc<File>,
+    slots: Vec<(Vec<u8>, Slot)>,
+}
+
+impl EngineSnapshot for DiskSnapshot {
+    fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    fn scan(&self) -> Scan<'_> {
+        self.scan_from(0)
+    }
+
+    fn scan_from(&self, start: usize) -> Scan<'_> {
+        let slots = self.slots.get(start..).unwrap_or_default();
+        Box::new(slots.iter().map(|(key, slot)| {
+            let entry = Entry {
+                value: read_value(&self.file, slot)?,
+                expires_at: slot.expires_at,
+                version: slot.version,
+            };
+            Ok((key.clone(), entry))
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::value::ListEnd;
+    use rand::Rng;
+
+    fn temp_log_path() -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "rust-edis-{}.data",
+            rand::thread_rng().gen::<u64>()
+        ))
+    }
+
+    fn set(key: &[u8], value: &[u8]) -> Mutation {
+        Mutation::Set {
+            key: key.to_vec(),
+            value: value.to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_apply_and_reopen() {
+        let path = temp_log_path();
+        let engine = DiskEngine::open(&path).unwrap();
+        engine.apply(&set(b"a", b"1"), 1).unwrap();
+        engine
+            .apply(
+                &Mutation::Push {
+                    key: b"list".to_vec(),
+                    end: ListEnd::Right,
+                    values: vec![b"x".to_vec(), b"y".to_vec()],
+                },
+                2,
+            )
+            .unwrap();
+        engine
+            .apply(
+                &Mutation::Expire {
+                    key: b"a".to_vec(),
+                    expires_at: 100,
+                },
+                3,
+            )
+            .unwrap();
+        engine
+            .apply(
+                &Mutation::Batch {
+                    mutations: vec![
+                        set(b"b", b"2"),
+                        Mutation::Delete {
+                            key: b"list".to_vec(),
+                        },
+                    ],
+                },
+                4,
+            )
+            .unwrap();
+
+        let check = |engine: &DiskEngine| {
+            assert_eq!(engine.durable_version(), 4);
+            assert_eq!(engine.len(), 2);
+            let a = engine.get(b"a").unwrap().unwrap();
+            assert_eq!(a.value, Value::from(b"1".to_vec()));
+            assert_eq!(a.expires_at, Some(100));
+            assert_eq!(a.version, 3);
+            assert_eq!(engine.get(b"list").unwrap(), None);
+            assert_eq!(engine.get(b"b").unwrap().unwrap().version, 4);
+            assert_eq!(engine.expired_keys(100), vec![b"a".to_vec()]);
+        };
+        check(&engine);
+        drop(engine);
+        check(&DiskEngine::open(&path).unwrap());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_dead_bytes() {
+        let path = temp_log_path();
+        let engine = DiskEngine::open(&path).unwrap();
+        // version and op count of the record, ahead of its ops
+        let record_overhead = RECORD_HEADER_LEN + 12;
+        engine.apply(&set(b"a", b"1"), 1).unwrap();
+        let put_len = PUT_OP_OVERHEAD + 2;
+        assert_eq!(engine.state.read().unwrap().end, record_overhead + put_len);
+
+        engine.apply(&set(b"a", b"2"), 2).unwrap();
+        assert_eq!(engine.state.read().unwrap().dead_bytes, put_len);
+
+        engine
+            .apply(&Mutation::Delete { key: b"a".to_vec() }, 3)
+            .unwrap();
+        let state = engine.state.read().unwrap();
+        let delete_len = KEY_OP_OVERHEAD + 1;
+        assert_eq!(state.end, 3 * record_overhead + 2 * put_len + delete_len);
+        assert_eq!(state.dead_bytes, 2 * put_len + delete_len);
+        drop(state);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_torn_tail_is_truncated() {
+        let path = temp_log_path();
+        let engine = DiskEngine::open(&path).unwrap();
+        engine.apply(&set(b"a", b"1"), 1).unwrap();
+        engine.apply(&set(b"b", b"2"), 2).unwrap();
+        let len = engine.state.read().unwrap().end;
+        drop(engine);
+
+        let file = OpenOptions::new().write(true).open(&path).unwrap();
+        file.set_len(len - 3).unwrap();
+        let engine = DiskEngine::open(&path).unwrap();
+        assert_eq!(engine.durable_version(), 1);
+        assert_eq!(engine.get(b"b").unwrap(), None);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_damaged_length_is_rejected() {
+        let path = temp_log_path();
+        let engine = DiskEngine::open(&path).unwrap();
+        engine.apply(&set(b"a", b"1"), 1).unwrap();
+        let first_end = engine.state.read().unwrap().end;
+        engine.apply(&set(b"b", b"2"), 2).unwrap();
+        engine.apply(&set(b"c", b"3"), 3).unwrap();
+        drop(engine);
+
+        // the length of the second record points past the end of the log
+        let mut damaged = fs::read(&path).unwrap();
+        let at = first_end as usize + 4;
+        damaged[at..at + 4].copy_from_slice(&10_000u32.to_le_bytes());
+        fs::write(&path, &damaged).unwrap();
+        assert!(DiskEngine::open(&path).is_err());
+        assert_eq!(fs::read(&path).unwrap(), damaged);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_snapshot_outlives_rewrite() {
+        let path = temp_log_path();
+        let engine = DiskEngine::open(&path).unwrap();
+        engine.apply(&set(b"a", b"1"), 1).unwrap();
+        engine.apply(&set(b"a", b"2"), 2).unwrap();
+        let snapshot = engine.snapshot().unwrap();
+
+        let mut keyspace = Keyspace::new();
+        keyspace.insert(b"b".to_vec(), b"3".to_vec());
+        engine.restore(keyspace, 7).unwrap();
+        assert_eq!(engine.durable_version(), 7);
+        assert_eq!(engine.get(b"a").unwrap(), None);
+
+        // the snapshot still reads the values from before the rewrite
+        let entries: Vec<_> = snapshot.scan().collect::<Result<_>>().unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].1.value, Value::from(b"2".to_vec()));
+
+        drop(engine);
+        let engine = DiskEngine::open(&path).unwrap();
+        assert_eq!(engine.durable_version(), 7);
+        assert_eq!(
+            engine.get(b"b").unwrap().unwrap().value,
+            Value::from(b"3".to_vec())
+        );
+        fs::remove_file(&path).unwrap();
+    }
+}
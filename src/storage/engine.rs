@@ -0,0 +1,424 @@
+//! Where a shard keeps its keys. Commands never talk to an engine directly:
+//! they run against a `Keyspace` holding just the keys they touch, which the
+//! in-memory engine lends out a stripe of as is and other engines load on demand.
+
+use super::disk::DiskEngine;
+use super::keyspace::Keyspace;
+use super::mutation::Mutation;
+use super::value::Value;
+use anyhow::{Context, Result};
+use std::collections::hash_map::DefaultHasher;
+use std::fmt::Debug;
+use std::hash::{Hash, Hasher};
+use std::ops::Deref;
+use std::path::Path;
+use std::sync::{Arc, RwLock, RwLockReadGuard};
+
+/// Engine a shard keeps its keys in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum EngineKind {
+    /// every key and value in memory, persisted through snapshots and the aof
+    Memory,
+    /// values in a log file on disk with only the keys in memory, so the
+    /// dataset can outgrow the memory of the machine
+    Disk,
+}
+
+/// Opens the engine of the given kind. The disk engine keeps its log at `path`.
+pub fn open_engine(kind: EngineKind, path: Option<&Path>) -> Result<Box<dyn StorageEngine>> {
+    Ok(match kind {
+        EngineKind::Memory => Box::new(MemoryEngine::new()),
+        EngineKind::Disk => Box::new(DiskEngine::open(
+            path.context("the disk engine needs a --data-path")?,
+        )?),
+    })
+}
+
+/// A key's value along with its expiry and the version that last modified it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub value: Value,
+    pub expires_at: Option<u64>,
+    pub version: u64,
+}
+
+/// Iterator over the keys of an engine along with their entries
+pub type Scan<'a> = Box<dyn Iterator<Item = Result<(Vec<u8>, Entry)>> + Send + 'a>;
+
+/// Point-in-time copy of an engine that later writes leave alone, so it can
+/// be saved on another thread while the engine moves on
+pub trait EngineSnapshot: Debug + Send {
+    fn len(&self) -> usize;
+
+    #[allow(unused)]
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Every key, expired or not, in no particular order
+    fn scan(&self) -> Scan<'_>;
+
+    /// The keys [`EngineSnapshot::scan`] yields from the `start`th on, so
+    /// a snapshot can be sent a chunk at a time
+    fn scan_from(&self, start: usize) -> Scan<'_> {
+        Box::new(self.scan().skip(start))
+    }
+}
+
+/// Engines lock themselves, so commands read from them while a commit is
+/// being applied. Commits are applied one at a time, in version order.
+pub trait StorageEngine: Debug + Send + Sync {
+    /// Entry of `key`, expired or not
+    fn get(&self, key: &[u8]) -> Result<Option<Entry>>;
+
+    fn snapshot(&self) -> Result<Box<dyn EngineSnapshot>>;
+
+    #[allow(unused)]
+    fn len(&self) -> usize;
+
+    #[allow(unused)]
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Calls `visit` with every key that has an expiry, along with it
+    fn visit_expiries(&self, visit: &mut dyn FnMut(&[u8], u64));
+
+    /// Replaces everything the engine holds with `keyspace`, which is at `version`
+    fn restore(&self, keyspace: Keyspace, version: u64) -> Result<()>;
+
+    /// Last version the engine still holds after a restart, 0 if it holds nothing then
+    fn durable_version(&self) -> u64 {
+        0
+    }
+
+    /// Keys whose expiry is at or before `now`
+    #[allow(unused)]
+    fn expired_keys(&self, now: u64) -> Vec<Vec<u8>> {
+        let mut expired = Vec::new();
+        self.visit_expiries(&mut |key, at| {
+            if at <= now {
+                expired.push(key.to_vec());
+            }
+        });
+        expired
+    }
+
+    /// Keyspace holding `keys` for commands to run against. Engines that keep
+    /// their keys elsewhere load just those keys into a new one.
+    fn view(&self, keys: &[&[u8]]) -> Result<View<'_>> {
+        Ok(View::Owned(load(self, keys)?))
+    }
+
+    /// Applies a mutation as the given version
+    fn apply(&self, mutation: &Mutation, version: u64) -> Result<()>;
+}
+
+/// Copies `keys` out of `engine` into a keyspace of their own
+fn load(engine: &(impl StorageEngine + ?Sized), keys: &[&[u8]]) -> Result<Keyspace> {
+    let mut keyspace = Keyspace::new();
+    for &key in keys {
+        if let Some(entry) = engine.get(key)? {
+            keyspace.insert_entry(key.to_vec(), entry);
+        }
+    }
+    Ok(keyspace)
+}
+
+/// Keyspace a command runs against, which holds at least the keys it was
+/// made for
+#[derive(Debug)]
+pub enum View<'a> {
+    /// The stripe of the in-memory engine every key is in. Commits to that
+    /// stripe wait until it is dropped.
+    Stripe(RwLockReadGuard<'a, Arc<Keyspace>>),
+    /// Copies of the keys
+    Owned(Keyspace),
+}
+
+impl View<'_> {
+    pub fn into_owned(self) -> Keyspace {
+        match self {
+            View::Stripe(stripe) => Keyspace::clone(&stripe),
+            View::Owned(keyspace) => keyspace,
+        }
+    }
+}
+
+impl Deref for View<'_> {
+    type Target = Keyspace;
+
+    fn deref(&self) -> &Keyspace {
+        match self {
+            View::Stripe(stripe) => stripe,
+            View::Owned(keyspace) => keyspace,
+        }
+    }
+}
+
+/// Number of stripes keys are spread over, each locked on its own
+pub const STRIPES: usize = 64;
+
+/// Stripe `key` falls in
+pub fn stripe(key: &[u8]) -> usize {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    hasher.finish() as usize % STRIPES
+}
+
+impl EngineSnapshot for Keyspace {
+    fn len(&self) -> usize {
+        Keyspace::len(self)
+    }
+
+    fn scan(&self) -> Scan<'_> {
+        self.scan_from(0)
+    }
+
+    /// Skips the keys before `start` without copying their values
+    fn scan_from(&self, start: usize) -> Scan<'_> {
+        Box::new(
+            self.iter()
+                .skip(start)
+                .map(|(key, value, expires_at, version)| {
+                    let entry = Entry {
+                        value: value.clone(),
+                        expires_at,
+                        version,
+                    };
+                    Ok((key.clone(), entry))
+                }),
+        )
+    }
+}
+
+/// The in-memory engine. Keys are spread over stripes that are locked on
+/// their own, so applying a commit only holds up reads of its own stripes.
+/// Snapshots share the stripes, and a commit copies a stripe the first time
+/// it changes one a snapshot still holds.
+#[derive(Debug)]
+pub struct MemoryEngine {
+    stripes: Vec<RwLock<Arc<Keyspace>>>,
+}
+
+/// Snapshot of the in-memory engine, holding on to its stripes as they were
+#[derive(Debug)]
+struct MemorySnapshot {
+    stripes: Vec<Arc<Keyspace>>,
+}
+
+impl EngineSnapshot for MemorySnapshot {
+    fn len(&self) -> usize {
+        self.stripes.iter().map(|stripe| stripe.len()).sum()
+    }
+
+    fn scan(&self) -> Scan<'_> {
+        self.scan_from(0)
+    }
+
+    /// Skips the stripes before `start` whole
+    fn scan_from(&self, mut start: usize) -> Scan<'_> {
+        let mut stripes = self.stripes.iter();
+        for stripe in stripes.by_ref() {
+            if start < stripe.len() {
+                let rest = stripes.flat_map(|stripe| stripe.scan());
+                return Box::new(stripe.scan_from(start).chain(rest));
+            }
+            start -= stripe.len();
+        }
+        Box::new(std::iter::empty())
+    }
+}
+
+impl Default for MemoryEngine {
+    fn default() -> Self {
+        MemoryEngine {
+            stripes: (0..STRIPES).map(|_| RwLock::default()).collect(),
+        }
+    }
+}
+
+impl MemoryEngine {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stripes `keys` fall in, in ascending order, which is the order they are locked in
+    fn stripes_of(keys: &[&[u8]]) -> Vec<usize> {
+        let mut stripes: Vec<usize> = keys.iter().map(|key| stripe(key)).collect();
+        stripes.sort_unstable();
+        stripes.dedup();
+        stripes
+    }
+}
+
+impl StorageEngine for MemoryEngine {
+    fn get(&self, key: &[u8]) -> Result<Option<Entry>> {
+        Ok(self.stripes[stripe(key)].read().unwrap().entry(key))
+    }
+
+    fn snapshot(&self) -> Result<Box<dyn EngineSnapshot>> {
+        // every stripe is held at once, so no commit lands halfway through,
+        // but only while the stripes are shared, never while they are copied
+        let guards: Vec<_> = self.stripes.iter().map(|s| s.read().unwrap()).collect();
+        Ok(Box::new(MemorySnapshot {
+            stripes: guards.iter().map(|stripe| Arc::clone(stripe)).collect(),
+        }))
+    }
+
+    fn len(&self) -> usize {
+        self.stripes.iter().map(|s| s.read().unwrap().len()).sum()
+    }
+
+    fn visit_expiries(&self, visit: &mut dyn FnMut(&[u8], u64)) {
+        for stripe in &self.stripes {
+            for (key, at) in stripe.read().unwrap().volatile_keys() {
+                visit(key, at);
+            }
+        }
+    }
+
+    fn restore(&self, keyspace: Keyspace, _version: u64) -> Result<()> {
+        let mut restored: Vec<_> = (0..STRIPES).map(|_| Keyspace::new()).collect();
+        for (key, entry) in keyspace.into_entries() {
+            restored[stripe(&key)].insert_entry(key, entry);
+        }
+        let mut stripes: Vec<_> = self.stripes.iter().map(|s| s.write().unwrap()).collect();
+        for (stripe, keyspace) in stripes.iter_mut().zip(restored) {
+            **stripe = Arc::new(keyspace);
+        }
+        Ok(())
+    }
+
+    fn view(&self, keys: &[&[u8]]) -> Result<View<'_>> {
+        let stripes = Self::stripes_of(keys);
+        if let [only] = stripes[..] {
+            return Ok(View::Stripe(self.stripes[only].read().unwrap()));
+        }
+        // the keys span stripes, which are all held while they are copied
+        // so the view never shows half of a commit
+        let guards: Vec<_> = stripes
+            .iter()
+            .map(|&i| (i, self.stripes[i].read().unwrap()))
+            .collect();
+        let mut keyspace = Keyspace::new();
+        for &key in keys {
+            let (_, guard) = guards.iter().find(|(i, _)| *i == stripe(key)).unwrap();
+            if let Some(entry) = guard.entry(key) {
+                keyspace.insert_entry(key.to_vec(), entry);
+            }
+        }
+        Ok(View::Owned(keyspace))
+    }
+
+    /// Holds every stripe the mutation touches while it is applied, so a
+    /// batch spanning stripes shows up all at once
+    fn apply(&self, mutation: &Mutation, version: u64) -> Result<()> {
+        let keys = mutation.keys();
+        let stripes = Self::stripes_of(&keys);
+        if let [only] = stripes[..] {
+            mutation.apply(
+                Arc::make_mut(&mut self.stripes[only].write().unwrap()),
+                version,
+            );
+            return Ok(());
+        }
+        let mut guards: Vec<_> = stripes
+            .iter()
+            .map(|&i| (i, self.stripes[i].write().unwrap()))
+            .collect();
+        for part in mutation.parts() {
+            let (_, guard) = guards
+                .iter_mut()
+                .find(|(i, _)| *i == stripe(part.key()))
+                .unwrap();
+            part.apply(Arc::make_mut(guard), version);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set(key: &[u8], value: &[u8]) -> Mutation {
+        Mutation::Set {
+            key: key.to_vec(),
+            value: value.to_vec(),
+        }
+    }
+
+    /// Keys that fall in different stripes
+    fn keys_in_two_stripes() -> (Vec<u8>, Vec<u8>) {
+        let first = b"key0".to_vec();
+        let second = (1..)
+            .map(|i| format!("key{}", i).into_bytes())
+            .find(|key| stripe(key) != stripe(&first))
+            .unwrap();
+        (first, second)
+    }
+
+    #[test]
+    fn test_memory_engine_batch_spans_stripes() {
+        let engine = MemoryEngine::new();
+        let (a, b) = keys_in_two_stripes();
+        engine
+            .apply(
+                &Mutation::Batch {
+                    mutations: vec![set(&a, b"1"), set(&b, b"2")],
+                },
+                1,
+            )
+            .unwrap();
+        assert_eq!(engine.len(), 2);
+        assert_eq!(engine.get(&b).unwrap().unwrap().version, 1);
+
+        // a view of one stripe borrows it, one spanning stripes copies the keys
+        assert!(matches!(engine.view(&[&a]).unwrap(), View::Stripe(_)));
+        let view = engine.view(&[&a, &b]).unwrap();
+        assert!(matches!(view, View::Owned(_)));
+        assert_eq!(view.len(), 2);
+        drop(view);
+
+        let mut keyspace = Keyspace::new();
+        keyspace.insert(b"c".to_vec(), b"3".to_vec());
+        keyspace.set_expiry(b"c", 100);
+        engine.restore(keyspace, 5).unwrap();
+        assert_eq!(engine.get(&a).unwrap(), None);
+        assert_eq!(engine.expired_keys(100), vec![b"c".to_vec()]);
+        assert_eq!(engine.snapshot().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_memory_snapshot_shares_stripes() {
+        let engine = MemoryEngine::new();
+        let (a, b) = keys_in_two_stripes();
+        engine.apply(&set(&a, b"1"), 1).unwrap();
+        engine.apply(&set(&b, b"2"), 2).unwrap();
+        let snapshot = engine.snapshot().unwrap();
+        assert_eq!(
+            Arc::strong_count(&engine.stripes[stripe(&a)].read().unwrap()),
+            2
+        );
+
+        // a commit copies the stripe it changes and leaves the snapshot alone
+        engine.apply(&set(&a, b"3"), 3).unwrap();
+        assert_eq!(
+            Arc::strong_count(&engine.stripes[stripe(&a)].read().unwrap()),
+            1
+        );
+        assert_eq!(
+            Arc::strong_count(&engine.stripes[stripe(&b)].read().unwrap()),
+            2
+        );
+        let scanned: Vec<_> = snapshot.scan().map(Result::unwrap).collect();
+        assert_eq!(scanned.len(), 2);
+        let (_, entry) = scanned.iter().find(|(key, _)| *key == a).unwrap();
+        assert_eq!(entry.value, Value::from(b"1".to_vec()));
+
+        let rest: Vec<_> = snapshot.scan_from(1).map(Result::unwrap).collect();
+        assert_eq!(rest, scanned[1..]);
+        assert_eq!(snapshot.scan_from(2).count(), 0);
+    }
+}
@@ -0,0 +1,173 @@
+//! Geohashes of the points in a geospatial index. A point is stored as a
+//! member of a sorted set whose score is the 52 bit interleaved geohash of
+//! its coordinates, so points that are close together have close scores and
+//! an area can be searched with a few score ranges.
+
+use std::ops::Range;
+
+pub const LON_MIN: f64 = -180.0;
+pub const LON_MAX: f64 = 180.0;
+/// Latitudes are limited to the ones the web mercator projection can show
+pub const LAT_MIN: f64 = -85.05112878;
+pub const LAT_MAX: f64 = 85.05112878;
+
+/// Bits of each coordinate in a geohash
+const STEP: u32 = 26;
+
+/// Earth radius in meters, the same one redis uses
+const EARTH_RADIUS: f64 = 6372797.560856;
+
+/// Spreads the bits of `value` out to the even bits of the result
+fn spread(value: u64) -> u64 {
+    (0..STEP).fold(0, |hash, bit| hash | ((value >> bit & 1) << (2 * bit)))
+}
+
+/// Collects the even bits of `hash`, undoing `spread`
+fn squash(hash: u64) -> u64 {
+    (0..STEP).fold(0, |value, bit| value | ((hash >> (2 * bit) & 1) << bit))
+}
+
+/// Index of the cell `coordinate` falls in when `min..max` is split into
+/// `2^step` cells. Coordinates outside the range give indexes outside it.
+fn cell(coordinate: f64, min: f64, max: f64, step: u32) -> i64 {
+    ((coordinate - min) / (max - min) * (1u64 << step) as f64).floor() as i64
+}
+
+/// Geohash of a point, or None if the coordinates are out of range
+pub fn encode(lon: f64, lat: f64) -> Option<u64> {
+    if !(LON_MIN..=LON_MAX).contains(&lon) || !(LAT_MIN..=LAT_MAX).contains(&lat) {
+        return None;
+    }
+    let last = (1 << STEP) - 1;
+    let lat = cell(lat, LAT_MIN, LAT_MAX, STEP).min(last) as u64;
+    let lon = cell(lon, LON_MIN, LON_MAX, STEP).min(last) as u64;
+    Some(spread(lat) | spread(lon) << 1)
+}
+
+/// Center of the cell a geohash stands for, as longitude and latitude
+pub fn decode(hash: u64) -> (f64, f64) {
+    let cells = (1u64 << STEP) as f64;
+    let lat = squash(hash) as f64 + 0.5;
+    let lon = squash(hash >> 1) as f64 + 0.5;
+    (
+        (LON_MIN + lon / cells * (LON_MAX - LON_MIN)).clamp(LON_MIN, LON_MAX),
+        (LAT_MIN + lat / cells * (LAT_MAX - LAT_MIN)).clamp(LAT_MIN, LAT_MAX),
+    )
+}
+
+/// Great circle distance between two points in meters
+pub fn distance(lon1: f64, lat1: f64, lon2: f64, lat2: f64) -> f64 {
+    let (lat1, lat2) = (lat1.to_radians(), lat2.to_radians());
+    let u = ((lat2 - lat1) / 2.0).sin();
+    let v = ((lon2 - lon1).to_radians() / 2.0).sin();
+    2.0 * EARTH_RADIUS * (u * u + lat1.cos() * lat2.cos() * v * v).sqrt().asin()
+}
+
+/// Degrees of latitude and longitude that cover `height` and `width` meters
+/// around `lat`, so every point that far away is inside the box they span
+pub fn box_around(lat: f64, width: f64, height: f64) -> (f64, f64) {
+    let lat_delta = (height / EARTH_RADIUS).to_degrees();
+    // a degree of longitude is shortest on the edge nearest to a pole
+    let edge = (lat.abs() + lat_delta).min(90.0).to_radians();
+    let lon_delta = (width / (EARTH_RADIUS * edge.cos())).to_degrees();
+    (lon_delta.min(LON_MAX), lat_delta)
+}
+
+/// Geohash ranges that together hold every point of the box between the
+/// given coordinates, along with some points around it. Longitudes past
+/// -180 or 180 wrap around.
+pub fn covering_ranges(min_lon: f64, max_lon: f64, min_lat: f64, max_lat: f64) -> Vec<Range<u64>> {
+    // the finest grid that covers the box with at most 16 cells
+    let (step, lats, lons) = (0..=STEP)
+        .rev()
+        .find_map(|step| {
+            let last = (1 << step) - 1;
+            let lats = cell(min_lat, LAT_MIN, LAT_MAX, step).clamp(0, last)
+                ..=cell(max_lat, LAT_MIN, LAT_MAX, step).clamp(0, last);
+            let lons = cell(min_lon, LON_MIN, LON_MAX, step)
+                ..=cell(max_lon, LON_MIN, LON_MAX, step)
+                    .min(cell(min_lon, LON_MIN, LON_MAX, step) + last);
+            let count = (lats.end() - lats.start() + 1) * (lons.end() - lons.start() + 1);
+            (count <= 16).then_some((step, lats, lons))
+        })
+        .unwrap_or((0, 0..=0, 0..=0));
+
+    let shift = 2 * (STEP - step);
+    let mut ranges: Vec<Range<u64>> = lats
+        .flat_map(|lat| {
+            lons.clone().map(move |lon| {
+                let lon = lon.rem_euclid(1 << step) as u64;
+                let hash = spread(lat as u64) | spread(lon) << 1;
+                hash << shift..(hash + 1) << shift
+            })
+        })
+        .collect();
+    ranges.sort_by_key(|range| range.start);
+    ranges.dedup();
+
+    // merge neighbouring cells into one range
+    let mut merged: Vec<Range<u64>> = Vec::new();
+    for range in ranges {
+        match merged.last_mut() {
+            Some(last) if last.end == range.start => last.end = range.end,
+            _ => merged.push(range),
+        }
+    }
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode() {
+        // the geohash redis gives Palermo
+        let hash = encode(13.361389, 38.115556).unwrap();
+        assert_eq!(hash, 3479099956230698);
+        let (lon, lat) = decode(hash);
+        assert!((lon - 13.361389).abs() < 1e-5 && (lat - 38.115556).abs() < 1e-5);
+
+        assert_eq!(encode(0.0, 86.0), None);
+        assert_eq!(encode(181.0, 0.0), None);
+        assert!(encode(LON_MAX, LAT_MAX).is_some());
+    }
+
+    #[test]
+    fn test_distance() {
+        // redis measures between the stored points, which are cell centers
+        let (lon1, lat1) = decode(encode(13.361389, 38.115556).unwrap());
+        let (lon2, lat2) = decode(encode(15.087269, 37.502669).unwrap());
+        let palermo_catania = distance(lon1, lat1, lon2, lat2);
+        assert!(
+            (palermo_catania - 166274.1516).abs() < 0.0001,
+            "{}",
+            palermo_catania
+        );
+    }
+
+    #[test]
+    fn test_covering_ranges() {
+        let (lon_delta, lat_delta) = box_around(38.0, 200_000.0, 200_000.0);
+        let ranges = covering_ranges(
+            14.0 - lon_delta,
+            14.0 + lon_delta,
+            38.0 - lat_delta,
+            38.0 + lat_delta,
+        );
+        assert!(ranges.len() <= 16);
+        for point in [(13.361389, 38.115556), (15.087269, 37.502669)] {
+            let hash = encode(point.0, point.1).unwrap();
+            assert!(ranges.iter().any(|range| range.contains(&hash)));
+        }
+        let far = encode(2.35, 48.85).unwrap();
+        assert!(!ranges.iter().any(|range| range.contains(&far)));
+
+        // boxes over the antimeridian wrap around
+        let ranges = covering_ranges(179.0, 181.0, 0.0, 1.0);
+        for lon in [179.5, -179.5] {
+            let hash = encode(lon, 0.5).unwrap();
+            assert!(ranges.iter().any(|range| range.contains(&hash)));
+        }
+    }
+}
@@ -0,0 +1,154 @@
+use std::collections::VecDeque;
+
+/// Log of the entries applied to a shard, addressed by version number.
+/// Versions start at 1. Entries up to `first_version - 1` have been
+/// compacted into a snapshot, or dropped to keep the log within its
+/// capacity, and can no longer be served.
+#[derive(Debug)]
+pub struct VersionHistory<T> {
+    first_version: u64,
+    entries: VecDeque<T>,
+    /// Most entries held at once; the oldest is dropped to make room for more
+    capacity: usize,
+}
+
+impl<T> Default for VersionHistory<T> {
+    fn default() -> Self {
+        Self::new(1)
+    }
+}
+
+impl<T> VersionHistory<T> {
+    /// Creates an empty history whose next entry will be `first_version`
+    pub fn new(first_version: u64) -> Self {
+        Self::with_capacity(first_version, usize::MAX)
+    }
+
+    /// Creates an empty history whose next entry will be `first_version`
+    /// and that holds at most `capacity` entries
+    pub fn with_capacity(first_version: u64, capacity: usize) -> Self {
+        VersionHistory {
+            first_version,
+            entries: VecDeque::new(),
+            capacity: capacity.max(1),
+        }
+    }
+
+    /// Version of the oldest entry still held
+    pub fn first_version(&self) -> u64 {
+        self.first_version
+    }
+
+    /// Version of the newest entry, or `first_version - 1` when empty
+    pub fn last_version(&self) -> u64 {
+        self.first_version + self.entries.len() as u64 - 1
+    }
+
+    /// Appends the entry of the next version, dropping the oldest entry
+    /// first if the history is at its capacity
+    pub fn push(&mut self, entry: T) {
+        if self.entries.len() >= self.capacity {
+            self.entries.pop_front();
+            self.first_version += 1;
+        }
+        self.entries.push_back(entry);
+    }
+
+    /// Entry the next push drops to stay within the capacity
+    #[allow(unused)]
+    pub fn next_dropped(&self) -> Option<&T> {
+        if self.entries.len() < self.capacity {
+            return None;
+        }
+        self.entries.front()
+    }
+
+    pub fn get(&self, version: u64) -> Option<&T> {
+        if version < self.first_version {
+            return None;
+        }
+        self.entries.get((version - self.first_version) as usize)
+    }
+
+    /// Whether `version` was dropped, by compaction or to stay within the capacity
+    pub fn is_compacted(&self, version: u64) -> bool {
+        version > 0 && version < self.first_version
+    }
+
+    /// Drops every entry up to and including `version`
+    pub fn truncate_through(&mut self, version: u64) {
+        while self.first_version <= version && self.entries.pop_front().is_some() {
+            self.first_version += 1;
+        }
+    }
+
+    /// Drops every entry and starts over with `first_version` as the next one
+    #[allow(unused)]
+    pub fn reset(&mut self, first_version: u64) {
+        self.entries.clear();
+        self.first_version = first_version;
+    }
+
+    /// Iterates over the held entries along with their versions
+    #[allow(unused)]
+    pub fn iter(&self) -> impl Iterator<Item = (u64, &T)> {
+        (self.first_version..).zip(self.entries.iter())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_and_truncate() {
+        let mut history = VersionHistory::default();
+        for i in 1..=5 {
+            history.push(i * 10);
+        }
+        assert_eq!(history.get(0), None);
+        assert_eq!(history.get(1), Some(&10));
+        assert_eq!(history.get(5), Some(&50));
+        assert_eq!(history.get(6), None);
+
+        history.truncate_through(3);
+        assert_eq!(history.first_version(), 4);
+        assert_eq!(history.last_version(), 5);
+        assert!(history.is_compacted(3));
+        assert_eq!(history.get(3), None);
+        assert_eq!(history.get(4), Some(&40));
+        assert_eq!(history.iter().collect::<Vec<_>>(), vec![(4, &40), (5, &50)]);
+
+        history.truncate_through(10);
+        assert_eq!(history.first_version(), 6);
+        assert_eq!(history.last_version(), 5);
+        history.push(60);
+        assert_eq!(history.get(6), Some(&60));
+    }
+
+    #[test]
+    fn test_capacity() {
+        let mut history = VersionHistory::with_capacity(1, 3);
+        for i in 1..=3 {
+            history.push(i * 10);
+        }
+        assert_eq!(history.next_dropped(), Some(&10));
+        history.push(40);
+        history.push(50);
+        assert_eq!(history.first_version(), 3);
+        assert_eq!(history.last_version(), 5);
+        assert!(history.is_compacted(2));
+        assert_eq!(history.get(2), None);
+        assert_eq!(history.get(3), Some(&30));
+        assert_eq!(history.next_dropped(), Some(&30));
+
+        history.truncate_through(4);
+        assert_eq!(history.next_dropped(), None);
+        history.reset(10);
+        assert_eq!(history.first_version(), 10);
+        assert_eq!(history.last_version(), 9);
+        assert!(history.is_compacted(9));
+        history.push(100);
+        assert_eq!(history.get(10), Some(&100));
+    }
+}
@@ -0,0 +1,190 @@
+use anyhow::{Context, Result};
+
+/// Bits of the hash that pick a register
+const PRECISION: u32 = 14;
+pub const REGISTERS: usize = 1 << PRECISION;
+
+const SPARSE: u8 = 0;
+const DENSE: u8 = 1;
+
+/// Cardinality estimator with a standard error of about 0.81%. Each register
+/// holds the longest run of trailing zeros seen in the hashes that map to it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HyperLogLog {
+    registers: Vec<u8>,
+}
+
+impl Default for HyperLogLog {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HyperLogLog {
+    pub fn new() -> Self {
+        HyperLogLog {
+            registers: vec![0; REGISTERS],
+        }
+    }
+
+    /// Register and value an element maps to. The hash is stable across
+    /// builds, so an element always lands in the same register.
+    pub fn register_of(element: &[u8]) -> (u16, u8) {
+        let hash = murmur_hash64a(element, 0xadc83b19);
+        let index = (hash & (REGISTERS as u64 - 1)) as u16;
+        // the guard bit bounds the count when the remaining bits are all zero
+        let rest = (hash >> PRECISION) | (1 << (64 - PRECISION));
+        (index, rest.trailing_zeros() as u8 + 1)
+    }
+
+    pub fn register(&self, index: u16) -> u8 {
+        self.registers[index as usize]
+    }
+
+    /// Raises a register to `value` if it is lower. Returns whether it changed.
+    pub fn raise(&mut self, index: u16, value: u8) -> bool {
+        let register = &mut self.registers[index as usize];
+        if *register >= value {
+            return false;
+        }
+        *register = value;
+        true
+    }
+
+    /// Registers that are not zero, with their values
+    pub fn iter(&self) -> impl Iterator<Item = (u16, u8)> + '_ {
+        self.registers
+            .iter()
+            .enumerate()
+            .filter(|(_, &value)| value != 0)
+            .map(|(index, &value)| (index as u16, value))
+    }
+
+    /// Estimated number of distinct elements added
+    pub fn count(&self) -> u64 {
+        let m = REGISTERS as f64;
+        let alpha = 0.7213 / (1.0 + 1.079 / m);
+        let mut sum = 0.0;
+        let mut zeros = 0;
+        for &value in &self.registers {
+            sum += 1.0 / (1u64 << value) as f64;
+            if value == 0 {
+                zeros += 1;
+            }
+        }
+        let estimate = alpha * m * m / sum;
+        // small cardinalities are far more accurate with linear counting
+        if estimate <= 2.5 * m && zeros > 0 {
+            return (m * (m / zeros as f64).ln()).round() as u64;
+        }
+        estimate.round() as u64
+    }
+
+    /// Encodes the registers, as index/value pairs while few of them are set
+    /// and as one byte per register after that
+    pub fn encode(&self) -> Vec<u8> {
+        let set = self.iter().count();
+        if set * 3 >= REGISTERS {
+            let mut buffer = vec![DENSE];
+            buffer.extend_from_slice(&self.registers);
+            return buffer;
+        }
+        let mut buffer = Vec::with_capacity(1 + set * 3);
+        buffer.push(SPARSE);
+        for (index, value) in self.iter() {
+            buffer.extend_from_slice(&index.to_le_bytes());
+            buffer.push(value);
+        }
+        buffer
+    }
+
+    pub fn decode(buffer: &[u8]) -> Result<Self> {
+        let (&encoding, registers) = buffer
+            .split_first()
+            .context("failed to get hyperloglog encoding")?;
+        let mut hll = HyperLogLog::new();
+        match encoding {
+            DENSE if registers.len() == REGISTERS => hll.registers = registers.to_vec(),
+            SPARSE if registers.len().is_multiple_of(3) => {
+                for register in registers.chunks(3) {
+                    let index = u16::from_le_bytes([register[0], register[1]]);
+                    anyhow::ensure!(
+                        (index as usize) < REGISTERS,
+                        "invalid hyperloglog register {}",
+                        index
+                    );
+                    hll.raise(index, register[2]);
+                }
+            }
+            _ => anyhow::bail!("invalid hyperloglog encoding"),
+        }
+        Ok(hll)
+    }
+}
+
+/// 64-bit MurmurHash2, the hash redis uses for its HyperLogLogs
+fn murmur_hash64a(data: &[u8], seed: u64) -> u64 {
+    const M: u64 = 0xc6a4a7935bd1e995;
+    const R: u32 = 47;
+
+    let mut h = seed ^ (data.len() as u64).wrapping_mul(M);
+    let mut chunks = data.chunks_exact(8);
+    for chunk in &mut chunks {
+        let mut k = u64::from_le_bytes(chunk.try_into().unwrap());
+        k = k.wrapping_mul(M);
+        k ^= k >> R;
+        k = k.wrapping_mul(M);
+        h ^= k;
+        h = h.wrapping_mul(M);
+    }
+    let tail = chunks.remainder();
+    if !tail.is_empty() {
+        for (i, &byte) in tail.iter().enumerate() {
+            h ^= (byte as u64) << (8 * i);
+        }
+        h = h.wrapping_mul(M);
+    }
+    h ^= h >> R;
+    h = h.wrapping_mul(M);
+    h ^= h >> R;
+    h
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_count() {
+        let mut hll = HyperLogLog::new();
+        assert_eq!(hll.count(), 0);
+        for _ in 0..3 {
+            for i in 0..100_000 {
+                let (index, value) = HyperLogLog::register_of(format!("user:{}", i).as_bytes());
+                hll.raise(index, value);
+            }
+        }
+        let count = hll.count() as f64;
+        assert!((count - 100_000.0).abs() < 100_000.0 * 0.03, "{}", count);
+    }
+
+    #[test]
+    fn test_encoding() {
+        let mut hll = HyperLogLog::new();
+        hll.raise(3, 5);
+        hll.raise(REGISTERS as u16 - 1, 2);
+        let encoded = hll.encode();
+        assert_eq!(encoded.len(), 7);
+        assert_eq!(HyperLogLog::decode(&encoded).unwrap(), hll);
+
+        for index in 0..REGISTERS as u16 {
+            hll.raise(index, 1);
+        }
+        let encoded = hll.encode();
+        assert_eq!(encoded.len(), REGISTERS + 1);
+        assert_eq!(HyperLogLog::decode(&encoded).unwrap(), hll);
+
+        assert!(HyperLogLog::decode(&[SPARSE, 0xff, 0xff, 1]).is_err());
+        assert!(HyperLogLog::decode(&[DENSE, 1]).is_err());
+    }
+}
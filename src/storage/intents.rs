@@ -0,0 +1,456 @@
+//! Intent records of the cross-shard transactions a write shard takes part
+//! in. Preparing a transaction locks its keys until the outcome is known, and
+//! the log lets a shard that crashed in between pick up where it left off.
+
+use anyhow::{Context, Result};
+use std::collections::{HashMap, VecDeque};
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Write};
+use std::net::{Ipv6Addr, SocketAddrV6};
+use std::path::{Path, PathBuf};
+
+use super::aof::{frame_record, read_records};
+use super::mutation::Mutation;
+use crate::messages::responses::command_response::Reply;
+
+/// How many outcomes are remembered for shards that ask about them late
+const DECIDED_RETAINED: usize = 10_000;
+
+/// Records appended before the log is rewritten down to what is still needed,
+/// if they also outnumber the records the rewrite keeps
+const COMPACT_AFTER: usize = 1024;
+
+/// A shard's part of a transaction that voted to commit and waits for the outcome
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PreparedTransaction {
+    /// Shard whose commit decides the outcome, None if that is this shard
+    pub primary: Option<SocketAddrV6>,
+    /// Unix milliseconds at which the shard voted
+    pub prepared_at: u64,
+    /// Keys no other write may touch until the outcome is known
+    pub keys: Vec<Vec<u8>>,
+    /// Everything the transaction writes on this shard, as a single batch
+    pub mutation: Mutation,
+    pub reply: Reply,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum IntentRecord {
+    Prepared {
+        txn_id: u128,
+        transaction: Box<PreparedTransaction>,
+    },
+    /// Logged before the batch is committed at `version`, so a crash in
+    /// between redoes the commit on restart
+    Committed {
+        txn_id: u128,
+        version: u64,
+    },
+    Aborted {
+        txn_id: u128,
+    },
+}
+
+/// Layout of an IntentRecord body, framed like an aof record
+/// | 1 byte | 16 bytes | ...  |
+/// | kind   | txn id   | rest |
+/// Prepared:  | 8 bytes     | 16 bytes   | 2 bytes      | 2 bytes | 4 bytes | N bytes | ... | ...   | ...      |
+///            | prepared at | primary ip | primary port | keys    | keylen  |   key   | ... | reply | mutation |
+/// Committed: | 8 bytes |
+///            | version |
+/// A primary port of 0 means this shard is the primary
+/// Integers are always encoded in little-endian order
+impl IntentRecord {
+    fn serialize(&self) -> Result<Vec<u8>> {
+        let mut body = Vec::new();
+        match self {
+            IntentRecord::Prepared {
+                txn_id,
+                transaction,
+            } => {
+                body.push(0);
+                body.extend_from_slice(&txn_id.to_le_bytes());
+                body.extend_from_slice(&transaction.prepared_at.to_le_bytes());
+                let (ip, port) = transaction
+                    .primary
+                    .map_or((0, 0), |primary| (primary.ip().to_bits(), primary.port()));
+                body.extend_from_slice(&ip.to_le_bytes());
+                body.extend_from_slice(&port.to_le_bytes());
+                let keys = u16::try_from(transaction.keys.len()).context("too many keys")?;
+                body.extend_from_slice(&keys.to_le_bytes());
+                for key in &transaction.keys {
+                    let key_len = u32::try_from(key.len()).context("key length overflow")?;
+                    body.extend_from_slice(&key_len.to_le_bytes());
+                    body.extend_from_slice(key);
+                }
+                transaction.reply.serialize_into(&mut body)?;
+                body.extend_from_slice(&transaction.mutation.serialize()?);
+            }
+            IntentRecord::Committed { txn_id, version } => {
+                body.push(1);
+                body.extend_from_slice(&txn_id.to_le_bytes());
+                body.extend_from_slice(&version.to_le_bytes());
+            }
+            IntentRecord::Aborted { txn_id } => {
+                body.push(2);
+                body.extend_from_slice(&txn_id.to_le_bytes());
+            }
+        }
+        frame_record(&body)
+    }
+
+    fn deserialize_body(body: &[u8]) -> Result<Self> {
+        let mut offset = 0;
+        let mut read = |len: usize, field: &str| -> Result<&[u8]> {
+            let bytes = body
+                .get(offset..offset + len)
+                .with_context(|| format!("failed to get {}", field))?;
+            offset += len;
+            Ok(bytes)
+        };
+
+        let kind = read(1, "kind")?[0];
+        let txn_id = u128::from_le_bytes(read(16, "txn id")?.try_into()?);
+        match kind {
+            0 => {
+                let prepared_at = u64::from_le_bytes(read(8, "prepared at")?.try_into()?);
+                let ip = u128::from_le_bytes(read(16, "primary ip")?.try_into()?);
+                let port = u16::from_le_bytes(read(2, "primary port")?.try_into()?);
+                let key_count = u16::from_le_bytes(read(2, "key count")?.try_into()?);
+                let mut keys = Vec::new();
+                for _ in 0..key_count {
+                    let key_len = u32::from_le_bytes(read(4, "key length")?.try_into()?) as usize;
+                    keys.push(read(key_len, "key")?.to_vec());
+                }
+                let reply = Reply::deserialize_from(body, &mut offset)?;
+                let mutation = Mutation::deserialize(&body[offset..])?;
+                Ok(IntentRecord::Prepared {
+                    txn_id,
+                    transaction: Box::new(PreparedTransaction {
+                        primary: (port != 0)
+                            .then(|| SocketAddrV6::new(Ipv6Addr::from(ip), port, 0, 0)),
+                        prepared_at,
+                        keys,
+                        mutation,
+                        reply,
+                    }),
+                })
+            }
+            1 => {
+                let version = u64::from_le_bytes(read(8, "version")?.try_into()?);
+                Ok(IntentRecord::Committed { txn_id, version })
+            }
+            2 => Ok(IntentRecord::Aborted { txn_id }),
+            kind => anyhow::bail!("unknown intent record kind {}", kind),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct IntentLog {
+    file: File,
+    path: PathBuf,
+    /// Records appended since the log was last rewritten
+    records: usize,
+}
+
+/// The transactions a write shard has prepared, the keys they hold, and
+/// how recently decided transactions ended
+#[allow(unused)]
+#[derive(Debug, Default)]
+pub struct Intents {
+    prepared: HashMap<u128, PreparedTransaction>,
+    locks: HashMap<Vec<u8>, u128>,
+    /// Whether each recently decided transaction committed, oldest first in `decided_order`
+    decided: HashMap<u128, bool>,
+    decided_order: VecDeque<u128>,
+    /// Only kept in memory when unset
+    log: Option<IntentLog>,
+}
+
+#[allow(unused)]
+impl Intents {
+    /// Opens (or creates) the log at `path` and restores the transactions
+    /// still in doubt. Also returns the batches of transactions that were
+    /// logged as committed, with their versions, since the shard may have
+    /// crashed before committing them. Call `compact` once they are redone.
+    pub fn open(path: &Path) -> Result<(Self, Vec<(u64, Mutation)>)> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)
+            .with_context(|| format!("failed to open intent log at {}", path.display()))?;
+
+        let mut buffer = Vec::new();
+        file.read_to_end(&mut buffer)?;
+
+        let mut intents = Intents::default();
+        let mut redo = Vec::new();
+        let records = read_records(&mut file, &buffer, "intent log", path)?;
+        let record_count = records.len();
+        for (offset, body) in records {
+            let record = IntentRecord::deserialize_body(body).with_context(|| {
+                format!(
+                    "intent log {} has a malformed record at offset {}",
+                    path.display(),
+                    offset
+                )
+            })?;
+            match record {
+                IntentRecord::Prepared {
+                    txn_id,
+                    transaction,
+                } => intents.hold(txn_id, *transaction),
+                IntentRecord::Committed { txn_id, version } => {
+                    if let Some(transaction) = intents.release(txn_id, true) {
+                        redo.push((version, transaction.mutation));
+                    }
+                }
+                IntentRecord::Aborted { txn_id } => {
+                    intents.release(txn_id, false);
+                }
+            }
+        }
+
+        intents.log = Some(IntentLog {
+            file,
+            path: path.to_path_buf(),
+            records: record_count,
+        });
+        Ok((intents, redo))
+    }
+
+    /// The transaction holding `key`, if any
+    pub fn holder(&self, key: &[u8]) -> Option<u128> {
+        self.locks.get(key).copied()
+    }
+
+    pub fn get(&self, txn_id: u128) -> Option<&PreparedTransaction> {
+        self.prepared.get(&txn_id)
+    }
+
+    /// Whether a transaction decided recently committed
+    pub fn outcome(&self, txn_id: u128) -> Option<bool> {
+        self.decided.get(&txn_id).copied()
+    }
+
+    /// Logs a transaction as prepared and locks its keys. The log is synced
+    /// before returning, since the vote must survive a crash.
+    pub fn prepare(&mut self, txn_id: u128, transaction: PreparedTransaction) -> Result<()> {
+        self.append(&IntentRecord::Prepared {
+            txn_id,
+            transaction: Box::new(transaction.clone()),
+        })?;
+        self.hold(txn_id, transaction);
+        Ok(())
+    }
+
+    /// Logs a transaction as committed at `version` and releases its keys.
+    /// The caller commits the returned batch at that version.
+    pub fn commit(&mut self, txn_id: u128, version: u64) -> Result<Option<PreparedTransaction>> {
+        self.append(&IntentRecord::Committed { txn_id, version })?;
+        Ok(self.release(txn_id, true))
+    }
+
+    /// Logs a transaction as aborted and releases its keys. The outcome is
+    /// remembered even if the transaction was never prepared here, so it
+    /// can't be prepared later on.
+    pub fn abort(&mut self, txn_id: u128) -> Result<Option<PreparedTransaction>> {
+        self.append(&IntentRecord::Aborted { txn_id })?;
+        Ok(self.release(txn_id, false))
+    }
+
+    /// Transactions prepared before `prepared_before` along with their primary
+    pub fn in_doubt(&self, prepared_before: u64) -> Vec<(u128, Option<SocketAddrV6>)> {
+        self.prepared
+            .iter()
+            .filter(|(_, transaction)| transaction.prepared_at < prepared_before)
+            .map(|(&txn_id, transaction)| (txn_id, transaction.primary))
+            .collect()
+    }
+
+    /// Rewrites the log down to the prepared transactions and remembered
+    /// outcomes once enough records have piled up since it was last
+    /// rewritten, so the cost of a rewrite is spread over at least as many
+    /// appends as it keeps. The new log is built in a temporary file and
+    /// renamed into place.
+    pub fn compact(&mut self) -> Result<()> {
+        let Some(log) = &self.log else {
+            return Ok(());
+        };
+        let kept = self.decided_order.len() + self.prepared.len();
+        if log.records < COMPACT_AFTER.max(kept) {
+            return Ok(());
+        }
+
+        let mut records: Vec<IntentRecord> = self
+            .decided_order
+            .iter()
+            .map(|&txn_id| match self.decided[&txn_id] {
+                // already committed, nothing to redo
+                true => IntentRecord::Committed { txn_id, version: 0 },
+                false => IntentRecord::Aborted { txn_id },
+            })
+            .collect();
+        records.extend(
+            self.prepared
+                .iter()
+                .map(|(&txn_id, transaction)| IntentRecord::Prepared {
+                    txn_id,
+                    transaction: Box::new(transaction.clone()),
+                }),
+        );
+
+        let log = self.log.as_mut().unwrap();
+        let tmp_path = log.path.with_extension("rewrite");
+        let mut tmp_file = File::create(&tmp_path)
+            .with_context(|| format!("failed to create {}", tmp_path.display()))?;
+        for record in &records {
+            tmp_file.write_all(&record.serialize()?)?;
+        }
+        tmp_file.sync_all()?;
+        fs::rename(&tmp_path, &log.path).with_context(|| {
+            format!(
+                "failed to move rewritten intent log to {}",
+                log.path.display()
+            )
+        })?;
+        log.file = OpenOptions::new().append(true).open(&log.path)?;
+        log.records = 0;
+        Ok(())
+    }
+
+    fn append(&mut self, record: &IntentRecord) -> Result<()> {
+        let Some(log) = &mut self.log else {
+            return Ok(());
+        };
+        log.file
+            .write_all(&record.serialize()?)
+            .with_context(|| format!("failed to append to intent log at {}", log.path.display()))?;
+        log.file.sync_data()?;
+        log.records += 1;
+        Ok(())
+    }
+
+    fn hold(&mut self, txn_id: u128, transaction: PreparedTransaction) {
+        for key in &transaction.keys {
+            self.locks.insert(key.clone(), txn_id);
+        }
+        self.prepared.insert(txn_id, transaction);
+    }
+
+    fn release(&mut self, txn_id: u128, committed: bool) -> Option<PreparedTransaction> {
+        if self.decided.insert(txn_id, committed).is_none() {
+            self.decided_order.push_back(txn_id);
+            if self.decided_order.len() > DECIDED_RETAINED {
+                let oldest = self.decided_order.pop_front().unwrap();
+                self.decided.remove(&oldest);
+            }
+        }
+        let transaction = self.prepared.remove(&txn_id)?;
+        for key in &transaction.keys {
+            self.locks.remove(key);
+        }
+        Some(transaction)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::Rng;
+
+    fn temp_log_path() -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "rust-edis-{}.intents",
+            rand::thread_rng().gen::<u64>()
+        ))
+    }
+
+    fn prepared(key: &str) -> PreparedTransaction {
+        PreparedTransaction {
+            primary: Some(SocketAddrV6::new(Ipv6Addr::LOCALHOST, 9000, 0, 0)),
+            prepared_at: 100,
+            keys: vec![key.as_bytes().to_vec()],
+            mutation: Mutation::Batch {
+                mutations: vec![Mutation::Set {
+                    key: key.as_bytes().to_vec(),
+                    value: b"1".to_vec(),
+                }],
+            },
+            reply: Reply::Array(vec![Reply::ok()]),
+        }
+    }
+
+    #[test]
+    fn test_locks_and_outcomes() {
+        let mut intents = Intents::default();
+        intents.prepare(1, prepared("a")).unwrap();
+        intents.prepare(2, prepared("b")).unwrap();
+        assert_eq!(intents.holder(b"a"), Some(1));
+        assert_eq!(intents.in_doubt(101).len(), 2);
+        assert!(intents.in_doubt(100).is_empty());
+
+        assert_eq!(intents.commit(1, 7).unwrap(), Some(prepared("a")));
+        assert_eq!(intents.holder(b"a"), None);
+        assert_eq!(intents.outcome(1), Some(true));
+
+        // aborting a transaction that was never prepared still remembers the outcome
+        assert_eq!(intents.abort(3).unwrap(), None);
+        assert_eq!(intents.outcome(3), Some(false));
+        assert_eq!(intents.outcome(2), None);
+    }
+
+    #[test]
+    fn test_recovery() {
+        let path = temp_log_path();
+        {
+            let (mut intents, redo) = Intents::open(&path).unwrap();
+            assert!(redo.is_empty());
+            intents.prepare(1, prepared("a")).unwrap();
+            intents.prepare(2, prepared("b")).unwrap();
+            intents.prepare(3, prepared("c")).unwrap();
+            intents.commit(1, 5).unwrap();
+            intents.abort(2).unwrap();
+        }
+
+        let (mut intents, redo) = Intents::open(&path).unwrap();
+        // the committed batch comes back in case it never made it to the aof
+        assert_eq!(redo, vec![(5, prepared("a").mutation)]);
+        assert_eq!(intents.get(3), Some(&prepared("c")));
+        assert_eq!(intents.holder(b"c"), Some(3));
+        assert_eq!(intents.holder(b"b"), None);
+        assert_eq!(intents.outcome(1), Some(true));
+        assert_eq!(intents.outcome(2), Some(false));
+
+        intents.log.as_mut().unwrap().records = COMPACT_AFTER;
+        intents.compact().unwrap();
+        drop(intents);
+        let (intents, redo) = Intents::open(&path).unwrap();
+        assert!(redo.is_empty());
+        assert_eq!(intents.get(3), Some(&prepared("c")));
+        assert_eq!(intents.outcome(1), Some(true));
+        assert_eq!(intents.outcome(2), Some(false));
+        assert_eq!(intents.log.as_ref().unwrap().records, 3);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_compaction_waits_for_enough_appends() {
+        let path = temp_log_path();
+        let (mut intents, _) = Intents::open(&path).unwrap();
+        for txn_id in 0..3 * COMPACT_AFTER as u128 {
+            intents.abort(txn_id).unwrap();
+            intents.compact().unwrap();
+        }
+        // rewritten once at COMPACT_AFTER records, after which the outcomes
+        // it keeps outnumber the records appended since
+        assert_eq!(intents.log.as_ref().unwrap().records, 2 * COMPACT_AFTER);
+        drop(intents);
+        let (intents, _) = Intents::open(&path).unwrap();
+        assert_eq!(intents.outcome(0), Some(false));
+        assert_eq!(intents.log.as_ref().unwrap().records, 3 * COMPACT_AFTER);
+        fs::remove_file(&path).unwrap();
+    }
+}
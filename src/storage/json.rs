@@ -0,0 +1,302 @@
+//! JSON documents and the subset of JSONPath used to address values in them.
+//! Paths starting with `$` may match any number of values; legacy paths like
+//! `.a.b` or `a[0]` address a single one. Both support `.key`, `['key']`,
+//! `[index]` with negative indexes counting from the end, and `*` wildcards.
+
+use anyhow::{bail, Context, Result};
+use serde_json::Value as Json;
+
+/// One step from a JSON value into one of its children
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Step {
+    Key(String),
+    Index(usize),
+}
+
+/// Segment of a path, which can match several steps
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Key(String),
+    Index(i64),
+    Wildcard,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Path {
+    segments: Vec<Segment>,
+    legacy: bool,
+}
+
+impl Path {
+    pub fn parse(path: &str) -> Result<Self> {
+        let (mut rest, legacy) = match path.strip_prefix('$') {
+            Some(rest) => (rest, false),
+            None if path == "." => ("", true),
+            None => (path, true),
+        };
+        let mut segments = Vec::new();
+        // legacy paths may leave out the leading dot
+        let mut first = legacy && !rest.is_empty() && !rest.starts_with(['.', '[']);
+        while !rest.is_empty() || first {
+            if let Some(bracketed) = rest.strip_prefix('[') {
+                let end = bracketed
+                    .find(']')
+                    .with_context(|| format!("invalid JSONPath '{}'", path))?;
+                let inner = &bracketed[..end];
+                segments.push(match inner {
+                    "*" => Segment::Wildcard,
+                    _ if inner.len() >= 2
+                        && (inner.starts_with('\'') && inner.ends_with('\'')
+                            || inner.starts_with('"') && inner.ends_with('"')) =>
+                    {
+                        Segment::Key(inner[1..inner.len() - 1].to_string())
+                    }
+                    _ => Segment::Index(
+                        inner
+                            .parse()
+                            .with_context(|| format!("invalid JSONPath '{}'", path))?,
+                    ),
+                });
+                rest = &bracketed[end + 1..];
+                continue;
+            }
+            let name = if first {
+                first = false;
+                rest
+            } else {
+                match rest.strip_prefix('.') {
+                    Some(name) => name,
+                    None => bail!("invalid JSONPath '{}'", path),
+                }
+            };
+            let end = name.find(['.', '[']).unwrap_or(name.len());
+            segments.push(match &name[..end] {
+                "" => bail!("invalid JSONPath '{}'", path),
+                "*" => Segment::Wildcard,
+                key => Segment::Key(key.to_string()),
+            });
+            rest = &name[end..];
+        }
+        Ok(Path { segments, legacy })
+    }
+
+    /// Whether this is a legacy path, which addresses a single value
+    pub fn is_legacy(&self) -> bool {
+        self.legacy
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.segments.is_empty()
+    }
+
+    /// Steps to every value in `document` the path matches, in document order
+    pub fn find(&self, document: &Json) -> Vec<Vec<Step>> {
+        let mut matches = vec![(Vec::new(), document)];
+        for segment in &self.segments {
+            let mut next = Vec::new();
+            for (steps, value) in matches {
+                let child = |step: Step, value| {
+                    let mut steps = steps.clone();
+                    steps.push(step);
+                    (steps, value)
+                };
+                match (segment, value) {
+                    (Segment::Key(key), Json::Object(object)) => {
+                        if let Some(value) = object.get(key) {
+                            next.push(child(Step::Key(key.clone()), value));
+                        }
+                    }
+                    (Segment::Index(index), Json::Array(array)) => {
+                        let index = if *index < 0 {
+                            *index + array.len() as i64
+                        } else {
+                            *index
+                        };
+                        if let Some(value) = usize::try_from(index)
+                            .ok()
+                            .and_then(|index| array.get(index).map(|value| (index, value)))
+                        {
+                            next.push(child(Step::Index(value.0), value.1));
+                        }
+                    }
+                    (Segment::Wildcard, Json::Object(object)) => {
+                        for (key, value) in object {
+                            next.push(child(Step::Key(key.clone()), value));
+                        }
+                    }
+                    (Segment::Wildcard, Json::Array(array)) => {
+                        for (index, value) in array.iter().enumerate() {
+                            next.push(child(Step::Index(index), value));
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            matches = next;
+        }
+        matches.into_iter().map(|(steps, _)| steps).collect()
+    }
+
+    /// The path to the parent of what this path matches, along with the key
+    /// it ends in, if it does end in a key
+    pub fn split_last_key(&self) -> Option<(Path, &str)> {
+        let (last, parent) = self.segments.split_last()?;
+        match last {
+            Segment::Key(key) => Some((
+                Path {
+                    segments: parent.to_vec(),
+                    legacy: self.legacy,
+                },
+                key,
+            )),
+            _ => None,
+        }
+    }
+}
+
+pub fn get<'a>(document: &'a Json, steps: &[Step]) -> Option<&'a Json> {
+    steps.iter().try_fold(document, |value, step| match step {
+        Step::Key(key) => value.get(key),
+        Step::Index(index) => value.get(index),
+    })
+}
+
+fn get_mut<'a>(document: &'a mut Json, steps: &[Step]) -> Option<&'a mut Json> {
+    steps.iter().try_fold(document, |value, step| match step {
+        Step::Key(key) => value.get_mut(key),
+        Step::Index(index) => value.get_mut(index),
+    })
+}
+
+/// Sets the value at `steps`, adding the last key to its object if it is
+/// missing. Returns false if there is nothing to set it in.
+pub fn set(document: &mut Json, steps: &[Step], value: Json) -> bool {
+    let Some((last, parent)) = steps.split_last() else {
+        *document = value;
+        return true;
+    };
+    match (get_mut(document, parent), last) {
+        (Some(Json::Object(object)), Step::Key(key)) => {
+            object.insert(key.clone(), value);
+            true
+        }
+        (Some(Json::Array(array)), Step::Index(index)) if *index < array.len() => {
+            array[*index] = value;
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Removes the value at `steps`, which can't be the root. Returns whether it existed.
+pub fn delete(document: &mut Json, steps: &[Step]) -> bool {
+    let Some((last, parent)) = steps.split_last() else {
+        return false;
+    };
+    match (get_mut(document, parent), last) {
+        (Some(Json::Object(object)), Step::Key(key)) => object.shift_remove(key).is_some(),
+        (Some(Json::Array(array)), Step::Index(index)) if *index < array.len() => {
+            array.remove(*index);
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Appends values to the array at `steps`, returning its new length, or
+/// None if there is no array there
+pub fn append(document: &mut Json, steps: &[Step], values: &[Json]) -> Option<usize> {
+    match get_mut(document, steps)? {
+        Json::Array(array) => {
+            array.extend_from_slice(values);
+            Some(array.len())
+        }
+        _ => None,
+    }
+}
+
+/// Encodes steps as a JSON array of keys and indexes
+pub fn encode_steps(steps: &[Step]) -> Vec<u8> {
+    let steps: Vec<Json> = steps
+        .iter()
+        .map(|step| match step {
+            Step::Key(key) => Json::from(key.as_str()),
+            Step::Index(index) => Json::from(*index),
+        })
+        .collect();
+    Json::Array(steps).to_string().into_bytes()
+}
+
+pub fn decode_steps(buffer: &[u8]) -> Result<Vec<Step>> {
+    let Json::Array(steps) = serde_json::from_slice(buffer)? else {
+        bail!("invalid JSON steps");
+    };
+    steps
+        .into_iter()
+        .map(|step| match step {
+            Json::String(key) => Ok(Step::Key(key)),
+            Json::Number(index) => Ok(Step::Index(
+                index.as_u64().context("invalid JSON index")? as usize
+            )),
+            _ => bail!("invalid JSON step"),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn key(key: &str) -> Step {
+        Step::Key(key.to_string())
+    }
+
+    #[test]
+    fn test_paths() {
+        let document = json!({"a": {"b": [1, 2, 3]}, "c": {"b": 4}, "d": "x"});
+        let find = |path: &str| Path::parse(path).unwrap().find(&document);
+
+        assert_eq!(find("$"), vec![Vec::<Step>::new()]);
+        assert_eq!(find("."), vec![Vec::<Step>::new()]);
+        assert_eq!(
+            find("$.a.b[-1]"),
+            vec![vec![key("a"), key("b"), Step::Index(2)]]
+        );
+        assert_eq!(
+            find("a.b[0]"),
+            vec![vec![key("a"), key("b"), Step::Index(0)]]
+        );
+        assert_eq!(find("$['a'][\"b\"][1]"), find(".a.b[1]"));
+        assert_eq!(
+            find("$.*.b"),
+            vec![vec![key("a"), key("b")], vec![key("c"), key("b")]]
+        );
+        assert_eq!(find("$.a.b[*]").len(), 3);
+        assert!(find("$.missing").is_empty());
+        assert!(find("$.d[0]").is_empty());
+
+        assert!(!Path::parse("$.a").unwrap().is_legacy());
+        assert!(Path::parse(".a").unwrap().is_legacy());
+        assert!(Path::parse("$.a[").is_err());
+        assert!(Path::parse("$a").is_err());
+        assert!(Path::parse("$.a..b").is_err());
+    }
+
+    #[test]
+    fn test_updates() {
+        let mut document = json!({"a": [1, 2, 3]});
+        assert!(set(&mut document, &[key("b")], json!(true)));
+        assert!(set(&mut document, &[key("a"), Step::Index(0)], json!(0)));
+        assert!(!set(&mut document, &[key("a"), Step::Index(3)], json!(0)));
+        assert!(!set(&mut document, &[key("x"), key("y")], json!(0)));
+        assert_eq!(append(&mut document, &[key("a")], &[json!(4)]), Some(4));
+        assert_eq!(append(&mut document, &[key("b")], &[json!(4)]), None);
+        assert!(delete(&mut document, &[key("a"), Step::Index(1)]));
+        assert!(!delete(&mut document, &[key("missing")]));
+        assert_eq!(document, json!({"a": [0, 3, 4], "b": true}));
+
+        let steps = vec![key("a"), Step::Index(7)];
+        assert_eq!(decode_steps(&encode_steps(&steps)).unwrap(), steps);
+    }
+}
@@ -0,0 +1,253 @@
+use super::engine::Entry;
+use super::memory::ENTRY_OVERHEAD;
+use super::value::Value;
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Milliseconds since the unix epoch, the unit every expiry is stored in
+pub fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// The keys of a shard along with their expiry times and the version that
+/// last modified them. An expired key stays in the map until its deletion
+/// is replicated, but it is never served.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Keyspace {
+    data: HashMap<Vec<u8>, Value>,
+    /// Absolute expiry of keys with a ttl, in milliseconds since the unix epoch
+    expires: HashMap<Vec<u8>, u64>,
+    /// Version of the last mutation to each key
+    versions: HashMap<Vec<u8>, u64>,
+}
+
+impl Keyspace {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    #[allow(unused)]
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Whether `key` has an expiry at or before `now`
+    pub fn is_expired(&self, key: &[u8], now: u64) -> bool {
+        self.expires.get(key).is_some_and(|&at| at <= now)
+    }
+
+    /// Value of `key`, unless it is missing or expired at `now`
+    pub fn get(&self, key: &[u8], now: u64) -> Option<&Value> {
+        if self.is_expired(key, now) {
+            return None;
+        }
+        self.data.get(key)
+    }
+
+    /// Mutable value of `key`, expired or not. Mutations are applied the same
+    /// way whenever they are replayed, so they never look at the clock.
+    pub fn get_mut(&mut self, key: &[u8]) -> Option<&mut Value> {
+        self.data.get_mut(key)
+    }
+
+    pub fn contains_key(&self, key: &[u8], now: u64) -> bool {
+        self.get(key, now).is_some()
+    }
+
+    /// Sets `key` to `value` and clears any expiry it had
+    pub fn insert(&mut self, key: Vec<u8>, value: impl Into<Value>) {
+        self.expires.remove(&key);
+        self.data.insert(key, value.into());
+    }
+
+    /// Replaces the value of an existing key, keeping its expiry
+    pub fn update(&mut self, key: &[u8], value: impl Into<Value>) {
+        if let Some(current) = self.data.get_mut(key) {
+            *current = value.into();
+        }
+    }
+
+    pub fn remove(&mut self, key: &[u8]) -> Option<Value> {
+        self.expires.remove(key);
+        self.versions.remove(key);
+        self.data.remove(key)
+    }
+
+    /// Version of the last mutation to `key`, or 0 if it is missing or expired at `now`
+    pub fn version(&self, key: &[u8], now: u64) -> u64 {
+        if !self.contains_key(key, now) {
+            return 0;
+        }
+        self.versions.get(key).copied().unwrap_or(0)
+    }
+
+    /// Records `version` as the last mutation to an existing key
+    pub fn set_version(&mut self, key: &[u8], version: u64) {
+        if self.data.contains_key(key) {
+            self.versions.insert(key.to_vec(), version);
+        }
+    }
+
+    /// Expiry of `key`, if it has one
+    pub fn expires_at(&self, key: &[u8]) -> Option<u64> {
+        self.expires.get(key).copied()
+    }
+
+    /// Makes an existing key expire at `at`. Returns false if there is no such key.
+    pub fn set_expiry(&mut self, key: &[u8], at: u64) -> bool {
+        if !self.data.contains_key(key) {
+            return false;
+        }
+        self.expires.insert(key.to_vec(), at);
+        true
+    }
+
+    /// Removes the expiry of `key`. Returns false if it had none.
+    pub fn persist(&mut self, key: &[u8]) -> bool {
+        self.expires.remove(key).is_some()
+    }
+
+    /// Estimated bytes `key` takes up along with its value, expiry and
+    /// version, expired or not
+    #[allow(unused)]
+    pub fn memory_usage(&self, key: &[u8]) -> Option<usize> {
+        let value = self.data.get(key)?;
+        Some(ENTRY_OVERHEAD + key.len() + value.memory_usage())
+    }
+
+    /// Every key with an expiry, along with it
+    pub fn volatile_keys(&self) -> impl Iterator<Item = (&Vec<u8>, u64)> {
+        self.expires.iter().map(|(key, &at)| (key, at))
+    }
+
+    /// Keys whose expiry is at or before `now`
+    #[allow(unused)]
+    pub fn expired_keys(&self, now: u64) -> Vec<Vec<u8>> {
+        self.expires
+            .iter()
+            .filter(|(_, &at)| at <= now)
+            .map(|(key, _)| key.clone())
+            .collect()
+    }
+
+    /// Value of `key` along with its expiry and version, expired or not
+    pub fn entry(&self, key: &[u8]) -> Option<Entry> {
+        Some(Entry {
+            value: self.data.get(key)?.clone(),
+            expires_at: self.expires.get(key).copied(),
+            version: self.versions.get(key).copied().unwrap_or(0),
+        })
+    }
+
+    /// Sets `key` to the value, expiry and version of `entry`
+    pub fn insert_entry(&mut self, key: Vec<u8>, entry: Entry) {
+        match entry.expires_at {
+            Some(at) => self.expires.insert(key.clone(), at),
+            None => self.expires.remove(&key),
+        };
+        match entry.version {
+            0 => self.versions.remove(&key),
+            version => self.versions.insert(key.clone(), version),
+        };
+        self.data.insert(key, entry.value);
+    }
+
+    /// Copy of just the given keys along with their expiries and versions,
+    /// to try out changes to a few keys without copying the rest
+    pub fn subset<'a>(&self, keys: impl IntoIterator<Item = &'a Vec<u8>>) -> Keyspace {
+        let mut subset = Keyspace::new();
+        for key in keys {
+            let Some(value) = self.data.get(key) else {
+                continue;
+            };
+            subset.data.insert(key.clone(), value.clone());
+            if let Some(&at) = self.expires.get(key) {
+                subset.expires.insert(key.clone(), at);
+            }
+            if let Some(&version) = self.versions.get(key) {
+                subset.versions.insert(key.clone(), version);
+            }
+        }
+        subset
+    }
+
+    /// Takes apart the keyspace into its keys along with their entries
+    pub fn into_entries(mut self) -> impl Iterator<Item = (Vec<u8>, Entry)> {
+        self.data.into_iter().map(move |(key, value)| {
+            let entry = Entry {
+                value,
+                expires_at: self.expires.remove(&key),
+                version: self.versions.remove(&key).unwrap_or(0),
+            };
+            (key, entry)
+        })
+    }
+
+    /// Iterates over every key, expired or not, with its value, expiry and version
+    pub fn iter(&self) -> impl Iterator<Item = (&Vec<u8>, &Value, Option<u64>, u64)> {
+        self.data.iter().map(|(key, value)| {
+            (
+                key,
+                value,
+                self.expires.get(key).copied(),
+                self.versions.get(key).copied().unwrap_or(0),
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expiry() {
+        let mut keyspace = Keyspace::new();
+        keyspace.insert(b"key".to_vec(), b"value".to_vec());
+        assert!(keyspace.set_expiry(b"key", 100));
+        assert!(!keyspace.set_expiry(b"missing", 100));
+
+        assert_eq!(
+            keyspace.get(b"key", 99),
+            Some(&Value::from(b"value".to_vec()))
+        );
+        assert_eq!(keyspace.get(b"key", 100), None);
+        assert_eq!(keyspace.expired_keys(100), vec![b"key".to_vec()]);
+        assert!(keyspace.expired_keys(99).is_empty());
+
+        // a plain set clears the expiry
+        keyspace.insert(b"key".to_vec(), b"value2".to_vec());
+        assert_eq!(keyspace.expires_at(b"key"), None);
+        assert!(keyspace.contains_key(b"key", 100));
+
+        keyspace.set_expiry(b"key", 100);
+        assert!(keyspace.persist(b"key"));
+        assert!(!keyspace.persist(b"key"));
+        assert!(keyspace.contains_key(b"key", 100));
+    }
+
+    #[test]
+    fn test_version() {
+        let mut keyspace = Keyspace::new();
+        keyspace.set_version(b"key", 1);
+        assert_eq!(keyspace.version(b"key", 0), 0);
+
+        keyspace.insert(b"key".to_vec(), b"value".to_vec());
+        keyspace.set_version(b"key", 2);
+        assert_eq!(keyspace.version(b"key", 0), 2);
+
+        keyspace.set_expiry(b"key", 100);
+        assert_eq!(keyspace.version(b"key", 100), 0);
+
+        keyspace.remove(b"key");
+        keyspace.insert(b"key".to_vec(), b"value".to_vec());
+        assert_eq!(keyspace.version(b"key", 0), 0);
+    }
+}
@@ -0,0 +1,356 @@
+//! Memory accounting for write shards and the eviction policies that keep a
+//! shard under its `maxmemory` limit. Sizes are estimates in the spirit of
+//! redis' MEMORY USAGE: collections are measured from a sample of their
+//! elements rather than walked in full, so accounting a write stays cheap.
+
+use rand::Rng;
+use std::collections::HashMap;
+
+use super::engine::StorageEngine;
+use super::mutation::Mutation;
+
+/// Bytes charged for every key and history entry on top of its contents,
+/// for the map slots and allocations holding it
+pub(crate) const ENTRY_OVERHEAD: usize = 64;
+/// Bytes charged for every element of a collection on top of its contents
+const ELEMENT_OVERHEAD: usize = 16;
+/// Elements of a collection measured to estimate its size
+const SIZE_SAMPLES: usize = 5;
+/// Keys sampled to pick one to evict under the lru and lfu policies
+const EVICTION_SAMPLES: usize = 16;
+/// Idle time after which a key's access frequency drops by one
+const LFU_DECAY_MS: u64 = 60_000;
+
+/// What a write shard does when a write would take it over `maxmemory`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum EvictionPolicy {
+    /// refuse writes that could grow the shard with an out of memory error
+    #[value(name = "noeviction")]
+    NoEviction,
+    /// evict the least recently used keys
+    AllkeysLru,
+    /// evict the least frequently used keys
+    AllkeysLfu,
+    /// evict the keys with an expiry that is closest
+    VolatileTtl,
+}
+
+/// Parses a byte count such as `1048576`, `512kb`, `100mb` or `2gb`
+#[allow(unused)]
+pub fn parse_bytes(arg: &str) -> Result<u64, String> {
+    let arg = arg.trim().to_lowercase();
+    let (number, unit) = match arg.find(|c: char| !c.is_ascii_digit()) {
+        Some(at) => arg.split_at(at),
+        None => (arg.as_str(), ""),
+    };
+    let multiplier: u64 = match unit {
+        "" | "b" => 1,
+        "kb" => 1 << 10,
+        "mb" => 1 << 20,
+        "gb" => 1 << 30,
+        _ => return Err(format!("unknown unit '{}', use b, kb, mb or gb", unit)),
+    };
+    number
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(multiplier))
+        .ok_or_else(|| format!("invalid byte count '{}'", arg))
+}
+
+/// Estimates the size of a collection of `len` elements from the sizes of
+/// its first few
+pub fn sampled_usage(len: usize, sizes: impl Iterator<Item = usize>) -> usize {
+    let (count, total) = sizes
+        .take(SIZE_SAMPLES)
+        .fold((0, 0), |(count, total), size| (count + 1, total + size));
+    if count == 0 {
+        return 0;
+    }
+    len * (total / count + ELEMENT_OVERHEAD)
+}
+
+/// Estimated bytes a mutation takes up in the version history
+#[allow(unused)]
+pub fn mutation_usage(mutation: &Mutation) -> usize {
+    ENTRY_OVERHEAD + mutation.key().len() + mutation.value().len()
+}
+
+#[derive(Debug)]
+struct KeyStats {
+    size: usize,
+    /// When the key was last written or read, in unix milliseconds
+    last_access: u64,
+    frequency: u32,
+    /// Position of the key in `Memory::keys`
+    slot: usize,
+}
+
+/// Memory a write shard uses for its keys and its version history, along
+/// with how recently and how often each key was used
+#[allow(unused)]
+#[derive(Debug)]
+pub struct Memory {
+    /// Limit in bytes, 0 for none
+    maxmemory: u64,
+    policy: EvictionPolicy,
+    stats: HashMap<Vec<u8>, KeyStats>,
+    /// Every tracked key, so eviction can sample them at random
+    keys: Vec<Vec<u8>>,
+    data_bytes: usize,
+    history_bytes: usize,
+}
+
+impl Default for Memory {
+    fn default() -> Self {
+        Memory::new(0, EvictionPolicy::NoEviction)
+    }
+}
+
+#[allow(unused)]
+impl Memory {
+    pub fn new(maxmemory: u64, policy: EvictionPolicy) -> Self {
+        Memory {
+            maxmemory,
+            policy,
+            stats: HashMap::new(),
+            keys: Vec::new(),
+            data_bytes: 0,
+            history_bytes: 0,
+        }
+    }
+
+    /// Bytes used by the keys and by the version history
+    pub fn used(&self) -> usize {
+        self.data_bytes + self.history_bytes
+    }
+
+    pub fn is_over_limit(&self) -> bool {
+        self.maxmemory > 0 && self.used() as u64 > self.maxmemory
+    }
+
+    /// Whether `mutations` can be committed, given whether eviction made room
+    /// for them. Without room only ones that don't take up more memory can.
+    pub fn admits<'a>(has_room: bool, mutations: impl IntoIterator<Item = &'a Mutation>) -> bool {
+        has_room || mutations.into_iter().all(Mutation::only_shrinks)
+    }
+
+    /// Records the size of `key` after a write to it, None once it is gone.
+    /// A write counts as a use of the key.
+    pub fn resize(&mut self, key: &[u8], size: Option<usize>, now: u64) {
+        let Some(size) = size else {
+            self.forget(key);
+            return;
+        };
+        match self.stats.get_mut(key) {
+            Some(stats) => {
+                self.data_bytes = self.data_bytes - stats.size + size;
+                stats.size = size;
+            }
+            None => {
+                self.data_bytes += size;
+                self.stats.insert(
+                    key.to_vec(),
+                    KeyStats {
+                        size,
+                        last_access: now,
+                        frequency: 0,
+                        slot: self.keys.len(),
+                    },
+                );
+                self.keys.push(key.to_vec());
+            }
+        }
+        self.touch(key, now);
+    }
+
+    /// Records a use of `key`
+    pub fn touch(&mut self, key: &[u8], now: u64) {
+        if let Some(stats) = self.stats.get_mut(key) {
+            stats.frequency = decayed(stats, now).saturating_add(1);
+            stats.last_access = now;
+        }
+    }
+
+    fn forget(&mut self, key: &[u8]) {
+        let Some(stats) = self.stats.remove(key) else {
+            return;
+        };
+        self.data_bytes -= stats.size;
+        self.keys.swap_remove(stats.slot);
+        if let Some(moved) = self.keys.get(stats.slot) {
+            self.stats.get_mut(moved).unwrap().slot = stats.slot;
+        }
+    }
+
+    pub fn history_grew(&mut self, bytes: usize) {
+        self.history_bytes += bytes;
+    }
+
+    pub fn history_shrank(&mut self, bytes: usize) {
+        self.history_bytes = self.history_bytes.saturating_sub(bytes);
+    }
+
+    /// Picks the key to evict next under the shard's policy, among the ones
+    /// `evictable` allows. None under noeviction or when no key qualifies.
+    pub fn victim(
+        &self,
+        engine: &dyn StorageEngine,
+        now: u64,
+        evictable: impl Fn(&[u8]) -> bool,
+    ) -> Option<Vec<u8>> {
+        match self.policy {
+            EvictionPolicy::NoEviction => None,
+            // every key with an expiry is a candidate, like redis' expires dict
+            EvictionPolicy::VolatileTtl => {
+                let mut closest: Option<(Vec<u8>, u64)> = None;
+                engine.visit_expiries(&mut |key, expires_at| {
+                    if closest.as_ref().is_none_or(|&(_, at)| expires_at < at) && evictable(key) {
+                        closest = Some((key.to_vec(), expires_at));
+                    }
+                });
+                closest.map(|(key, _)| key)
+            }
+            EvictionPolicy::AllkeysLru | EvictionPolicy::AllkeysLfu => {
+                let mut rng = rand::thread_rng();
+                let samples = if self.keys.len() <= EVICTION_SAMPLES {
+                    self.keys.iter().collect::<Vec<_>>()
+                } else {
+                    (0..EVICTION_SAMPLES)
+                        .map(|_| &self.keys[rng.gen_range(0..self.keys.len())])
+                        .collect()
+                };
+                samples
+                    .into_iter()
+                    .filter(|key| evictable(key))
+                    .min_by_key(|key| {
+                        let stats = &self.stats[*key];
+                        match self.policy {
+                            EvictionPolicy::AllkeysLfu => {
+                                (decayed(stats, now) as u64, stats.last_access)
+                            }
+                            _ => (stats.last_access, 0),
+                        }
+                    })
+                    .cloned()
+            }
+        }
+    }
+}
+
+/// Access frequency of a key, less one for every LFU_DECAY_MS it sat idle
+fn decayed(stats: &KeyStats, now: u64) -> u32 {
+    let idle_periods = now.saturating_sub(stats.last_access) / LFU_DECAY_MS;
+    stats
+        .frequency
+        .saturating_sub(u32::try_from(idle_periods).unwrap_or(u32::MAX))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::engine::MemoryEngine;
+    use crate::storage::keyspace::Keyspace;
+
+    fn engine(keyspace: &Keyspace) -> MemoryEngine {
+        let engine = MemoryEngine::new();
+        engine.restore(keyspace.clone(), 0).unwrap();
+        engine
+    }
+
+    fn tracked(memory: &mut Memory, keyspace: &mut Keyspace, key: &[u8], now: u64) {
+        keyspace.insert(key.to_vec(), b"value".to_vec());
+        memory.resize(key, keyspace.memory_usage(key), now);
+    }
+
+    #[test]
+    fn test_accounting() {
+        let mut memory = Memory::new(1, EvictionPolicy::NoEviction);
+        let mut keyspace = Keyspace::new();
+        assert!(!memory.is_over_limit());
+
+        tracked(&mut memory, &mut keyspace, b"a", 0);
+        tracked(&mut memory, &mut keyspace, b"b", 0);
+        let size = keyspace.memory_usage(b"a").unwrap();
+        assert_eq!(memory.used(), 2 * size);
+        assert!(memory.is_over_limit());
+        assert_eq!(memory.victim(&engine(&keyspace), 0, |_| true), None);
+
+        keyspace.insert(b"a".to_vec(), vec![0; 1000]);
+        memory.resize(b"a", keyspace.memory_usage(b"a"), 0);
+        assert_eq!(memory.used(), 2 * size + 995);
+
+        keyspace.remove(b"a");
+        memory.resize(b"a", None, 0);
+        assert_eq!(memory.used(), size);
+        assert_eq!(memory.keys, vec![b"b".to_vec()]);
+        assert_eq!(memory.stats[b"b".as_slice()].slot, 0);
+
+        memory.history_grew(10);
+        assert_eq!(memory.used(), size + 10);
+        memory.history_shrank(10);
+        assert_eq!(memory.used(), size);
+    }
+
+    #[test]
+    fn test_policies() {
+        let mut keyspace = Keyspace::new();
+        let mut lru = Memory::new(1, EvictionPolicy::AllkeysLru);
+        let mut lfu = Memory::new(1, EvictionPolicy::AllkeysLfu);
+        for memory in [&mut lru, &mut lfu] {
+            tracked(memory, &mut keyspace, b"old", 1);
+            tracked(memory, &mut keyspace, b"busy", 2);
+            tracked(memory, &mut keyspace, b"new", 3);
+            for now in 4..10 {
+                memory.touch(b"busy", now);
+            }
+        }
+        assert_eq!(
+            lru.victim(&engine(&keyspace), 10, |_| true),
+            Some(b"old".to_vec())
+        );
+        assert_eq!(
+            lru.victim(&engine(&keyspace), 10, |key| key != b"old"),
+            Some(b"new".to_vec())
+        );
+        // each key was used once except busy, and old was used first
+        assert_eq!(
+            lfu.victim(&engine(&keyspace), 10, |_| true),
+            Some(b"old".to_vec())
+        );
+        lfu.touch(b"old", 11);
+        lfu.touch(b"old", 12);
+        assert_eq!(
+            lfu.victim(&engine(&keyspace), 12, |_| true),
+            Some(b"new".to_vec())
+        );
+        // frequencies decay while keys sit idle
+        lfu.touch(b"new", 8 * LFU_DECAY_MS);
+        assert_eq!(
+            lfu.victim(&engine(&keyspace), 8 * LFU_DECAY_MS, |_| true),
+            Some(b"busy".to_vec())
+        );
+
+        let ttl = Memory::new(1, EvictionPolicy::VolatileTtl);
+        assert_eq!(ttl.victim(&engine(&keyspace), 0, |_| true), None);
+        keyspace.set_expiry(b"busy", 200);
+        keyspace.set_expiry(b"new", 100);
+        assert_eq!(
+            ttl.victim(&engine(&keyspace), 0, |_| true),
+            Some(b"new".to_vec())
+        );
+        assert_eq!(
+            ttl.victim(&engine(&keyspace), 0, |key| key != b"new"),
+            Some(b"busy".to_vec())
+        );
+    }
+
+    #[test]
+    fn test_parse_bytes() {
+        assert_eq!(parse_bytes("1024"), Ok(1024));
+        assert_eq!(parse_bytes("512kb"), Ok(512 << 10));
+        assert_eq!(parse_bytes("100MB"), Ok(100 << 20));
+        assert_eq!(parse_bytes("2gb"), Ok(2 << 30));
+        assert!(parse_bytes("10tb").is_err());
+        assert!(parse_bytes("mb").is_err());
+    }
+}
@@ -0,0 +1,17 @@
+pub mod aof;
+pub mod disk;
+pub mod engine;
+pub mod geohash;
+pub mod history;
+pub mod hyperloglog;
+pub mod intents;
+pub mod json;
+pub mod keyspace;
+pub mod memory;
+pub mod mutation;
+pub mod replicas;
+pub mod snapshot;
+pub mod sorted_set;
+pub mod store;
+pub mod stream;
+pub mod value;
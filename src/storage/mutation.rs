@@ -0,0 +1,1023 @@
+use super::hyperloglog::{HyperLogLog, REGISTERS};
+use super::json::{self, Step};
+use super::keyspace::Keyspace;
+use super::sorted_set::{Score, SortedSet};
+use super::stream::{Fields, Stream, StreamId};
+use super::value::{
+    decode_items, decode_pairs, decode_scores, encode_items, encode_pairs, encode_scores,
+    resolve_range, ListEnd, Value,
+};
+use anyhow::{Context, Result};
+use int_enum::IntEnum;
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, IntEnum, PartialEq, Eq)]
+pub enum MutationType {
+    Set = 0,
+    Delete = 1,
+    SetWithExpiry = 2,
+    Expire = 3,
+    Persist = 4,
+    Update = 5,
+    Push = 6,
+    Pop = 7,
+    Trim = 8,
+    SetFields = 9,
+    DeleteFields = 10,
+    AddMembers = 11,
+    RemoveMembers = 12,
+    SetScores = 13,
+    AddEntry = 14,
+    TrimStream = 15,
+    CreateGroup = 16,
+    DestroyGroup = 17,
+    DeliverEntries = 18,
+    AckEntries = 19,
+    RaiseRegisters = 20,
+    SetJsonPath = 21,
+    DeleteJsonPath = 22,
+    AppendJsonArray = 23,
+    Batch = 24,
+}
+
+/// A single change to the keyspace. Every version in the history is one
+/// mutation, and read shards replay them in order to catch up.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Mutation {
+    Set {
+        key: Vec<u8>,
+        value: Vec<u8>,
+    },
+    /// Tombstone left behind by a deleted or expired key
+    Delete {
+        key: Vec<u8>,
+    },
+    /// Set that also gives the key an absolute expiry, in unix milliseconds.
+    /// Expiries are absolute so replaying them later doesn't extend them.
+    SetWithExpiry {
+        key: Vec<u8>,
+        value: Vec<u8>,
+        expires_at: u64,
+    },
+    Expire {
+        key: Vec<u8>,
+        expires_at: u64,
+    },
+    Persist {
+        key: Vec<u8>,
+    },
+    /// Replaces the value of an existing key and keeps its expiry, for
+    /// commands like INCR that change a value in place
+    Update {
+        key: Vec<u8>,
+        value: Vec<u8>,
+    },
+    /// Pushes values one at a time onto an end of a list, creating it if needed
+    Push {
+        key: Vec<u8>,
+        end: ListEnd,
+        values: Vec<Vec<u8>>,
+    },
+    /// Pops up to `count` values off an end of a list. An emptied list is removed.
+    Pop {
+        key: Vec<u8>,
+        end: ListEnd,
+        count: u64,
+    },
+    /// Keeps only the inclusive range of a list that `start` and `stop` resolve to
+    Trim {
+        key: Vec<u8>,
+        start: i64,
+        stop: i64,
+    },
+    /// Sets fields of a hash, creating it if needed
+    SetFields {
+        key: Vec<u8>,
+        fields: Vec<(Vec<u8>, Vec<u8>)>,
+    },
+    /// Removes fields from a hash. An emptied hash is removed.
+    DeleteFields {
+        key: Vec<u8>,
+        fields: Vec<Vec<u8>>,
+    },
+    /// Adds members to a set, creating it if needed
+    AddMembers {
+        key: Vec<u8>,
+        members: Vec<Vec<u8>>,
+    },
+    /// Removes members from a set or a sorted set. An emptied set is removed.
+    RemoveMembers {
+        key: Vec<u8>,
+        members: Vec<Vec<u8>>,
+    },
+    /// Sets the scores of sorted set members, creating the sorted set if needed
+    SetScores {
+        key: Vec<u8>,
+        members: Vec<(Vec<u8>, Score)>,
+    },
+    /// Adds an entry to a stream, creating it if needed. The write shard
+    /// picks the id so every shard stores the entry under the same one.
+    AddEntry {
+        key: Vec<u8>,
+        id: StreamId,
+        fields: Fields,
+    },
+    /// Drops the oldest entries of a stream until at most `max_len` are left
+    TrimStream {
+        key: Vec<u8>,
+        max_len: u64,
+    },
+    /// Creates a consumer group on a stream, and the stream if it doesn't exist
+    CreateGroup {
+        key: Vec<u8>,
+        group: Vec<u8>,
+        last_delivered: StreamId,
+    },
+    DestroyGroup {
+        key: Vec<u8>,
+        group: Vec<u8>,
+    },
+    /// Marks stream entries as delivered to a consumer of a group, so they
+    /// are pending until acknowledged
+    DeliverEntries {
+        key: Vec<u8>,
+        group: Vec<u8>,
+        consumer: Vec<u8>,
+        ids: Vec<StreamId>,
+        delivered_at: u64,
+    },
+    AckEntries {
+        key: Vec<u8>,
+        group: Vec<u8>,
+        ids: Vec<StreamId>,
+    },
+    /// Raises HyperLogLog registers to at least the given values, creating
+    /// the HyperLogLog if needed. Only the registers that changed are sent.
+    RaiseRegisters {
+        key: Vec<u8>,
+        registers: Vec<(u16, u8)>,
+    },
+    /// Sets the value at a path of a JSON document, where an empty path
+    /// replaces the whole document and creates it if needed
+    SetJsonPath {
+        key: Vec<u8>,
+        path: Vec<Step>,
+        value: serde_json::Value,
+    },
+    /// Removes the value at a path of a JSON document
+    DeleteJsonPath {
+        key: Vec<u8>,
+        path: Vec<Step>,
+    },
+    /// Appends values to the array at a path of a JSON document
+    AppendJsonArray {
+        key: Vec<u8>,
+        path: Vec<Step>,
+        values: Vec<serde_json::Value>,
+    },
+    /// Mutations of a transaction, applied together as a single version so
+    /// no reader ever sees some of them without the others
+    Batch {
+        mutations: Vec<Mutation>,
+    },
+}
+
+impl Mutation {
+    pub fn mutation_type(&self) -> MutationType {
+        match self {
+            Mutation::Set { .. } => MutationType::Set,
+            Mutation::Delete { .. } => MutationType::Delete,
+            Mutation::SetWithExpiry { .. } => MutationType::SetWithExpiry,
+            Mutation::Expire { .. } => MutationType::Expire,
+            Mutation::Persist { .. } => MutationType::Persist,
+            Mutation::Update { .. } => MutationType::Update,
+            Mutation::Push { .. } => MutationType::Push,
+            Mutation::Pop { .. } => MutationType::Pop,
+            Mutation::Trim { .. } => MutationType::Trim,
+            Mutation::SetFields { .. } => MutationType::SetFields,
+            Mutation::DeleteFields { .. } => MutationType::DeleteFields,
+            Mutation::AddMembers { .. } => MutationType::AddMembers,
+            Mutation::RemoveMembers { .. } => MutationType::RemoveMembers,
+            Mutation::SetScores { .. } => MutationType::SetScores,
+            Mutation::AddEntry { .. } => MutationType::AddEntry,
+            Mutation::TrimStream { .. } => MutationType::TrimStream,
+            Mutation::CreateGroup { .. } => MutationType::CreateGroup,
+            Mutation::DestroyGroup { .. } => MutationType::DestroyGroup,
+            Mutation::DeliverEntries { .. } => MutationType::DeliverEntries,
+            Mutation::AckEntries { .. } => MutationType::AckEntries,
+            Mutation::RaiseRegisters { .. } => MutationType::RaiseRegisters,
+            Mutation::SetJsonPath { .. } => MutationType::SetJsonPath,
+            Mutation::DeleteJsonPath { .. } => MutationType::DeleteJsonPath,
+            Mutation::AppendJsonArray { .. } => MutationType::AppendJsonArray,
+            Mutation::Batch { .. } => MutationType::Batch,
+        }
+    }
+
+    /// Key the mutation changes, which is empty for a batch
+    pub fn key(&self) -> &[u8] {
+        match self {
+            Mutation::Set { key, .. }
+            | Mutation::Delete { key }
+            | Mutation::SetWithExpiry { key, .. }
+            | Mutation::Expire { key, .. }
+            | Mutation::Persist { key }
+            | Mutation::Update { key, .. }
+            | Mutation::Push { key, .. }
+            | Mutation::Pop { key, .. }
+            | Mutation::Trim { key, .. }
+            | Mutation::SetFields { key, .. }
+            | Mutation::DeleteFields { key, .. }
+            | Mutation::AddMembers { key, .. }
+            | Mutation::RemoveMembers { key, .. }
+            | Mutation::SetScores { key, .. }
+            | Mutation::AddEntry { key, .. }
+            | Mutation::TrimStream { key, .. }
+            | Mutation::CreateGroup { key, .. }
+            | Mutation::DestroyGroup { key, .. }
+            | Mutation::DeliverEntries { key, .. }
+            | Mutation::AckEntries { key, .. }
+            | Mutation::RaiseRegisters { key, .. }
+            | Mutation::SetJsonPath { key, .. }
+            | Mutation::DeleteJsonPath { key, .. }
+            | Mutation::AppendJsonArray { key, .. } => key,
+            Mutation::Batch { .. } => &[],
+        }
+    }
+
+    /// Every key the mutation changes, including the ones inside a batch
+    #[allow(unused)]
+    pub fn keys(&self) -> Vec<&[u8]> {
+        match self {
+            Mutation::Batch { mutations } => mutations.iter().flat_map(Mutation::keys).collect(),
+            mutation => vec![mutation.key()],
+        }
+    }
+
+    /// The single-key mutations this one is made of, itself unless it is a batch
+    pub fn parts(&self) -> Vec<&Mutation> {
+        match self {
+            Mutation::Batch { mutations } => mutations.iter().flat_map(Mutation::parts).collect(),
+            mutation => vec![mutation],
+        }
+    }
+
+    /// Whether the mutation only removes data or changes expiries, so it can
+    /// go ahead on a shard that is out of memory
+    pub fn only_shrinks(&self) -> bool {
+        match self {
+            Mutation::Delete { .. }
+            | Mutation::Expire { .. }
+            | Mutation::Persist { .. }
+            | Mutation::Pop { .. }
+            | Mutation::Trim { .. }
+            | Mutation::DeleteFields { .. }
+            | Mutation::RemoveMembers { .. }
+            | Mutation::TrimStream { .. }
+            | Mutation::DestroyGroup { .. }
+            | Mutation::AckEntries { .. }
+            | Mutation::DeleteJsonPath { .. } => true,
+            Mutation::Batch { mutations } => mutations.iter().all(Mutation::only_shrinks),
+            _ => false,
+        }
+    }
+
+    /// Type specific payload that goes with the key on the wire.
+    /// Expiries are encoded as a little-endian u64 ahead of any value, and
+    /// list ends as a byte ahead of the pushed values or the pop count.
+    pub fn value(&self) -> Vec<u8> {
+        match self {
+            Mutation::Set { value, .. } | Mutation::Update { value, .. } => value.clone(),
+            Mutation::Delete { .. } | Mutation::Persist { .. } => Vec::new(),
+            Mutation::SetWithExpiry {
+                value, expires_at, ..
+            } => {
+                let mut payload = expires_at.to_le_bytes().to_vec();
+                payload.extend_from_slice(value);
+                payload
+            }
+            Mutation::Expire { expires_at, .. } => expires_at.to_le_bytes().to_vec(),
+            Mutation::Push { end, values, .. } => {
+                let mut payload = vec![*end as u8];
+                payload.extend_from_slice(&encode_items(values));
+                payload
+            }
+            Mutation::Pop { end, count, .. } => {
+                let mut payload = vec![*end as u8];
+                payload.extend_from_slice(&count.to_le_bytes());
+                payload
+            }
+            Mutation::Trim { start, stop, .. } => {
+                let mut payload = start.to_le_bytes().to_vec();
+                payload.extend_from_slice(&stop.to_le_bytes());
+                payload
+            }
+            Mutation::SetFields { fields, .. } => {
+                encode_pairs(fields.iter().map(|(field, value)| (field, value)))
+            }
+            Mutation::DeleteFields { fields, .. } => encode_items(fields),
+            Mutation::AddMembers { members, .. } | Mutation::RemoveMembers { members, .. } => {
+                encode_items(members)
+            }
+            Mutation::SetScores { members, .. } => {
+                encode_scores(members.iter().map(|(member, score)| (member, score.0)))
+            }
+            Mutation::AddEntry { id, fields, .. } => {
+                let mut payload = id.to_bytes();
+                payload.extend_from_slice(&encode_pairs(
+                    fields.iter().map(|(field, value)| (field, value)),
+                ));
+                payload
+            }
+            Mutation::TrimStream { max_len, .. } => max_len.to_le_bytes().to_vec(),
+            Mutation::CreateGroup {
+                group,
+                last_delivered,
+                ..
+            } => {
+                let mut payload = last_delivered.to_bytes();
+                payload.extend_from_slice(group);
+                payload
+            }
+            Mutation::DestroyGroup { group, .. } => group.clone(),
+            Mutation::DeliverEntries {
+                group,
+                consumer,
+                ids,
+                delivered_at,
+                ..
+            } => {
+                let mut items = vec![group.clone(), consumer.clone()];
+                items.extend(ids.iter().map(|id| id.to_bytes()));
+                let mut payload = delivered_at.to_le_bytes().to_vec();
+                payload.extend_from_slice(&encode_items(&items));
+                payload
+            }
+            Mutation::AckEntries { group, ids, .. } => {
+                let mut items = vec![group.clone()];
+                items.extend(ids.iter().map(|id| id.to_bytes()));
+                encode_items(&items)
+            }
+            Mutation::RaiseRegisters { registers, .. } => registers
+                .iter()
+                .flat_map(|(index, value)| {
+                    let [low, high] = index.to_le_bytes();
+                    [low, high, *value]
+                })
+                .collect(),
+            Mutation::SetJsonPath { path, value, .. } => {
+                encode_items(&[json::encode_steps(path), value.to_string().into_bytes()])
+            }
+            Mutation::DeleteJsonPath { path, .. } => json::encode_steps(path),
+            Mutation::AppendJsonArray { path, values, .. } => {
+                let values = serde_json::Value::from(values.clone());
+                encode_items(&[json::encode_steps(path), values.to_string().into_bytes()])
+            }
+            // each mutation is its type, key and payload
+            Mutation::Batch { mutations } => encode_items(
+                &mutations
+                    .iter()
+                    .map(|mutation| {
+                        encode_items(&[
+                            vec![mutation.mutation_type() as u8],
+                            mutation.key().to_vec(),
+                            mutation.value(),
+                        ])
+                    })
+                    .collect::<Vec<_>>(),
+            ),
+        }
+    }
+
+    /// Rebuilds a mutation from its type, key and payload
+    pub fn from_parts(mutation_type: u8, key: Vec<u8>, value: Vec<u8>) -> Result<Self> {
+        let mutation_type = MutationType::try_from(mutation_type)
+            .map_err(|_| anyhow::anyhow!("invalid mutation type {}", mutation_type))?;
+        let read_u64 = |value: &[u8], offset: usize, field: &str| -> Result<u64> {
+            Ok(u64::from_le_bytes(
+                value
+                    .get(offset..offset + 8)
+                    .with_context(|| format!("failed to get {}", field))?
+                    .try_into()?,
+            ))
+        };
+        let expires_at = |value: &[u8]| read_u64(value, 0, "expiry");
+        let end = |value: &[u8]| -> Result<ListEnd> {
+            let end = *value.first().context("failed to get list end")?;
+            ListEnd::try_from(end).map_err(|_| anyhow::anyhow!("invalid list end {}", end))
+        };
+        Ok(match mutation_type {
+            MutationType::Set => Mutation::Set { key, value },
+            MutationType::Delete => Mutation::Delete { key },
+            MutationType::SetWithExpiry => Mutation::SetWithExpiry {
+                key,
+                expires_at: expires_at(&value)?,
+                value: value[8..].to_vec(),
+            },
+            MutationType::Expire => Mutation::Expire {
+                key,
+                expires_at: expires_at(&value)?,
+            },
+            MutationType::Persist => Mutation::Persist { key },
+            MutationType::Update => Mutation::Update { key, value },
+            MutationType::Push => Mutation::Push {
+                key,
+                end: end(&value)?,
+                values: decode_items(&value[1..])?,
+            },
+            MutationType::Pop => Mutation::Pop {
+                key,
+                end: end(&value)?,
+                count: read_u64(&value, 1, "pop count")?,
+            },
+            MutationType::Trim => Mutation::Trim {
+                key,
+                start: read_u64(&value, 0, "trim start")? as i64,
+                stop: read_u64(&value, 8, "trim stop")? as i64,
+            },
+            MutationType::SetFields => Mutation::SetFields {
+                key,
+                fields: decode_pairs(&value)?,
+            },
+            MutationType::DeleteFields => Mutation::DeleteFields {
+                key,
+                fields: decode_items(&value)?,
+            },
+            MutationType::AddMembers => Mutation::AddMembers {
+                key,
+                members: decode_items(&value)?,
+            },
+            MutationType::RemoveMembers => Mutation::RemoveMembers {
+                key,
+                members: decode_items(&value)?,
+            },
+            MutationType::SetScores => Mutation::SetScores {
+                key,
+                members: decode_scores(&value)?
+                    .into_iter()
+                    .map(|(member, score)| (member, Score(score)))
+                    .collect(),
+            },
+            MutationType::AddEntry => Mutation::AddEntry {
+                key,
+                id: StreamId::from_bytes(&value)?,
+                fields: decode_pairs(value.get(16..).context("failed to get entry fields")?)?,
+            },
+            MutationType::TrimStream => Mutation::TrimStream {
+                key,
+                max_len: read_u64(&value, 0, "max length")?,
+            },
+            MutationType::CreateGroup => Mutation::CreateGroup {
+                key,
+                last_delivered: StreamId::from_bytes(&value)?,
+                group: value.get(16..).context("failed to get group")?.to_vec(),
+            },
+            MutationType::DestroyGroup => Mutation::DestroyGroup { key, group: value },
+            MutationType::DeliverEntries => {
+                let delivered_at = read_u64(&value, 0, "delivery time")?;
+                let mut items = decode_items(&value[8..])?.into_iter();
+                let group = items.next().context("failed to get group")?;
+                let consumer = items.next().context("failed to get consumer")?;
+                Mutation::DeliverEntries {
+                    key,
+                    group,
+                    consumer,
+                    ids: items
+                        .map(|id| StreamId::from_bytes(&id))
+                        .collect::<Result<_>>()?,
+                    delivered_at,
+                }
+            }
+            MutationType::AckEntries => {
+                let mut items = decode_items(&value)?.into_iter();
+                let group = items.next().context("failed to get group")?;
+                Mutation::AckEntries {
+                    key,
+                    group,
+                    ids: items
+                        .map(|id| StreamId::from_bytes(&id))
+                        .collect::<Result<_>>()?,
+                }
+            }
+            MutationType::RaiseRegisters => {
+                anyhow::ensure!(
+                    value.len().is_multiple_of(3),
+                    "invalid hyperloglog registers"
+                );
+                Mutation::RaiseRegisters {
+                    key,
+                    registers: value
+                        .chunks(3)
+                        .map(|register| {
+                            let index = u16::from_le_bytes([register[0], register[1]]);
+                            anyhow::ensure!(
+                                (index as usize) < REGISTERS,
+                                "invalid hyperloglog register {}",
+                                index
+                            );
+                            Ok((index, register[2]))
+                        })
+                        .collect::<Result<_>>()?,
+                }
+            }
+            MutationType::SetJsonPath => {
+                let [path, value] = <[Vec<u8>; 2]>::try_from(decode_items(&value)?)
+                    .map_err(|_| anyhow::anyhow!("invalid JSON path update"))?;
+                Mutation::SetJsonPath {
+                    key,
+                    path: json::decode_steps(&path)?,
+                    value: serde_json::from_slice(&value)?,
+                }
+            }
+            MutationType::DeleteJsonPath => Mutation::DeleteJsonPath {
+                key,
+                path: json::decode_steps(&value)?,
+            },
+            MutationType::AppendJsonArray => {
+                let [path, values] = <[Vec<u8>; 2]>::try_from(decode_items(&value)?)
+                    .map_err(|_| anyhow::anyhow!("invalid JSON array append"))?;
+                Mutation::AppendJsonArray {
+                    key,
+                    path: json::decode_steps(&path)?,
+                    values: serde_json::from_slice(&values)?,
+                }
+            }
+            MutationType::Batch => Mutation::Batch {
+                mutations: decode_items(&value)?
+                    .into_iter()
+                    .map(|mutation| {
+                        let [mutation_type, key, value] =
+                            <[Vec<u8>; 3]>::try_from(decode_items(&mutation)?)
+                                .map_err(|_| anyhow::anyhow!("invalid batched mutation"))?;
+                        let mutation_type = *mutation_type
+                            .first()
+                            .context("failed to get mutation type")?;
+                        Mutation::from_parts(mutation_type, key, value)
+                    })
+                    .collect::<Result<_>>()?,
+            },
+        })
+    }
+
+    /// Applies the mutation to a keyspace as the given version
+    pub fn apply(&self, keyspace: &mut Keyspace, version: u64) {
+        match self {
+            Mutation::Set { key, value } => {
+                keyspace.insert(key.clone(), value.clone());
+            }
+            Mutation::Delete { key } => {
+                keyspace.remove(key);
+            }
+            Mutation::SetWithExpiry {
+                key,
+                value,
+                expires_at,
+            } => {
+                keyspace.insert(key.clone(), value.clone());
+                keyspace.set_expiry(key, *expires_at);
+            }
+            Mutation::Expire { key, expires_at } => {
+                keyspace.set_expiry(key, *expires_at);
+            }
+            Mutation::Persist { key } => {
+                keyspace.persist(key);
+            }
+            Mutation::Update { key, value } => {
+                keyspace.update(key, value.clone());
+            }
+            Mutation::Push { key, end, values } => {
+                if !matches!(keyspace.get_mut(key), Some(Value::List(_))) {
+                    keyspace.insert(key.clone(), Value::List(Default::default()));
+                }
+                if let Some(Value::List(list)) = keyspace.get_mut(key) {
+                    for value in values {
+                        match end {
+                            ListEnd::Left => list.push_front(value.clone()),
+                            ListEnd::Right => list.push_back(value.clone()),
+                        }
+                    }
+                }
+            }
+            Mutation::Pop { key, end, count } => {
+                if let Some(Value::List(list)) = keyspace.get_mut(key) {
+                    let count = (*count as usize).min(list.len());
+                    match end {
+                        ListEnd::Left => {
+                            list.drain(..count);
+                        }
+                        ListEnd::Right => list.truncate(list.len() - count),
+                    }
+                    if list.is_empty() {
+                        keyspace.remove(key);
+                    }
+                }
+            }
+            Mutation::Trim { key, start, stop } => {
+                if let Some(Value::List(list)) = keyspace.get_mut(key) {
+                    match resolve_range(*start, *stop, list.len()) {
+                        Some(range) => {
+                            list.truncate(range.end() + 1);
+                            list.drain(..range.start());
+                        }
+                        None => list.clear(),
+                    }
+                    if list.is_empty() {
+                        keyspace.remove(key);
+                    }
+                }
+            }
+            Mutation::SetFields { key, fields } => {
+                if !matches!(keyspace.get_mut(key), Some(Value::Hash(_))) {
+                    keyspace.insert(key.clone(), Value::Hash(Default::default()));
+                }
+                if let Some(Value::Hash(hash)) = keyspace.get_mut(key) {
+                    hash.extend(fields.iter().cloned());
+                }
+            }
+            Mutation::DeleteFields { key, fields } => {
+                if let Some(Value::Hash(hash)) = keyspace.get_mut(key) {
+                    for field in fields {
+                        hash.remove(field);
+                    }
+                    if hash.is_empty() {
+                        keyspace.remove(key);
+                    }
+                }
+            }
+            Mutation::AddMembers { key, members } => {
+                if !matches!(keyspace.get_mut(key), Some(Value::Set(_))) {
+                    keyspace.insert(key.clone(), Value::Set(Default::default()));
+                }
+                if let Some(Value::Set(set)) = keyspace.get_mut(key) {
+                    set.extend(members.iter().cloned());
+                }
+            }
+            Mutation::RemoveMembers { key, members } => {
+                let emptied = match keyspace.get_mut(key) {
+                    Some(Value::Set(set)) => {
+                        for member in members {
+                            set.remove(member);
+                        }
+                        set.is_empty()
+                    }
+                    Some(Value::SortedSet(set)) => {
+                        for member in members {
+                            set.remove(member);
+                        }
+                        set.is_empty()
+                    }
+                    _ => false,
+                };
+                if emptied {
+                    keyspace.remove(key);
+                }
+            }
+            Mutation::SetScores { key, members } => {
+                if !matches!(keyspace.get_mut(key), Some(Value::SortedSet(_))) {
+                    keyspace.insert(key.clone(), Value::SortedSet(SortedSet::new()));
+                }
+                if let Some(Value::SortedSet(set)) = keyspace.get_mut(key) {
+                    for (member, score) in members {
+                        set.insert(member.clone(), score.0);
+                    }
+                }
+            }
+            Mutation::AddEntry { key, id, fields } => {
+                if !matches!(keyspace.get_mut(key), Some(Value::Stream(_))) {
+                    keyspace.insert(key.clone(), Value::Stream(Stream::new()));
+                }
+                if let Some(Value::Stream(stream)) = keyspace.get_mut(key) {
+                    stream.add(*id, fields.clone());
+                }
+            }
+            Mutation::TrimStream { key, max_len } => {
+                if let Some(Value::Stream(stream)) = keyspace.get_mut(key) {
+                    stream.trim(*max_len as usize);
+                }
+            }
+            Mutation::CreateGroup {
+                key,
+                group,
+                last_delivered,
+            } => {
+                if !matches!(keyspace.get_mut(key), Some(Value::Stream(_))) {
+                    keyspace.insert(key.clone(), Value::Stream(Stream::new()));
+                }
+                if let Some(Value::Stream(stream)) = keyspace.get_mut(key) {
+                    stream.create_group(group.clone(), *last_delivered);
+                }
+            }
+            Mutation::DestroyGroup { key, group } => {
+                if let Some(Value::Stream(stream)) = keyspace.get_mut(key) {
+                    stream.destroy_group(group);
+                }
+            }
+            Mutation::DeliverEntries {
+                key,
+                group,
+                consumer,
+                ids,
+                delivered_at,
+            } => {
+                if let Some(Value::Stream(stream)) = keyspace.get_mut(key) {
+                    stream.deliver(group, consumer, ids, *delivered_at);
+                }
+            }
+            Mutation::AckEntries { key, group, ids } => {
+                if let Some(Value::Stream(stream)) = keyspace.get_mut(key) {
+                    stream.ack(group, ids);
+                }
+            }
+            Mutation::RaiseRegisters { key, registers } => {
+                if !matches!(keyspace.get_mut(key), Some(Value::HyperLogLog(_))) {
+                    keyspace.insert(key.clone(), Value::HyperLogLog(HyperLogLog::new()));
+                }
+                if let Some(Value::HyperLogLog(hll)) = keyspace.get_mut(key) {
+                    for &(index, value) in registers {
+                        hll.raise(index, value);
+                    }
+                }
+            }
+            Mutation::SetJsonPath { key, path, value } => match keyspace.get_mut(key) {
+                Some(Value::Json(document)) => {
+                    json::set(document, path, value.clone());
+                }
+                _ if path.is_empty() => keyspace.insert(key.clone(), Value::Json(value.clone())),
+                _ => {}
+            },
+            Mutation::DeleteJsonPath { key, path } => {
+                if let Some(Value::Json(document)) = keyspace.get_mut(key) {
+                    json::delete(document, path);
+                }
+            }
+            Mutation::AppendJsonArray { key, path, values } => {
+                if let Some(Value::Json(document)) = keyspace.get_mut(key) {
+                    json::append(document, path, values);
+                }
+            }
+            Mutation::Batch { mutations } => {
+                for mutation in mutations {
+                    mutation.apply(keyspace, version);
+                }
+                return;
+            }
+        }
+        keyspace.set_version(self.key(), version);
+    }
+}
+
+/// Layout of a serialized Mutation
+/// | 1 byte | 4 bytes | N bytes | 4 bytes  | M bytes |
+/// | type   | keylen  |   key   | valuelen |  value  |
+/// Integers are always encoded in little-endian order
+impl Mutation {
+    pub fn serialize(&self) -> Result<Vec<u8>> {
+        let value = self.value();
+        let key_len = u32::try_from(self.key().len()).context("key length overflow")?;
+        let value_len = u32::try_from(value.len()).context("value length overflow")?;
+
+        let mut buffer = vec![self.mutation_type() as u8];
+        buffer.extend_from_slice(&key_len.to_le_bytes());
+        buffer.extend_from_slice(self.key());
+        buffer.extend_from_slice(&value_len.to_le_bytes());
+        buffer.extend_from_slice(&value);
+        Ok(buffer)
+    }
+
+    pub fn deserialize(buffer: &[u8]) -> Result<Self> {
+        let mutation_type = *buffer.first().context("failed to get mutation type")?;
+        let key_len = u32::from_le_bytes(
+            buffer
+                .get(1..5)
+                .context("failed to get key length")?
+                .try_into()?,
+        ) as usize;
+        let key = buffer
+            .get(5..5 + key_len)
+            .context("failed to get key")?
+            .to_vec();
+        let value_len = u32::from_le_bytes(
+            buffer
+                .get(5 + key_len..9 + key_len)
+                .context("failed to get value length")?
+                .try_into()?,
+        ) as usize;
+        let value = buffer
+            .get(9 + key_len..9 + key_len + value_len)
+            .context("failed to get value")?
+            .to_vec();
+        Mutation::from_parts(mutation_type, key, value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip() {
+        let mutations = vec![
+            Mutation::Set {
+                key: b"key".to_vec(),
+                value: vec![0xff, 0x00],
+            },
+            Mutation::Delete {
+                key: b"key".to_vec(),
+            },
+            Mutation::SetWithExpiry {
+                key: b"key".to_vec(),
+                value: b"value".to_vec(),
+                expires_at: 1_700_000_000_000,
+            },
+            Mutation::Expire {
+                key: b"key".to_vec(),
+                expires_at: 1_700_000_000_000,
+            },
+            Mutation::Persist {
+                key: b"key".to_vec(),
+            },
+            Mutation::Update {
+                key: b"key".to_vec(),
+                value: b"2".to_vec(),
+            },
+            Mutation::Push {
+                key: b"key".to_vec(),
+                end: ListEnd::Left,
+                values: vec![b"a".to_vec(), Vec::new()],
+            },
+            Mutation::Pop {
+                key: b"key".to_vec(),
+                end: ListEnd::Right,
+                count: 3,
+            },
+            Mutation::Trim {
+                key: b"key".to_vec(),
+                start: -3,
+                stop: 10,
+            },
+            Mutation::SetFields {
+                key: b"key".to_vec(),
+                fields: vec![
+                    (b"field".to_vec(), b"value".to_vec()),
+                    (Vec::new(), Vec::new()),
+                ],
+            },
+            Mutation::DeleteFields {
+                key: b"key".to_vec(),
+                fields: vec![b"field".to_vec()],
+            },
+            Mutation::AddMembers {
+                key: b"key".to_vec(),
+                members: vec![b"a".to_vec(), b"b".to_vec()],
+            },
+            Mutation::RemoveMembers {
+                key: b"key".to_vec(),
+                members: vec![b"a".to_vec()],
+            },
+            Mutation::SetScores {
+                key: b"key".to_vec(),
+                members: vec![
+                    (b"a".to_vec(), Score(-1.5)),
+                    (b"b".to_vec(), Score(f64::INFINITY)),
+                ],
+            },
+            Mutation::AddEntry {
+                key: b"key".to_vec(),
+                id: StreamId { ms: 1, seq: 2 },
+                fields: vec![(b"field".to_vec(), b"value".to_vec())],
+            },
+            Mutation::TrimStream {
+                key: b"key".to_vec(),
+                max_len: 10,
+            },
+            Mutation::CreateGroup {
+                key: b"key".to_vec(),
+                group: b"group".to_vec(),
+                last_delivered: StreamId::MAX,
+            },
+            Mutation::DestroyGroup {
+                key: b"key".to_vec(),
+                group: b"group".to_vec(),
+            },
+            Mutation::DeliverEntries {
+                key: b"key".to_vec(),
+                group: b"group".to_vec(),
+                consumer: b"consumer".to_vec(),
+                ids: vec![StreamId { ms: 1, seq: 2 }, StreamId::MIN],
+                delivered_at: 1_700_000_000_000,
+            },
+            Mutation::AckEntries {
+                key: b"key".to_vec(),
+                group: b"group".to_vec(),
+                ids: vec![StreamId { ms: 1, seq: 2 }],
+            },
+            Mutation::RaiseRegisters {
+                key: b"key".to_vec(),
+                registers: vec![(0, 1), (16383, 51)],
+            },
+            Mutation::SetJsonPath {
+                key: b"key".to_vec(),
+                path: vec![Step::Key("a".to_string()), Step::Index(1)],
+                value: serde_json::json!({"b": [1.5, null, "c"]}),
+            },
+            Mutation::DeleteJsonPath {
+                key: b"key".to_vec(),
+                path: vec![Step::Key("a".to_string())],
+            },
+            Mutation::AppendJsonArray {
+                key: b"key".to_vec(),
+                path: Vec::new(),
+                values: vec![serde_json::json!(1), serde_json::json!("x")],
+            },
+            Mutation::Batch {
+                mutations: vec![
+                    Mutation::Delete { key: b"a".to_vec() },
+                    Mutation::Pop {
+                        key: b"b".to_vec(),
+                        end: ListEnd::Right,
+                        count: 2,
+                    },
+                ],
+            },
+            Mutation::Batch {
+                mutations: Vec::new(),
+            },
+        ];
+        for original in mutations {
+            let serialized = original.serialize().unwrap();
+            assert_eq!(Mutation::deserialize(&serialized).unwrap(), original);
+        }
+    }
+
+    #[test]
+    fn test_apply_tombstone() {
+        let mut keyspace = Keyspace::new();
+        Mutation::Set {
+            key: b"key".to_vec(),
+            value: b"value".to_vec(),
+        }
+        .apply(&mut keyspace, 1);
+        assert_eq!(
+            keyspace.get(b"key", 0),
+            Some(&Value::from(b"value".to_vec()))
+        );
+        assert_eq!(keyspace.version(b"key", 0), 1);
+
+        Mutation::Delete {
+            key: b"key".to_vec(),
+        }
+        .apply(&mut keyspace, 1);
+        assert!(keyspace.is_empty());
+    }
+
+    #[test]
+    fn test_apply_expiry() {
+        let mut keyspace = Keyspace::new();
+        Mutation::SetWithExpiry {
+            key: b"key".to_vec(),
+            value: b"value".to_vec(),
+            expires_at: 100,
+        }
+        .apply(&mut keyspace, 1);
+        assert_eq!(keyspace.expires_at(b"key"), Some(100));
+
+        Mutation::Persist {
+            key: b"key".to_vec(),
+        }
+        .apply(&mut keyspace, 1);
+        assert_eq!(keyspace.expires_at(b"key"), None);
+
+        Mutation::Expire {
+            key: b"key".to_vec(),
+            expires_at: 50,
+        }
+        .apply(&mut keyspace, 1);
+        assert!(!keyspace.contains_key(b"key", 50));
+    }
+
+    #[test]
+    fn test_apply_batch() {
+        let mut keyspace = Keyspace::new();
+        keyspace.insert(b"empty".to_vec(), b"".to_vec());
+        keyspace.set_version(b"empty", 1);
+        let batch = Mutation::Batch {
+            mutations: vec![
+                Mutation::Set {
+                    key: b"a".to_vec(),
+                    value: b"1".to_vec(),
+                },
+                Mutation::Push {
+                    key: b"b".to_vec(),
+                    end: ListEnd::Left,
+                    values: vec![b"x".to_vec()],
+                },
+            ],
+        };
+        batch.apply(&mut keyspace, 2);
+        assert_eq!(batch.keys(), vec![&b"a"[..], &b"b"[..]]);
+
+        // every key in the batch is at its version, and nothing else is
+        assert_eq!(keyspace.version(b"a", 0), 2);
+        assert_eq!(keyspace.version(b"b", 0), 2);
+        assert_eq!(keyspace.version(b"empty", 0), 1);
+    }
+}
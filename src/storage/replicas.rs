@@ -0,0 +1,145 @@
+//! Replication progress of a write shard's read shards. Each read shard
+//! acknowledges the latest version it has applied, and writes made with a
+//! write quorum wait here until enough read shards have applied them.
+
+use std::collections::HashMap;
+use std::net::SocketAddrV6;
+
+/// A write waiting for read shards to apply its version
+#[allow(unused)]
+#[derive(Debug)]
+pub struct QuorumWaiter<T> {
+    pub version: u64,
+    /// Time in unix milliseconds at which the write stops waiting
+    pub deadline: u64,
+    pub client: T,
+}
+
+#[allow(unused)]
+#[derive(Debug)]
+pub struct Replicas<T> {
+    /// The read shards attached to the write shard, as the info server last listed them
+    readers: Vec<SocketAddrV6>,
+    /// Latest version each read shard has acknowledged
+    applied: HashMap<SocketAddrV6, u64>,
+    waiters: Vec<QuorumWaiter<T>>,
+}
+
+impl<T> Default for Replicas<T> {
+    fn default() -> Self {
+        Replicas {
+            readers: Vec::new(),
+            applied: HashMap::new(),
+            waiters: Vec::new(),
+        }
+    }
+}
+
+#[allow(unused)]
+impl<T> Replicas<T> {
+    /// Replaces the known read shards, forgetting the ones that left
+    pub fn set_readers(&mut self, readers: Vec<SocketAddrV6>) {
+        self.applied.retain(|reader, _| readers.contains(reader));
+        self.readers = readers;
+    }
+
+    /// Records that `reader` has applied every version up to `version`
+    pub fn acknowledge(&mut self, reader: SocketAddrV6, version: u64) {
+        let applied = self.applied.entry(reader).or_default();
+        *applied = (*applied).max(version);
+    }
+
+    /// How many of the known read shards have applied `version`
+    pub fn confirmations(&self, version: u64) -> usize {
+        self.readers
+            .iter()
+            .filter(|reader| self.applied.get(reader).is_some_and(|&v| v >= version))
+            .count()
+    }
+
+    pub fn wait(&mut self, version: u64, deadline: u64, client: T) {
+        self.waiters.push(QuorumWaiter {
+            version,
+            deadline,
+            client,
+        });
+    }
+
+    /// Takes the waiters that reached `quorum`, and then the ones whose
+    /// deadline passed without reaching it
+    pub fn settle(
+        &mut self,
+        quorum: usize,
+        now: u64,
+    ) -> (Vec<QuorumWaiter<T>>, Vec<QuorumWaiter<T>>) {
+        let mut confirmed = Vec::new();
+        let mut timed_out = Vec::new();
+        for waiter in std::mem::take(&mut self.waiters) {
+            if self.confirmations(waiter.version) >= quorum {
+                confirmed.push(waiter);
+            } else if waiter.deadline <= now {
+                timed_out.push(waiter);
+            } else {
+                self.waiters.push(waiter);
+            }
+        }
+        (confirmed, timed_out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv6Addr;
+
+    fn reader(port: u16) -> SocketAddrV6 {
+        SocketAddrV6::new(Ipv6Addr::LOCALHOST, port, 0, 0)
+    }
+
+    #[test]
+    fn test_quorum() {
+        let mut replicas = Replicas::default();
+        replicas.set_readers(vec![reader(1), reader(2), reader(3)]);
+        replicas.wait(1, 100, "first");
+        replicas.wait(2, 100, "second");
+
+        replicas.acknowledge(reader(1), 2);
+        // acknowledgements from shards that aren't attached don't count
+        replicas.acknowledge(reader(9), 2);
+        replicas.acknowledge(reader(2), 1);
+        assert_eq!(replicas.confirmations(1), 2);
+        assert_eq!(replicas.confirmations(2), 1);
+
+        let (confirmed, timed_out) = replicas.settle(2, 0);
+        assert_eq!(confirmed.len(), 1);
+        assert_eq!(confirmed[0].client, "first");
+        assert!(timed_out.is_empty());
+
+        // an older acknowledgement never moves a shard back
+        replicas.acknowledge(reader(1), 1);
+        let (confirmed, timed_out) = replicas.settle(2, 100);
+        assert!(confirmed.is_empty());
+        assert_eq!(timed_out.len(), 1);
+        assert_eq!(timed_out[0].client, "second");
+
+        replicas.set_readers(vec![reader(2)]);
+        assert_eq!(replicas.confirmations(1), 1);
+        replicas.set_readers(vec![reader(1), reader(2)]);
+        assert_eq!(replicas.confirmations(2), 0);
+    }
+
+    #[test]
+    fn test_burst_settled_by_one_acknowledgement() {
+        let mut replicas = Replicas::default();
+        replicas.set_readers(vec![reader(1), reader(2)]);
+        for version in 1..=100 {
+            replicas.wait(version, 1_000, version);
+        }
+
+        // a read shard acknowledges the latest version it applied, which covers the earlier ones
+        replicas.acknowledge(reader(1), 100);
+        let (confirmed, timed_out) = replicas.settle(1, 0);
+        assert_eq!(confirmed.len(), 100);
+        assert!(timed_out.is_empty());
+    }
+}
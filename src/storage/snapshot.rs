@@ -0,0 +1,396 @@
+use super::engine::{EngineSnapshot, Entry};
+use super::keyspace::Keyspace;
+use super::value::{Value, ValueType};
+use anyhow::{Context, Result};
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+const SNAPSHOT_MAGIC: &[u8; 8] = b"EDISSNAP";
+const SNAPSHOT_FORMAT_VERSION: u8 = 4;
+
+/// Point-in-time copy of a shard's keyspace, tagged with the version
+/// of the last write it contains
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Snapshot {
+    pub version: u64,
+    pub keyspace: Keyspace,
+}
+
+fn read_u32(buffer: &[u8], offset: &mut usize, field: &str) -> Result<u32> {
+    let value = u32::from_le_bytes(
+        buffer
+            .get(*offset..*offset + 4)
+            .with_context(|| format!("failed to get {}", field))?
+            .try_into()?,
+    );
+    *offset += 4;
+    Ok(value)
+}
+
+fn read_u64(buffer: &[u8], offset: &mut usize, field: &str) -> Result<u64> {
+    let value = u64::from_le_bytes(
+        buffer
+            .get(*offset..*offset + 8)
+            .with_context(|| format!("failed to get {}", field))?
+            .try_into()?,
+    );
+    *offset += 8;
+    Ok(value)
+}
+
+fn read_bytes(buffer: &[u8], offset: &mut usize, field: &str) -> Result<Vec<u8>> {
+    let len = read_u32(buffer, offset, field)? as usize;
+    let bytes = buffer
+        .get(*offset..*offset + len)
+        .with_context(|| format!("failed to get {}", field))?;
+    *offset += len;
+    Ok(bytes.to_vec())
+}
+
+/// Writes a key and its entry in the layout of a snapshot entry
+fn write_entry(
+    write: &mut impl FnMut(&[u8]) -> Result<()>,
+    key: &[u8],
+    entry: &Entry,
+) -> Result<()> {
+    let key_len = u32::try_from(key.len()).context("key length overflow")?;
+    let encoded = entry.value.encode();
+    let value_len = u32::try_from(encoded.len()).context("value length overflow")?;
+    write(&key_len.to_le_bytes())?;
+    write(key)?;
+    write(&[entry.value.value_type() as u8])?;
+    write(&value_len.to_le_bytes())?;
+    write(&encoded)?;
+    write(&entry.expires_at.unwrap_or(0).to_le_bytes())?;
+    write(&entry.version.to_le_bytes())
+}
+
+/// Reads the snapshot entry at `offset` in the given format. Keys of formats
+/// without a key version get `version`.
+fn read_entry(
+    buffer: &[u8],
+    offset: &mut usize,
+    format: u8,
+    version: u64,
+) -> Result<(Vec<u8>, Entry)> {
+    let key = read_bytes(buffer, offset, "key")?;
+    let value_type = if format >= 4 {
+        let value_type = *buffer.get(*offset).context("failed to get value type")?;
+        *offset += 1;
+        value_type
+    } else {
+        ValueType::String as u8
+    };
+    let value = Value::decode(value_type, read_bytes(buffer, offset, "value")?)?;
+    let expires_at = if format >= 2 {
+        read_u64(buffer, offset, "expiry")?
+    } else {
+        0
+    };
+    let key_version = if format >= 3 {
+        read_u64(buffer, offset, "key version")?
+    } else {
+        version
+    };
+    let entry = Entry {
+        value,
+        expires_at: (expires_at != 0).then_some(expires_at),
+        version: key_version,
+    };
+    Ok((key, entry))
+}
+
+/// Layout of a snapshot file
+/// | 8 bytes    | 1 byte | 8 bytes | 8 bytes | ...     | 4 bytes |
+/// | "EDISSNAP" | format | version | count   | entries | crc32   |
+/// Layout of each of the `count` entries
+/// | 4 bytes | N bytes | 1 byte | 4 bytes  | M bytes | 8 bytes    | 8 bytes |
+/// | keylen  |   key   | type   | valuelen |  value  | expires at | version |
+/// type is the ValueType the value is encoded as. expires at is in unix
+/// milliseconds, 0 for keys without an expiry. version is the version that
+/// last modified the key.
+/// Older formats are still loaded. Formats 1 to 3 only hold strings and have
+/// no type. Format 1 has neither of the trailing fields and format 2 has no
+/// version; such keys get the snapshot's version.
+/// crc32 covers every byte before it
+/// Integers are always encoded in little-endian order
+impl Snapshot {
+    /// Writes a snapshot of `source` at `version` to `out` one key at a
+    /// time, so an engine larger than memory can be saved
+    pub fn write_to(out: &mut impl Write, version: u64, source: &dyn EngineSnapshot) -> Result<()> {
+        let mut hasher = crc32fast::Hasher::new();
+        let mut write = |bytes: &[u8]| -> Result<()> {
+            hasher.update(bytes);
+            Ok(out.write_all(bytes)?)
+        };
+        write(SNAPSHOT_MAGIC)?;
+        write(&[SNAPSHOT_FORMAT_VERSION])?;
+        write(&version.to_le_bytes())?;
+        write(&(source.len() as u64).to_le_bytes())?;
+        for entry in source.scan() {
+            let (key, entry) = entry?;
+            write_entry(&mut write, &key, &entry)?;
+        }
+        out.write_all(&hasher.finalize().to_le_bytes())?;
+        Ok(())
+    }
+
+    /// Writes the entries of `source` from the `start`th on to `out`, in the
+    /// layout of the snapshot file, until `out` holds at least `max_bytes`
+    /// or the entries run out. Returns the number of entries written.
+    pub fn write_entries(
+        out: &mut Vec<u8>,
+        source: &dyn EngineSnapshot,
+        start: usize,
+        max_bytes: usize,
+    ) -> Result<usize> {
+        let mut written = 0;
+        for entry in source.scan_from(start) {
+            let (key, entry) = entry?;
+            let mut write = |bytes: &[u8]| -> Result<()> {
+                out.extend_from_slice(bytes);
+                Ok(())
+            };
+            write_entry(&mut write, &key, &entry)?;
+            written += 1;
+            if out.len() >= max_bytes {
+                break;
+            }
+        }
+        Ok(written)
+    }
+
+    /// Reads entries written by [`Snapshot::write_entries`] into `keyspace`
+    /// and returns how many there were
+    #[allow(unused)]
+    pub fn read_entries(buffer: &[u8], keyspace: &mut Keyspace) -> Result<usize> {
+        let mut offset = 0;
+        let mut count = 0;
+        while offset < buffer.len() {
+            let (key, entry) = read_entry(buffer, &mut offset, SNAPSHOT_FORMAT_VERSION, 0)?;
+            keyspace.insert_entry(key, entry);
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    #[allow(unused)]
+    pub fn serialize(&self) -> Result<Vec<u8>> {
+        let mut buffer = Vec::new();
+        Snapshot::write_to(&mut buffer, self.version, &self.keyspace)?;
+        Ok(buffer)
+    }
+
+    pub fn deserialize(buffer: &[u8]) -> Result<Self> {
+        if buffer.len() < 4 {
+            anyhow::bail!("snapshot is too short");
+        }
+        let (body, crc) = buffer.split_at(buffer.len() - 4);
+        if crc32fast::hash(body) != u32::from_le_bytes(crc.try_into()?) {
+            anyhow::bail!("snapshot checksum mismatch");
+        }
+        if body.get(0..8) != Some(SNAPSHOT_MAGIC.as_slice()) {
+            anyhow::bail!("not a snapshot file");
+        }
+        let format = *body.get(8).context("failed to get format version")?;
+        if !(1..=SNAPSHOT_FORMAT_VERSION).contains(&format) {
+            anyhow::bail!("unsupported snapshot format version {}", format);
+        }
+        let version = u64::from_le_bytes(
+            body.get(9..17)
+                .context("failed to get version")?
+                .try_into()?,
+        );
+        let count = u64::from_le_bytes(
+            body.get(17..25)
+                .context("failed to get entry count")?
+                .try_into()?,
+        );
+
+        let mut offset = 25;
+        let mut keyspace = Keyspace::new();
+        for _ in 0..count {
+            let (key, entry) = read_entry(body, &mut offset, format, version)?;
+            keyspace.insert_entry(key, entry);
+        }
+        if offset != body.len() {
+            anyhow::bail!("snapshot has trailing bytes");
+        }
+        Ok(Snapshot { version, keyspace })
+    }
+
+    /// Writes the snapshot to a temporary file and renames it over `path`,
+    /// so a crash mid-save never leaves a half-written snapshot behind
+    #[allow(unused)]
+    pub fn save(&self, path: &Path) -> Result<()> {
+        Snapshot::save_from(path, self.version, &self.keyspace)
+    }
+
+    /// Saves a snapshot of `source` at `version` the way `save` does
+    pub fn save_from(path: &Path, version: u64, source: &dyn EngineSnapshot) -> Result<()> {
+        let tmp_path = path.with_extension("tmp");
+        let file = File::create(&tmp_path)
+            .with_context(|| format!("failed to create {}", tmp_path.display()))?;
+        let mut out = BufWriter::new(&file);
+        Snapshot::write_to(&mut out, version, source)?;
+        out.flush()?;
+        drop(out);
+        file.sync_all()?;
+        fs::rename(&tmp_path, path)
+            .with_context(|| format!("failed to move snapshot to {}", path.display()))?;
+        Ok(())
+    }
+
+    /// Loads the snapshot at `path`, or returns None if there is none yet
+    pub fn load(path: &Path) -> Result<Option<Self>> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        let buffer =
+            fs::read(path).with_context(|| format!("failed to read {}", path.display()))?;
+        let snapshot = Snapshot::deserialize(&buffer)
+            .with_context(|| format!("failed to load snapshot {}", path.display()))?;
+        Ok(Some(snapshot))
+    }
+}
+
+/// Saves snapshots of a shard on a background thread, one at a time
+#[derive(Debug)]
+pub struct Snapshotter {
+    path: PathBuf,
+    in_progress: Arc<AtomicBool>,
+}
+
+impl Snapshotter {
+    pub fn new(path: PathBuf) -> Self {
+        Snapshotter {
+            path,
+            in_progress: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Starts writing a snapshot of `source` at `version` and calls
+    /// `on_saved` with the version once it is on disk. Returns false if a
+    /// save is already running.
+    pub fn save_in_background<F>(
+        &self,
+        version: u64,
+        source: Box<dyn EngineSnapshot>,
+        on_saved: F,
+    ) -> bool
+    where
+        F: FnOnce(u64) + Send + 'static,
+    {
+        if self.in_progress.swap(true, Ordering::AcqRel) {
+            return false;
+        }
+
+        let path = self.path.clone();
+        let in_progress = self.in_progress.clone();
+        std::thread::spawn(move || {
+            match Snapshot::save_from(&path, version, source.as_ref()) {
+                Ok(()) => {
+                    println!("saved snapshot at version {}", version);
+                    on_saved(version);
+                }
+                Err(e) => eprintln!("Failed to save snapshot: {:?}", e),
+            }
+            in_progress.store(false, Ordering::Release);
+        });
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::Rng;
+
+    fn temp_snapshot_path() -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "rust-edis-{}.snapshot",
+            rand::thread_rng().gen::<u64>()
+        ))
+    }
+
+    #[test]
+    fn test_roundtrip_basic() {
+        let mut original = Snapshot {
+            version: 42,
+            keyspace: Keyspace::new(),
+        };
+        original.keyspace.insert(b"key".to_vec(), b"value".to_vec());
+        original.keyspace.insert(vec![0xff, 0xfe], vec![0x00, 0x80]);
+        original.keyspace.insert(
+            b"list".to_vec(),
+            Value::List(vec![b"a".to_vec(), b"b".to_vec()].into()),
+        );
+        original.keyspace.set_expiry(b"key", 1_700_000_000_000);
+        original.keyspace.set_version(b"key", 40);
+
+        let serialized = original.serialize().unwrap();
+        let deserialized = Snapshot::deserialize(&serialized).unwrap();
+        assert_eq!(original, deserialized);
+    }
+
+    #[test]
+    fn test_corruption_is_detected() {
+        let mut original = Snapshot {
+            version: 1,
+            keyspace: Keyspace::new(),
+        };
+        original.keyspace.insert(b"key".to_vec(), b"value".to_vec());
+
+        let mut serialized = original.serialize().unwrap();
+        serialized[30] ^= 0xff;
+        assert!(Snapshot::deserialize(&serialized).is_err());
+    }
+
+    #[test]
+    fn test_entries_in_chunks() {
+        let mut keyspace = Keyspace::new();
+        for i in 0..10 {
+            keyspace.insert(format!("key{}", i).into_bytes(), vec![b'v'; 100]);
+        }
+        keyspace.set_expiry(b"key3", 1_700_000_000_000);
+        keyspace.set_version(b"key3", 40);
+
+        // every chunk ends with the entry that takes it past the limit
+        let mut assembled = Keyspace::new();
+        let mut start = 0;
+        while start < keyspace.len() {
+            let mut chunk = Vec::new();
+            let written = Snapshot::write_entries(&mut chunk, &keyspace, start, 250).unwrap();
+            assert_eq!(written, 2.min(keyspace.len() - start));
+            assert_eq!(
+                Snapshot::read_entries(&chunk, &mut assembled).unwrap(),
+                written
+            );
+            start += written;
+        }
+        assert_eq!(assembled, keyspace);
+
+        let mut chunk = Vec::new();
+        Snapshot::write_entries(&mut chunk, &keyspace, 0, 1).unwrap();
+        assert!(Snapshot::read_entries(&chunk[..chunk.len() - 1], &mut assembled).is_err());
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let path = temp_snapshot_path();
+        assert_eq!(Snapshot::load(&path).unwrap(), None);
+
+        let mut original = Snapshot {
+            version: 7,
+            keyspace: Keyspace::new(),
+        };
+        original.keyspace.insert(b"key".to_vec(), b"value".to_vec());
+        original.save(&path).unwrap();
+
+        assert_eq!(Snapshot::load(&path).unwrap(), Some(original));
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,164 @@
+use std::cmp::Ordering;
+use std::collections::{BTreeSet, HashMap};
+use std::ops::{Bound, RangeInclusive};
+
+/// Score of a sorted set member. Scores are never NaN, which gives them a
+/// total order.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Score(pub f64);
+
+impl Eq for Score {}
+
+impl PartialOrd for Score {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Score {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+/// Members ordered by score, then by member. Scores are looked up in the
+/// map and ordered walks go through the index.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SortedSet {
+    scores: HashMap<Vec<u8>, Score>,
+    index: BTreeSet<(Score, Vec<u8>)>,
+}
+
+impl SortedSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.scores.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.scores.is_empty()
+    }
+
+    pub fn score(&self, member: &[u8]) -> Option<f64> {
+        self.scores.get(member).map(|score| score.0)
+    }
+
+    /// Sets the score of `member`. Returns true if it is a new member.
+    pub fn insert(&mut self, member: Vec<u8>, score: f64) -> bool {
+        let previous = self.scores.insert(member.clone(), Score(score));
+        if let Some(previous) = previous {
+            self.index.remove(&(previous, member.clone()));
+        }
+        self.index.insert((Score(score), member));
+        previous.is_none()
+    }
+
+    pub fn remove(&mut self, member: &[u8]) -> bool {
+        match self.scores.remove(member) {
+            Some(score) => {
+                self.index.remove(&(score, member.to_vec()));
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Position of `member` counting from the lowest score. This walks the
+    /// index up to the member, so it is linear in the rank.
+    pub fn rank(&self, member: &[u8]) -> Option<usize> {
+        let score = *self.scores.get(member)?;
+        Some(self.index.range(..(score, member.to_vec())).count())
+    }
+
+    /// Members with their scores, from the lowest score up
+    pub fn iter(&self) -> impl Iterator<Item = (&Vec<u8>, f64)> {
+        self.index.iter().map(|(score, member)| (member, score.0))
+    }
+
+    pub fn range_by_rank(
+        &self,
+        range: RangeInclusive<usize>,
+    ) -> impl Iterator<Item = (&Vec<u8>, f64)> {
+        self.iter()
+            .skip(*range.start())
+            .take(range.end() - range.start() + 1)
+    }
+
+    /// Members whose score lies between `min` and `max`, from the lowest score up
+    pub fn range_by_score(
+        &self,
+        min: Bound<f64>,
+        max: Bound<f64>,
+    ) -> impl Iterator<Item = (&Vec<u8>, f64)> {
+        let start = match min {
+            Bound::Included(min) => Bound::Included((Score(min), Vec::new())),
+            // the empty member sorts first, so skip everything at `min` below
+            Bound::Excluded(min) => Bound::Included((Score(min), Vec::new())),
+            Bound::Unbounded => Bound::Unbounded,
+        };
+        self.index
+            .range((start, Bound::Unbounded))
+            .skip_while(move |(score, _)| matches!(min, Bound::Excluded(min) if score.0 == min))
+            .take_while(move |(score, _)| match max {
+                Bound::Included(max) => score.0 <= max,
+                Bound::Excluded(max) => score.0 < max,
+                Bound::Unbounded => true,
+            })
+            .map(|(score, member)| (member, score.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn members<'a>(iter: impl Iterator<Item = (&'a Vec<u8>, f64)>) -> Vec<&'a [u8]> {
+        iter.map(|(member, _)| member.as_slice()).collect()
+    }
+
+    #[test]
+    fn test_order() {
+        let mut set = SortedSet::new();
+        assert!(set.insert(b"c".to_vec(), 3.0));
+        assert!(set.insert(b"a".to_vec(), 1.0));
+        assert!(set.insert(b"b".to_vec(), 1.0));
+        assert!(!set.insert(b"c".to_vec(), 0.5));
+
+        assert_eq!(members(set.iter()), vec![b"c", b"a", b"b"]);
+        assert_eq!(set.rank(b"b"), Some(2));
+        assert_eq!(set.rank(b"missing"), None);
+        assert_eq!(members(set.range_by_rank(1..=5)), vec![b"a", b"b"]);
+
+        assert!(set.remove(b"a"));
+        assert!(!set.remove(b"a"));
+        assert_eq!(set.len(), 2);
+        assert_eq!(set.score(b"c"), Some(0.5));
+    }
+
+    #[test]
+    fn test_range_by_score() {
+        let mut set = SortedSet::new();
+        for (member, score) in [(b"a", 1.0), (b"b", 2.0), (b"c", 2.0), (b"d", f64::INFINITY)] {
+            set.insert(member.to_vec(), score);
+        }
+        assert_eq!(
+            members(set.range_by_score(Bound::Included(2.0), Bound::Included(2.0))),
+            vec![b"b", b"c"]
+        );
+        assert_eq!(
+            members(set.range_by_score(Bound::Excluded(1.0), Bound::Excluded(f64::INFINITY))),
+            vec![b"b", b"c"]
+        );
+        assert_eq!(
+            members(set.range_by_score(Bound::Unbounded, Bound::Excluded(2.0))),
+            vec![b"a"]
+        );
+        assert_eq!(
+            members(set.range_by_score(Bound::Included(2.5), Bound::Unbounded)),
+            vec![b"d"]
+        );
+    }
+}
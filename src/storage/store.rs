@@ -0,0 +1,504 @@
+//! The storage engine of a shard together with the history of versions
+//! applied to it, shared between every thread the router handles requests on.
+//!
+//! Writes go through a [`Writer`], which holds the locks of the stripes its
+//! keys fall in, so writes to keys in different stripes go ahead side by
+//! side while a write can count on its own keys staying put between checking
+//! them and committing. Commits themselves pass through a short sequencer
+//! that hands out the next version and applies and records the mutation
+//! before the next one gets its version, so the engine, the history and
+//! anything logged along the way always see versions in order. Readers
+//! never take a key lock: the engine locks itself only while a commit is
+//! applied to the stripes it touches, and the current version is an atomic
+//! published once the mutation is visible.
+//!
+//! The store also serves snapshots to read shards resyncing from them, a
+//! chunk at a time, so neither side ever holds the whole snapshot encoded.
+
+use super::aof::AofEntry;
+use super::engine::{stripe, EngineSnapshot, MemoryEngine, StorageEngine, View, STRIPES};
+use super::history::VersionHistory;
+use super::keyspace::Keyspace;
+use super::mutation::Mutation;
+use super::snapshot::Snapshot;
+use anyhow::Result;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+/// Bytes of entries in a chunk of a snapshot sent to a read shard, far
+/// below the largest message the router takes
+const SYNC_CHUNK_BYTES: usize = 16 << 20;
+/// How long a snapshot sent in chunks is kept after its last chunk was asked for
+const SYNC_IDLE_MS: u64 = 30_000;
+
+/// Snapshot being sent to read shards a chunk at a time
+#[derive(Debug)]
+struct SyncSource {
+    snapshot: Box<dyn EngineSnapshot>,
+    last_used: u64,
+}
+
+/// Chunk of a snapshot for a read shard resyncing from it
+#[derive(Debug)]
+pub struct SnapshotChunk {
+    /// Version the snapshot is at
+    pub version: u64,
+    /// Number of entries of the snapshot before the chunk
+    pub offset: u64,
+    /// Number of entries in the whole snapshot
+    pub total: u64,
+    /// Entries laid out as in the snapshot file
+    pub entries: Vec<u8>,
+    pub done: bool,
+}
+
+#[derive(Debug)]
+pub struct Store {
+    /// One lock per stripe of keys, held by the writers of keys in it
+    key_locks: Vec<Mutex<()>>,
+    /// Held while a commit is given its version and applied
+    sequencer: Mutex<()>,
+    /// Last version applied to the engine. Only stored by the holder of the
+    /// sequencer once the engine has applied it.
+    version: AtomicU64,
+    engine: Box<dyn StorageEngine>,
+    history: RwLock<VersionHistory<Mutation>>,
+    /// Snapshots being sent in chunks, by version
+    syncs: Mutex<HashMap<u64, SyncSource>>,
+}
+
+impl Default for Store {
+    fn default() -> Self {
+        Self::new(Box::new(MemoryEngine::new()), VersionHistory::default())
+    }
+}
+
+impl Store {
+    /// Creates a store at the last version of `history`, which `engine`
+    /// must already have every entry of applied
+    pub fn new(engine: Box<dyn StorageEngine>, history: VersionHistory<Mutation>) -> Self {
+        Store {
+            key_locks: (0..STRIPES).map(|_| Mutex::new(())).collect(),
+            sequencer: Mutex::new(()),
+            version: AtomicU64::new(history.last_version()),
+            engine,
+            history: RwLock::new(history),
+            syncs: Mutex::default(),
+        }
+    }
+
+    /// Last version applied to the engine
+    pub fn version(&self) -> u64 {
+        self.version.load(Ordering::Acquire)
+    }
+
+    /// The engine. Every version up to [`Store::version`] is applied to it.
+    #[allow(unused)]
+    pub fn engine(&self) -> &dyn StorageEngine {
+        self.engine.as_ref()
+    }
+
+    /// Keyspace holding `keys` for commands to run against
+    pub fn view<'k>(&self, keys: impl IntoIterator<Item = &'k [u8]>) -> Result<View<'_>> {
+        self.engine.view(&keys.into_iter().collect::<Vec<_>>())
+    }
+
+    /// Read access to the version history. It holds every version up to
+    /// [`Store::version`] that has not been compacted.
+    pub fn history(&self) -> RwLockReadGuard<'_, VersionHistory<Mutation>> {
+        self.history.read().unwrap()
+    }
+
+    /// Point-in-time copy of the engine along with the version it is at
+    pub fn snapshot(&self) -> Result<(u64, Box<dyn EngineSnapshot>)> {
+        let _sequencer = self.sequencer.lock().unwrap();
+        Ok((self.version(), self.engine.snapshot()?))
+    }
+
+    /// Chunk of a snapshot of the engine, for a read shard that has to
+    /// resync from it, holding the entries from the `offset`th on. Offset 0
+    /// starts a sync from the latest version, and the chunks after it come
+    /// from the snapshot at the `version` it got, which is kept until it
+    /// goes unasked for a while. None if it is no longer kept.
+    pub fn snapshot_chunk(
+        &self,
+        version: u64,
+        offset: u64,
+        now: u64,
+    ) -> Result<Option<SnapshotChunk>> {
+        let mut syncs = self.syncs.lock().unwrap();
+        syncs.retain(|_, source| now < source.last_used + SYNC_IDLE_MS);
+        let version = if offset == 0 {
+            // read shards syncing at the same version share a snapshot, so
+            // the entries stay in the order the others are counting on
+            let _sequencer = self.sequencer.lock().unwrap();
+            let version = self.version();
+            if let Entry::Vacant(vacant) = syncs.entry(version) {
+                vacant.insert(SyncSource {
+                    snapshot: self.engine.snapshot()?,
+                    last_used: now,
+                });
+            }
+            version
+        } else {
+            version
+        };
+        let Some(source) = syncs.get_mut(&version) else {
+            return Ok(None);
+        };
+        source.last_used = now;
+        let mut entries = Vec::new();
+        let count = Snapshot::write_entries(
+            &mut entries,
+            source.snapshot.as_ref(),
+            offset as usize,
+            SYNC_CHUNK_BYTES,
+        )?;
+        let total = source.snapshot.len() as u64;
+        Ok(Some(SnapshotChunk {
+            version,
+            offset,
+            total,
+            entries,
+            done: offset + count as u64 >= total,
+        }))
+    }
+
+    /// Waits for the writers of any of `keys` to finish and returns a writer
+    /// that can commit mutations to them. Stripes are locked in ascending
+    /// order, so writers of overlapping keys never deadlock.
+    #[allow(unused)]
+    pub fn writer<'k>(&self, keys: impl IntoIterator<Item = &'k [u8]>) -> Writer<'_> {
+        let mut stripes: Vec<usize> = keys.into_iter().map(stripe).collect();
+        stripes.sort_unstable();
+        stripes.dedup();
+        self.lock_stripes(stripes)
+    }
+
+    /// Writer holding every key, for replacing the whole engine or for
+    /// anything that must not run alongside another write
+    pub fn exclusive(&self) -> Writer<'_> {
+        self.lock_stripes((0..STRIPES).collect())
+    }
+
+    fn lock_stripes(&self, stripes: Vec<usize>) -> Writer<'_> {
+        Writer {
+            held: stripes
+                .into_iter()
+                .map(|i| (i, self.key_locks[i].lock().unwrap()))
+                .collect(),
+            store: self,
+        }
+    }
+}
+
+/// Right to commit to a set of keys of a [`Store`]. Nothing else changes
+/// them while it is held, so anything checked against them still holds when
+/// the mutation it leads to is committed.
+#[derive(Debug)]
+pub struct Writer<'a> {
+    /// Stripes held, along with their locks
+    held: Vec<(usize, MutexGuard<'a, ()>)>,
+    store: &'a Store,
+}
+
+impl Writer<'_> {
+    /// Last version committed, by this writer or any other
+    #[allow(unused)]
+    pub fn version(&self) -> u64 {
+        self.store.version()
+    }
+
+    /// Whether the writer may commit to `key`
+    pub fn holds(&self, key: &[u8]) -> bool {
+        let stripe = stripe(key);
+        self.held.iter().any(|(i, _)| *i == stripe)
+    }
+
+    /// Adds `key` to the keys the writer holds, unless another writer holds
+    /// it. Never waits, as the writer already holds keys out of order.
+    #[allow(unused)]
+    pub fn try_lock(&mut self, key: &[u8]) -> bool {
+        if self.holds(key) {
+            return true;
+        }
+        let stripe = stripe(key);
+        match self.store.key_locks[stripe].try_lock() {
+            Ok(guard) => {
+                self.held.push((stripe, guard));
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// Applies a mutation as the next version and records it in the history.
+    /// The version stays where it was if the engine fails to apply it.
+    #[allow(unused)]
+    pub fn commit(&mut self, mutation: Mutation) -> Result<u64> {
+        self.commit_logged(mutation, |_| Ok(()))
+    }
+
+    /// Like [`Writer::commit`], but hands the entry to `log` once it has its
+    /// version and before it is applied, so whatever `log` writes is in
+    /// version order. Nothing is applied if `log` fails.
+    pub fn commit_logged(
+        &mut self,
+        mutation: Mutation,
+        log: impl FnOnce(&AofEntry) -> Result<()>,
+    ) -> Result<u64> {
+        if let Some(key) = mutation.keys().into_iter().find(|key| !self.holds(key)) {
+            anyhow::bail!("writer does not hold key {}", String::from_utf8_lossy(key));
+        }
+        let _sequencer = self.store.sequencer.lock().unwrap();
+        let entry = AofEntry {
+            version: self.store.version.load(Ordering::Relaxed) + 1,
+            mutation,
+        };
+        log(&entry)?;
+        self.store.engine.apply(&entry.mutation, entry.version)?;
+        // readers that see the version can fetch its entry
+        self.store.history.write().unwrap().push(entry.mutation);
+        self.store.version.store(entry.version, Ordering::Release);
+        Ok(entry.version)
+    }
+
+    /// Replaces the engine with `keyspace`, which is at `version`, and
+    /// empties the history, whose entries no longer lead up to it. Only an
+    /// exclusive writer may restore.
+    #[allow(unused)]
+    pub fn restore(&mut self, keyspace: Keyspace, version: u64) -> Result<()> {
+        anyhow::ensure!(
+            self.held.len() == STRIPES,
+            "restoring needs an exclusive writer"
+        );
+        let _sequencer = self.store.sequencer.lock().unwrap();
+        self.store.engine.restore(keyspace, version)?;
+        self.store.history.write().unwrap().reset(version + 1);
+        self.store.version.store(version, Ordering::Release);
+        Ok(())
+    }
+
+    /// Write access to the version history, for compacting it
+    pub fn history_mut(&mut self) -> RwLockWriteGuard<'_, VersionHistory<Mutation>> {
+        self.store.history.write().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::disk::DiskEngine;
+    use crate::storage::value::Value;
+    use rand::Rng;
+    use std::sync::Arc;
+    use std::thread;
+
+    fn set(key: &[u8], value: u64) -> Mutation {
+        Mutation::Set {
+            key: key.to_vec(),
+            value: value.to_string().into_bytes(),
+        }
+    }
+
+    #[test]
+    fn test_commit() {
+        let store = Store::default();
+        let mut writer = store.writer([b"a".as_slice(), b"b".as_slice()]);
+        assert_eq!(writer.commit(set(b"a", 1)).unwrap(), 1);
+        assert_eq!(writer.commit(set(b"b", 2)).unwrap(), 2);
+        assert_eq!(writer.version(), 2);
+        writer.history_mut().truncate_through(1);
+        drop(writer);
+
+        assert_eq!(store.version(), 2);
+        assert_eq!(store.view([b"b".as_slice()]).unwrap().version(b"b", 0), 2);
+        assert!(store.history().is_compacted(1));
+        assert_eq!(store.history().get(2), Some(&set(b"b", 2)));
+
+        let (version, snapshot) = store.snapshot().unwrap();
+        assert_eq!(version, 2);
+        assert_eq!(snapshot.len(), 2);
+    }
+
+    /// A key in none of `stripes`
+    fn key_outside(stripes: &[usize]) -> Vec<u8> {
+        (0..)
+            .map(|i| format!("key{}", i).into_bytes())
+            .find(|key| !stripes.contains(&stripe(key)))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_commit_needs_the_keys() {
+        let store = Store::default();
+        let other = key_outside(&[stripe(b"a")]);
+        let mut writer = store.writer([b"a".as_slice()]);
+        assert!(writer.commit(set(&other, 1)).is_err());
+        assert_eq!(store.version(), 0);
+
+        // a key nobody else holds can be added, one another writer holds can't
+        assert!(writer.try_lock(&other));
+        assert_eq!(writer.commit(set(&other, 1)).unwrap(), 1);
+        let third = key_outside(&[stripe(b"a"), stripe(&other)]);
+        let mut second = store.writer([third.as_slice()]);
+        assert!(!second.try_lock(&other));
+        assert!(second.restore(Keyspace::new(), 5).is_err());
+    }
+
+    #[test]
+    fn test_restore() {
+        let store = Store::new(
+            Box::new(MemoryEngine::new()),
+            VersionHistory::with_capacity(1, 2),
+        );
+        let mut writer = store.exclusive();
+        for i in 1..=3 {
+            writer.commit(set(b"a", i)).unwrap();
+        }
+        assert!(store.history().is_compacted(1));
+
+        let mut keyspace = Keyspace::new();
+        set(b"b", 10).apply(&mut keyspace, 10);
+        writer.restore(keyspace, 10).unwrap();
+        assert_eq!(writer.commit(set(b"b", 11)).unwrap(), 11);
+        drop(writer);
+
+        assert_eq!(store.version(), 11);
+        assert!(store.history().is_compacted(10));
+        assert_eq!(store.history().get(11), Some(&set(b"b", 11)));
+        assert_eq!(store.engine().len(), 1);
+    }
+
+    #[test]
+    fn test_disk_engine_views() {
+        let path = std::env::temp_dir().join(format!(
+            "rust-edis-{}.data",
+            rand::thread_rng().gen::<u64>()
+        ));
+        let store = Store::new(
+            Box::new(DiskEngine::open(&path).unwrap()),
+            VersionHistory::default(),
+        );
+        let mut writer = store.exclusive();
+        writer.commit(set(b"a", 1)).unwrap();
+        writer.commit(set(b"b", 2)).unwrap();
+        drop(writer);
+
+        // only the keys asked for are loaded
+        let view = store.view([b"a".as_slice()]).unwrap();
+        assert_eq!(view.get(b"a", 0), Some(&Value::from(b"1".to_vec())));
+        assert_eq!(view.get(b"b", 0), None);
+        drop(view);
+        assert_eq!(store.engine().len(), 2);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_readers_see_every_published_version() {
+        let store = Arc::new(Store::default());
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let store = store.clone();
+                thread::spawn(move || {
+                    let mut last = 0;
+                    while last < 1_000 {
+                        let version = store.version();
+                        let data = store.view([b"key".as_slice()]).unwrap();
+                        assert!(version >= last);
+                        last = version;
+                        if version == 0 {
+                            continue;
+                        }
+                        // the key holds the version that last wrote it, which
+                        // may be applied already but not published yet
+                        let Some(Value::String(value)) = data.get(b"key", 0) else {
+                            panic!("version {} is published but not applied", version);
+                        };
+                        let applied: u64 = String::from_utf8_lossy(value).parse().unwrap();
+                        assert!(applied >= version);
+                        assert!(store.history().get(version).is_some());
+                    }
+                })
+            })
+            .collect();
+
+        for version in 1..=1_000 {
+            assert_eq!(
+                store
+                    .writer([b"key".as_slice()])
+                    .commit(set(b"key", version))
+                    .unwrap(),
+                version
+            );
+        }
+        for reader in readers {
+            reader.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_snapshot_chunks() {
+        let store = Store::default();
+        let mut writer = store.exclusive();
+        for i in 0..3 {
+            writer
+                .commit(set(format!("key{}", i).as_bytes(), i))
+                .unwrap();
+        }
+        drop(writer);
+
+        let first = store.snapshot_chunk(0, 0, 0).unwrap().unwrap();
+        assert_eq!((first.version, first.total), (3, 3));
+        assert!(first.done);
+        let mut keyspace = Keyspace::new();
+        assert_eq!(
+            Snapshot::read_entries(&first.entries, &mut keyspace).unwrap(),
+            3
+        );
+
+        // later writes don't show up in the snapshot a sync is reading from
+        store
+            .writer([b"late".as_slice()])
+            .commit(set(b"late", 4))
+            .unwrap();
+        let again = store.snapshot_chunk(3, 1, 1_000).unwrap().unwrap();
+        assert_eq!((again.version, again.offset, again.total), (3, 1, 3));
+        assert!(store
+            .snapshot_chunk(3, 1, 1_000 + SYNC_IDLE_MS)
+            .unwrap()
+            .is_none());
+        assert_eq!(store.snapshot_chunk(0, 0, 0).unwrap().unwrap().total, 4);
+    }
+
+    #[test]
+    fn test_writers_of_different_keys_commit_side_by_side() {
+        let store = Arc::new(Store::default());
+        let writers: Vec<_> = (0..4)
+            .map(|i| {
+                let store = store.clone();
+                thread::spawn(move || {
+                    let key = format!("key{}", i).into_bytes();
+                    for n in 1..=250 {
+                        store.writer([key.as_slice()]).commit(set(&key, n)).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        // every commit got its own version, and the history holds them in order
+        assert_eq!(store.version(), 1_000);
+        assert_eq!(store.history().iter().count(), 1_000);
+        assert_eq!(store.engine().len(), 4);
+        for i in 0..4 {
+            let key = format!("key{}", i).into_bytes();
+            let data = store.view([key.as_slice()]).unwrap();
+            assert_eq!(data.get(&key, 0), Some(&Value::from(b"250".to_vec())));
+        }
+    }
+}
@@ -0,0 +1,375 @@
+use super::memory::sampled_usage;
+use super::value::{decode_items, decode_pairs, encode_items, encode_pairs};
+use anyhow::{Context, Result};
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// Id of a stream entry: the millisecond it was added in and a sequence
+/// number for entries added in the same millisecond
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct StreamId {
+    pub ms: u64,
+    pub seq: u64,
+}
+
+impl StreamId {
+    pub const MIN: StreamId = StreamId { ms: 0, seq: 0 };
+    pub const MAX: StreamId = StreamId {
+        ms: u64::MAX,
+        seq: u64::MAX,
+    };
+
+    /// Parses "<ms>-<seq>", or "<ms>" with `default_seq` as the sequence number
+    pub fn parse(arg: &[u8], default_seq: u64) -> Option<Self> {
+        let arg = std::str::from_utf8(arg).ok()?;
+        match arg.split_once('-') {
+            Some((ms, seq)) => Some(StreamId {
+                ms: ms.parse().ok()?,
+                seq: seq.parse().ok()?,
+            }),
+            None => Some(StreamId {
+                ms: arg.parse().ok()?,
+                seq: default_seq,
+            }),
+        }
+    }
+
+    /// The smallest id after this one
+    pub fn next(&self) -> Option<StreamId> {
+        match self.seq.checked_add(1) {
+            Some(seq) => Some(StreamId { ms: self.ms, seq }),
+            None => Some(StreamId {
+                ms: self.ms.checked_add(1)?,
+                seq: 0,
+            }),
+        }
+    }
+
+    pub fn to_bytes(self) -> Vec<u8> {
+        let mut bytes = self.ms.to_le_bytes().to_vec();
+        bytes.extend_from_slice(&self.seq.to_le_bytes());
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        Ok(StreamId {
+            ms: u64::from_le_bytes(bytes.get(0..8).context("failed to get id")?.try_into()?),
+            seq: u64::from_le_bytes(bytes.get(8..16).context("failed to get id")?.try_into()?),
+        })
+    }
+}
+
+impl fmt::Display for StreamId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}-{}", self.ms, self.seq)
+    }
+}
+
+pub type Fields = Vec<(Vec<u8>, Vec<u8>)>;
+
+/// An entry delivered to a consumer of a group that it hasn't acknowledged yet
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PendingEntry {
+    pub consumer: Vec<u8>,
+    /// When the entry was last delivered, in unix milliseconds
+    pub delivered_at: u64,
+    pub deliveries: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConsumerGroup {
+    pub last_delivered: StreamId,
+    pub pending: BTreeMap<StreamId, PendingEntry>,
+}
+
+/// Append-only log of entries with increasing ids, along with the consumer
+/// groups reading it. Trimming a stream down to nothing keeps the key, so
+/// its last id and groups survive.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Stream {
+    entries: BTreeMap<StreamId, Fields>,
+    last_id: StreamId,
+    groups: BTreeMap<Vec<u8>, ConsumerGroup>,
+}
+
+impl Stream {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    #[allow(unused)]
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Estimated bytes the stream takes up, measured from a sample of its
+    /// entries and of the entries pending in each group
+    #[allow(unused)]
+    pub fn memory_usage(&self) -> usize {
+        let id_size = std::mem::size_of::<StreamId>();
+        let entries = sampled_usage(
+            self.entries.len(),
+            self.entries.values().map(|fields| {
+                id_size + fields.iter().map(|(f, v)| f.len() + v.len()).sum::<usize>()
+            }),
+        );
+        let groups: usize = self
+            .groups
+            .iter()
+            .map(|(name, group)| {
+                name.len()
+                    + sampled_usage(
+                        group.pending.len(),
+                        group
+                            .pending
+                            .values()
+                            .map(|p| id_size + p.consumer.len() + 16),
+                    )
+            })
+            .sum();
+        entries + groups
+    }
+
+    /// Id of the last entry ever added, even if it was trimmed since
+    pub fn last_id(&self) -> StreamId {
+        self.last_id
+    }
+
+    /// Id for an entry added at `now`, which is always after the last one
+    pub fn next_id(&self, now: u64) -> Option<StreamId> {
+        if now > self.last_id.ms {
+            Some(StreamId { ms: now, seq: 0 })
+        } else {
+            self.last_id.next()
+        }
+    }
+
+    pub fn add(&mut self, id: StreamId, fields: Fields) {
+        self.entries.insert(id, fields);
+        self.last_id = self.last_id.max(id);
+    }
+
+    /// Entries with ids between `start` and `end`, both included
+    pub fn range(
+        &self,
+        start: StreamId,
+        end: StreamId,
+    ) -> impl Iterator<Item = (&StreamId, &Fields)> {
+        // an empty range would make BTreeMap::range panic
+        let end = end.max(start);
+        self.entries
+            .range(start..=end)
+            .filter(move |(id, _)| **id <= end)
+    }
+
+    pub fn get(&self, id: &StreamId) -> Option<&Fields> {
+        self.entries.get(id)
+    }
+
+    /// Removes the oldest entries until at most `max_len` are left. Returns how many were removed.
+    pub fn trim(&mut self, max_len: usize) -> usize {
+        let mut removed = 0;
+        while self.entries.len() > max_len {
+            self.entries.pop_first();
+            removed += 1;
+        }
+        removed
+    }
+
+    pub fn group(&self, name: &[u8]) -> Option<&ConsumerGroup> {
+        self.groups.get(name)
+    }
+
+    /// Creates a group that delivers entries after `last_delivered`. Returns false if it exists.
+    pub fn create_group(&mut self, name: Vec<u8>, last_delivered: StreamId) -> bool {
+        if self.groups.contains_key(&name) {
+            return false;
+        }
+        let group = ConsumerGroup {
+            last_delivered,
+            pending: BTreeMap::new(),
+        };
+        self.groups.insert(name, group);
+        true
+    }
+
+    pub fn destroy_group(&mut self, name: &[u8]) -> bool {
+        self.groups.remove(name).is_some()
+    }
+
+    /// Records that `ids` were delivered to `consumer` at `now`. They stay
+    /// pending until acknowledged.
+    pub fn deliver(&mut self, group: &[u8], consumer: &[u8], ids: &[StreamId], now: u64) {
+        let Some(group) = self.groups.get_mut(group) else {
+            return;
+        };
+        for id in ids {
+            let pending = group.pending.entry(*id).or_insert(PendingEntry {
+                consumer: consumer.to_vec(),
+                delivered_at: now,
+                deliveries: 0,
+            });
+            pending.consumer = consumer.to_vec();
+            pending.delivered_at = now;
+            pending.deliveries += 1;
+            group.last_delivered = group.last_delivered.max(*id);
+        }
+    }
+
+    /// Acknowledges pending entries of a group. Returns how many were pending.
+    pub fn ack(&mut self, group: &[u8], ids: &[StreamId]) -> usize {
+        let Some(group) = self.groups.get_mut(group) else {
+            return 0;
+        };
+        ids.iter()
+            .filter(|id| group.pending.remove(id).is_some())
+            .count()
+    }
+}
+
+/// Layout of an encoded stream, as a sequence of items
+/// | last id | entry count | (id, fields)... | (group, last delivered, pending)... |
+/// Ids are two little-endian u64s and the entry count one. fields is an
+/// encoded list of field-value pairs, and pending an encoded list of
+/// (id, consumer, delivered at and deliveries as two u64s) for each entry.
+impl Stream {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut items = vec![
+            self.last_id.to_bytes(),
+            (self.entries.len() as u64).to_le_bytes().to_vec(),
+        ];
+        for (id, fields) in &self.entries {
+            items.push(id.to_bytes());
+            items.push(encode_pairs(
+                fields.iter().map(|(field, value)| (field, value)),
+            ));
+        }
+        for (name, group) in &self.groups {
+            items.push(name.clone());
+            items.push(group.last_delivered.to_bytes());
+            let mut pending = Vec::new();
+            for (id, entry) in &group.pending {
+                pending.push(id.to_bytes());
+                pending.push(entry.consumer.clone());
+                let mut delivery = entry.delivered_at.to_le_bytes().to_vec();
+                delivery.extend_from_slice(&entry.deliveries.to_le_bytes());
+                pending.push(delivery);
+            }
+            items.push(encode_items(&pending));
+        }
+        encode_items(&items)
+    }
+
+    pub fn decode(buffer: &[u8]) -> Result<Self> {
+        let mut items = decode_items(buffer)?.into_iter();
+        let mut next = |field: &str| {
+            items
+                .next()
+                .with_context(|| format!("failed to get {}", field))
+        };
+        let last_id = StreamId::from_bytes(&next("last id")?)?;
+        let count = u64::from_le_bytes(next("entry count")?.as_slice().try_into()?);
+
+        let mut stream = Stream {
+            last_id,
+            ..Stream::default()
+        };
+        for _ in 0..count {
+            let id = StreamId::from_bytes(&next("entry id")?)?;
+            stream
+                .entries
+                .insert(id, decode_pairs(&next("entry fields")?)?);
+        }
+        while let Ok(name) = next("group name") {
+            let last_delivered = StreamId::from_bytes(&next("last delivered id")?)?;
+            let mut group = ConsumerGroup {
+                last_delivered,
+                pending: BTreeMap::new(),
+            };
+            let pending = decode_items(&next("pending entries")?)?;
+            for entry in pending.chunks(3) {
+                let [id, consumer, delivery] = entry else {
+                    anyhow::bail!("truncated pending entry");
+                };
+                let delivered_at = u64::from_le_bytes(
+                    delivery
+                        .get(0..8)
+                        .context("failed to get delivery time")?
+                        .try_into()?,
+                );
+                let deliveries = u64::from_le_bytes(
+                    delivery
+                        .get(8..16)
+                        .context("failed to get deliveries")?
+                        .try_into()?,
+                );
+                group.pending.insert(
+                    StreamId::from_bytes(id)?,
+                    PendingEntry {
+                        consumer: consumer.clone(),
+                        delivered_at,
+                        deliveries,
+                    },
+                );
+            }
+            stream.groups.insert(name, group);
+        }
+        Ok(stream)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn id(ms: u64, seq: u64) -> StreamId {
+        StreamId { ms, seq }
+    }
+
+    #[test]
+    fn test_ids() {
+        assert_eq!(StreamId::parse(b"5-3", 0), Some(id(5, 3)));
+        assert_eq!(StreamId::parse(b"5", u64::MAX), Some(id(5, u64::MAX)));
+        assert_eq!(StreamId::parse(b"x-1", 0), None);
+        assert_eq!(id(5, 3).to_string(), "5-3");
+
+        let mut stream = Stream::new();
+        assert_eq!(stream.next_id(10), Some(id(10, 0)));
+        stream.add(id(10, 0), Vec::new());
+        // the clock went backwards, ids keep increasing anyway
+        assert_eq!(stream.next_id(9), Some(id(10, 1)));
+        assert_eq!(StreamId::MAX.next(), None);
+    }
+
+    #[test]
+    fn test_groups() {
+        let mut stream = Stream::new();
+        for ms in 1..=3 {
+            stream.add(
+                id(ms, 0),
+                vec![(b"n".to_vec(), ms.to_string().into_bytes())],
+            );
+        }
+        assert!(stream.create_group(b"workers".to_vec(), StreamId::MIN));
+        assert!(!stream.create_group(b"workers".to_vec(), StreamId::MIN));
+
+        stream.deliver(b"workers", b"w1", &[id(1, 0), id(2, 0)], 100);
+        stream.deliver(b"workers", b"w2", &[id(2, 0)], 200);
+        let group = stream.group(b"workers").unwrap();
+        assert_eq!(group.last_delivered, id(2, 0));
+        assert_eq!(group.pending[&id(2, 0)].consumer, b"w2".to_vec());
+        assert_eq!(group.pending[&id(2, 0)].deliveries, 2);
+
+        assert_eq!(stream.ack(b"workers", &[id(1, 0), id(3, 0)]), 1);
+        assert_eq!(stream.trim(1), 2);
+        assert_eq!(stream.range(StreamId::MIN, StreamId::MAX).count(), 1);
+        assert_eq!(stream.range(id(3, 1), id(3, 0)).count(), 0);
+
+        let decoded = Stream::decode(&stream.encode()).unwrap();
+        assert_eq!(decoded, stream);
+    }
+}
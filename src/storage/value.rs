@@ -0,0 +1,309 @@
+use super::hyperloglog::{HyperLogLog, REGISTERS};
+use super::memory::sampled_usage;
+use super::sorted_set::SortedSet;
+use super::stream::Stream;
+use anyhow::{Context, Result};
+use int_enum::IntEnum;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::ops::RangeInclusive;
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, IntEnum, PartialEq, Eq)]
+pub enum ValueType {
+    String = 0,
+    List = 1,
+    Hash = 2,
+    Set = 3,
+    SortedSet = 4,
+    Stream = 5,
+    HyperLogLog = 6,
+    Json = 7,
+}
+
+/// End of a list that is pushed to or popped from
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, IntEnum, PartialEq, Eq)]
+pub enum ListEnd {
+    Left = 0,
+    Right = 1,
+}
+
+/// Value stored under a key. Commands work on a single type and fail with
+/// WRONGTYPE when the key holds another one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    String(Vec<u8>),
+    List(VecDeque<Vec<u8>>),
+    Hash(HashMap<Vec<u8>, Vec<u8>>),
+    Set(HashSet<Vec<u8>>),
+    SortedSet(SortedSet),
+    Stream(Stream),
+    HyperLogLog(HyperLogLog),
+    Json(serde_json::Value),
+}
+
+impl From<Vec<u8>> for Value {
+    fn from(value: Vec<u8>) -> Self {
+        Value::String(value)
+    }
+}
+
+impl Value {
+    pub fn value_type(&self) -> ValueType {
+        match self {
+            Value::String(_) => ValueType::String,
+            Value::List(_) => ValueType::List,
+            Value::Hash(_) => ValueType::Hash,
+            Value::Set(_) => ValueType::Set,
+            Value::SortedSet(_) => ValueType::SortedSet,
+            Value::Stream(_) => ValueType::Stream,
+            Value::HyperLogLog(_) => ValueType::HyperLogLog,
+            Value::Json(_) => ValueType::Json,
+        }
+    }
+
+    pub fn as_string(&self) -> Option<&Vec<u8>> {
+        match self {
+            Value::String(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn as_list(&self) -> Option<&VecDeque<Vec<u8>>> {
+        match self {
+            Value::List(list) => Some(list),
+            _ => None,
+        }
+    }
+
+    pub fn as_hash(&self) -> Option<&HashMap<Vec<u8>, Vec<u8>>> {
+        match self {
+            Value::Hash(hash) => Some(hash),
+            _ => None,
+        }
+    }
+
+    pub fn as_set(&self) -> Option<&HashSet<Vec<u8>>> {
+        match self {
+            Value::Set(set) => Some(set),
+            _ => None,
+        }
+    }
+
+    pub fn as_sorted_set(&self) -> Option<&SortedSet> {
+        match self {
+            Value::SortedSet(set) => Some(set),
+            _ => None,
+        }
+    }
+
+    pub fn as_stream(&self) -> Option<&Stream> {
+        match self {
+            Value::Stream(stream) => Some(stream),
+            _ => None,
+        }
+    }
+
+    pub fn as_hyperloglog(&self) -> Option<&HyperLogLog> {
+        match self {
+            Value::HyperLogLog(hll) => Some(hll),
+            _ => None,
+        }
+    }
+
+    pub fn as_json(&self) -> Option<&serde_json::Value> {
+        match self {
+            Value::Json(document) => Some(document),
+            _ => None,
+        }
+    }
+
+    /// Estimated bytes the value takes up. Collections are measured from a
+    /// sample of their elements.
+    #[allow(unused)]
+    pub fn memory_usage(&self) -> usize {
+        match self {
+            Value::String(value) => value.len(),
+            Value::List(list) => sampled_usage(list.len(), list.iter().map(Vec::len)),
+            Value::Hash(hash) => {
+                sampled_usage(hash.len(), hash.iter().map(|(f, v)| f.len() + v.len()))
+            }
+            Value::Set(set) => sampled_usage(set.len(), set.iter().map(Vec::len)),
+            // members are stored twice, once by score and once by name
+            Value::SortedSet(set) => sampled_usage(
+                set.len(),
+                set.iter().map(|(member, _)| 2 * member.len() + 8),
+            ),
+            Value::Stream(stream) => stream.memory_usage(),
+            Value::HyperLogLog(_) => REGISTERS,
+            Value::Json(document) => document.to_string().len(),
+        }
+    }
+
+    /// Encodes the contents of the value. The type is stored next to them
+    /// and has to be passed back to `decode`.
+    pub fn encode(&self) -> Vec<u8> {
+        match self {
+            Value::String(value) => value.clone(),
+            Value::List(list) => encode_items(list),
+            Value::Hash(hash) => encode_pairs(hash),
+            Value::Set(set) => encode_items(set),
+            Value::SortedSet(set) => encode_scores(set.iter()),
+            Value::Stream(stream) => stream.encode(),
+            Value::HyperLogLog(hll) => hll.encode(),
+            Value::Json(document) => document.to_string().into_bytes(),
+        }
+    }
+
+    pub fn decode(value_type: u8, bytes: Vec<u8>) -> Result<Self> {
+        let value_type = ValueType::try_from(value_type)
+            .map_err(|_| anyhow::anyhow!("invalid value type {}", value_type))?;
+        Ok(match value_type {
+            ValueType::String => Value::String(bytes),
+            ValueType::List => Value::List(decode_items(&bytes)?.into()),
+            ValueType::Hash => Value::Hash(decode_pairs(&bytes)?.into_iter().collect()),
+            ValueType::Set => Value::Set(decode_items(&bytes)?.into_iter().collect()),
+            ValueType::SortedSet => {
+                let mut set = SortedSet::new();
+                for (member, score) in decode_scores(&bytes)? {
+                    set.insert(member, score);
+                }
+                Value::SortedSet(set)
+            }
+            ValueType::Stream => Value::Stream(Stream::decode(&bytes)?),
+            ValueType::HyperLogLog => Value::HyperLogLog(HyperLogLog::decode(&bytes)?),
+            ValueType::Json => Value::Json(serde_json::from_slice(&bytes)?),
+        })
+    }
+}
+
+/// Layout of a sequence of items
+/// | 4 bytes | N bytes | ...
+/// | itemlen |  item   | ...
+/// Integers are always encoded in little-endian order
+/// Items arrive in messages that encode their length as a u32, so it always fits.
+pub fn encode_items<'a>(items: impl IntoIterator<Item = &'a Vec<u8>>) -> Vec<u8> {
+    let mut buffer = Vec::new();
+    for item in items {
+        buffer.extend_from_slice(&(item.len() as u32).to_le_bytes());
+        buffer.extend_from_slice(item);
+    }
+    buffer
+}
+
+pub fn decode_items(buffer: &[u8]) -> Result<Vec<Vec<u8>>> {
+    let mut items = Vec::new();
+    let mut offset = 0;
+    while offset < buffer.len() {
+        let item_len = u32::from_le_bytes(
+            buffer
+                .get(offset..offset + 4)
+                .context("failed to get item length")?
+                .try_into()?,
+        ) as usize;
+        let item = buffer
+            .get(offset + 4..offset + 4 + item_len)
+            .context("failed to get item")?;
+        items.push(item.to_vec());
+        offset += 4 + item_len;
+    }
+    Ok(items)
+}
+
+/// Encodes field-value pairs as a sequence of items, each field followed by its value
+pub fn encode_pairs<'a>(pairs: impl IntoIterator<Item = (&'a Vec<u8>, &'a Vec<u8>)>) -> Vec<u8> {
+    encode_items(pairs.into_iter().flat_map(|(field, value)| [field, value]))
+}
+
+pub fn decode_pairs(buffer: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+    let mut items = decode_items(buffer)?.into_iter();
+    let mut pairs = Vec::new();
+    while let Some(field) = items.next() {
+        let value = items.next().context("failed to get value of field")?;
+        pairs.push((field, value));
+    }
+    Ok(pairs)
+}
+
+/// Encodes sorted set members as pairs of each member and its score as a little-endian f64
+pub fn encode_scores<'a>(members: impl IntoIterator<Item = (&'a Vec<u8>, f64)>) -> Vec<u8> {
+    let members: Vec<(&Vec<u8>, Vec<u8>)> = members
+        .into_iter()
+        .map(|(member, score)| (member, score.to_le_bytes().to_vec()))
+        .collect();
+    encode_pairs(members.iter().map(|(member, score)| (*member, score)))
+}
+
+pub fn decode_scores(buffer: &[u8]) -> Result<Vec<(Vec<u8>, f64)>> {
+    decode_pairs(buffer)?
+        .into_iter()
+        .map(|(member, score)| {
+            let score = f64::from_le_bytes(score.as_slice().try_into().context("invalid score")?);
+            if score.is_nan() {
+                anyhow::bail!("score is not a number");
+            }
+            Ok((member, score))
+        })
+        .collect()
+}
+
+/// Resolves inclusive `start` and `stop` indexes into a sequence of `len`
+/// items, where negative indexes count from the end like they do in redis.
+/// None if the range selects nothing.
+pub fn resolve_range(start: i64, stop: i64, len: usize) -> Option<RangeInclusive<usize>> {
+    let len = len as i64;
+    let start = if start < 0 {
+        (start + len).max(0)
+    } else {
+        start
+    };
+    let stop = if stop < 0 {
+        stop + len
+    } else {
+        stop.min(len - 1)
+    };
+    if start > stop || start >= len {
+        return None;
+    }
+    Some(start as usize..=stop as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip() {
+        let values = vec![
+            Value::String(vec![0xff, 0x00]),
+            Value::List(VecDeque::new()),
+            Value::List(vec![b"a".to_vec(), Vec::new(), b"ccc".to_vec()].into()),
+            Value::Hash(HashMap::from([
+                (b"name".to_vec(), b"edis".to_vec()),
+                (b"age".to_vec(), b"1".to_vec()),
+            ])),
+            Value::Set(HashSet::from([b"a".to_vec(), b"b".to_vec()])),
+            Value::SortedSet({
+                let mut set = SortedSet::new();
+                set.insert(b"a".to_vec(), 1.5);
+                set.insert(b"b".to_vec(), f64::NEG_INFINITY);
+                set
+            }),
+        ];
+        for original in values {
+            let encoded = original.encode();
+            let decoded = Value::decode(original.value_type() as u8, encoded).unwrap();
+            assert_eq!(decoded, original);
+        }
+    }
+
+    #[test]
+    fn test_resolve_range() {
+        assert_eq!(resolve_range(0, -1, 3), Some(0..=2));
+        assert_eq!(resolve_range(-2, 10, 3), Some(1..=2));
+        assert_eq!(resolve_range(-10, 0, 3), Some(0..=0));
+        assert_eq!(resolve_range(2, 1, 3), None);
+        assert_eq!(resolve_range(3, 5, 3), None);
+        assert_eq!(resolve_range(0, -1, 0), None);
+    }
+}
@@ -1,20 +1,28 @@
 use std::sync::{Arc, Mutex};
 
-use crate::io::router::{RouterBuilder, RouterClient, RouterHandler};
+use crate::io::router::{HandlerResponse, RouterBuilder, RouterClient, RouterHandler};
 use crate::messages::{
     requests::{
-        announce_shard_request::AnnounceShardRequest,
+        announce_shard_request::AnnounceShardRequest, bg_save_request::BgSaveRequest,
+        command_request::CommandRequest, delete_request::DeleteRequest,
+        exists_request::ExistsRequest, expire_request::ExpireRequest,
         get_client_shard_info_request::GetClientShardInfoRequest,
         get_shared_peers_request::GetSharedPeersRequest,
         query_version_request::QueryVersionRequest, read_request::ReadRequest,
-        write_request::WriteRequest,
+        replica_ack_request::ReplicaAckRequest, sync_request::SyncRequest,
+        transaction_request::TransactionRequest, ttl_request::TtlRequest,
+        two_phase_request::TwoPhaseRequest, write_request::WriteRequest,
     },
     responses::{
-        announce_shard_response::AnnounceShardResponse,
+        announce_shard_response::AnnounceShardResponse, bg_save_response::BgSaveResponse,
+        command_response::CommandResponse, delete_response::DeleteResponse,
+        exists_response::ExistsResponse, expire_response::ExpireResponse,
         get_client_shard_info_response::GetClientShardInfoResponse,
         get_shared_peers_response::GetSharedPeersResponse,
         query_version_response::QueryVersionResponse, read_response::ReadResponse,
-        write_response::WriteResponse,
+        replica_ack_response::ReplicaAckResponse, sync_response::SyncResponse,
+        transaction_response::TransactionResponse, ttl_response::TtlResponse,
+        two_phase_response::TwoPhaseResponse, write_response::WriteResponse,
     },
 };
 
@@ -27,6 +35,16 @@ pub struct TestRouterClient {
     pub get_shared_peers_responses: Arc<Mutex<Vec<GetSharedPeersResponse>>>,
     pub read_responses: Arc<Mutex<Vec<ReadResponse>>>,
     pub write_responses: Arc<Mutex<Vec<WriteResponse>>>,
+    pub bg_save_responses: Arc<Mutex<Vec<BgSaveResponse>>>,
+    pub delete_responses: Arc<Mutex<Vec<DeleteResponse>>>,
+    pub exists_responses: Arc<Mutex<Vec<ExistsResponse>>>,
+    pub expire_responses: Arc<Mutex<Vec<ExpireResponse>>>,
+    pub ttl_responses: Arc<Mutex<Vec<TtlResponse>>>,
+    pub command_responses: Arc<Mutex<Vec<CommandResponse>>>,
+    pub transaction_responses: Arc<Mutex<Vec<TransactionResponse>>>,
+    pub two_phase_responses: Arc<Mutex<Vec<TwoPhaseResponse>>>,
+    pub replica_ack_responses: Arc<Mutex<Vec<ReplicaAckResponse>>>,
+    pub sync_responses: Arc<Mutex<Vec<SyncResponse>>>,
 
     router: RouterBuilder<TestRouterClientHandler>,
 }
@@ -46,7 +64,17 @@ impl TestRouterClient {
         let get_shared_peers_responses = Arc::new(Mutex::new(Vec::new()));
         let read_responses = Arc::new(Mutex::new(Vec::new()));
         let write_responses = Arc::new(Mutex::new(Vec::new()));
+        let bg_save_responses = Arc::new(Mutex::new(Vec::new()));
+        let delete_responses = Arc::new(Mutex::new(Vec::new()));
+        let exists_responses = Arc::new(Mutex::new(Vec::new()));
 
+        let expire_responses = Arc::new(Mutex::new(Vec::new()));
+        let ttl_responses = Arc::new(Mutex::new(Vec::new()));
+        let command_responses = Arc::new(Mutex::new(Vec::new()));
+        let transaction_responses = Arc::new(Mutex::new(Vec::new()));
+        let two_phase_responses = Arc::new(Mutex::new(Vec::new()));
+        let replica_ack_responses = Arc::new(Mutex::new(Vec::new()));
+        let sync_responses = Arc::new(Mutex::new(Vec::new()));
         let router_handler = TestRouterClientHandler {
             query_version_responses: query_version_responses.clone(),
             announce_shard_responses: announce_shard_responses.clone(),
@@ -54,6 +82,16 @@ impl TestRouterClient {
             get_shared_peers_responses: get_shared_peers_responses.clone(),
             read_responses: read_responses.clone(),
             write_responses: write_responses.clone(),
+            bg_save_responses: bg_save_responses.clone(),
+            delete_responses: delete_responses.clone(),
+            exists_responses: exists_responses.clone(),
+            expire_responses: expire_responses.clone(),
+            ttl_responses: ttl_responses.clone(),
+            command_responses: command_responses.clone(),
+            transaction_responses: transaction_responses.clone(),
+            two_phase_responses: two_phase_responses.clone(),
+            replica_ack_responses: replica_ack_responses.clone(),
+            sync_responses: sync_responses.clone(),
         };
         let router = RouterBuilder::new(router_handler, None);
 
@@ -64,6 +102,16 @@ impl TestRouterClient {
             get_shared_peers_responses,
             read_responses,
             write_responses,
+            bg_save_responses,
+            delete_responses,
+            exists_responses,
+            expire_responses,
+            ttl_responses,
+            command_responses,
+            transaction_responses,
+            two_phase_responses,
+            replica_ack_responses,
+            sync_responses,
             router,
         }
     }
@@ -81,6 +129,16 @@ pub struct TestRouterClientHandler {
     get_shared_peers_responses: Arc<Mutex<Vec<GetSharedPeersResponse>>>,
     read_responses: Arc<Mutex<Vec<ReadResponse>>>,
     write_responses: Arc<Mutex<Vec<WriteResponse>>>,
+    bg_save_responses: Arc<Mutex<Vec<BgSaveResponse>>>,
+    delete_responses: Arc<Mutex<Vec<DeleteResponse>>>,
+    exists_responses: Arc<Mutex<Vec<ExistsResponse>>>,
+    expire_responses: Arc<Mutex<Vec<ExpireResponse>>>,
+    ttl_responses: Arc<Mutex<Vec<TtlResponse>>>,
+    command_responses: Arc<Mutex<Vec<CommandResponse>>>,
+    transaction_responses: Arc<Mutex<Vec<TransactionResponse>>>,
+    two_phase_responses: Arc<Mutex<Vec<TwoPhaseResponse>>>,
+    replica_ack_responses: Arc<Mutex<Vec<ReplicaAckResponse>>>,
+    sync_responses: Arc<Mutex<Vec<SyncResponse>>>,
 }
 
 impl RouterHandler for TestRouterClientHandler {
@@ -99,11 +157,11 @@ impl RouterHandler for TestRouterClientHandler {
         unimplemented!()
     }
 
-    fn handle_read_request(&self, _req: &ReadRequest) -> ReadResponse {
+    fn handle_read_request(&self, _req: &ReadRequest) -> HandlerResponse<ReadResponse> {
         unimplemented!()
     }
 
-    fn handle_write_request(&self, _req: &WriteRequest) -> WriteResponse {
+    fn handle_write_request(&self, _req: &WriteRequest) -> HandlerResponse<WriteResponse> {
         unimplemented!()
     }
 
@@ -128,6 +186,30 @@ impl RouterHandler for TestRouterClientHandler {
         unimplemented!()
     }
 
+    fn handle_bg_save_request(&self, _req: &BgSaveRequest) -> BgSaveResponse {
+        unimplemented!()
+    }
+
+    fn handle_delete_request(&self, _req: &DeleteRequest) -> DeleteResponse {
+        unimplemented!()
+    }
+
+    fn handle_exists_request(&self, _req: &ExistsRequest) -> ExistsResponse {
+        unimplemented!()
+    }
+
+    fn handle_expire_request(&self, _req: &ExpireRequest) -> ExpireResponse {
+        unimplemented!()
+    }
+
+    fn handle_ttl_request(&self, _req: &TtlRequest) -> TtlResponse {
+        unimplemented!()
+    }
+
+    fn handle_command_request(&self, _req: &CommandRequest) -> HandlerResponse<CommandResponse> {
+        unimplemented!()
+    }
+
     /// Callbacks for handling responses to outbound requests
     fn handle_announce_shard_response(&self, res: &AnnounceShardResponse) {
         let mut arr = self.announce_shard_responses.lock().unwrap();
@@ -158,4 +240,70 @@ impl RouterHandler for TestRouterClientHandler {
         let mut arr = self.get_shared_peers_responses.lock().unwrap();
         arr.push(res.clone());
     }
+
+    fn handle_bg_save_response(&self, res: &BgSaveResponse) {
+        let mut arr = self.bg_save_responses.lock().unwrap();
+        arr.push(res.clone());
+    }
+
+    fn handle_delete_response(&self, res: &DeleteResponse) {
+        let mut arr = self.delete_responses.lock().unwrap();
+        arr.push(res.clone());
+    }
+
+    fn handle_exists_response(&self, res: &ExistsResponse) {
+        let mut arr = self.exists_responses.lock().unwrap();
+        arr.push(res.clone());
+    }
+
+    fn handle_expire_response(&self, res: &ExpireResponse) {
+        let mut arr = self.expire_responses.lock().unwrap();
+        arr.push(res.clone());
+    }
+
+    fn handle_ttl_response(&self, res: &TtlResponse) {
+        let mut arr = self.ttl_responses.lock().unwrap();
+        arr.push(res.clone());
+    }
+
+    fn handle_command_response(&self, res: &CommandResponse) {
+        let mut arr = self.command_responses.lock().unwrap();
+        arr.push(res.clone());
+    }
+
+    fn handle_transaction_request(&self, _req: &TransactionRequest) -> TransactionResponse {
+        unimplemented!()
+    }
+
+    fn handle_transaction_response(&self, res: &TransactionResponse) {
+        let mut arr = self.transaction_responses.lock().unwrap();
+        arr.push(res.clone());
+    }
+
+    fn handle_two_phase_request(&self, _req: &TwoPhaseRequest) -> TwoPhaseResponse {
+        unimplemented!()
+    }
+
+    fn handle_two_phase_response(&self, res: &TwoPhaseResponse) {
+        let mut arr = self.two_phase_responses.lock().unwrap();
+        arr.push(res.clone());
+    }
+
+    fn handle_replica_ack_request(&self, _req: &ReplicaAckRequest) -> ReplicaAckResponse {
+        unimplemented!()
+    }
+
+    fn handle_replica_ack_response(&self, res: &ReplicaAckResponse) {
+        let mut arr = self.replica_ack_responses.lock().unwrap();
+        arr.push(res.clone());
+    }
+
+    fn handle_sync_request(&self, _req: &SyncRequest) -> SyncResponse {
+        unimplemented!()
+    }
+
+    fn handle_sync_response(&self, res: &SyncResponse) {
+        let mut arr = self.sync_responses.lock().unwrap();
+        arr.push(res.clone());
+    }
 }
@@ -1,96 +1,915 @@
 use anyhow::Result;
+use clap::Parser;
 use messages::requests::announce_shard_request::ShardType;
 use rand::Rng;
-use std::collections::HashMap;
+use std::collections::HashSet;
 use std::net::{Ipv6Addr, SocketAddrV6};
+use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
+use tokio::sync::oneshot;
 use tokio::time;
+#[allow(unused)]
+mod commands;
 mod integration;
 mod messages;
+mod storage;
 mod utils;
+use crate::commands::blocked::BlockedClients;
 use crate::messages::{
     requests::{
         announce_shard_request::AnnounceShardRequest,
+        bg_save_request::BgSaveRequest,
+        command_request::CommandRequest,
+        delete_request::DeleteRequest,
+        exists_request::ExistsRequest,
+        expire_request::ExpireRequest,
         get_client_shard_info_request::GetClientShardInfoRequest,
-        get_shared_peers_request::GetSharedPeersRequest, get_version_request::GetVersionRequest,
-        query_version_request::QueryVersionRequest, read_request::ReadRequest,
-        write_request::WriteRequest,
+        get_shared_peers_request::GetSharedPeersRequest,
+        get_version_request::GetVersionRequest,
+        query_version_request::QueryVersionRequest,
+        read_request::ReadRequest,
+        replica_ack_request::ReplicaAckRequest,
+        sync_request::SyncRequest,
+        transaction_request::TransactionRequest,
+        ttl_request::TtlRequest,
+        two_phase_request::{Phase, TwoPhaseRequest},
+        write_request::{ExpiryType, WriteCondition, WriteRequest},
     },
     responses::{
         announce_shard_response::AnnounceShardResponse,
+        bg_save_response::{BgSaveResponse, BgSaveResponseError},
+        command_response::{CommandResponse, Reply},
+        delete_response::{DeleteResponse, DeleteResponseError},
+        exists_response::ExistsResponse,
+        expire_response::{ExpireResponse, ExpireResponseError},
         get_client_shard_info_response::GetClientShardInfoResponse,
         get_shared_peers_response::GetSharedPeersResponse,
         get_version_response::{GetVersionResponse, GetVersionResponseError},
         query_version_response::QueryVersionResponse,
         read_response::ReadResponse,
-        write_response::WriteResponse,
+        replica_ack_response::ReplicaAckResponse,
+        sync_response::{SyncResponse, SyncResponseError},
+        transaction_response::TransactionResponse,
+        ttl_response::TtlResponse,
+        two_phase_response::{TwoPhaseOutcome, TwoPhaseResponse},
+        write_response::{WriteResponse, WriteResponseError},
     },
 };
+use crate::storage::aof::{AofEntry, AppendOnlyFile, FsyncPolicy};
+use crate::storage::engine::{open_engine, EngineKind};
+use crate::storage::history::VersionHistory;
+use crate::storage::intents::{Intents, PreparedTransaction};
+use crate::storage::keyspace::now_millis;
+use crate::storage::memory::{mutation_usage, parse_bytes, EvictionPolicy, Memory, ENTRY_OVERHEAD};
+use crate::storage::mutation::Mutation;
+use crate::storage::replicas::Replicas;
+use crate::storage::snapshot::{Snapshot, Snapshotter};
+use crate::storage::store::{Store, Writer};
 mod io;
-use io::router::{RouterBuilder, RouterHandler};
+use io::router::{HandlerResponse, RouterBuilder, RouterHandler};
 
 static MAIN_INSTANCE_IP_PORT: SocketAddrV6 = SocketAddrV6::new(Ipv6Addr::LOCALHOST, 8080, 0, 0);
 
+/// How long a prepared cross-shard transaction waits for its coordinator
+/// before the shard goes looking for the outcome itself
+const IN_DOUBT_TIMEOUT_MS: u64 = 5_000;
+
+#[derive(Parser, Debug)]
+pub struct WriteShardArgs {
+    /// Path of the append-only file; persistence is disabled when unset.
+    /// Cross-shard transactions log their intents next to it.
+    #[arg(long)]
+    aof_path: Option<PathBuf>,
+
+    /// How often the append-only file is fsynced
+    #[arg(long, value_enum, default_value_t = FsyncPolicy::Everysec)]
+    appendfsync: FsyncPolicy,
+
+    /// Path of the snapshot file; snapshots are disabled when unset
+    #[arg(long)]
+    snapshot_path: Option<PathBuf>,
+
+    /// Seconds between automatic snapshots
+    #[arg(long, default_value_t = 300)]
+    snapshot_interval: u64,
+
+    /// Number of read shards that must apply a write before it is
+    /// acknowledged; writes are acknowledged right away when 0
+    #[arg(long, default_value_t = 0)]
+    write_quorum: usize,
+
+    /// Milliseconds a write waits for its quorum before it is acknowledged
+    /// with a partial-ack error
+    #[arg(long, default_value_t = 1_000)]
+    quorum_timeout_ms: u64,
+
+    /// Memory limit for the keys and the version history, such as 100mb;
+    /// unlimited when 0
+    #[arg(long, default_value = "0", value_parser = parse_bytes)]
+    maxmemory: u64,
+
+    /// What the shard does once it reaches its memory limit
+    #[arg(long, value_enum, default_value_t = EvictionPolicy::NoEviction)]
+    maxmemory_policy: EvictionPolicy,
+
+    /// Engine the keys are kept in
+    #[arg(long, value_enum, default_value_t = EngineKind::Memory)]
+    storage_engine: EngineKind,
+
+    /// Path of the disk engine's data file
+    #[arg(long)]
+    data_path: Option<PathBuf>,
+
+    /// Number of versions kept for read shards to catch up from; a read
+    /// shard that falls further behind resyncs from a full snapshot
+    #[arg(long, default_value_t = 100_000)]
+    backlog_size: usize,
+}
+
 #[derive(Debug)]
 struct WriteShard {
-    data: Arc<Mutex<HashMap<String, String>>>,
-    version_history: Arc<Mutex<Vec<(String, String)>>>,
-    current_version: Arc<Mutex<u64>>,
+    /// Keys and version history; writes hold the keys they touch through its writers
+    store: Arc<Store>,
+    aof: Option<Arc<Mutex<AppendOnlyFile>>>,
+    snapshotter: Option<Snapshotter>,
+    /// Clients waiting in a blocking command for a value to arrive
+    blocked: Mutex<BlockedClients<oneshot::Sender<CommandResponse>>>,
+    /// Cross-shard transactions prepared here and the keys they hold
+    intents: Mutex<Intents>,
+    /// Number the info server gave the shard, echoed in write responses
+    writer_number: Mutex<u16>,
+    /// Versions the read shards have applied, and writes waiting on them
+    replicas: Mutex<Replicas<oneshot::Sender<WriteResponse>>>,
+    write_quorum: usize,
+    quorum_timeout_ms: u64,
+    /// Memory used by the keys and the version history, and how each key is used
+    memory: Arc<Mutex<Memory>>,
 }
 
 impl WriteShard {
+    #[allow(unused)]
     fn new() -> Self {
         WriteShard {
-            data: Arc::new(Mutex::new(HashMap::new())),
-            version_history: Arc::new(Mutex::new(Vec::new())),
-            current_version: Arc::new(Mutex::new(0)),
+            store: Arc::default(),
+            aof: None,
+            snapshotter: None,
+            blocked: Mutex::default(),
+            intents: Mutex::default(),
+            writer_number: Mutex::default(),
+            replicas: Mutex::default(),
+            write_quorum: 0,
+            quorum_timeout_ms: 1_000,
+            memory: Arc::default(),
         }
     }
-}
 
-impl RouterHandler for WriteShard {
-    fn handle_write_request(&self, req: &WriteRequest) -> WriteResponse {
-        // Extract key and value from the request
-        let key = String::from_utf8(req.key.clone()).unwrap();
-        let value = String::from_utf8(req.value.clone()).unwrap();
-
-        // Lock and increment the current version
-        let mut current_version = self.current_version.lock().unwrap();
-        *current_version += 1;
-
-        // Lock and update the data
-        let mut data = self.data.lock().unwrap();
-        data.insert(key.clone(), value.clone());
-
-        // Lock and update the version history
-        let mut version_history = self.version_history.lock().unwrap();
-        version_history.push((key.clone(), value.clone()));
+    /// Restores the shard from its latest snapshot and then replays the
+    /// append-only file on top of it, for whichever of the two are configured
+    fn open(args: &WriteShardArgs) -> Result<Self> {
+        let snapshot = match &args.snapshot_path {
+            Some(snapshot_path) => Snapshot::load(snapshot_path)?.unwrap_or_default(),
+            None => Snapshot::default(),
+        };
+        let engine = open_engine(args.storage_engine, args.data_path.as_deref())?;
+        let mut current_version = engine.durable_version();
+        // the engine lost more than the snapshot has, or holds nothing itself
+        if current_version < snapshot.version {
+            engine.restore(snapshot.keyspace, snapshot.version)?;
+            current_version = snapshot.version;
+        }
+        let mut version_history =
+            VersionHistory::with_capacity(current_version + 1, args.backlog_size);
+
+        let aof = match &args.aof_path {
+            Some(aof_path) => {
+                let (aof, entries) = AppendOnlyFile::open(aof_path, args.appendfsync)?;
+                for entry in entries {
+                    // already held by the engine or the snapshot
+                    if entry.version <= current_version {
+                        continue;
+                    }
+                    if entry.version != current_version + 1 {
+                        anyhow::bail!(
+                            "aof entry has version {} but expected {}",
+                            entry.version,
+                            current_version + 1
+                        );
+                    }
+                    current_version = entry.version;
+                    engine.apply(&entry.mutation, entry.version)?;
+                    version_history.push(entry.mutation);
+                }
+                Some(Arc::new(Mutex::new(aof)))
+            }
+            None => None,
+        };
+        let (intents, redo) = match &args.aof_path {
+            Some(aof_path) => Intents::open(&aof_path.with_extension("intents"))?,
+            None => (Intents::default(), Vec::new()),
+        };
+
+        let mut memory = Memory::new(args.maxmemory, args.maxmemory_policy);
+        let now = now_millis();
+        for item in engine.snapshot()?.scan() {
+            let (key, entry) = item?;
+            let usage = ENTRY_OVERHEAD + key.len() + entry.value.memory_usage();
+            memory.resize(&key, Some(usage), now);
+        }
+        for (_, mutation) in version_history.iter() {
+            memory.history_grew(mutation_usage(mutation));
+        }
+
+        let write_shard = WriteShard {
+            store: Arc::new(Store::new(engine, version_history)),
+            aof,
+            snapshotter: args.snapshot_path.clone().map(Snapshotter::new),
+            blocked: Mutex::default(),
+            intents: Mutex::new(intents),
+            writer_number: Mutex::default(),
+            replicas: Mutex::default(),
+            write_quorum: args.write_quorum,
+            quorum_timeout_ms: args.quorum_timeout_ms,
+            memory: Arc::new(Mutex::new(memory)),
+        };
+
+        // transactions logged as committed whose batch never made it to the aof
+        let mut writer = write_shard.store.exclusive();
+        for (version, mutation) in redo {
+            if version <= writer.version() {
+                continue;
+            }
+            if version != writer.version() + 1 {
+                anyhow::bail!(
+                    "committed transaction has version {} but expected {}",
+                    version,
+                    writer.version() + 1
+                );
+            }
+            write_shard.commit(&mut writer, mutation)?;
+        }
+        println!("restored write shard at version {}", writer.version());
+        drop(writer);
+        write_shard.intents.lock().unwrap().compact()?;
+        Ok(write_shard)
+    }
+
+    /// Whether any writes have been applied since the last snapshot
+    fn has_unsaved_writes(&self) -> bool {
+        let version_history = self.store.history();
+        version_history.last_version() >= version_history.first_version()
+    }
+
+    /// Snapshots the keyspace on a background thread. Once the snapshot is on
+    /// disk, the version history and the aof are compacted up to its version.
+    fn bg_save(&self) -> BgSaveResponse {
+        let Some(snapshotter) = &self.snapshotter else {
+            return BgSaveResponse {
+                error: BgSaveResponseError::SnapshotsDisabled as u8,
+                version: 0,
+            };
+        };
+
+        // The snapshot shares the values of the keyspace instead of copying
+        // them, so writes are barely held off while it is taken or saved
+        let (version, source) = match self.store.snapshot() {
+            Ok(snapshot) => snapshot,
+            Err(e) => {
+                eprintln!("Failed to snapshot keyspace: {:?}", e);
+                return BgSaveResponse {
+                    error: BgSaveResponseError::SnapshotFailed as u8,
+                    version: 0,
+                };
+            }
+        };
+
+        let store = self.store.clone();
+        let aof = self.aof.clone();
+        let memory = self.memory.clone();
+        let started = snapshotter.save_in_background(version, source, move |version| {
+            // Writes are held off while the history is compacted and the aof
+            // rewrite begins, not while the new aof is written
+            let (mut rewrite, entries) = {
+                let mut writer = store.exclusive();
+                let mut version_history = writer.history_mut();
+                let compacted: usize = version_history
+                    .iter()
+                    .take_while(|&(v, _)| v <= version)
+                    .map(|(_, mutation)| mutation_usage(mutation))
+                    .sum();
+                version_history.truncate_through(version);
+                memory.lock().unwrap().history_shrank(compacted);
+                // the aof still holds versions the backlog dropped since the
+                // snapshot was taken, so it is kept until a snapshot has them
+                if version_history.first_version() > version + 1 {
+                    return;
+                }
+                let Some(aof) = &aof else {
+                    return;
+                };
+                let entries: Vec<AofEntry> = version_history
+                    .iter()
+                    .map(|(version, mutation)| AofEntry {
+                        version,
+                        mutation: mutation.clone(),
+                    })
+                    .collect();
+                match aof.lock().unwrap().begin_rewrite() {
+                    Ok(rewrite) => (rewrite, entries),
+                    Err(e) => {
+                        eprintln!("Failed to rewrite aof: {:?}", e);
+                        return;
+                    }
+                }
+            };
+            if let Err(e) = rewrite.write(&entries) {
+                eprintln!("Failed to rewrite aof: {:?}", e);
+                return;
+            }
+            // appends wait on the aof only while what they wrote meanwhile is copied over
+            if let Some(aof) = aof {
+                if let Err(e) = aof.lock().unwrap().finish_rewrite(rewrite) {
+                    eprintln!("Failed to rewrite aof: {:?}", e);
+                }
+            }
+        });
+
+        BgSaveResponse {
+            error: if started {
+                BgSaveResponseError::NoError as u8
+            } else {
+                BgSaveResponseError::AlreadyInProgress as u8
+            },
+            version,
+        }
+    }
+
+    /// Logs, applies and records a mutation as the next version. The caller
+    /// holds the mutation's keys, and the store hands out versions in the
+    /// order they are logged.
+    fn commit(&self, writer: &mut Writer, mutation: Mutation) -> Result<u64> {
+        self.commit_logged(writer, mutation, |_| Ok(()))
+    }
+
+    /// Like [`WriteShard::commit`], but calls `log` with the version before
+    /// the mutation is appended to the aof
+    fn commit_logged(
+        &self,
+        writer: &mut Writer,
+        mutation: Mutation,
+        log: impl FnOnce(u64) -> Result<()>,
+    ) -> Result<u64> {
+        let keys: Vec<Vec<u8>> = mutation.keys().into_iter().map(<[u8]>::to_vec).collect();
+        let usage = mutation_usage(&mutation);
+        let mut dropped = None;
+        let version = writer.commit_logged(mutation, |entry| {
+            log(entry.version)?;
+            if let Some(aof) = &self.aof {
+                aof.lock().unwrap().append(entry)?;
+            }
+            dropped = self.store.history().next_dropped().map(mutation_usage);
+            Ok(())
+        })?;
+
+        // the keys are let go of before the memory is locked, which may wait
+        // on a thread looking for a victim in the engine
+        let sizes: Vec<Option<usize>> = {
+            let data = self.store.view(keys.iter().map(Vec::as_slice))?;
+            keys.iter().map(|key| data.memory_usage(key)).collect()
+        };
+        let mut memory = self.memory.lock().unwrap();
+        let now = now_millis();
+        for (key, size) in keys.iter().zip(sizes) {
+            memory.resize(key, size, now);
+        }
+        memory.history_grew(usage);
+        if let Some(dropped) = dropped {
+            memory.history_shrank(dropped);
+        }
+        Ok(version)
+    }
+
+    /// Evicts keys under the shard's policy until it is back under its
+    /// memory limit. Evictions are committed as deletes, so read shards drop
+    /// the keys as well. Keys another writer holds are passed over. Returns
+    /// false if the shard is still over the limit, because its policy is
+    /// noeviction or nothing is left it may evict.
+    fn make_room(&self, writer: &mut Writer) -> bool {
+        let mut busy: HashSet<Vec<u8>> = HashSet::new();
+        loop {
+            let victim = {
+                let intents = self.intents.lock().unwrap();
+                let memory = self.memory.lock().unwrap();
+                if !memory.is_over_limit() {
+                    return true;
+                }
+                // keys held by a prepared transaction have to stay until it is decided
+                memory.victim(self.store.engine(), now_millis(), |key| {
+                    intents.holder(key).is_none() && !busy.contains(key)
+                })
+            };
+            let Some(key) = victim else {
+                return false;
+            };
+            // a transaction may have been prepared on it before it was locked
+            if !writer.try_lock(&key) || self.is_locked(std::iter::once(key.as_slice())) {
+                busy.insert(key);
+                continue;
+            }
+            match self.commit(writer, Mutation::Delete { key: key.clone() }) {
+                Ok(version) => println!(
+                    "evicted key: {}, version: {}",
+                    String::from_utf8_lossy(&key),
+                    version
+                ),
+                Err(e) => {
+                    eprintln!("Failed to append eviction to aof: {:?}", e);
+                    return false;
+                }
+            }
+        }
+    }
+
+    /// Hands values that just arrived on `keys` to the clients blocked on
+    /// them, the one that has waited longest first. Each client's command
+    /// runs again under a writer of its own keys, and its pop is committed
+    /// like any other write. The caller no longer holds any keys.
+    fn serve_blocked(&self, keys: &[Vec<u8>], now: u64) {
+        for key in keys {
+            loop {
+                let (id, args) = {
+                    let mut blocked = self.blocked.lock().unwrap();
+                    let Some((id, waiter)) = blocked.first(key) else {
+                        break;
+                    };
+                    // the router drops the receiver when the client can no longer be answered
+                    if waiter.client.is_closed() {
+                        blocked.unblock(id);
+                        continue;
+                    }
+                    (id, waiter.args.clone())
+                };
+                let keys = commands::command_keys(&args);
+                let mut writer = self.store.writer(keys.iter().map(Vec::as_slice));
+                let outcome = match self.store.view(keys.iter().map(Vec::as_slice)) {
+                    Ok(data) => commands::execute_write(&data, &args, now),
+                    Err(e) => {
+                        eprintln!("Failed to load keys: {:?}", e);
+                        return;
+                    }
+                };
+                // the key ran out of values, or no longer holds a list
+                if !matches!(outcome.reply, Reply::Array(_)) {
+                    break;
+                }
+                // another thread served the client, or it timed out, while the keys were locked
+                let Some(waiter) = self.blocked.lock().unwrap().unblock(id) else {
+                    continue;
+                };
+                for mutation in outcome.mutations {
+                    if let Err(e) = self.commit(&mut writer, mutation) {
+                        eprintln!("Failed to append blocked command to aof: {:?}", e);
+                        let _ = waiter.client.send(CommandResponse {
+                            reply: Reply::error("ERR failed to persist the write"),
+                        });
+                        return;
+                    }
+                }
+                let _ = waiter.client.send(CommandResponse {
+                    reply: outcome.reply,
+                });
+            }
+        }
+    }
+
+    /// Answers the blocked clients whose timeout has passed with a nil reply
+    fn time_out_blocked(&self, now: u64) -> usize {
+        let timed_out = self.blocked.lock().unwrap().time_out(now);
+        let count = timed_out.len();
+        for waiter in timed_out {
+            let _ = waiter.client.send(CommandResponse { reply: Reply::Nil });
+        }
+        count
+    }
+
+    /// Commits a write, acknowledging it without waiting for the read shards
+    fn write(&self, req: &WriteRequest) -> WriteResponse {
+        // Keys and values are stored as raw bytes, exactly as they came in
+        let key = req.key.clone();
+        let value = req.value.clone();
+
+        let (Ok(expiry_type), Ok(condition)) = (
+            ExpiryType::try_from(req.expiry_type),
+            WriteCondition::try_from(req.condition),
+        ) else {
+            return self.write_response(WriteResponseError::Error, 0);
+        };
+
+        // Lock the key and log the write before applying it. Holding the
+        // lock keeps the key from changing between the check and the write.
+        let mut writer = self.store.writer([key.as_slice()]);
+        if self.is_locked(std::iter::once(key.as_slice())) {
+            return self.write_response(WriteResponseError::Locked, 0);
+        }
+        if !self.make_room(&mut writer) {
+            return self.write_response(WriteResponseError::OutOfMemory, 0);
+        }
+        let now = now_millis();
+        let key_version = match self.store.view([key.as_slice()]) {
+            Ok(data) => data.version(&key, now),
+            Err(e) => {
+                eprintln!("Failed to load key: {:?}", e);
+                return self.write_response(WriteResponseError::Error, 0);
+            }
+        };
+        if !condition.holds(key_version, req.expected_version) {
+            return self.write_response(WriteResponseError::PreconditionFailed, key_version);
+        }
+
+        let mutation = match expiry_type.expires_at(req.expiry, now) {
+            Some(expires_at) => Mutation::SetWithExpiry {
+                key: key.clone(),
+                value: value.clone(),
+                expires_at,
+            },
+            None => Mutation::Set {
+                key: key.clone(),
+                value: value.clone(),
+            },
+        };
+        let version = match self.commit(&mut writer, mutation) {
+            Ok(version) => version,
+            Err(e) => {
+                eprintln!("Failed to append write to aof: {:?}", e);
+                return self.write_response(WriteResponseError::Error, 0);
+            }
+        };
         println!(
             "wrote key: {}, value: {}, version: {}",
-            key, value, *current_version
+            String::from_utf8_lossy(&key),
+            String::from_utf8_lossy(&value),
+            version
+        );
+        self.write_response(WriteResponseError::NoError, version)
+    }
+
+    /// Acknowledges the writes enough read shards have applied, and the ones
+    /// that ran out of time with a partial-ack error. Either way the write
+    /// stays committed.
+    fn settle_quorum(&self, now: u64) {
+        let (confirmed, timed_out) = self.replicas.lock().unwrap().settle(self.write_quorum, now);
+        for (waiters, error) in [
+            (confirmed, WriteResponseError::NoError),
+            (timed_out, WriteResponseError::QuorumTimeout),
+        ] {
+            for waiter in waiters {
+                let _ = waiter
+                    .client
+                    .send(self.write_response(error, waiter.version));
+            }
+        }
+    }
+
+    /// Response to a write, carrying the shard's writer number so the client
+    /// can tie the version to this shard
+    fn write_response(&self, error: WriteResponseError, version: u64) -> WriteResponse {
+        WriteResponse {
+            error: error as u8,
+            version,
+            writer_number: *self.writer_number.lock().unwrap(),
+        }
+    }
+
+    /// Whether any of `keys` is held by a prepared cross-shard transaction,
+    /// which no other write may touch until the transaction is decided
+    fn is_locked<'a>(&self, mut keys: impl Iterator<Item = &'a [u8]>) -> bool {
+        let intents = self.intents.lock().unwrap();
+        keys.any(|key| intents.holder(key).is_some())
+    }
+
+    /// Checks this shard's part of a cross-shard transaction and votes on it.
+    /// A yes vote is logged and locks the keys, and the writes worked out now
+    /// are the ones committed later, since nothing can change the keys in between.
+    fn prepare(&self, req: &TwoPhaseRequest) -> TwoPhaseResponse {
+        let respond = |outcome: TwoPhaseOutcome, reply: Reply| TwoPhaseResponse {
+            txn_id: req.txn_id,
+            outcome: outcome as u8,
+            reply,
+        };
+        let transaction = &req.transaction;
+        let mut keys: Vec<Vec<u8>> = transaction
+            .commands
+            .iter()
+            .flat_map(|args| commands::command_keys(args))
+            .chain(transaction.watches.iter().map(|(key, _)| key))
+            .cloned()
+            .collect();
+        keys.sort();
+        keys.dedup();
+
+        let mut writer = self.store.writer(keys.iter().map(Vec::as_slice));
+        let has_room = self.make_room(&mut writer);
+        let now = now_millis();
+        let mut intents = self.intents.lock().unwrap();
+        if intents.get(req.txn_id).is_some() {
+            return respond(TwoPhaseOutcome::Prepared, Reply::Nil);
+        }
+        // the primary presumed it aborted after a shard asked about it
+        match intents.outcome(req.txn_id) {
+            Some(true) => return respond(TwoPhaseOutcome::Committed, Reply::Nil),
+            Some(false) => return respond(TwoPhaseOutcome::Aborted, Reply::Nil),
+            None => {}
+        }
+
+        if keys.iter().any(|key| intents.holder(key).is_some()) {
+            return respond(TwoPhaseOutcome::Aborted, Reply::locked());
+        }
+
+        let outcome = {
+            let data = match self.store.view(keys.iter().map(Vec::as_slice)) {
+                Ok(data) => data,
+                Err(e) => {
+                    eprintln!("Failed to load keys: {:?}", e);
+                    return respond(
+                        TwoPhaseOutcome::Aborted,
+                        Reply::error("ERR failed to load keys"),
+                    );
+                }
+            };
+            if transaction
+                .watches
+                .iter()
+                .any(|(key, version)| data.version(key, now) != *version)
+            {
+                return respond(TwoPhaseOutcome::Aborted, Reply::Nil);
+            }
+            commands::execute_transaction(&data, &transaction.commands, now)
+        };
+        if let Reply::Error(_) = outcome.reply {
+            return respond(TwoPhaseOutcome::Aborted, outcome.reply);
+        }
+        if !Memory::admits(has_room, &outcome.mutations) {
+            return respond(TwoPhaseOutcome::Aborted, Reply::out_of_memory());
+        }
+
+        let prepared = PreparedTransaction {
+            primary: (!req.is_primary())
+                .then(|| SocketAddrV6::new(Ipv6Addr::from(req.primary_ip), req.primary_port, 0, 0)),
+            prepared_at: now,
+            keys,
+            mutation: Mutation::Batch {
+                mutations: outcome.mutations,
+            },
+            reply: outcome.reply,
+        };
+        if let Err(e) = intents.prepare(req.txn_id, prepared) {
+            eprintln!("Failed to log prepared transaction: {:?}", e);
+            return respond(
+                TwoPhaseOutcome::Aborted,
+                Reply::error("ERR failed to persist the write"),
+            );
+        }
+        respond(TwoPhaseOutcome::Prepared, Reply::Nil)
+    }
+
+    /// Commits a prepared transaction as a single version and releases its keys
+    fn commit_prepared(&self, txn_id: u128, is_primary: bool) -> TwoPhaseResponse {
+        let respond = |outcome: TwoPhaseOutcome, reply: Reply| TwoPhaseResponse {
+            txn_id,
+            outcome: outcome as u8,
+            reply,
+        };
+        // the keys are locked before the intents, so they are looked up first
+        // and checked again once both are held
+        let (mut writer, mut intents) = loop {
+            let keys = match self.intents.lock().unwrap().get(txn_id) {
+                Some(transaction) => transaction.keys.clone(),
+                None => Vec::new(),
+            };
+            let writer = self.store.writer(keys.iter().map(Vec::as_slice));
+            let intents = self.intents.lock().unwrap();
+            // they only change if the transaction was prepared in between
+            if intents.get(txn_id).is_none_or(|t| t.keys == keys) {
+                break (writer, intents);
+            }
+        };
+        let Some(transaction) = intents.get(txn_id) else {
+            return match intents.outcome(txn_id) {
+                Some(true) => respond(TwoPhaseOutcome::Committed, Reply::Nil),
+                Some(false) => respond(TwoPhaseOutcome::Aborted, Reply::Nil),
+                // the primary's commit decides the outcome, so one it has no record of is aborted
+                None if is_primary => match intents.abort(txn_id) {
+                    Ok(_) => respond(TwoPhaseOutcome::Aborted, Reply::Nil),
+                    Err(e) => {
+                        eprintln!("Failed to log aborted transaction: {:?}", e);
+                        respond(
+                            TwoPhaseOutcome::Prepared,
+                            Reply::error("ERR failed to persist the outcome"),
+                        )
+                    }
+                },
+                // the vote was lost, or decided so long ago the outcome was
+                // forgotten, so whether the writes were applied can't be told
+                None => respond(
+                    TwoPhaseOutcome::Unknown,
+                    Reply::error("ERR no record of the transaction, its writes may be missing"),
+                ),
+            };
+        };
+
+        let mutation = transaction.mutation.clone();
+        let keys: Vec<Vec<u8>> = mutation.keys().into_iter().map(<[u8]>::to_vec).collect();
+        // the version is logged first, so a crash before the aof append redoes the commit
+        let mut committed = None;
+        let logged = if keys.is_empty() {
+            intents
+                .commit(txn_id, 0)
+                .map(|transaction| committed = transaction)
+        } else {
+            self.commit_logged(&mut writer, mutation, |version| {
+                committed = intents.commit(txn_id, version)?;
+                Ok(())
+            })
+            .map(|_| ())
+        };
+        let Some(transaction) = committed else {
+            eprintln!(
+                "Failed to log committed transaction: {:?}",
+                logged.unwrap_err()
+            );
+            return respond(
+                TwoPhaseOutcome::Prepared,
+                Reply::error("ERR failed to persist the outcome"),
+            );
+        };
+        if let Err(e) = logged {
+            eprintln!("Failed to append transaction to aof: {:?}", e);
+            return respond(
+                TwoPhaseOutcome::Committed,
+                Reply::error("ERR failed to persist the write"),
+            );
+        }
+        if let Err(e) = intents.compact() {
+            eprintln!("Failed to compact intent log: {:?}", e);
+        }
+        drop(intents);
+        drop(writer);
+        println!("committed transaction {}", txn_id);
+
+        self.serve_blocked(&keys, now_millis());
+        respond(TwoPhaseOutcome::Committed, transaction.reply)
+    }
+
+    /// Aborts a transaction and releases its keys, unless it already committed
+    fn abort_prepared(&self, txn_id: u128) -> TwoPhaseResponse {
+        let respond = |outcome: TwoPhaseOutcome, reply: Reply| TwoPhaseResponse {
+            txn_id,
+            outcome: outcome as u8,
+            reply,
+        };
+        let mut intents = self.intents.lock().unwrap();
+        if intents.outcome(txn_id) == Some(true) {
+            return respond(TwoPhaseOutcome::Committed, Reply::Nil);
+        }
+        if let Err(e) = intents.abort(txn_id) {
+            eprintln!("Failed to log aborted transaction: {:?}", e);
+            return respond(
+                TwoPhaseOutcome::Prepared,
+                Reply::error("ERR failed to persist the outcome"),
+            );
+        }
+        if let Err(e) = intents.compact() {
+            eprintln!("Failed to compact intent log: {:?}", e);
+        }
+        respond(TwoPhaseOutcome::Aborted, Reply::Nil)
+    }
+
+    /// Tells a shard in doubt how a transaction this shard is the primary of
+    /// ended. One it has no record of is presumed aborted, and stays aborted
+    /// should its prepare still turn up.
+    fn transaction_status(&self, txn_id: u128) -> TwoPhaseResponse {
+        let outcome = {
+            let intents = self.intents.lock().unwrap();
+            if intents.get(txn_id).is_some() {
+                Some(TwoPhaseOutcome::Prepared)
+            } else {
+                intents.outcome(txn_id).map(|committed| match committed {
+                    true => TwoPhaseOutcome::Committed,
+                    false => TwoPhaseOutcome::Aborted,
+                })
+            }
+        };
+        match outcome {
+            Some(outcome) => TwoPhaseResponse {
+                txn_id,
+                outcome: outcome as u8,
+                reply: Reply::Nil,
+            },
+            None => self.abort_prepared(txn_id),
+        }
+    }
+
+    /// Aborts the transactions this shard is the primary of that have waited
+    /// too long for their coordinator, and returns the others along with the
+    /// primary that can tell how they ended
+    fn resolve_in_doubt(&self, now: u64) -> Vec<(u128, SocketAddrV6)> {
+        let in_doubt = self
+            .intents
+            .lock()
+            .unwrap()
+            .in_doubt(now.saturating_sub(IN_DOUBT_TIMEOUT_MS));
+        let mut unresolved = Vec::new();
+        for (txn_id, primary) in in_doubt {
+            match primary {
+                Some(primary) => unresolved.push((txn_id, primary)),
+                None => {
+                    self.abort_prepared(txn_id);
+                    println!("aborted in-doubt transaction {}", txn_id);
+                }
+            }
+        }
+        unresolved
+    }
+
+    /// Deletes every key whose expiry has passed. The deletes go through the
+    /// version history like any other, so read shards drop the keys as well.
+    /// Keys held by a prepared transaction wait until it is decided.
+    fn expire_keys(&self) -> usize {
+        let now = now_millis();
+        let expired_keys = self.store.engine().expired_keys(now);
+        let mut expired = 0;
+        for key in expired_keys {
+            let mut writer = self.store.writer([key.as_slice()]);
+            if self.is_locked(std::iter::once(key.as_slice())) {
+                continue;
+            }
+            // a write may have brought the key back before it was locked
+            match self.store.view([key.as_slice()]) {
+                Ok(data) if data.is_expired(&key, now) => {}
+                Ok(_) => continue,
+                Err(e) => {
+                    eprintln!("Failed to load key: {:?}", e);
+                    continue;
+                }
+            }
+            let mutation = Mutation::Delete { key: key.clone() };
+            match self.commit(&mut writer, mutation) {
+                Ok(version) => {
+                    expired += 1;
+                    println!(
+                        "expired key: {}, version: {}",
+                        String::from_utf8_lossy(&key),
+                        version
+                    );
+                }
+                Err(e) => {
+                    eprintln!("Failed to append expiry to aof: {:?}", e);
+                    break;
+                }
+            }
+        }
+        expired
+    }
+}
+
+impl RouterHandler for WriteShard {
+    fn handle_write_request(&self, req: &WriteRequest) -> HandlerResponse<WriteResponse> {
+        let res = self.write(req);
+        if self.write_quorum == 0 || res.error != WriteResponseError::NoError as u8 {
+            return HandlerResponse::Ready(res);
+        }
+        let (sender, receiver) = oneshot::channel();
+        self.replicas.lock().unwrap().wait(
+            res.version,
+            now_millis() + self.quorum_timeout_ms,
+            sender,
         );
-        // Create a successful response
-        WriteResponse { error: 0 }
+        HandlerResponse::Deferred(receiver)
     }
 
     fn handle_get_version_request(&self, req: &GetVersionRequest) -> GetVersionResponse {
-        // Lock the version history to find the requested version
-        let version_history = self.version_history.lock().unwrap();
+        let version_history = self.store.history();
 
-        if let Some((key, value)) = version_history.get(req.version as usize - 1) {
+        if let Some(mutation) = version_history.get(req.version) {
             // Create a successful response
             let response = GetVersionResponse {
                 error: GetVersionResponseError::NoError as u8,
-                key: key.clone().into_bytes(),
-                value: value.clone().into_bytes(),
                 version: req.version,
+                mutation_type: mutation.mutation_type() as u8,
+                key: mutation.key().to_vec(),
+                value: mutation.value(),
             };
             return response;
         }
 
         // If the version is not found, return an error response
+        let error = if version_history.is_compacted(req.version) {
+            GetVersionResponseError::FullResyncRequired
+        } else {
+            GetVersionResponseError::KeyNotFound
+        };
         GetVersionResponse {
-            error: GetVersionResponseError::KeyNotFound as u8,
+            error: error as u8,
+            mutation_type: 0,
             key: Vec::new(),   // No key in the error case
             value: Vec::new(), // No value in the error case
             version: req.version,
@@ -98,19 +917,65 @@ impl RouterHandler for WriteShard {
     }
 
     fn handle_query_version_request(&self, _req: &QueryVersionRequest) -> QueryVersionResponse {
-        // Lock the current version to read its value
-        let current_version = self.current_version.lock().unwrap();
+        // The version is published without the writer, so queries never wait on writes
+        let version = self.store.version();
+
+        println!("sent version: {}", version);
 
-        println!("sent version: {}", *current_version);
+        QueryVersionResponse { version }
+    }
+
+    fn handle_bg_save_request(&self, _req: &BgSaveRequest) -> BgSaveResponse {
+        self.bg_save()
+    }
 
-        if *current_version > 0 {
-            // Create the response with the latest version
-            QueryVersionResponse {
-                version: *current_version,
+    fn handle_delete_request(&self, req: &DeleteRequest) -> DeleteResponse {
+        // Only keys that exist leave a tombstone, so readers never replay no-op deletes
+        let mut writer = self.store.writer(req.keys.iter().map(Vec::as_slice));
+        if self.is_locked(req.keys.iter().map(Vec::as_slice)) {
+            return DeleteResponse {
+                error: DeleteResponseError::Locked as u8,
+                deleted: 0,
+            };
+        }
+        let mut deleted = 0;
+        for key in &req.keys {
+            // expired keys are already gone as far as clients can tell, the sweeper deletes them
+            let exists = match self.store.view([key.as_slice()]) {
+                Ok(data) => data.contains_key(key, now_millis()),
+                Err(e) => {
+                    eprintln!("Failed to load key: {:?}", e);
+                    return DeleteResponse {
+                        error: DeleteResponseError::Error as u8,
+                        deleted,
+                    };
+                }
+            };
+            if !exists {
+                continue;
+            }
+            let mutation = Mutation::Delete { key: key.clone() };
+            match self.commit(&mut writer, mutation) {
+                Ok(version) => {
+                    deleted += 1;
+                    println!(
+                        "deleted key: {}, version: {}",
+                        String::from_utf8_lossy(key),
+                        version
+                    );
+                }
+                Err(e) => {
+                    eprintln!("Failed to append delete to aof: {:?}", e);
+                    return DeleteResponse {
+                        error: DeleteResponseError::Error as u8,
+                        deleted,
+                    };
+                }
             }
-        } else {
-            // No data available
-            QueryVersionResponse { version: 0 }
+        }
+        DeleteResponse {
+            error: DeleteResponseError::NoError as u8,
+            deleted,
         }
     }
 
@@ -126,7 +991,22 @@ impl RouterHandler for WriteShard {
         unimplemented!()
     }
 
-    fn handle_read_request(&self, _req: &ReadRequest) -> ReadResponse {
+    fn handle_read_request(&self, req: &ReadRequest) -> HandlerResponse<ReadResponse> {
+        // reads land here when a read shard is behind the client's session,
+        // and the write shard is never behind its own versions
+        let now = now_millis();
+        self.memory.lock().unwrap().touch(&req.key, now);
+        let data = match self.store.view([req.key.as_slice()]) {
+            Ok(data) => data,
+            Err(e) => {
+                eprintln!("Failed to load key: {:?}", e);
+                return HandlerResponse::Ready(commands::read_failed(&req.key));
+            }
+        };
+        HandlerResponse::Ready(commands::read_key(&data, &req.key, now))
+    }
+
+    fn handle_exists_request(&self, _req: &ExistsRequest) -> ExistsResponse {
         unimplemented!()
     }
 
@@ -138,8 +1018,8 @@ impl RouterHandler for WriteShard {
     }
 
     /// Callbacks for handling responses to outbound requests
-    fn handle_announce_shard_response(&self, _res: &AnnounceShardResponse) {
-        // nothing to do
+    fn handle_announce_shard_response(&self, res: &AnnounceShardResponse) {
+        *self.writer_number.lock().unwrap() = res.writer_number;
     }
 
     fn handle_get_client_shard_info_response(&self, _res: &GetClientShardInfoResponse) {
@@ -158,21 +1038,419 @@ impl RouterHandler for WriteShard {
         unimplemented!()
     }
 
-    fn handle_get_shared_peers_response(&self, _res: &GetSharedPeersResponse) {
-        unimplemented!()
+    fn handle_get_shared_peers_response(&self, res: &GetSharedPeersResponse) {
+        // the info server lists the write shard first, then its read shards
+        let readers = res
+            .peer_ips
+            .iter()
+            .skip(1)
+            .map(|(ip, port)| SocketAddrV6::new(Ipv6Addr::from(*ip), *port, 0, 0))
+            .collect();
+        self.replicas.lock().unwrap().set_readers(readers);
+        self.settle_quorum(now_millis());
     }
 
     fn handle_get_version_response(&self, _res: &GetVersionResponse) {
         unimplemented!()
     }
+
+    fn handle_bg_save_response(&self, _res: &BgSaveResponse) {
+        unimplemented!()
+    }
+
+    fn handle_delete_response(&self, _res: &DeleteResponse) {
+        unimplemented!()
+    }
+
+    fn handle_exists_response(&self, _res: &ExistsResponse) {
+        unimplemented!()
+    }
+
+    fn handle_expire_request(&self, req: &ExpireRequest) -> ExpireResponse {
+        let Ok(expiry_type) = ExpiryType::try_from(req.expiry_type) else {
+            return ExpireResponse {
+                error: ExpireResponseError::Error as u8,
+                updated: false,
+            };
+        };
+
+        let mut writer = self.store.writer([req.key.as_slice()]);
+        if self.is_locked(std::iter::once(req.key.as_slice())) {
+            return ExpireResponse {
+                error: ExpireResponseError::Locked as u8,
+                updated: false,
+            };
+        }
+        let now = now_millis();
+        let mutation = {
+            let data = match self.store.view([req.key.as_slice()]) {
+                Ok(data) => data,
+                Err(e) => {
+                    eprintln!("Failed to load key: {:?}", e);
+                    return ExpireResponse {
+                        error: ExpireResponseError::Error as u8,
+                        updated: false,
+                    };
+                }
+            };
+            let key = req.key.clone();
+            if !data.contains_key(&key, now) {
+                None
+            } else if let Some(expires_at) = expiry_type.expires_at(req.expiry, now) {
+                Some(Mutation::Expire { key, expires_at })
+            } else if data.expires_at(&key).is_some() {
+                Some(Mutation::Persist { key })
+            } else {
+                None
+            }
+        };
+        let Some(mutation) = mutation else {
+            return ExpireResponse {
+                error: ExpireResponseError::NoError as u8,
+                updated: false,
+            };
+        };
+
+        match self.commit(&mut writer, mutation) {
+            Ok(_) => ExpireResponse {
+                error: ExpireResponseError::NoError as u8,
+                updated: true,
+            },
+            Err(e) => {
+                eprintln!("Failed to append expire to aof: {:?}", e);
+                ExpireResponse {
+                    error: ExpireResponseError::Error as u8,
+                    updated: false,
+                }
+            }
+        }
+    }
+
+    fn handle_expire_response(&self, _res: &ExpireResponse) {
+        unimplemented!()
+    }
+
+    fn handle_ttl_request(&self, _req: &TtlRequest) -> TtlResponse {
+        unimplemented!()
+    }
+
+    fn handle_ttl_response(&self, _res: &TtlResponse) {
+        unimplemented!()
+    }
+
+    fn handle_command_request(&self, req: &CommandRequest) -> HandlerResponse<CommandResponse> {
+        // the key locks keep other writes out between reading the current value and committing
+        let keys = commands::command_keys(&req.args);
+        let mut writer = self.store.writer(keys.iter().map(Vec::as_slice));
+        if self.is_locked(keys.iter().map(Vec::as_slice)) {
+            return HandlerResponse::Ready(CommandResponse {
+                reply: Reply::locked(),
+            });
+        }
+        // like redis, keys are evicted before the command runs, and only
+        // commands that could grow the shard are refused when that falls short
+        let has_room = self.make_room(&mut writer);
+        let now = now_millis();
+        {
+            let mut memory = self.memory.lock().unwrap();
+            for key in keys {
+                memory.touch(key, now);
+            }
+        }
+        let outcome = match self.store.view(keys.iter().map(Vec::as_slice)) {
+            Ok(data) => commands::execute_write(&data, &req.args, now),
+            Err(e) => {
+                eprintln!("Failed to load keys: {:?}", e);
+                return HandlerResponse::Ready(CommandResponse {
+                    reply: Reply::error("ERR failed to load keys"),
+                });
+            }
+        };
+
+        // a blocking command that found nothing waits for a write to one of its keys
+        if outcome.reply == Reply::Nil {
+            if let Some(timeout) = commands::block_timeout(&req.args) {
+                let (sender, receiver) = oneshot::channel();
+                let deadline = (timeout > 0).then_some(now + timeout);
+                self.blocked
+                    .lock()
+                    .unwrap()
+                    .block(req.args.clone(), deadline, sender);
+                return HandlerResponse::Deferred(receiver);
+            }
+        }
+
+        if !Memory::admits(has_room, &outcome.mutations) {
+            return HandlerResponse::Ready(CommandResponse {
+                reply: Reply::out_of_memory(),
+            });
+        }
+
+        let keys: Vec<Vec<u8>> = outcome
+            .mutations
+            .iter()
+            .flat_map(Mutation::keys)
+            .map(<[u8]>::to_vec)
+            .collect();
+        for mutation in outcome.mutations {
+            if let Err(e) = self.commit(&mut writer, mutation) {
+                eprintln!("Failed to append command to aof: {:?}", e);
+                return HandlerResponse::Ready(CommandResponse {
+                    reply: Reply::error("ERR failed to persist the write"),
+                });
+            }
+        }
+        drop(writer);
+        self.serve_blocked(&keys, now);
+        HandlerResponse::Ready(CommandResponse {
+            reply: outcome.reply,
+        })
+    }
+
+    fn handle_command_response(&self, _res: &CommandResponse) {
+        unimplemented!()
+    }
+
+    fn handle_transaction_request(&self, req: &TransactionRequest) -> TransactionResponse {
+        let keys: Vec<&[u8]> = req
+            .commands
+            .iter()
+            .flat_map(|args| commands::command_keys(args))
+            .chain(req.watches.iter().map(|(key, _)| key))
+            .map(Vec::as_slice)
+            .collect();
+        let mut writer = self.store.writer(keys.iter().copied());
+        if self.is_locked(keys.iter().copied()) {
+            return TransactionResponse {
+                reply: Reply::locked(),
+            };
+        }
+        let has_room = self.make_room(&mut writer);
+        let now = now_millis();
+        let outcome = {
+            let data = match self.store.view(keys) {
+                Ok(data) => data,
+                Err(e) => {
+                    eprintln!("Failed to load keys: {:?}", e);
+                    return TransactionResponse {
+                        reply: Reply::error("ERR failed to load keys"),
+                    };
+                }
+            };
+            // a watched key that changed since the client read it aborts the transaction
+            if req
+                .watches
+                .iter()
+                .any(|(key, version)| data.version(key, now) != *version)
+            {
+                return TransactionResponse { reply: Reply::Nil };
+            }
+            commands::execute_transaction(&data, &req.commands, now)
+        };
+
+        // the whole transaction is a single version
+        let mutation = Mutation::Batch {
+            mutations: outcome.mutations,
+        };
+        if !Memory::admits(has_room, [&mutation]) {
+            return TransactionResponse {
+                reply: Reply::out_of_memory(),
+            };
+        }
+        let keys: Vec<Vec<u8>> = mutation.keys().into_iter().map(<[u8]>::to_vec).collect();
+        if !keys.is_empty() {
+            if let Err(e) = self.commit(&mut writer, mutation) {
+                eprintln!("Failed to append transaction to aof: {:?}", e);
+                return TransactionResponse {
+                    reply: Reply::error("ERR failed to persist the write"),
+                };
+            }
+        }
+        drop(writer);
+        self.serve_blocked(&keys, now);
+        TransactionResponse {
+            reply: outcome.reply,
+        }
+    }
+
+    fn handle_transaction_response(&self, _res: &TransactionResponse) {
+        unimplemented!()
+    }
+
+    fn handle_two_phase_request(&self, req: &TwoPhaseRequest) -> TwoPhaseResponse {
+        match Phase::try_from(req.phase) {
+            Ok(Phase::Prepare) => self.prepare(req),
+            Ok(Phase::Commit) => self.commit_prepared(req.txn_id, req.is_primary()),
+            Ok(Phase::Abort) => self.abort_prepared(req.txn_id),
+            Ok(Phase::Status) => self.transaction_status(req.txn_id),
+            Err(_) => TwoPhaseResponse {
+                txn_id: req.txn_id,
+                outcome: TwoPhaseOutcome::Aborted as u8,
+                reply: Reply::error("ERR unknown two-phase commit phase"),
+            },
+        }
+    }
+
+    /// The primary's answer about a transaction this shard was left in doubt on
+    fn handle_two_phase_response(&self, res: &TwoPhaseResponse) {
+        match TwoPhaseOutcome::try_from(res.outcome) {
+            Ok(TwoPhaseOutcome::Committed) => {
+                self.commit_prepared(res.txn_id, false);
+            }
+            Ok(TwoPhaseOutcome::Aborted) => {
+                self.abort_prepared(res.txn_id);
+                println!("aborted in-doubt transaction {}", res.txn_id);
+            }
+            // still undecided, ask again later
+            Ok(TwoPhaseOutcome::Prepared | TwoPhaseOutcome::Unknown) | Err(_) => {}
+        }
+    }
+
+    fn handle_replica_ack_request(&self, req: &ReplicaAckRequest) -> ReplicaAckResponse {
+        let reader = SocketAddrV6::new(Ipv6Addr::from(req.ip), req.port, 0, 0);
+        self.replicas
+            .lock()
+            .unwrap()
+            .acknowledge(reader, req.version);
+        self.settle_quorum(now_millis());
+        ReplicaAckResponse {}
+    }
+
+    fn handle_replica_ack_response(&self, _res: &ReplicaAckResponse) {
+        unimplemented!()
+    }
+
+    fn handle_sync_request(&self, req: &SyncRequest) -> SyncResponse {
+        let failed = |error: SyncResponseError| SyncResponse {
+            error: error as u8,
+            version: req.version,
+            offset: req.offset,
+            total: 0,
+            done: false,
+            entries: Vec::new(),
+        };
+        match self
+            .store
+            .snapshot_chunk(req.version, req.offset, now_millis())
+        {
+            Ok(Some(chunk)) => SyncResponse {
+                error: SyncResponseError::NoError as u8,
+                version: chunk.version,
+                offset: chunk.offset,
+                total: chunk.total,
+                done: chunk.done,
+                entries: chunk.entries,
+            },
+            Ok(None) => failed(SyncResponseError::SnapshotGone),
+            Err(e) => {
+                eprintln!("Failed to snapshot keyspace: {:?}", e);
+                failed(SyncResponseError::SnapshotFailed)
+            }
+        }
+    }
+
+    fn handle_sync_response(&self, _res: &SyncResponse) {
+        unimplemented!()
+    }
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let write_shard_router = WriteShard::new();
+    let args = WriteShardArgs::parse();
+    let write_shard_router = WriteShard::open(&args)?;
     let mut write_shard_server = RouterBuilder::new(write_shard_router, None);
+    let write_shard = write_shard_server.get_handler_arc();
     let writer_ip_port = write_shard_server.bind().await?;
 
+    if let (Some(aof), FsyncPolicy::Everysec) = (write_shard.aof.clone(), args.appendfsync) {
+        tokio::spawn(async move {
+            let mut interval = time::interval(time::Duration::from_secs(1));
+            loop {
+                interval.tick().await;
+                // fsync through a second handle so writes are not stuck behind the disk
+                let sync_handle = aof.lock().unwrap().sync_handle();
+                match sync_handle {
+                    Ok(file) => {
+                        if let Err(e) = tokio::fs::File::from_std(file).sync_data().await {
+                            eprintln!("Failed to fsync aof: {:?}", e);
+                        }
+                    }
+                    Err(e) => eprintln!("Failed to get aof handle: {:?}", e),
+                }
+            }
+        });
+    }
+
+    {
+        let write_shard = write_shard.clone();
+        tokio::spawn(async move {
+            let mut interval = time::interval(time::Duration::from_millis(100));
+            loop {
+                interval.tick().await;
+                write_shard.expire_keys();
+                write_shard.time_out_blocked(now_millis());
+                write_shard.settle_quorum(now_millis());
+            }
+        });
+    }
+
+    if args.snapshot_path.is_some() {
+        let write_shard = write_shard.clone();
+        tokio::spawn(async move {
+            let mut interval =
+                time::interval(time::Duration::from_secs(args.snapshot_interval.max(1)));
+            // the first tick completes immediately
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                if write_shard.has_unsaved_writes() {
+                    write_shard.bg_save();
+                }
+            }
+        });
+    }
+
+    {
+        let write_shard = write_shard.clone();
+        let router_client = write_shard_server.get_router_client();
+        tokio::spawn(async move {
+            let mut interval = time::interval(time::Duration::from_secs(1));
+            loop {
+                interval.tick().await;
+                for (txn_id, primary) in write_shard.resolve_in_doubt(now_millis()) {
+                    let request = TwoPhaseRequest::new(txn_id, Phase::Status, 0, 0);
+                    if let Err(e) = router_client
+                        .queue_request::<TwoPhaseRequest>(request, primary)
+                        .await
+                    {
+                        eprintln!("Failed to ask {} about transaction: {:?}", primary, e);
+                    }
+                }
+            }
+        });
+    }
+
+    // the read shards whose acknowledgements count towards the quorum
+    if args.write_quorum > 0 {
+        let write_shard = write_shard.clone();
+        let router_client = write_shard_server.get_router_client();
+        tokio::spawn(async move {
+            let mut interval = time::interval(time::Duration::from_secs(1));
+            loop {
+                interval.tick().await;
+                let request = GetSharedPeersRequest {
+                    writer_number: *write_shard.writer_number.lock().unwrap(),
+                };
+                if let Err(e) = router_client
+                    .queue_request::<GetSharedPeersRequest>(request, MAIN_INSTANCE_IP_PORT)
+                    .await
+                {
+                    eprintln!("Failed to send GetSharedPeersRequest: {:?}", e);
+                }
+            }
+        });
+    }
+
     let shard_id: u128 = rand::thread_rng().gen();
 
     let client1 = write_shard_server.get_router_client();
@@ -206,3 +1484,765 @@ async fn main() -> Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::engine::MemoryEngine;
+    use crate::storage::keyspace::Keyspace;
+    use crate::storage::mutation::MutationType;
+    use crate::storage::value::Value;
+
+    #[test]
+    fn test_handle_write_request_binary() {
+        let write_shard = WriteShard::new();
+
+        let key = vec![0xc3, 0x28];
+        let value: Vec<u8> = (0..=255).rev().collect();
+        let res = write_shard.write(&WriteRequest {
+            key: key.clone(),
+            value: value.clone(),
+            expiry_type: ExpiryType::None as u8,
+            expiry: 0,
+            condition: WriteCondition::Always as u8,
+            expected_version: 0,
+        });
+        assert_eq!(res.error, WriteResponseError::NoError as u8);
+
+        let res = write_shard.handle_get_version_request(&GetVersionRequest { version: 1 });
+        assert_eq!(res.error, GetVersionResponseError::NoError as u8);
+        assert_eq!(res.key, key);
+        assert_eq!(res.value, value);
+    }
+
+    #[test]
+    fn test_write_acknowledgements() {
+        let write_shard = WriteShard::new();
+        write_shard.handle_announce_shard_response(&AnnounceShardResponse { writer_number: 2 });
+
+        for version in 1..=2 {
+            let res = write_shard.write(&WriteRequest {
+                key: b"key".to_vec(),
+                value: version.to_string().into_bytes(),
+                expiry_type: ExpiryType::None as u8,
+                expiry: 0,
+                condition: WriteCondition::Always as u8,
+                expected_version: 0,
+            });
+            assert_eq!(res.error, WriteResponseError::NoError as u8);
+            assert_eq!(res.version, version);
+            assert_eq!(res.writer_number, 2);
+        }
+
+        // reads redirected from a stale read shard see every write
+        let HandlerResponse::Ready(res) = write_shard.handle_read_request(&ReadRequest {
+            key: b"key".to_vec(),
+            min_version: 2,
+        }) else {
+            panic!("the write shard never waits");
+        };
+        assert_eq!(res.value, b"2".to_vec());
+        assert_eq!(res.version, 2);
+    }
+
+    #[test]
+    fn test_write_quorum() {
+        let mut write_shard = WriteShard::new();
+        write_shard.write_quorum = 2;
+        write_shard.handle_get_shared_peers_response(&GetSharedPeersResponse {
+            peer_ips: vec![(1, 8081), (1, 8082), (1, 8083)],
+        });
+        let write = |value: &[u8]| match write_shard.handle_write_request(&WriteRequest {
+            key: b"key".to_vec(),
+            value: value.to_vec(),
+            expiry_type: ExpiryType::None as u8,
+            expiry: 0,
+            condition: WriteCondition::Always as u8,
+            expected_version: 0,
+        }) {
+            HandlerResponse::Deferred(receiver) => receiver,
+            HandlerResponse::Ready(_) => panic!("the write waits for its quorum"),
+        };
+        let ack = |port, version| {
+            write_shard.handle_replica_ack_request(&ReplicaAckRequest {
+                ip: 1,
+                port,
+                version,
+            })
+        };
+
+        let mut first = write(b"a");
+        ack(8082, 1);
+        assert!(first.try_recv().is_err());
+        // the write shard's own address is not one of its read shards
+        ack(8081, 1);
+        assert!(first.try_recv().is_err());
+        ack(8083, 1);
+        let res = first.try_recv().unwrap();
+        assert_eq!(res.error, WriteResponseError::NoError as u8);
+        assert_eq!(res.version, 1);
+
+        let mut second = write(b"b");
+        ack(8082, 2);
+        write_shard.settle_quorum(now_millis() + write_shard.quorum_timeout_ms);
+        let res = second.try_recv().unwrap();
+        assert_eq!(res.error, WriteResponseError::QuorumTimeout as u8);
+        assert_eq!(res.version, 2);
+        // the write stays committed
+        let data = write_shard.store.view([b"key".as_slice()]).unwrap();
+        assert!(matches!(data.get(b"key", now_millis()), Some(Value::String(v)) if v == b"b"));
+    }
+
+    #[test]
+    fn test_replication_backlog() {
+        let mut write_shard = WriteShard::new();
+        write_shard.store = Arc::new(Store::new(
+            Box::new(MemoryEngine::new()),
+            VersionHistory::with_capacity(1, 2),
+        ));
+        for value in ["a", "b", "c"] {
+            write_shard.write(&WriteRequest {
+                key: b"key".to_vec(),
+                value: value.as_bytes().to_vec(),
+                expiry_type: ExpiryType::None as u8,
+                expiry: 0,
+                condition: WriteCondition::Always as u8,
+                expected_version: 0,
+            });
+        }
+
+        let res = write_shard.handle_get_version_request(&GetVersionRequest { version: 0 });
+        assert_eq!(res.error, GetVersionResponseError::KeyNotFound as u8);
+        let res = write_shard.handle_get_version_request(&GetVersionRequest { version: 1 });
+        assert_eq!(res.error, GetVersionResponseError::FullResyncRequired as u8);
+        let res = write_shard.handle_get_version_request(&GetVersionRequest { version: 2 });
+        assert_eq!(res.value, b"b".to_vec());
+        // only the versions still in the backlog count towards memory
+        let data_usage = write_shard
+            .store
+            .view([b"key".as_slice()])
+            .unwrap()
+            .memory_usage(b"key")
+            .unwrap();
+        let history_usage: usize = write_shard
+            .store
+            .history()
+            .iter()
+            .map(|(_, mutation)| mutation_usage(mutation))
+            .sum();
+        assert_eq!(
+            write_shard.memory.lock().unwrap().used(),
+            data_usage + history_usage
+        );
+
+        // a reader that fell behind the backlog gets the whole keyspace instead
+        let res = write_shard.handle_sync_request(&SyncRequest {
+            version: 0,
+            offset: 0,
+        });
+        assert_eq!(res.error, SyncResponseError::NoError as u8);
+        assert_eq!((res.version, res.total, res.done), (3, 1, true));
+        let mut keyspace = Keyspace::new();
+        assert_eq!(
+            Snapshot::read_entries(&res.entries, &mut keyspace).unwrap(),
+            1
+        );
+        assert_eq!(keyspace.get(b"key", 0), Some(&Value::from(b"c".to_vec())));
+
+        // a snapshot nobody is syncing from any more is gone
+        let res = write_shard.handle_sync_request(&SyncRequest {
+            version: 2,
+            offset: 1,
+        });
+        assert_eq!(res.error, SyncResponseError::SnapshotGone as u8);
+    }
+
+    #[test]
+    fn test_maxmemory() {
+        let value = vec![b'v'; 200];
+        let write = |write_shard: &WriteShard, key: &str| {
+            write_shard.write(&WriteRequest {
+                key: key.as_bytes().to_vec(),
+                value: value.clone(),
+                expiry_type: ExpiryType::None as u8,
+                expiry: 0,
+                condition: WriteCondition::Always as u8,
+                expected_version: 0,
+            })
+        };
+
+        // each write takes up its key and value twice, in the data and in the history
+        let mut write_shard = WriteShard::new();
+        write_shard.memory = Arc::new(Mutex::new(Memory::new(1_000, EvictionPolicy::NoEviction)));
+        for key in ["a", "b"] {
+            assert_eq!(
+                write(&write_shard, key).error,
+                WriteResponseError::NoError as u8
+            );
+        }
+        assert_eq!(
+            write(&write_shard, "c").error,
+            WriteResponseError::OutOfMemory as u8
+        );
+        let HandlerResponse::Ready(res) = write_shard.handle_command_request(&CommandRequest {
+            args: vec![b"rpush".to_vec(), b"list".to_vec(), b"x".to_vec()],
+        }) else {
+            panic!("rpush never blocks");
+        };
+        assert_eq!(res.reply, Reply::out_of_memory());
+        // deletes still go through and free up memory
+        write_shard.handle_delete_request(&DeleteRequest {
+            keys: vec![b"a".to_vec()],
+        });
+        assert_eq!(
+            write(&write_shard, "c").error,
+            WriteResponseError::NoError as u8
+        );
+
+        let mut write_shard = WriteShard::new();
+        write_shard.memory = Arc::new(Mutex::new(Memory::new(1_500, EvictionPolicy::AllkeysLru)));
+        for key in ["a", "b", "c", "d"] {
+            assert_eq!(
+                write(&write_shard, key).error,
+                WriteResponseError::NoError as u8
+            );
+        }
+        // the least recently used key was evicted, and the eviction replicated as a delete
+        let data = write_shard
+            .store
+            .view([b"a".as_slice(), b"d".as_slice()])
+            .unwrap();
+        assert!(!data.contains_key(b"a", now_millis()));
+        assert!(data.contains_key(b"d", now_millis()));
+        drop(data);
+        let res = write_shard.handle_get_version_request(&GetVersionRequest { version: 4 });
+        assert_eq!(res.mutation_type, MutationType::Delete as u8);
+        assert_eq!(res.key, b"a".to_vec());
+        assert!(write_shard.memory.lock().unwrap().used() <= 2_000);
+    }
+
+    #[test]
+    fn test_handle_delete_request() {
+        let write_shard = WriteShard::new();
+        for key in [b"a", b"b"] {
+            write_shard.write(&WriteRequest {
+                key: key.to_vec(),
+                value: b"value".to_vec(),
+                expiry_type: ExpiryType::None as u8,
+                expiry: 0,
+                condition: WriteCondition::Always as u8,
+                expected_version: 0,
+            });
+        }
+
+        let res = write_shard.handle_delete_request(&DeleteRequest {
+            keys: vec![b"a".to_vec(), b"missing".to_vec(), b"b".to_vec()],
+        });
+        assert_eq!(res.error, DeleteResponseError::NoError as u8);
+        assert_eq!(res.deleted, 2);
+        assert!(write_shard.store.engine().is_empty());
+
+        // missing keys do not take up a version
+        assert_eq!(write_shard.store.version(), 4);
+        let res = write_shard.handle_get_version_request(&GetVersionRequest { version: 4 });
+        assert_eq!(res.error, GetVersionResponseError::NoError as u8);
+        assert_eq!(res.mutation_type, MutationType::Delete as u8);
+        assert_eq!(res.key, b"b".to_vec());
+    }
+
+    #[test]
+    fn test_expire_keys() {
+        let write_shard = WriteShard::new();
+        let now = now_millis();
+        write_shard.write(&WriteRequest {
+            key: b"session".to_vec(),
+            value: b"token".to_vec(),
+            expiry_type: ExpiryType::PxAt as u8,
+            expiry: now + 60_000,
+            condition: WriteCondition::Always as u8,
+            expected_version: 0,
+        });
+        write_shard.write(&WriteRequest {
+            key: b"stale".to_vec(),
+            value: b"token".to_vec(),
+            expiry_type: ExpiryType::PxAt as u8,
+            expiry: now - 1,
+            condition: WriteCondition::Always as u8,
+            expected_version: 0,
+        });
+        assert_eq!(
+            write_shard
+                .store
+                .view([b"session".as_slice()])
+                .unwrap()
+                .expires_at(b"session"),
+            Some(now + 60_000)
+        );
+
+        // expired keys count as missing before the sweeper gets to them
+        let res = write_shard.handle_expire_request(&ExpireRequest {
+            key: b"stale".to_vec(),
+            expiry_type: ExpiryType::Ex as u8,
+            expiry: 10,
+        });
+        assert!(!res.updated);
+
+        assert_eq!(write_shard.expire_keys(), 1);
+        let res = write_shard.handle_get_version_request(&GetVersionRequest { version: 3 });
+        assert_eq!(res.mutation_type, MutationType::Delete as u8);
+        assert_eq!(res.key, b"stale".to_vec());
+
+        let res = write_shard.handle_expire_request(&ExpireRequest {
+            key: b"session".to_vec(),
+            expiry_type: ExpiryType::None as u8,
+            expiry: 0,
+        });
+        assert!(res.updated);
+        assert_eq!(
+            write_shard
+                .store
+                .view([b"session".as_slice()])
+                .unwrap()
+                .expires_at(b"session"),
+            None
+        );
+        let res = write_shard.handle_get_version_request(&GetVersionRequest { version: 4 });
+        assert_eq!(res.mutation_type, MutationType::Persist as u8);
+
+        // nothing left to persist
+        let res = write_shard.handle_expire_request(&ExpireRequest {
+            key: b"session".to_vec(),
+            expiry_type: ExpiryType::None as u8,
+            expiry: 0,
+        });
+        assert!(!res.updated);
+    }
+
+    #[test]
+    fn test_conditional_writes() {
+        let write_shard = WriteShard::new();
+        let write = |condition: WriteCondition, expected_version: u64, value: &[u8]| {
+            write_shard.write(&WriteRequest {
+                key: b"config".to_vec(),
+                value: value.to_vec(),
+                expiry_type: ExpiryType::None as u8,
+                expiry: 0,
+                condition: condition as u8,
+                expected_version,
+            })
+        };
+
+        let res = write(WriteCondition::IfPresent, 0, b"a");
+        assert_eq!(res.error, WriteResponseError::PreconditionFailed as u8);
+        assert_eq!(res.version, 0);
+
+        let res = write(WriteCondition::IfAbsent, 0, b"a");
+        assert_eq!(res.error, WriteResponseError::NoError as u8);
+        assert_eq!(res.version, 1);
+
+        let res = write(WriteCondition::IfAbsent, 0, b"b");
+        assert_eq!(res.error, WriteResponseError::PreconditionFailed as u8);
+        assert_eq!(res.version, 1);
+
+        // two writers read version 1, only the first one wins
+        let res = write(WriteCondition::IfVersion, 1, b"b");
+        assert_eq!(res.error, WriteResponseError::NoError as u8);
+        assert_eq!(res.version, 2);
+        let res = write(WriteCondition::IfVersion, 1, b"c");
+        assert_eq!(res.error, WriteResponseError::PreconditionFailed as u8);
+        assert_eq!(res.version, 2);
+
+        let res = write(WriteCondition::IfPresent, 0, b"d");
+        assert_eq!(res.error, WriteResponseError::NoError as u8);
+        assert_eq!(
+            write_shard
+                .store
+                .view([b"config".as_slice()])
+                .unwrap()
+                .get(b"config", 0),
+            Some(&Value::from(b"d".to_vec()))
+        );
+        // failed writes don't take up a version
+        assert_eq!(write_shard.store.version(), 3);
+    }
+
+    #[test]
+    fn test_commands() {
+        let write_shard = WriteShard::new();
+        let command = |args: &[&str]| match write_shard.handle_command_request(&CommandRequest {
+            args: args.iter().map(|arg| arg.as_bytes().to_vec()).collect(),
+        }) {
+            HandlerResponse::Ready(res) => res.reply,
+            HandlerResponse::Deferred(_) => panic!("{:?} blocked", args),
+        };
+
+        assert_eq!(command(&["INCRBY", "counter", "5"]), Reply::Integer(5));
+        assert_eq!(command(&["decr", "counter"]), Reply::Integer(4));
+        assert_eq!(
+            command(&["incr", "counter", "extra"]),
+            Reply::wrong_arity("incr")
+        );
+        assert!(matches!(command(&["get", "counter"]), Reply::Error(_)));
+        assert_eq!(command(&["append", "counter", "x"]), Reply::Integer(2));
+        assert_eq!(command(&["incr", "counter"]), Reply::not_an_integer());
+
+        // only commands that changed something took a version
+        assert_eq!(write_shard.store.version(), 3);
+        assert_eq!(
+            write_shard
+                .store
+                .view([b"counter".as_slice()])
+                .unwrap()
+                .get(b"counter", 0),
+            Some(&Value::from(b"4x".to_vec()))
+        );
+    }
+
+    #[test]
+    fn test_blocking_pops() {
+        let write_shard = WriteShard::new();
+        let command = |args: &[&str]| {
+            write_shard.handle_command_request(&CommandRequest {
+                args: args.iter().map(|arg| arg.as_bytes().to_vec()).collect(),
+            })
+        };
+        let deferred = |res: HandlerResponse<CommandResponse>| match res {
+            HandlerResponse::Deferred(receiver) => receiver,
+            HandlerResponse::Ready(res) => panic!("answered right away with {}", res.reply),
+        };
+        let bulks = |values: &[&str]| {
+            Reply::Array(
+                values
+                    .iter()
+                    .map(|value| Reply::Bulk(value.as_bytes().to_vec()))
+                    .collect(),
+            )
+        };
+
+        let mut first = deferred(command(&["blpop", "jobs", "0"]));
+        let mut second = deferred(command(&["brpop", "other", "jobs", "0"]));
+        let mut timed_out = deferred(command(&["blpop", "jobs", "0.01"]));
+
+        // one value only wakes the client that blocked first
+        assert!(matches!(
+            command(&["rpush", "jobs", "a"]),
+            HandlerResponse::Ready(res) if res.reply == Reply::Integer(1)
+        ));
+        assert_eq!(first.try_recv().unwrap().reply, bulks(&["jobs", "a"]));
+        assert!(second.try_recv().is_err());
+
+        command(&["rpush", "jobs", "b", "c"]);
+        assert_eq!(second.try_recv().unwrap().reply, bulks(&["jobs", "c"]));
+        assert_eq!(timed_out.try_recv().unwrap().reply, bulks(&["jobs", "b"]));
+        assert!(write_shard.store.engine().is_empty());
+        // the pushes and the three pops each took a version
+        assert_eq!(write_shard.store.version(), 5);
+
+        let mut timed_out = deferred(command(&["blpop", "jobs", "0.01"]));
+        assert_eq!(write_shard.time_out_blocked(now_millis() + 5_000), 1);
+        assert_eq!(timed_out.try_recv().unwrap().reply, Reply::Nil);
+
+        // clients that went away don't take values
+        drop(deferred(command(&["blpop", "jobs", "0"])));
+        command(&["rpush", "jobs", "d"]);
+        assert!(write_shard.blocked.lock().unwrap().is_empty());
+        assert_eq!(
+            write_shard
+                .store
+                .view([b"jobs".as_slice()])
+                .unwrap()
+                .get(b"jobs", 0),
+            Some(&Value::List(vec![b"d".to_vec()].into()))
+        );
+    }
+
+    #[test]
+    fn test_transactions() {
+        let write_shard = WriteShard::new();
+        let transaction = |watches: &[(&str, u64)], commands: &[&[&str]]| {
+            write_shard
+                .handle_transaction_request(&TransactionRequest {
+                    watches: watches
+                        .iter()
+                        .map(|(key, version)| (key.as_bytes().to_vec(), *version))
+                        .collect(),
+                    commands: commands
+                        .iter()
+                        .map(|args| args.iter().map(|arg| arg.as_bytes().to_vec()).collect())
+                        .collect(),
+                })
+                .reply
+        };
+
+        assert_eq!(
+            transaction(&[("a", 0)], &[&["incrby", "a", "5"], &["rpush", "b", "x"]]),
+            Reply::Array(vec![Reply::Integer(5), Reply::Integer(1)])
+        );
+        // both commands went into one version
+        assert_eq!(write_shard.store.version(), 1);
+        let res = write_shard.handle_get_version_request(&GetVersionRequest { version: 1 });
+        assert_eq!(res.mutation_type, MutationType::Batch as u8);
+        assert_eq!(
+            write_shard
+                .store
+                .view([b"b".as_slice()])
+                .unwrap()
+                .version(b"b", 0),
+            1
+        );
+
+        // a is at version 1 now, so watching it at 0 aborts
+        assert_eq!(transaction(&[("a", 0)], &[&["incr", "a"]]), Reply::Nil);
+        assert!(matches!(
+            transaction(&[("a", 1)], &[&["incr", "a"], &["incr", "b"]]),
+            Reply::Error(message) if message.starts_with("EXECABORT")
+        ));
+        assert_eq!(write_shard.store.version(), 1);
+        assert_eq!(
+            write_shard
+                .store
+                .view([b"a".as_slice()])
+                .unwrap()
+                .get(b"a", 0),
+            Some(&Value::from(b"5".to_vec()))
+        );
+
+        // commands that only read don't take a version
+        assert_eq!(
+            transaction(&[], &[&["lrange", "b", "0", "-1"]]),
+            Reply::Array(vec![Reply::Array(vec![Reply::Bulk(b"x".to_vec())])])
+        );
+        assert_eq!(write_shard.store.version(), 1);
+    }
+
+    #[test]
+    fn test_two_phase_commit() {
+        let primary = WriteShard::new();
+        let secondary = WriteShard::new();
+        let args = |args: &[&str]| -> Vec<Vec<u8>> {
+            args.iter().map(|arg| arg.as_bytes().to_vec()).collect()
+        };
+        let prepare = |shard: &WriteShard, txn_id: u128, is_primary: bool, commands: &[&[&str]]| {
+            let port = if is_primary { 0 } else { 8081 };
+            shard.handle_two_phase_request(&TwoPhaseRequest {
+                txn_id,
+                phase: Phase::Prepare as u8,
+                primary_ip: 1,
+                primary_port: port,
+                transaction: TransactionRequest {
+                    watches: Vec::new(),
+                    commands: commands.iter().map(|command| args(command)).collect(),
+                },
+            })
+        };
+        let step = |shard: &WriteShard, txn_id: u128, phase: Phase, is_primary: bool| {
+            let port = if is_primary { 0 } else { 8081 };
+            shard.handle_two_phase_request(&TwoPhaseRequest::new(txn_id, phase, 1, port))
+        };
+        let outcome = |res: &TwoPhaseResponse| TwoPhaseOutcome::try_from(res.outcome).unwrap();
+
+        let res = prepare(&primary, 1, true, &[&["incrby", "a", "5"]]);
+        assert_eq!(outcome(&res), TwoPhaseOutcome::Prepared);
+        let res = prepare(&secondary, 1, false, &[&["rpush", "b", "x"]]);
+        assert_eq!(outcome(&res), TwoPhaseOutcome::Prepared);
+
+        // prepared keys refuse every other write until the outcome is known
+        let res = primary.handle_command_request(&CommandRequest {
+            args: args(&["incr", "a"]),
+        });
+        assert!(matches!(res, HandlerResponse::Ready(res) if res.reply == Reply::locked()));
+        let res = secondary.write(&WriteRequest {
+            key: b"b".to_vec(),
+            value: b"value".to_vec(),
+            expiry_type: ExpiryType::None as u8,
+            expiry: 0,
+            condition: WriteCondition::Always as u8,
+            expected_version: 0,
+        });
+        assert_eq!(res.error, WriteResponseError::Locked as u8);
+        let res = prepare(&secondary, 2, false, &[&["rpush", "b", "y"]]);
+        assert_eq!(outcome(&res), TwoPhaseOutcome::Aborted);
+        assert_eq!(res.reply, Reply::locked());
+        assert!(secondary.store.engine().is_empty());
+
+        let res = step(&primary, 1, Phase::Commit, true);
+        assert_eq!(outcome(&res), TwoPhaseOutcome::Committed);
+        assert_eq!(res.reply, Reply::Array(vec![Reply::Integer(5)]));
+        let res = step(&secondary, 1, Phase::Commit, false);
+        assert_eq!(res.reply, Reply::Array(vec![Reply::Integer(1)]));
+        assert_eq!(
+            secondary
+                .store
+                .view([b"b".as_slice()])
+                .unwrap()
+                .version(b"b", 0),
+            1
+        );
+        // committing again is harmless
+        let res = step(&secondary, 1, Phase::Commit, false);
+        assert_eq!(outcome(&res), TwoPhaseOutcome::Committed);
+        assert_eq!(secondary.store.version(), 1);
+        // a shard with no record of the transaction doesn't claim it committed
+        let res = step(&secondary, 6, Phase::Commit, false);
+        assert_eq!(outcome(&res), TwoPhaseOutcome::Unknown);
+        assert!(matches!(res.reply, Reply::Error(_)));
+
+        // a failing command votes no and holds nothing
+        let res = prepare(&secondary, 3, false, &[&["incr", "b"]]);
+        assert_eq!(outcome(&res), TwoPhaseOutcome::Aborted);
+        assert!(matches!(res.reply, Reply::Error(message) if message.starts_with("EXECABORT")));
+
+        // the primary presumes a transaction it never heard of aborted, for good
+        let res = step(&primary, 4, Phase::Status, true);
+        assert_eq!(outcome(&res), TwoPhaseOutcome::Aborted);
+        let res = prepare(&primary, 4, true, &[&["incr", "a"]]);
+        assert_eq!(outcome(&res), TwoPhaseOutcome::Aborted);
+
+        // shards left in doubt settle it themselves: the primary aborts, the
+        // others ask the primary
+        assert_eq!(
+            outcome(&prepare(&primary, 5, true, &[&["incr", "a"]])),
+            TwoPhaseOutcome::Prepared
+        );
+        assert_eq!(
+            outcome(&prepare(&secondary, 5, false, &[&["lpop", "b"]])),
+            TwoPhaseOutcome::Prepared
+        );
+        assert!(primary.resolve_in_doubt(now_millis()).is_empty());
+        let later = now_millis() + IN_DOUBT_TIMEOUT_MS + 1;
+        assert!(primary.resolve_in_doubt(later).is_empty());
+        let res = step(&primary, 5, Phase::Status, true);
+        assert_eq!(outcome(&res), TwoPhaseOutcome::Aborted);
+        let unresolved = secondary.resolve_in_doubt(later);
+        assert_eq!(unresolved.len(), 1);
+        assert_eq!(unresolved[0].0, 5);
+        secondary.handle_two_phase_response(&res);
+        assert_eq!(secondary.intents.lock().unwrap().holder(b"b"), None);
+        assert_eq!(
+            primary.store.view([b"a".as_slice()]).unwrap().get(b"a", 0),
+            Some(&Value::from(b"5".to_vec()))
+        );
+        assert_eq!(secondary.store.version(), 1);
+    }
+
+    #[test]
+    fn test_two_phase_recovery() {
+        let aof_path =
+            std::env::temp_dir().join(format!("rust-edis-{}.aof", rand::thread_rng().gen::<u64>()));
+        let args = WriteShardArgs {
+            aof_path: Some(aof_path.clone()),
+            appendfsync: FsyncPolicy::Always,
+            snapshot_path: None,
+            snapshot_interval: 300,
+            write_quorum: 0,
+            quorum_timeout_ms: 1_000,
+            maxmemory: 0,
+            maxmemory_policy: EvictionPolicy::NoEviction,
+            storage_engine: EngineKind::Memory,
+            data_path: None,
+            backlog_size: 100_000,
+        };
+        let prepare = |shard: &WriteShard, txn_id: u128, key: &str| {
+            shard.handle_two_phase_request(&TwoPhaseRequest {
+                txn_id,
+                phase: Phase::Prepare as u8,
+                primary_ip: 1,
+                primary_port: 8081,
+                transaction: TransactionRequest {
+                    watches: Vec::new(),
+                    commands: vec![vec![b"incr".to_vec(), key.as_bytes().to_vec()]],
+                },
+            })
+        };
+
+        {
+            let write_shard = WriteShard::open(&args).unwrap();
+            prepare(&write_shard, 1, "a");
+            prepare(&write_shard, 2, "b");
+            // crash after logging the commit but before the batch reaches the aof
+            write_shard.intents.lock().unwrap().commit(2, 1).unwrap();
+        }
+
+        let write_shard = WriteShard::open(&args).unwrap();
+        assert_eq!(write_shard.store.version(), 1);
+        assert_eq!(
+            write_shard
+                .store
+                .view([b"b".as_slice()])
+                .unwrap()
+                .get(b"b", 0),
+            Some(&Value::from(b"1".to_vec()))
+        );
+        // the vote survived, and so did its lock
+        assert_eq!(write_shard.intents.lock().unwrap().holder(b"a"), Some(1));
+        let res =
+            write_shard.handle_two_phase_request(&TwoPhaseRequest::new(1, Phase::Commit, 1, 8081));
+        assert_eq!(res.reply, Reply::Array(vec![Reply::Integer(1)]));
+        drop(write_shard);
+
+        // the redone commit went into the aof, so it isn't redone twice
+        let write_shard = WriteShard::open(&args).unwrap();
+        assert_eq!(write_shard.store.version(), 2);
+        assert_eq!(write_shard.intents.lock().unwrap().holder(b"a"), None);
+        std::fs::remove_file(&aof_path).unwrap();
+        std::fs::remove_file(aof_path.with_extension("intents")).unwrap();
+    }
+
+    #[test]
+    fn test_disk_engine_restart() {
+        let path =
+            std::env::temp_dir().join(format!("rust-edis-{}", rand::thread_rng().gen::<u64>()));
+        let args = WriteShardArgs {
+            aof_path: Some(path.with_extension("aof")),
+            appendfsync: FsyncPolicy::Always,
+            snapshot_path: None,
+            snapshot_interval: 300,
+            write_quorum: 0,
+            quorum_timeout_ms: 1_000,
+            maxmemory: 0,
+            maxmemory_policy: EvictionPolicy::NoEviction,
+            storage_engine: EngineKind::Disk,
+            data_path: Some(path.with_extension("data")),
+            backlog_size: 100_000,
+        };
+        let write = |shard: &WriteShard, key: &str, value: &str| {
+            shard.write(&WriteRequest {
+                key: key.as_bytes().to_vec(),
+                value: value.as_bytes().to_vec(),
+                expiry_type: ExpiryType::None as u8,
+                expiry: 0,
+                condition: WriteCondition::Always as u8,
+                expected_version: 0,
+            })
+        };
+
+        {
+            let write_shard = WriteShard::open(&args).unwrap();
+            write(&write_shard, "a", "1");
+            write(&write_shard, "b", "2");
+            write(&write_shard, "a", "3");
+        }
+
+        // the engine already holds every version in the aof, so none are replayed
+        let write_shard = WriteShard::open(&args).unwrap();
+        assert_eq!(write_shard.store.version(), 3);
+        assert!(!write_shard.has_unsaved_writes());
+        assert_eq!(write_shard.store.engine().len(), 2);
+        let data = write_shard
+            .store
+            .view([b"a".as_slice(), b"b".as_slice()])
+            .unwrap();
+        assert_eq!(data.get(b"a", 0), Some(&Value::from(b"3".to_vec())));
+        assert_eq!(data.version(b"b", 0), 2);
+        drop(data);
+
+        assert_eq!(
+            write(&write_shard, "c", "4").error,
+            WriteResponseError::NoError as u8
+        );
+        assert_eq!(write_shard.store.version(), 4);
+        drop(write_shard);
+        std::fs::remove_file(path.with_extension("aof")).unwrap();
+        std::fs::remove_file(path.with_extension("data")).unwrap();
+    }
+}